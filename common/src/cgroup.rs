@@ -0,0 +1,106 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use cgroups_rs::{
+    cpu::CpuController, cpuacct::CpuAcctController, memory::MemController, pid::PidController,
+    Cgroup, MaxValue,
+};
+use containerd_shim::protos::{
+    cgroups::metrics::{CPUStat, CPUUsage, MemoryEntry, MemoryStat, Metrics, PidsStat},
+    protobuf::MessageField,
+};
+
+// collect_metrics collects the metrics of the cgroup in the given path, which are the total of
+// all the processes in it, it works with both cgroup v1 and v2.
+pub fn collect_metrics(cgroup_path: &str) -> Metrics {
+    let cgroup = Cgroup::load(
+        cgroups_rs::hierarchies::auto(),
+        cgroup_path.trim_start_matches('/'),
+    );
+    let mut metrics = Metrics::new();
+
+    let mut cpu_usage = CPUUsage::new();
+    if let Some(cpuacct) = cgroup.controller_of::<CpuAcctController>() {
+        let acct = cpuacct.cpuacct();
+        cpu_usage.total = acct.usage;
+        cpu_usage.user = acct.usage_user;
+        cpu_usage.kernel = acct.usage_sys;
+    } else if let Some(cpu) = cgroup.controller_of::<CpuController>() {
+        parse_cpu_stat(&cpu.cpu().stat, &mut cpu_usage);
+    }
+    let mut cpu = CPUStat::new();
+    cpu.usage = MessageField::some(cpu_usage);
+    metrics.cpu = MessageField::some(cpu);
+
+    if let Some(mem) = cgroup.controller_of::<MemController>() {
+        let stat = mem.memory_stat();
+        let mut usage = MemoryEntry::new();
+        usage.usage = stat.usage_in_bytes;
+        usage.max = stat.max_usage_in_bytes;
+        usage.limit = stat.limit_in_bytes as u64;
+        usage.failcnt = stat.fail_cnt;
+        let mut memory = MemoryStat::new();
+        memory.usage = MessageField::some(usage);
+        memory.total_inactive_file = stat.stat.total_inactive_file;
+        metrics.memory = MessageField::some(memory);
+    }
+
+    if let Some(pid) = cgroup.controller_of::<PidController>() {
+        let mut pids = PidsStat::new();
+        pids.current = pid.get_pid_current().unwrap_or_default();
+        if let Ok(MaxValue::Value(limit)) = pid.get_pid_max() {
+            pids.limit = limit as u64;
+        }
+        metrics.pids = MessageField::some(pids);
+    }
+    metrics
+}
+
+// parse_cpu_stat parses the cpu.stat of cgroup v2, in which the usage is in microseconds.
+fn parse_cpu_stat(stat: &str, cpu_usage: &mut CPUUsage) {
+    for line in stat.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(key), Some(value)) = (
+            fields.next(),
+            fields.next().and_then(|v| v.parse::<u64>().ok()),
+        ) else {
+            continue;
+        };
+        match key {
+            "usage_usec" => cpu_usage.total = value * 1000,
+            "user_usec" => cpu_usage.user = value * 1000,
+            "system_usec" => cpu_usage.kernel = value * 1000,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_shim::protos::cgroups::metrics::CPUUsage;
+
+    use crate::cgroup::parse_cpu_stat;
+
+    #[test]
+    fn test_parse_cpu_stat() {
+        let stat = "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\nnr_periods 0\n";
+        let mut cpu_usage = CPUUsage::new();
+        parse_cpu_stat(stat, &mut cpu_usage);
+        assert_eq!(cpu_usage.total, 1500000);
+        assert_eq!(cpu_usage.user, 1000000);
+        assert_eq!(cpu_usage.kernel, 500000);
+    }
+}
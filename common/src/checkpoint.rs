@@ -0,0 +1,498 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::{Component, Path, PathBuf};
+
+use containerd_sandbox::spec::JsonSpec;
+use containerd_shim::{
+    api::CheckpointTaskRequest,
+    asynchronous::util::{read_spec, write_str_to_file},
+    error::Result,
+    other, other_error,
+    protos::{
+        protobuf::{CodedInputStream, Message},
+        shim::oci::{CheckpointOptions, Options},
+    },
+    util::CONFIG_FILE_NAME,
+};
+use serde::Serialize;
+use tokio::process::Command;
+
+pub const DEFAULT_RUNC_ROOT: &str = "/run/containerd/runc";
+pub const DEFAULT_COMMAND: &str = "runc";
+
+// the layout of the checkpoint archive of the Kubernetes container checkpoint API
+pub const CHECKPOINT_DIR_NAME: &str = "checkpoint";
+const SPEC_DUMP_FILE: &str = "spec.dump";
+const CONFIG_DUMP_FILE: &str = "config.dump";
+// the dir in the bundle where the checkpoint archive is extracted to for restore
+pub const RESTORE_DIR_NAME: &str = "restore";
+
+const ANNOTATION_CONTAINER_NAME: &str = "io.kubernetes.cri.container-name";
+const ANNOTATION_IMAGE_NAME: &str = "io.kubernetes.cri.image-name";
+
+// RuncCommand builds the runc commands that the runc crate does not support,
+// with the same global options as the runc instance of the container.
+#[derive(Clone, Debug)]
+pub struct RuncCommand {
+    command: String,
+    global_args: Vec<String>,
+}
+
+impl RuncCommand {
+    pub fn new(runtime: &str, namespace: &str, bundle: impl AsRef<Path>, opts: &Options) -> Self {
+        let command = if runtime.is_empty() {
+            DEFAULT_COMMAND
+        } else {
+            runtime
+        };
+        let mut global_args = vec![
+            "--root".to_string(),
+            runc_root(namespace, opts).to_string_lossy().to_string(),
+            "--log".to_string(),
+            bundle
+                .as_ref()
+                .join("log.json")
+                .to_string_lossy()
+                .to_string(),
+            "--log-format".to_string(),
+            "json".to_string(),
+        ];
+        if opts.systemd_cgroup {
+            global_args.push("--systemd-cgroup".to_string());
+        }
+        Self {
+            command: command.to_string(),
+            global_args,
+        }
+    }
+
+    pub fn checkpoint(&self, id: &str, opts: &CheckpointOpts) -> Command {
+        let mut args = vec!["checkpoint".to_string()];
+        args.extend(opts.args());
+        args.push(id.to_string());
+        self.command(args)
+    }
+
+    pub fn restore(&self, id: &str, opts: &RestoreOpts) -> Command {
+        let mut args = vec!["restore".to_string()];
+        args.extend(opts.args());
+        args.push(id.to_string());
+        self.command(args)
+    }
+
+    fn command(&self, args: Vec<String>) -> Command {
+        let mut cmd = Command::new(&self.command);
+        cmd.args(&self.global_args);
+        cmd.args(args);
+        cmd.stdin(std::process::Stdio::null());
+        cmd.stdout(std::process::Stdio::piped());
+        cmd.stderr(std::process::Stdio::piped());
+        cmd
+    }
+}
+
+pub fn runc_root(namespace: &str, opts: &Options) -> PathBuf {
+    let root = opts.root.as_str();
+    Path::new(if root.is_empty() {
+        DEFAULT_RUNC_ROOT
+    } else {
+        root
+    })
+    .join(namespace)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CheckpointOpts {
+    pub image_path: String,
+    pub work_path: String,
+    pub exit: bool,
+    pub open_tcp: bool,
+    pub external_unix_sockets: bool,
+    pub terminal: bool,
+    pub file_locks: bool,
+    pub empty_namespaces: Vec<String>,
+    pub cgroups_mode: String,
+}
+
+impl CheckpointOpts {
+    // from_request parses the runc checkpoint options in the request, the images are always
+    // dumped to the given image path, as the path in the request may be a host path.
+    pub fn from_request(
+        req: &CheckpointTaskRequest,
+        image_path: &str,
+        work_path: &str,
+    ) -> Result<Self> {
+        let mut options = CheckpointOptions::new();
+        if let Some(any) = req.options.as_ref() {
+            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
+            options
+                .merge_from(&mut input)
+                .map_err(|e| other!("failed to parse checkpoint options: {}", e))?;
+        }
+        let work_path = if options.work_path.is_empty() {
+            work_path.to_string()
+        } else {
+            options.work_path.to_string()
+        };
+        Ok(Self {
+            image_path: image_path.to_string(),
+            work_path,
+            exit: options.exit,
+            open_tcp: options.open_tcp,
+            external_unix_sockets: options.external_unix_sockets,
+            terminal: options.terminal,
+            file_locks: options.file_locks,
+            empty_namespaces: options.empty_namespaces.to_vec(),
+            cgroups_mode: options.cgroups_mode.to_string(),
+        })
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--image-path".to_string(),
+            self.image_path.to_string(),
+            "--work-path".to_string(),
+            self.work_path.to_string(),
+        ];
+        if !self.exit {
+            args.push("--leave-running".to_string());
+        }
+        if self.open_tcp {
+            args.push("--tcp-established".to_string());
+        }
+        if self.external_unix_sockets {
+            args.push("--ext-unix-sk".to_string());
+        }
+        if self.terminal {
+            args.push("--shell-job".to_string());
+        }
+        if self.file_locks {
+            args.push("--file-locks".to_string());
+        }
+        for ns in &self.empty_namespaces {
+            args.push("--empty-ns".to_string());
+            args.push(ns.to_string());
+        }
+        if !self.cgroups_mode.is_empty() {
+            args.push("--manage-cgroups-mode".to_string());
+            args.push(self.cgroups_mode.to_string());
+        }
+        args
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RestoreOpts {
+    pub image_path: String,
+    pub work_path: String,
+    pub bundle: String,
+    pub pid_file: String,
+    pub console_socket: Option<String>,
+    pub no_pivot: bool,
+}
+
+impl RestoreOpts {
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--image-path".to_string(),
+            self.image_path.to_string(),
+            "--work-path".to_string(),
+            self.work_path.to_string(),
+            "--bundle".to_string(),
+            self.bundle.to_string(),
+            "--pid-file".to_string(),
+            self.pid_file.to_string(),
+            "--detach".to_string(),
+        ];
+        if let Some(s) = &self.console_socket {
+            args.push("--console-socket".to_string());
+            args.push(s.to_string());
+        }
+        if self.no_pivot {
+            args.push("--no-pivot".to_string());
+        }
+        args
+    }
+}
+
+// ContainerConfig is the content of config.dump in the checkpoint archive,
+// which describes the container that is checkpointed.
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ContainerConfig {
+    id: String,
+    name: String,
+    rootfs_image_name: String,
+    runtime: String,
+}
+
+// write_dump_files writes the spec and config of the container into the work dir,
+// next to the checkpoint images.
+pub async fn write_dump_files(
+    id: &str,
+    bundle: &str,
+    runtime: &str,
+    work_dir: &Path,
+) -> Result<()> {
+    let spec: JsonSpec = read_spec(bundle).await?;
+    let config = ContainerConfig {
+        id: id.to_string(),
+        name: spec
+            .annotations
+            .get(ANNOTATION_CONTAINER_NAME)
+            .cloned()
+            .unwrap_or_default(),
+        rootfs_image_name: spec
+            .annotations
+            .get(ANNOTATION_IMAGE_NAME)
+            .cloned()
+            .unwrap_or_default(),
+        runtime: if runtime.is_empty() {
+            DEFAULT_COMMAND.to_string()
+        } else {
+            runtime.to_string()
+        },
+    };
+    let config_content = serde_json::to_string(&config)
+        .map_err(other_error!(e, "failed to marshal checkpoint config"))?;
+    write_str_to_file(work_dir.join(CONFIG_DUMP_FILE), config_content).await?;
+    tokio::fs::copy(
+        Path::new(bundle).join(CONFIG_FILE_NAME),
+        work_dir.join(SPEC_DUMP_FILE),
+    )
+    .await
+    .map_err(other_error!(e, "failed to dump spec of checkpoint"))?;
+    Ok(())
+}
+
+// create_archive packs the checkpoint images and the dump files in the work dir
+// into a tar archive in the format of the Kubernetes checkpoint API.
+pub async fn create_archive(work_dir: &Path, archive: &str) -> Result<()> {
+    if let Some(parent) = Path::new(archive).parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(other_error!(e, "failed to create archive dir"))?;
+    }
+    let output = Command::new("tar")
+        .arg("-cf")
+        .arg(archive)
+        .arg("-C")
+        .arg(work_dir)
+        .args([CHECKPOINT_DIR_NAME, CONFIG_DUMP_FILE, SPEC_DUMP_FILE])
+        .output()
+        .await
+        .map_err(other_error!(e, "failed to execute tar"))?;
+    if !output.status.success() {
+        return Err(other!(
+            "failed to create checkpoint archive {}, {}",
+            archive,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+// extract_archive extracts the checkpoint archive, which should be in the checkpoint dir,
+// into the bundle of the container, and returns the path of the checkpoint images in it.
+pub async fn extract_archive(archive: &str, bundle: &str, checkpoint_dir: &str) -> Result<String> {
+    check_archive(archive, checkpoint_dir).await?;
+    let restore_dir = Path::new(bundle).join(RESTORE_DIR_NAME);
+    tokio::fs::create_dir_all(&restore_dir)
+        .await
+        .map_err(other_error!(e, "failed to create restore dir"))?;
+    let output = Command::new("tar")
+        .arg("-xf")
+        .arg(archive)
+        .arg("-C")
+        .arg(&restore_dir)
+        .output()
+        .await
+        .map_err(other_error!(e, "failed to execute tar"))?;
+    if !output.status.success() {
+        return Err(other!(
+            "failed to extract checkpoint archive {}, {}",
+            archive,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(restore_dir
+        .join(CHECKPOINT_DIR_NAME)
+        .to_string_lossy()
+        .to_string())
+}
+
+// check_archive makes sure that the archive is in the checkpoint dir, and that all its members
+// are extracted into the target dir, as it is extracted by root on the host.
+pub async fn check_archive(archive: &str, checkpoint_dir: &str) -> Result<()> {
+    let dir = tokio::fs::canonicalize(checkpoint_dir)
+        .await
+        .map_err(other_error!(
+            e,
+            format!("failed to resolve checkpoint dir {}", checkpoint_dir)
+        ))?;
+    let path = tokio::fs::canonicalize(archive)
+        .await
+        .map_err(other_error!(
+            e,
+            format!("failed to resolve checkpoint archive {}", archive)
+        ))?;
+    if !path.starts_with(&dir) {
+        return Err(other!(
+            "checkpoint archive {} is not in the checkpoint dir {}",
+            archive,
+            checkpoint_dir
+        ));
+    }
+
+    let output = Command::new("tar")
+        .arg("-tf")
+        .arg(&path)
+        .output()
+        .await
+        .map_err(other_error!(e, "failed to execute tar"))?;
+    if !output.status.success() {
+        return Err(other!(
+            "failed to list checkpoint archive {}, {}",
+            archive,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    if let Some(member) = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|m| !is_relative_member(m))
+    {
+        return Err(other!(
+            "checkpoint archive {} has member {} out of the extract dir",
+            archive,
+            member
+        ));
+    }
+    Ok(())
+}
+
+fn is_relative_member(member: &str) -> bool {
+    Path::new(member)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use temp_dir::TempDir;
+
+    use crate::checkpoint::{
+        create_archive, extract_archive, is_relative_member, CheckpointOpts, RestoreOpts,
+        CHECKPOINT_DIR_NAME, CONFIG_DUMP_FILE, SPEC_DUMP_FILE,
+    };
+
+    #[test]
+    fn test_checkpoint_args() {
+        let opts = CheckpointOpts {
+            image_path: "/run/kuasar/state/c1/checkpoint".to_string(),
+            work_path: "/run/kuasar/state/c1/work".to_string(),
+            exit: false,
+            open_tcp: true,
+            empty_namespaces: vec!["network".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            opts.args(),
+            vec![
+                "--image-path",
+                "/run/kuasar/state/c1/checkpoint",
+                "--work-path",
+                "/run/kuasar/state/c1/work",
+                "--leave-running",
+                "--tcp-established",
+                "--empty-ns",
+                "network",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_restore_args() {
+        let opts = RestoreOpts {
+            image_path: "/run/kuasar/state/c1/checkpoint".to_string(),
+            work_path: "/run/kuasar/state/c1/work".to_string(),
+            bundle: "/run/kuasar/state/c1".to_string(),
+            pid_file: "/run/kuasar/state/c1/init.pid".to_string(),
+            console_socket: None,
+            no_pivot: true,
+        };
+        let args = opts.args();
+        assert!(args.contains(&"--detach".to_string()));
+        assert!(args.contains(&"--no-pivot".to_string()));
+        assert!(!args.contains(&"--console-socket".to_string()));
+    }
+
+    #[test]
+    fn test_is_relative_member() {
+        assert!(is_relative_member("checkpoint/pages-1.img"));
+        assert!(is_relative_member("./checkpoint/"));
+        assert!(!is_relative_member("/etc/passwd"));
+        assert!(!is_relative_member("../../etc/passwd"));
+        assert!(!is_relative_member("checkpoint/../../etc/passwd"));
+    }
+
+    #[tokio::test]
+    async fn test_extract_archive() {
+        let checkpoint_dir = TempDir::new().unwrap();
+        let work_dir = checkpoint_dir.child("work");
+        let image_dir = work_dir.join(CHECKPOINT_DIR_NAME);
+        tokio::fs::create_dir_all(&image_dir).await.unwrap();
+        tokio::fs::write(image_dir.join("pages-1.img"), "pages")
+            .await
+            .unwrap();
+        tokio::fs::write(work_dir.join(CONFIG_DUMP_FILE), "{}")
+            .await
+            .unwrap();
+        tokio::fs::write(work_dir.join(SPEC_DUMP_FILE), "{}")
+            .await
+            .unwrap();
+        let archive = checkpoint_dir.child("checkpoint.tar");
+        let archive = archive.to_str().unwrap();
+        create_archive(&work_dir, archive).await.unwrap();
+
+        // the images are in the checkpoint dir of the archive, not in the restore dir itself
+        let bundle = TempDir::new().unwrap();
+        let image_path = extract_archive(
+            archive,
+            bundle.path().to_str().unwrap(),
+            checkpoint_dir.path().to_str().unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            Path::new(&image_path),
+            bundle.path().join("restore").join(CHECKPOINT_DIR_NAME)
+        );
+        assert!(Path::new(&image_path).join("pages-1.img").exists());
+
+        // the archive out of the checkpoint dir is rejected
+        let other_dir = TempDir::new().unwrap();
+        assert!(extract_archive(
+            archive,
+            bundle.path().to_str().unwrap(),
+            other_dir.path().to_str().unwrap(),
+        )
+        .await
+        .is_err());
+    }
+}
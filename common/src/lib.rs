@@ -0,0 +1,23 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+// The code shared by the sandboxers and task servers of the different runtimes,
+// each module is behind a feature so that they only depend on what they use.
+
+#[cfg(feature = "cgroup")]
+pub mod cgroup;
+#[cfg(feature = "checkpoint")]
+pub mod checkpoint;
@@ -0,0 +1,53 @@
+/*
+Copyright 2024 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(author, about, long_about = None)]
+pub struct Args {
+    /// Sandboxer working directory, default is `/var/lib/kuasar-quark`
+    #[arg(
+        short,
+        long,
+        value_name = "DIR",
+        default_value = "/var/lib/kuasar-quark"
+    )]
+    pub dir: String,
+
+    /// Address for sandboxer's server, default is `/run/quark-sandboxer.sock`
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        default_value = "/run/quark-sandboxer.sock"
+    )]
+    pub listen: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::args::Args;
+
+    #[test]
+    fn test_args_parse_default() {
+        let args = Args::parse();
+        assert_eq!(args.dir, "/var/lib/kuasar-quark");
+        assert_eq!(args.listen, "/run/quark-sandboxer.sock");
+    }
+}
@@ -14,24 +14,38 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
+
+use clap::Parser;
 
 use crate::sandbox::QuarkSandboxer;
 
+mod args;
 mod mount;
 mod sandbox;
+mod spec;
 mod utils;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = args::Args::parse();
     env_logger::Builder::from_default_env()
         .format_timestamp_micros()
         .init();
     let sandboxer = QuarkSandboxer {
         sandboxes: Arc::new(Default::default()),
     };
-    containerd_sandbox::run("io.containerd.sandboxer.quark.v1", sandboxer)
-        .await
-        .unwrap();
+    // Do recovery job
+    if Path::new(&args.dir).exists() {
+        sandboxer.recover(&args.dir).await?;
+    }
+    containerd_sandbox::run(
+        "io.containerd.sandboxer.quark.v1",
+        &args.listen,
+        &args.dir,
+        sandboxer,
+    )
+    .await
+    .unwrap();
     Ok(())
 }
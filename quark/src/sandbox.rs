@@ -14,51 +14,67 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, fs::read_to_string, path::Path, sync::Arc};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
 use containerd_sandbox::{
-    data::{ContainerData, SandboxData},
+    data::{ContainerData, Io, SandboxData},
     error::{Error, Result},
     signal::ExitSignal,
-    spec::{
-        JsonSpec, Linux, LinuxCPU, LinuxHugepageLimit, LinuxMemory, LinuxNamespace, LinuxResources,
-        Process, Root,
-    },
+    spec::JsonSpec,
     Container, ContainerOption, Sandbox, SandboxOption, SandboxStatus, Sandboxer,
 };
+use log::{info, warn};
 use nix::{
     errno::Errno,
+    libc,
     mount::umount,
     sys::signal::{kill, Signal},
-    unistd::Pid,
+    unistd::{gethostname, Pid},
 };
 use prost_types::Any;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    fs::{create_dir_all, remove_dir_all},
+    fs::{copy, create_dir_all, remove_dir_all, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
     sync::{Mutex, RwLock},
 };
 
 use crate::{
     mount::bind_mount,
-    utils::{cleanup_mounts, mount_rootfs, write_file_atomic},
+    spec::{
+        create_spec, parse_dnsoptions, ETC_HOSTS, ETC_RESOLV, HOSTNAME_FILENAME, HOSTS_FILENAME,
+        RESOLV_FILENAME,
+    },
+    utils::{
+        check_process_cmdline, cleanup_mounts, mount_rootfs, read_pid, unmount, write_file_atomic,
+        MNT_NOFOLLOW,
+    },
 };
 
+// the exit code of the sandbox whose quark process exited while the sandboxer was down
+const EXIT_CODE_UNKNOWN: u32 = 255;
+
+const QUARK_BIN: &str = "quark";
+
 pub struct QuarkSandboxer {
     #[allow(clippy::type_complexity)]
     pub(crate) sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<QuarkSandbox>>>>>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct QuarkSandbox {
-    pub(crate) _id: String,
+    pub(crate) id: String,
     pub(crate) base_dir: String,
     pub(crate) data: SandboxData,
     pub(crate) status: SandboxStatus,
     pub(crate) containers: HashMap<String, QuarkContainer>,
+    #[serde(skip, default)]
     pub(crate) exit_signal: Arc<ExitSignal>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct QuarkContainer {
     pub(crate) data: ContainerData,
 }
@@ -69,7 +85,7 @@ impl Sandboxer for QuarkSandboxer {
 
     async fn create(&self, id: &str, s: SandboxOption) -> Result<()> {
         let mut sandbox = QuarkSandbox {
-            _id: id.to_string(),
+            id: id.to_string(),
             base_dir: s.base_dir,
             data: s.sandbox,
             status: SandboxStatus::Created,
@@ -83,9 +99,10 @@ impl Sandboxer for QuarkSandboxer {
         create_dir_all(&quark_sandbox_bundle)
             .await
             .map_err(|e| anyhow!("failed to create {}, {}", quark_sandbox_bundle, e))?;
+        sandbox.setup_sandbox_files().await?;
         // create spec from PodSandboxConfig
         if sandbox.data.spec.is_none() {
-            sandbox.data.spec = Some(self.create_spec(&sandbox.data)?);
+            sandbox.data.spec = Some(create_spec(&sandbox.data, &quark_sandbox_bundle)?);
         }
         if let Some(spec) = &sandbox.data.spec {
             // create root path
@@ -111,6 +128,7 @@ impl Sandboxer for QuarkSandboxer {
                 .map_err(|e| anyhow!("failed to decode sandbox spec {}", e))?;
             write_file_atomic(&spec_file, spec_buf.as_slice()).await?;
         }
+        sandbox.dump().await?;
         let mut sandboxes = self.sandboxes.write().await;
         sandboxes.insert(id.to_string(), Arc::new(Mutex::new(sandbox)));
         Ok(())
@@ -119,7 +137,7 @@ impl Sandboxer for QuarkSandboxer {
     async fn start(&self, id: &str) -> Result<()> {
         let sandbox_mutex = self.sandbox(id).await?;
         let mut sandbox = sandbox_mutex.lock().await;
-        let mut cmd = tokio::process::Command::new("quark");
+        let mut cmd = tokio::process::Command::new(QUARK_BIN);
         let quark_sandbox_bundle = sandbox.get_sandbox_bundle();
         cmd.arg("-r");
         cmd.arg(&quark_sandbox_bundle);
@@ -137,13 +155,12 @@ impl Sandboxer for QuarkSandboxer {
             .spawn()
             .map_err(|e| anyhow!("failed to spawn quark sandbox command, {}", e))?;
         child.wait().await?;
-        let pid_str = read_to_string(&pid_path)
-            .map_err(|e| anyhow!("failed to read file {}, {}", pid_path, e))?;
-        let pid = pid_str
-            .parse::<u32>()
-            .map_err(|e| anyhow!("failed to parse pid {}, {}", pid_str, e))?;
+        let pid = read_pid(&pid_path)?;
         sandbox.status = SandboxStatus::Running(pid);
         sandbox.data.task_address = format!("unix://{}", task_address);
+        sandbox.dump().await.inspect_err(|_| {
+            kill(Pid::from_raw(pid as i32), Signal::SIGKILL).unwrap_or_default();
+        })?;
         Ok(())
     }
 
@@ -173,6 +190,7 @@ impl Sandboxer for QuarkSandboxer {
         let ts = time::OffsetDateTime::now_utc().unix_timestamp_nanos();
         sandbox.status = SandboxStatus::Stopped(0, ts);
         sandbox.exit_signal.signal();
+        sandbox.dump().await?;
         Ok(())
     }
 
@@ -189,6 +207,8 @@ impl Sandboxer for QuarkSandboxer {
                 e
             )
         })?;
+        drop(sandbox);
+        self.sandboxes.write().await.remove(id);
         Ok(())
     }
 }
@@ -256,6 +276,7 @@ impl Sandbox for QuarkSandbox {
         write_file_atomic(&config_path, spec_content.as_slice()).await?;
         let container = QuarkContainer { data };
         self.containers.insert(id.to_string(), container);
+        self.dump().await?;
         Ok(())
     }
 
@@ -320,6 +341,7 @@ impl Sandbox for QuarkSandbox {
         for p in new_processes {
             self.container_mut(id)?.data.processes.push(p);
         }
+        self.dump().await?;
         Ok(())
     }
 
@@ -330,6 +352,7 @@ impl Sandbox for QuarkSandbox {
             .await
             .map_err(|e| anyhow!("failed to remove bundle {}, {}", bundle, e))?;
         self.containers.remove(id);
+        self.dump().await?;
         Ok(())
     }
 
@@ -351,17 +374,65 @@ impl QuarkSandbox {
         format!("{}/sandbox/{}", self.base_dir, id)
     }
 
-    async fn bind_mount_io(
+    // setup_sandbox_files writes the hostname, hosts and resolv.conf files of the sandbox
+    // to the sandbox bundle, which are mounted by the spec created in `create_spec`.
+    async fn setup_sandbox_files(&self) -> Result<()> {
+        let bundle = self.get_sandbox_bundle();
+        let config = self.data.config.as_ref();
+
+        // Handle hostname
+        let mut hostname = config.map(|c| c.hostname.clone()).unwrap_or_default();
+        if hostname.is_empty() {
+            let mut buf = [0u8; 256];
+            hostname = gethostname(&mut buf)
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+        }
+        hostname.push('\n');
+        let hostname_path = Path::new(&bundle).join(HOSTNAME_FILENAME);
+        tokio::fs::write(&hostname_path, hostname)
+            .await
+            .map_err(|e| anyhow!("write hostname: {}", e))?;
+
+        // handle hosts
+        let hosts_path = Path::new(&bundle).join(HOSTS_FILENAME);
+        copy(ETC_HOSTS, hosts_path)
+            .await
+            .map_err(|e| anyhow!("copy hosts: {}", e))?;
+
+        // handle resolv.conf
+        let resolv_path = Path::new(&bundle).join(RESOLV_FILENAME);
+        match config
+            .and_then(|c| c.dns_config.as_ref())
+            .map(|dns_config| {
+                parse_dnsoptions(
+                    &dns_config.servers,
+                    &dns_config.searches,
+                    &dns_config.options,
+                )
+            }) {
+            Some(resolv_content) if !resolv_content.is_empty() => {
+                tokio::fs::write(resolv_path, resolv_content)
+                    .await
+                    .map_err(|e| anyhow!("write resolv.conf: {}", e))?;
+            }
+            _ => {
+                copy(ETC_RESOLV, resolv_path)
+                    .await
+                    .map_err(|e| anyhow!("copy resolv.conf: {}", e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_io_path(
         &self,
         container_id: &str,
         process_id: Option<&str>,
-        io_file: &str,
         stdio_type: &str,
-    ) -> Result<String> {
-        if io_file.is_empty() {
-            return Ok("".to_string());
-        }
-        let std_new = if let Some(p_id) = process_id {
+    ) -> String {
+        if let Some(p_id) = process_id {
             format!(
                 "{}/{}-{}",
                 self.get_container_bundle(container_id),
@@ -370,7 +441,20 @@ impl QuarkSandbox {
             )
         } else {
             format!("{}/{}", self.get_container_bundle(container_id), stdio_type)
-        };
+        }
+    }
+
+    async fn bind_mount_io(
+        &self,
+        container_id: &str,
+        process_id: Option<&str>,
+        io_file: &str,
+        stdio_type: &str,
+    ) -> Result<String> {
+        if io_file.is_empty() {
+            return Ok("".to_string());
+        }
+        let std_new = self.get_io_path(container_id, process_id, stdio_type);
         bind_mount(io_file, &std_new, &[]).await?;
         let io_file_in = if let Some(p_id) = process_id {
             format!("/{}/{}-{}", container_id, p_id, stdio_type)
@@ -402,6 +486,103 @@ impl QuarkSandbox {
         Ok(())
     }
 
+    // get_io_paths returns the paths in the container bundle that the io files are bind mounted to.
+    fn get_io_paths(&self, container_id: &str, process_id: Option<&str>, io: &Io) -> Vec<String> {
+        [
+            (&io.stdin, "stdin"),
+            (&io.stdout, "stdout"),
+            (&io.stderr, "stderr"),
+        ]
+        .iter()
+        .filter(|(f, _)| !f.is_empty())
+        .map(|(_, t)| self.get_io_path(container_id, process_id, t))
+        .collect()
+    }
+
+    // cleanup unmounts the io files, rootfs and volumes of all the containers in the sandbox.
+    async fn cleanup(&self) {
+        for (id, c) in &self.containers {
+            let mut io_paths = vec![];
+            if let Some(io) = &c.data.io {
+                io_paths.append(&mut self.get_io_paths(id, None, io));
+            }
+            for p in &c.data.processes {
+                if let Some(io) = &p.io {
+                    io_paths.append(&mut self.get_io_paths(id, Some(&p.id), io));
+                }
+            }
+            for path in io_paths {
+                unmount(&path, libc::MNT_DETACH | MNT_NOFOLLOW).unwrap_or_else(|e| {
+                    warn!("failed to unmount container io {}, {:?}", path, e);
+                });
+            }
+        }
+        cleanup_mounts(&self.base_dir).await.unwrap_or_else(|e| {
+            warn!("failed to cleanup mounts in {}, {:?}", self.base_dir, e);
+        });
+    }
+
+    async fn recover<P: AsRef<Path>>(base_dir: P) -> Result<Self> {
+        let dump_path = base_dir.as_ref().join("sandbox.json");
+        let mut dump_file = OpenOptions::new()
+            .read(true)
+            .open(&dump_path)
+            .await
+            .map_err(Error::IO)?;
+        let mut content = vec![];
+        dump_file
+            .read_to_end(&mut content)
+            .await
+            .map_err(Error::IO)?;
+        let mut sb = serde_json::from_slice::<QuarkSandbox>(content.as_slice())
+            .map_err(|e| anyhow!("failed to deserialize sandbox, {}", e))?;
+
+        match sb.status {
+            SandboxStatus::Running(_) => {
+                // re-adopt the quark process if it is still alive,
+                // and the pid is not reused by another process.
+                let pid_path = format!("{}/pid", sb.base_dir);
+                match read_pid(&pid_path) {
+                    Ok(pid) if check_process_cmdline(pid, QUARK_BIN, &sb.id).is_ok() => {
+                        info!("recover quark sandbox {} with pid {}", sb.id, pid);
+                        sb.status = SandboxStatus::Running(pid);
+                    }
+                    _ => {
+                        warn!("quark process of sandbox {} exited, clean it up", sb.id);
+                        sb.cleanup().await;
+                        let ts = time::OffsetDateTime::now_utc().unix_timestamp_nanos();
+                        sb.status = SandboxStatus::Stopped(EXIT_CODE_UNKNOWN, ts);
+                        sb.exit_signal.signal();
+                    }
+                }
+                sb.dump().await?;
+            }
+            SandboxStatus::Stopped(_, _) => {
+                sb.exit_signal.signal();
+            }
+            _ => {}
+        }
+        Ok(sb)
+    }
+
+    async fn dump(&self) -> Result<()> {
+        let dump_data =
+            serde_json::to_vec(&self).map_err(|e| anyhow!("failed to serialize sandbox, {}", e))?;
+        let dump_path = format!("{}/sandbox.json", self.base_dir);
+        let mut dump_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&dump_path)
+            .await
+            .map_err(Error::IO)?;
+        dump_file
+            .write_all(dump_data.as_slice())
+            .await
+            .map_err(Error::IO)?;
+        Ok(())
+    }
+
     fn container_mut(&mut self, id: &str) -> Result<&mut <Self as Sandbox>::Container> {
         self.containers
             .get_mut(id)
@@ -417,108 +598,32 @@ impl Container for QuarkContainer {
 }
 
 impl QuarkSandboxer {
-    fn create_spec(&self, data: &SandboxData) -> Result<JsonSpec> {
-        let mut spec = JsonSpec::default();
-        let config = data
-            .config
-            .as_ref()
-            .ok_or(anyhow!("no PodSandboxConfig in request"))?;
-
-        // construct Linux Configs from PodSandboxConfig
-        let mut linux = Linux {
-            uid_mappings: vec![],
-            gid_mappings: vec![],
-            sysctl: Default::default(),
-            resources: None,
-            cgroups_path: "".to_string(),
-            namespaces: vec![],
-            devices: vec![],
-            seccomp: None,
-            rootfs_propagation: "".to_string(),
-            masked_path: vec![],
-            readonly_path: vec![],
-            mount_label: "".to_string(),
-            intel_rdt: None,
-        };
-        if let Some(lc) = &config.linux {
-            if let Some(resources) = &lc.resources {
-                let mut spec_resources = LinuxResources {
-                    devices: vec![],
-                    memory: None,
-                    cpu: None,
-                    pids: None,
-                    block_io: None,
-                    hugepage_limits: vec![],
-                    network: None,
-                    rdma: Default::default(),
-                    files: None,
-                };
-
-                let mut memory = LinuxMemory {
-                    limit: Some(resources.memory_limit_in_bytes as u64),
-                    reservation: None,
-                    swap: None,
-                    kernel: None,
-                    kernel_tcp: None,
-                    swappiness: None,
-                    disable_oom_killer: None,
-                };
-                if resources.memory_limit_in_bytes > 0 {
-                    memory.limit = Some(resources.memory_limit_in_bytes as u64);
-                }
-                if resources.memory_swap_limit_in_bytes > 0 {
-                    memory.swap = Some(resources.memory_swap_limit_in_bytes as u64);
-                }
-                let mut cpu = LinuxCPU {
-                    shares: None,
-                    quota: None,
-                    period: None,
-                    realtime_runtime: None,
-                    realtime_period: None,
-                    cpus: resources.cpuset_cpus.to_string(),
-                    mems: resources.cpuset_mems.to_string(),
-                };
-                if resources.cpu_period > 0 {
-                    cpu.period = Some(resources.cpu_period as u64);
-                }
-                if resources.cpu_quota > 0 {
-                    cpu.quota = Some(resources.cpu_quota);
-                }
-                if resources.cpu_shares > 0 {
-                    cpu.shares = Some(resources.cpu_shares as u64);
-                }
-                spec_resources.cpu = Some(cpu);
-
-                if !resources.hugepage_limits.is_empty() {
-                    for l in &resources.hugepage_limits {
-                        spec_resources.hugepage_limits.push(LinuxHugepageLimit {
-                            page_size: l.page_size.to_string(),
-                            limit: l.limit,
-                        });
+    pub async fn recover(&self, dir: &str) -> Result<()> {
+        let mut subs = tokio::fs::read_dir(dir).await.map_err(Error::IO)?;
+        while let Some(entry) = subs.next_entry().await.map_err(Error::IO)? {
+            if let Ok(t) = entry.file_type().await {
+                if t.is_dir() {
+                    let path = Path::new(dir).join(entry.file_name());
+                    match QuarkSandbox::recover(&path).await {
+                        Ok(sb) => {
+                            let sb_mutex = Arc::new(Mutex::new(sb));
+                            self.sandboxes
+                                .write()
+                                .await
+                                .insert(entry.file_name().to_string_lossy().to_string(), sb_mutex);
+                        }
+                        Err(e) => {
+                            warn!("failed to recover sandbox {:?}, {:?}", entry.file_name(), e);
+                            // unmount before removing, or the files of the bind mounts are removed
+                            let base_dir = path.to_string_lossy().to_string();
+                            cleanup_mounts(&base_dir).await.unwrap_or_default();
+                            remove_dir_all(&path).await.unwrap_or_default();
+                        }
                     }
                 }
-                linux.resources = Some(spec_resources);
             }
         }
-
-        if !data.netns.is_empty() {
-            linux.namespaces.push(LinuxNamespace {
-                r#type: "network".to_string(),
-                path: data.netns.to_string(),
-            });
-        }
-        spec.linux = Some(linux);
-
-        // TODO construct Process from PodSandboxConfig
-        let process = Process::new();
-        spec.process = Some(process);
-        // TODO add root path
-        let root = Root {
-            path: "rootfs".to_string(),
-            readonly: false,
-        };
-        spec.root = Some(root);
-        Ok(spec)
+        Ok(())
     }
 }
 
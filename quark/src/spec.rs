@@ -0,0 +1,529 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use anyhow::anyhow;
+use containerd_sandbox::{
+    cri::api::v1::{LinuxContainerResources, NamespaceMode, NamespaceOption},
+    data::SandboxData,
+    error::Result,
+    spec::{
+        JsonSpec, Linux, LinuxCPU, LinuxHugepageLimit, LinuxMemory, LinuxNamespace, LinuxResources,
+        Mount, Process, Root,
+    },
+};
+
+pub const ETC_HOSTS: &str = "/etc/hosts";
+pub const ETC_HOSTNAME: &str = "/etc/hostname";
+pub const ETC_RESOLV: &str = "/etc/resolv.conf";
+pub const HOSTS_FILENAME: &str = "hosts";
+pub const HOSTNAME_FILENAME: &str = "hostname";
+pub const RESOLV_FILENAME: &str = "resolv.conf";
+
+const DEFAULT_PATH_ENV: &str = "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+// the annotations that containerd sets to the spec of the sandbox container
+const ANNOTATION_CONTAINER_TYPE: &str = "io.kubernetes.cri.container-type";
+const ANNOTATION_SANDBOX_ID: &str = "io.kubernetes.cri.sandbox-id";
+const ANNOTATION_SANDBOX_NAME: &str = "io.kubernetes.cri.sandbox-name";
+const ANNOTATION_SANDBOX_NAMESPACE: &str = "io.kubernetes.cri.sandbox-namespace";
+const ANNOTATION_SANDBOX_UID: &str = "io.kubernetes.cri.sandbox-uid";
+const ANNOTATION_SANDBOX_LOG_DIR: &str = "io.kubernetes.cri.sandbox-log-directory";
+const CONTAINER_TYPE_SANDBOX: &str = "sandbox";
+
+const DEFAULT_MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/sys/firmware",
+];
+const DEFAULT_READONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+// create_spec translates the PodSandboxConfig to the OCI spec of the quark sandbox,
+// the hostname, hosts and resolv.conf files in `files_dir` are bind mounted into the sandbox.
+pub fn create_spec(data: &SandboxData, files_dir: &str) -> Result<JsonSpec> {
+    let mut spec = JsonSpec::default();
+    let config = data
+        .config
+        .as_ref()
+        .ok_or(anyhow!("no PodSandboxConfig in request"))?;
+    let security_context = config
+        .linux
+        .as_ref()
+        .and_then(|l| l.security_context.as_ref());
+
+    spec.hostname.clone_from(&config.hostname);
+
+    // construct annotations from PodSandboxConfig
+    spec.annotations = config.annotations.clone();
+    spec.annotations.insert(
+        ANNOTATION_CONTAINER_TYPE.to_string(),
+        CONTAINER_TYPE_SANDBOX.to_string(),
+    );
+    spec.annotations
+        .insert(ANNOTATION_SANDBOX_ID.to_string(), data.id.to_string());
+    if let Some(metadata) = &config.metadata {
+        spec.annotations
+            .insert(ANNOTATION_SANDBOX_NAME.to_string(), metadata.name.clone());
+        spec.annotations.insert(
+            ANNOTATION_SANDBOX_NAMESPACE.to_string(),
+            metadata.namespace.clone(),
+        );
+        spec.annotations
+            .insert(ANNOTATION_SANDBOX_UID.to_string(), metadata.uid.clone());
+    }
+    if !config.log_directory.is_empty() {
+        spec.annotations.insert(
+            ANNOTATION_SANDBOX_LOG_DIR.to_string(),
+            config.log_directory.clone(),
+        );
+    }
+
+    // construct Linux Configs from PodSandboxConfig
+    let mut linux = Linux {
+        uid_mappings: vec![],
+        gid_mappings: vec![],
+        sysctl: Default::default(),
+        resources: None,
+        cgroups_path: "".to_string(),
+        namespaces: vec![],
+        devices: vec![],
+        seccomp: None,
+        rootfs_propagation: "".to_string(),
+        masked_path: vec![],
+        readonly_path: vec![],
+        mount_label: "".to_string(),
+        intel_rdt: None,
+    };
+    if let Some(lc) = &config.linux {
+        linux.sysctl = lc.sysctls.clone();
+        if !lc.cgroup_parent.is_empty() {
+            linux.cgroups_path = format!("{}/{}", lc.cgroup_parent, data.id);
+        }
+        if let Some(resources) = &lc.resources {
+            let total = match &lc.overhead {
+                Some(overhead) => merge_overhead(resources, overhead),
+                None => resources.clone(),
+            };
+            linux.resources = Some(to_linux_resources(&total));
+        }
+    }
+    linux.namespaces = sandbox_namespaces(
+        &data.netns,
+        security_context.and_then(|s| s.namespace_options.as_ref()),
+    );
+    if !security_context.map(|s| s.privileged).unwrap_or_default() {
+        linux.masked_path = DEFAULT_MASKED_PATHS.iter().map(|p| p.to_string()).collect();
+        linux.readonly_path = DEFAULT_READONLY_PATHS
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+    }
+    spec.linux = Some(linux);
+
+    // construct Process from PodSandboxConfig
+    let mut process = Process::new();
+    process.cwd = "/".to_string();
+    process.env = vec![DEFAULT_PATH_ENV.to_string()];
+    if let Some(sc) = security_context {
+        if let Some(uid) = &sc.run_as_user {
+            process.user.uid = uid.value as u32;
+        }
+        if let Some(gid) = &sc.run_as_group {
+            process.user.gid = gid.value as u32;
+        }
+        process.user.additional_gids = sc.supplemental_groups.iter().map(|g| *g as u32).collect();
+    }
+    spec.process = Some(process);
+
+    let readonly = security_context
+        .map(|s| s.readonly_rootfs)
+        .unwrap_or_default();
+    spec.root = Some(Root {
+        path: "rootfs".to_string(),
+        readonly,
+    });
+    spec.mounts = sandbox_file_mounts(files_dir, readonly);
+    Ok(spec)
+}
+
+// sandbox_namespaces returns the namespaces of the sandbox, no namespace is created
+// for the one shared with the node.
+fn sandbox_namespaces(netns: &str, options: Option<&NamespaceOption>) -> Vec<LinuxNamespace> {
+    let (network, pid, ipc) = options.map(|o| (o.network(), o.pid(), o.ipc())).unwrap_or((
+        NamespaceMode::Pod,
+        NamespaceMode::Pod,
+        NamespaceMode::Pod,
+    ));
+    let namespace = |r#type: &str, path: &str| LinuxNamespace {
+        r#type: r#type.to_string(),
+        path: path.to_string(),
+    };
+
+    let mut namespaces = vec![namespace("mount", "")];
+    if network != NamespaceMode::Node {
+        namespaces.push(namespace("network", netns));
+        namespaces.push(namespace("uts", ""));
+    }
+    if pid != NamespaceMode::Node {
+        namespaces.push(namespace("pid", ""));
+    }
+    if ipc != NamespaceMode::Node {
+        namespaces.push(namespace("ipc", ""));
+    }
+    namespaces
+}
+
+// merge_overhead adds the overhead of the pod to the resources of the pod, so that the
+// resource limits of the quark sandbox cover the ones consumed by quark itself.
+// A resource that is not limited (0 or -1) is kept unlimited.
+fn merge_overhead(
+    resources: &LinuxContainerResources,
+    overhead: &LinuxContainerResources,
+) -> LinuxContainerResources {
+    let mut total = resources.clone();
+    if resources.cpu_quota > 0 && overhead.cpu_period != 0 {
+        total.cpu_quota += overhead.cpu_quota * resources.cpu_period / overhead.cpu_period;
+    }
+    if resources.cpu_shares > 0 {
+        total.cpu_shares += overhead.cpu_shares;
+    }
+    if resources.memory_limit_in_bytes > 0 {
+        total.memory_limit_in_bytes += overhead.memory_limit_in_bytes;
+    }
+    if resources.memory_swap_limit_in_bytes > 0 {
+        total.memory_swap_limit_in_bytes += overhead.memory_swap_limit_in_bytes;
+    }
+    // no limit of the page size in the resources means it is unlimited
+    for h in &overhead.hugepage_limits {
+        if let Some(l) = total
+            .hugepage_limits
+            .iter_mut()
+            .find(|l| l.page_size == h.page_size)
+        {
+            l.limit += h.limit;
+        }
+    }
+    total
+}
+
+fn to_linux_resources(resources: &LinuxContainerResources) -> LinuxResources {
+    let mut spec_resources = LinuxResources {
+        devices: vec![],
+        memory: None,
+        cpu: None,
+        pids: None,
+        block_io: None,
+        hugepage_limits: vec![],
+        network: None,
+        rdma: Default::default(),
+        files: None,
+    };
+
+    let mut memory = LinuxMemory {
+        limit: None,
+        reservation: None,
+        swap: None,
+        kernel: None,
+        kernel_tcp: None,
+        swappiness: None,
+        disable_oom_killer: None,
+    };
+    if resources.memory_limit_in_bytes > 0 {
+        memory.limit = Some(resources.memory_limit_in_bytes as u64);
+    }
+    if resources.memory_swap_limit_in_bytes > 0 {
+        memory.swap = Some(resources.memory_swap_limit_in_bytes as u64);
+    }
+    spec_resources.memory = Some(memory);
+
+    let mut cpu = LinuxCPU {
+        shares: None,
+        quota: None,
+        period: None,
+        realtime_runtime: None,
+        realtime_period: None,
+        cpus: resources.cpuset_cpus.to_string(),
+        mems: resources.cpuset_mems.to_string(),
+    };
+    if resources.cpu_period > 0 {
+        cpu.period = Some(resources.cpu_period as u64);
+    }
+    if resources.cpu_quota > 0 {
+        cpu.quota = Some(resources.cpu_quota);
+    }
+    if resources.cpu_shares > 0 {
+        cpu.shares = Some(resources.cpu_shares as u64);
+    }
+    spec_resources.cpu = Some(cpu);
+
+    for l in &resources.hugepage_limits {
+        spec_resources.hugepage_limits.push(LinuxHugepageLimit {
+            page_size: l.page_size.to_string(),
+            limit: l.limit,
+        });
+    }
+    spec_resources
+}
+
+// sandbox_file_mounts mounts the hostname, hosts and resolv.conf files
+// written by `setup_sandbox_files` to the sandbox.
+fn sandbox_file_mounts(files_dir: &str, readonly: bool) -> Vec<Mount> {
+    let rw_option = if readonly { "ro" } else { "rw" };
+    [
+        (ETC_HOSTNAME, HOSTNAME_FILENAME),
+        (ETC_HOSTS, HOSTS_FILENAME),
+        (ETC_RESOLV, RESOLV_FILENAME),
+    ]
+    .iter()
+    .map(|(dst, filename)| Mount {
+        destination: dst.to_string(),
+        r#type: "bind".to_string(),
+        source: format!("{}/{}", files_dir, filename),
+        options: vec!["rbind", "rprivate", rw_option]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+    })
+    .collect()
+}
+
+// parse_dnsoptions parse DNS options into resolv.conf format content,
+// if none option is specified, will return empty with no error.
+pub fn parse_dnsoptions(servers: &[String], searches: &[String], options: &[String]) -> String {
+    let mut resolv_content = String::new();
+
+    if !searches.is_empty() {
+        resolv_content.push_str(&format!("search {}\n", searches.join(" ")));
+    }
+
+    if !servers.is_empty() {
+        resolv_content.push_str(&format!("nameserver {}\n", servers.join("\nnameserver ")));
+    }
+
+    if !options.is_empty() {
+        resolv_content.push_str(&format!("options {}\n", options.join(" ")));
+    }
+
+    resolv_content
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_sandbox::{
+        cri::api::v1::{
+            HugepageLimit, LinuxContainerResources, LinuxPodSandboxConfig,
+            LinuxSandboxSecurityContext, NamespaceMode, NamespaceOption,
+        },
+        data::SandboxData,
+        PodSandboxConfig,
+    };
+
+    use crate::spec::{create_spec, merge_overhead, sandbox_namespaces};
+
+    fn resources(quota: i64, shares: i64, memory: i64, hugepage: u64) -> LinuxContainerResources {
+        LinuxContainerResources {
+            cpu_period: 100000,
+            cpu_quota: quota,
+            cpu_shares: shares,
+            memory_limit_in_bytes: memory,
+            hugepage_limits: vec![HugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: hugepage,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_overhead() {
+        let overhead = LinuxContainerResources {
+            cpu_period: 50000,
+            cpu_quota: 25000,
+            cpu_shares: 128,
+            memory_limit_in_bytes: 100,
+            hugepage_limits: vec![
+                HugepageLimit {
+                    page_size: "2MB".to_string(),
+                    limit: 2,
+                },
+                HugepageLimit {
+                    page_size: "1GB".to_string(),
+                    limit: 1,
+                },
+            ],
+            ..Default::default()
+        };
+        // (resources, expected total)
+        let cases = vec![
+            (
+                resources(200000, 1024, 1000, 4),
+                resources(250000, 1152, 1100, 6),
+            ),
+            // the unlimited resources are kept unlimited
+            (resources(0, 0, 0, 4), resources(0, 0, 0, 6)),
+            (resources(-1, 1024, 1000, 4), resources(-1, 1152, 1100, 6)),
+        ];
+        for (res, expected) in cases {
+            assert_eq!(merge_overhead(&res, &overhead), expected);
+        }
+        // the overhead of an unset cpu period is ignored
+        let overhead = LinuxContainerResources {
+            cpu_quota: 25000,
+            ..Default::default()
+        };
+        let res = resources(200000, 1024, 1000, 4);
+        assert_eq!(merge_overhead(&res, &overhead), res);
+    }
+
+    #[test]
+    fn test_sandbox_namespaces() {
+        let options = |network: NamespaceMode, pid: NamespaceMode, ipc: NamespaceMode| {
+            Some(NamespaceOption {
+                network: network as i32,
+                pid: pid as i32,
+                ipc: ipc as i32,
+                ..Default::default()
+            })
+        };
+        // (namespace options, expected namespaces)
+        let cases = vec![
+            (
+                None,
+                vec![
+                    ("mount", ""),
+                    ("network", "/var/run/netns/test"),
+                    ("uts", ""),
+                    ("pid", ""),
+                    ("ipc", ""),
+                ],
+            ),
+            (
+                options(
+                    NamespaceMode::Pod,
+                    NamespaceMode::Container,
+                    NamespaceMode::Pod,
+                ),
+                vec![
+                    ("mount", ""),
+                    ("network", "/var/run/netns/test"),
+                    ("uts", ""),
+                    ("pid", ""),
+                    ("ipc", ""),
+                ],
+            ),
+            (
+                options(NamespaceMode::Node, NamespaceMode::Pod, NamespaceMode::Pod),
+                vec![("mount", ""), ("pid", ""), ("ipc", "")],
+            ),
+            (
+                options(
+                    NamespaceMode::Node,
+                    NamespaceMode::Node,
+                    NamespaceMode::Node,
+                ),
+                vec![("mount", "")],
+            ),
+        ];
+        for (opts, expected) in cases {
+            let namespaces = sandbox_namespaces("/var/run/netns/test", opts.as_ref());
+            let namespaces = namespaces
+                .iter()
+                .map(|n| (n.r#type.as_str(), n.path.as_str()))
+                .collect::<Vec<_>>();
+            assert_eq!(namespaces, expected);
+        }
+    }
+
+    #[test]
+    fn test_create_spec() {
+        assert!(create_spec(&SandboxData::default(), "/run/test").is_err());
+
+        let sandbox_data = |privileged: bool, readonly: bool, overhead: i64| {
+            let config = PodSandboxConfig {
+                hostname: "test-host".to_string(),
+                linux: Some(LinuxPodSandboxConfig {
+                    cgroup_parent: "/kubepods".to_string(),
+                    security_context: Some(LinuxSandboxSecurityContext {
+                        privileged,
+                        readonly_rootfs: readonly,
+                        ..Default::default()
+                    }),
+                    resources: Some(resources(200000, 1024, 1000, 4)),
+                    overhead: Some(LinuxContainerResources {
+                        memory_limit_in_bytes: overhead,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            SandboxData {
+                id: "test".to_string(),
+                netns: "/var/run/netns/test".to_string(),
+                config: Some(config),
+                ..Default::default()
+            }
+        };
+        // (privileged, readonly rootfs, memory overhead, expected memory limit)
+        let cases = vec![
+            (false, false, 0, 1000),
+            (false, true, 100, 1100),
+            (true, false, 100, 1100),
+        ];
+        for (privileged, readonly, overhead, memory) in cases {
+            let spec =
+                create_spec(&sandbox_data(privileged, readonly, overhead), "/run/test").unwrap();
+            assert_eq!(spec.hostname, "test-host");
+            assert_eq!(
+                spec.annotations.get("io.kubernetes.cri.container-type"),
+                Some(&"sandbox".to_string())
+            );
+            assert_eq!(
+                spec.annotations.get("io.kubernetes.cri.sandbox-id"),
+                Some(&"test".to_string())
+            );
+            let linux = spec.linux.as_ref().unwrap();
+            assert_eq!(linux.cgroups_path, "/kubepods/test");
+            let limit = linux
+                .resources
+                .as_ref()
+                .and_then(|r| r.memory.as_ref())
+                .and_then(|m| m.limit);
+            assert_eq!(limit, Some(memory));
+            assert_eq!(linux.masked_path.is_empty(), privileged);
+            assert_eq!(linux.readonly_path.is_empty(), privileged);
+            assert_eq!(spec.root.as_ref().unwrap().readonly, readonly);
+            assert_eq!(spec.mounts.len(), 3);
+            let rw_option = if readonly { "ro" } else { "rw" };
+            assert!(spec
+                .mounts
+                .iter()
+                .all(|m| m.options.contains(&rw_option.to_string())));
+        }
+    }
+}
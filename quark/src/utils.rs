@@ -67,6 +67,41 @@ pub async fn write_file_atomic<P: AsRef<Path>>(path: P, s: &[u8]) -> Result<()>
         .map_err(|e| anyhow!("failed to rename file:{}, {}", tmp_path.display(), e).into())
 }
 
+pub fn read_pid<P: AsRef<Path>>(pid_path: P) -> Result<u32> {
+    let pid_path = pid_path.as_ref();
+    let pid_str = std::fs::read_to_string(pid_path)
+        .map_err(|e| anyhow!("failed to read file {}, {}", pid_path.display(), e))?;
+    let pid = pid_str
+        .trim()
+        .parse::<u32>()
+        .map_err(|e| anyhow!("failed to parse pid {}, {}", pid_str, e))?;
+    Ok(pid)
+}
+
+// check_process_cmdline makes sure the process is still the quark of the sandbox,
+// because the pid may be reused by another process after the original one exited.
+pub fn check_process_cmdline(pid: u32, bin: &str, id: &str) -> Result<()> {
+    let cmdline_path = format!("/proc/{}/cmdline", pid);
+    let cmdline = std::fs::read(&cmdline_path)
+        .map_err(|e| anyhow!("failed to read file {}, {}", cmdline_path, e))?;
+    let args = cmdline
+        .split(|b| *b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect::<Vec<_>>();
+    let is_bin = args
+        .first()
+        .map(|arg0| Path::new(arg0).ends_with(bin))
+        .unwrap_or_default();
+    if is_bin && args.iter().any(|arg| arg == id) {
+        Ok(())
+    } else {
+        Err(Error::NotFound(format!(
+            "process {} of sandbox {}",
+            pid, id
+        )))
+    }
+}
+
 pub fn _bind_socket(socket_path: &str) -> Result<RawFd> {
     let fd = socket(
         AddressFamily::Unix,
@@ -36,6 +36,14 @@ pub struct Args {
     )]
     pub listen: String,
 
+    /// Directory of the checkpoint archives, default is `/var/lib/kuasar-runc/checkpoints`
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "/var/lib/kuasar-runc/checkpoints"
+    )]
+    pub checkpoint_dir: String,
+
     // log_level is optional and should not have default value if not given, since
     // it can be defined in configuration file.
     /// Logging level for sandboxer [trace, debug, info, warn, error, fatal, panic]
@@ -55,6 +63,7 @@ mod tests {
         assert!(!args.version);
         assert_eq!(args.dir, "/run/kuasar-runc");
         assert_eq!(args.listen, "/run/runc-sandboxer.sock");
+        assert_eq!(args.checkpoint_dir, "/var/lib/kuasar-runc/checkpoints");
         assert!(args.log_level.is_none());
     }
 }
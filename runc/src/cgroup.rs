@@ -0,0 +1,395 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{error::Error, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use cgroups_rs::{
+    cgroup_builder::CgroupBuilder, cpu::CpuController, cpuset::CpuSetController,
+    hugetlb::HugeTlbController, memory::MemController, Cgroup, CgroupPid,
+};
+use containerd_sandbox::{cri::api::v1::LinuxContainerResources, data::SandboxData};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_CGROUP_PARENT_PATH: &str = "kuasar-runc";
+// the sandbox process is put in a leaf cgroup of the pod cgroup, as processes are only allowed
+// in the leaf cgroups in cgroup v2, and the containers are in the sibling cgroups of it.
+pub const SANDBOX_PROCESS_CGROUP_NAME: &str = "sandbox";
+const CGROUP_V2_MOUNTPOINT: &str = "/sys/fs/cgroup";
+
+/// `SandboxCgroup` is the pod cgroup of a runc sandbox, which contains the sandbox process and
+/// all the containers in the sandbox, and is limited by the total resources of the pod.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct SandboxCgroup {
+    pub cgroup_path: String,
+    #[serde(skip)]
+    pub sandbox_cgroup: Cgroup,
+    #[serde(skip)]
+    pub process_cgroup: Cgroup,
+}
+
+impl SandboxCgroup {
+    pub fn create_sandbox_cgroup(data: &SandboxData) -> Result<Self> {
+        let cgroup_parent_path = data
+            .config
+            .as_ref()
+            .and_then(|c| c.linux.as_ref())
+            .map(|l| l.cgroup_parent.as_str())
+            .filter(|p| !p.is_empty())
+            .unwrap_or(DEFAULT_CGROUP_PARENT_PATH);
+        // CgroupBuilder::new() func doesn't accept the cgroup name has "/" prefix
+        let cgroup_path = format!("{}/{}", cgroup_parent_path.trim_start_matches('/'), data.id);
+        let mut cgroup = Self {
+            cgroup_path,
+            ..Default::default()
+        };
+        cgroup.load()?;
+        Ok(cgroup)
+    }
+
+    // load creates the cgroups if they are not existed, it is also called in recovery.
+    pub fn load(&mut self) -> Result<()> {
+        if self.cgroup_path.is_empty() {
+            return Ok(());
+        }
+        self.sandbox_cgroup =
+            CgroupBuilder::new(&self.cgroup_path).build(cgroups_rs::hierarchies::auto())?;
+        let process_cgroup_path = format!("{}/{}", self.cgroup_path, SANDBOX_PROCESS_CGROUP_NAME);
+        self.process_cgroup =
+            CgroupBuilder::new(&process_cgroup_path).build(cgroups_rs::hierarchies::auto())?;
+        Ok(())
+    }
+
+    pub fn update_resources(&self, data: &SandboxData) -> Result<()> {
+        if self.cgroup_path.is_empty() {
+            return Ok(());
+        }
+        // apply the total resources = sum(containers_resources) + pod_overhead
+        if let Some(total_resources) = get_total_resources(data) {
+            apply_cpu_resource(&self.sandbox_cgroup, &total_resources)?;
+            apply_memory_resource(&self.sandbox_cgroup, &total_resources)?;
+            apply_cpuset_resources(&self.sandbox_cgroup, &total_resources)?;
+            apply_hugetlb_resources(&self.sandbox_cgroup, &total_resources)?;
+        }
+        Ok(())
+    }
+
+    pub fn add_process(&self, pid: u32) -> Result<()> {
+        if self.cgroup_path.is_empty() {
+            return Ok(());
+        }
+        self.process_cgroup
+            .add_task_by_tgid(CgroupPid::from(pid as u64))?;
+        Ok(())
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        if self.cgroup_path.is_empty() {
+            return Ok(());
+        }
+        remove_cgroup(&self.process_cgroup)?;
+        remove_cgroup(&self.sandbox_cgroup)?;
+        Ok(())
+    }
+
+    // container_cgroup_path returns the cgroup path of the container in the pod cgroup.
+    pub fn container_cgroup_path(&self, container_id: &str) -> String {
+        format!("/{}/{}", self.cgroup_path, container_id)
+    }
+}
+
+// get_total_resources returns the resources of the pod with the pod overhead added,
+// a resource that is not limited (0 or -1) is kept unlimited.
+fn get_total_resources(data: &SandboxData) -> Option<LinuxContainerResources> {
+    let linux = data.config.as_ref().and_then(|c| c.linux.as_ref())?;
+    let mut total = linux.resources.clone()?;
+    if let Some(overhead) = &linux.overhead {
+        if total.cpu_quota > 0 && overhead.cpu_period != 0 {
+            total.cpu_quota += overhead.cpu_quota * total.cpu_period / overhead.cpu_period;
+        }
+        if total.cpu_shares > 0 {
+            total.cpu_shares += overhead.cpu_shares;
+        }
+        if total.memory_limit_in_bytes > 0 {
+            total.memory_limit_in_bytes += overhead.memory_limit_in_bytes;
+        }
+        if total.memory_swap_limit_in_bytes > 0 {
+            total.memory_swap_limit_in_bytes += overhead.memory_swap_limit_in_bytes;
+        }
+        // no limit of the page size in the resources means it is unlimited
+        for h in &overhead.hugepage_limits {
+            if let Some(l) = total
+                .hugepage_limits
+                .iter_mut()
+                .find(|l| l.page_size == h.page_size)
+            {
+                l.limit += h.limit;
+            }
+        }
+    }
+    Some(total)
+}
+
+fn apply_cpu_resource(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
+    let cpu_controller: &CpuController = cgroup
+        .controller_of()
+        .ok_or_else(|| anyhow!("No cpu controller attached!"))?;
+
+    if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+        // cpu.max holds both quota and period, so write them in one go
+        let quota = (res.cpu_quota != 0).then_some(res.cpu_quota);
+        let period = match res.cpu_period {
+            0 => None,
+            p => Some(p.try_into()?),
+        };
+        if quota.is_some() || period.is_some() {
+            cpu_controller.set_cfs_quota_and_period(quota, period)?;
+        }
+        if res.cpu_shares != 0 {
+            cpu_controller.set_shares(convert_cpu_shares_to_weight(res.cpu_shares.try_into()?))?;
+        }
+        return Ok(());
+    }
+
+    if res.cpu_period != 0 {
+        cpu_controller.set_cfs_period(res.cpu_period.try_into()?)?;
+    }
+    if res.cpu_quota != 0 {
+        cpu_controller.set_cfs_quota(res.cpu_quota)?;
+    }
+    if res.cpu_shares != 0 {
+        cpu_controller.set_shares(res.cpu_shares.try_into()?)?;
+    }
+
+    Ok(())
+}
+
+fn apply_memory_resource(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
+    let mem_controller: &MemController = cgroup
+        .controller_of()
+        .ok_or_else(|| anyhow!("No memory controller attached!"))?;
+
+    if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+        // memory.max and memory.swap.max default to "max", which can not be written as -1
+        if res.memory_limit_in_bytes > 0 {
+            mem_controller.set_limit(res.memory_limit_in_bytes)?;
+        }
+        // memory.swap.max is absent if swap accounting is disabled in the kernel
+        if res.memory_swap_limit_in_bytes != 0
+            && cgroup_v2_path(cgroup).join("memory.swap.max").exists()
+        {
+            let swap = convert_memory_swap_to_v2(
+                res.memory_swap_limit_in_bytes,
+                res.memory_limit_in_bytes,
+            )?;
+            if swap >= 0 {
+                mem_controller.set_memswap_limit(swap)?;
+            }
+        }
+        return Ok(());
+    }
+
+    if res.memory_limit_in_bytes != 0 {
+        mem_controller.set_limit(res.memory_limit_in_bytes)?;
+    }
+    if res.memory_swap_limit_in_bytes != 0 {
+        mem_controller.set_memswap_limit(res.memory_swap_limit_in_bytes)?;
+    }
+
+    Ok(())
+}
+
+fn apply_cpuset_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
+    let cpuset_controller: &CpuSetController = cgroup
+        .controller_of()
+        .ok_or_else(|| anyhow!("No cpuset controller attached!"))?;
+
+    if !res.cpuset_cpus.is_empty() {
+        cpuset_controller.set_cpus(&res.cpuset_cpus)?;
+    }
+    if !res.cpuset_mems.is_empty() {
+        cpuset_controller.set_mems(&res.cpuset_mems)?;
+    }
+
+    Ok(())
+}
+
+fn apply_hugetlb_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
+    // hugetlb controller is optional in cgroup v2, only require it when limits are specified
+    if res.hugepage_limits.is_empty() {
+        return Ok(());
+    }
+    let hugetlb_controller: &HugeTlbController = cgroup
+        .controller_of()
+        .ok_or_else(|| anyhow!("No hugetlb controller attached!"))?;
+    for h in res.hugepage_limits.iter() {
+        hugetlb_controller.set_limit_in_bytes(h.page_size.as_str(), h.limit)?;
+    }
+    Ok(())
+}
+
+// convert_cpu_shares_to_weight maps cpu.shares in [2, 262144] to cpu.weight in [1, 10000],
+// which is the same conversion as runc and the kubelet do.
+fn convert_cpu_shares_to_weight(shares: u64) -> u64 {
+    if shares == 0 {
+        return 0;
+    }
+    1 + (shares.saturating_sub(2) * 9999) / 262142
+}
+
+// convert_memory_swap_to_v2 converts the CRI memory swap limit, which is the total of memory and
+// swap, to the value of memory.swap.max, which only limits the swap usage.
+fn convert_memory_swap_to_v2(memory_swap: i64, memory: i64) -> Result<i64> {
+    if memory_swap < 0 {
+        return Ok(-1);
+    }
+    if memory <= 0 {
+        return Err(anyhow!(
+            "memory swap limit {} is set without a memory limit",
+            memory_swap
+        ));
+    }
+    if memory_swap < memory {
+        return Err(anyhow!(
+            "memory swap limit {} should not be less than memory limit {}",
+            memory_swap,
+            memory
+        ));
+    }
+    Ok(memory_swap - memory)
+}
+
+fn cgroup_v2_path(cgroup: &Cgroup) -> PathBuf {
+    PathBuf::from(CGROUP_V2_MOUNTPOINT).join(cgroup.path())
+}
+
+fn remove_cgroup(cgroup: &Cgroup) -> Result<()> {
+    // move the remaining tasks to parent cgroup before removing it
+    for tid in cgroup.tasks() {
+        cgroup.move_task_to_parent(tid).unwrap_or_default();
+    }
+
+    // Should ignore the NotFound error of cgroup path as it may be already deleted.
+    if let Err(e) = cgroup.delete() {
+        if e.kind() == &cgroups_rs::error::ErrorKind::RemoveFailed {
+            if let Some(cause) = e.source() {
+                if let Some(ioe) = cause.downcast_ref::<std::io::Error>() {
+                    if ioe.kind() == std::io::ErrorKind::NotFound {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_sandbox::{
+        cri::api::v1::{HugepageLimit, LinuxContainerResources, LinuxPodSandboxConfig},
+        data::SandboxData,
+        PodSandboxConfig,
+    };
+
+    use crate::cgroup::{convert_memory_swap_to_v2, get_total_resources};
+
+    #[test]
+    fn test_get_total_resources() {
+        let mut config = PodSandboxConfig::default();
+        config.linux = Some(LinuxPodSandboxConfig {
+            resources: Some(LinuxContainerResources {
+                cpu_period: 100000,
+                cpu_quota: 200000,
+                cpu_shares: 2048,
+                memory_limit_in_bytes: 1024 * 1024 * 1024,
+                hugepage_limits: vec![HugepageLimit {
+                    page_size: "2MB".to_string(),
+                    limit: 4 * 1024 * 1024,
+                }],
+                ..Default::default()
+            }),
+            overhead: Some(LinuxContainerResources {
+                cpu_period: 50000,
+                cpu_quota: 25000,
+                cpu_shares: 128,
+                memory_limit_in_bytes: 100 * 1024 * 1024,
+                hugepage_limits: vec![HugepageLimit {
+                    page_size: "2MB".to_string(),
+                    limit: 2 * 1024 * 1024,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let mut data = SandboxData::default();
+        data.config = Some(config);
+        let total = get_total_resources(&data).unwrap();
+        assert_eq!(total.cpu_period, 100000);
+        assert_eq!(total.cpu_quota, 250000);
+        assert_eq!(total.cpu_shares, 2048 + 128);
+        assert_eq!(total.memory_limit_in_bytes, 1124 * 1024 * 1024);
+        assert_eq!(total.hugepage_limits.len(), 1);
+        assert_eq!(total.hugepage_limits[0].limit, 6 * 1024 * 1024);
+
+        assert!(get_total_resources(&SandboxData::default()).is_none());
+    }
+
+    #[test]
+    fn test_get_total_resources_unlimited() {
+        let mut config = PodSandboxConfig::default();
+        config.linux = Some(LinuxPodSandboxConfig {
+            resources: Some(LinuxContainerResources {
+                cpu_period: 100000,
+                cpu_quota: -1,
+                memory_swap_limit_in_bytes: -1,
+                ..Default::default()
+            }),
+            overhead: Some(LinuxContainerResources {
+                cpu_period: 100000,
+                cpu_quota: 50000,
+                cpu_shares: 128,
+                memory_limit_in_bytes: 100 * 1024 * 1024,
+                memory_swap_limit_in_bytes: 100 * 1024 * 1024,
+                hugepage_limits: vec![HugepageLimit {
+                    page_size: "2MB".to_string(),
+                    limit: 2 * 1024 * 1024,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let mut data = SandboxData::default();
+        data.config = Some(config);
+        // the overhead is not added to the resources without limits
+        let total = get_total_resources(&data).unwrap();
+        assert_eq!(total.cpu_quota, -1);
+        assert_eq!(total.cpu_shares, 0);
+        assert_eq!(total.memory_limit_in_bytes, 0);
+        assert_eq!(total.memory_swap_limit_in_bytes, -1);
+        assert!(total.hugepage_limits.is_empty());
+    }
+
+    #[test]
+    fn test_convert_memory_swap_to_v2() {
+        assert_eq!(convert_memory_swap_to_v2(-1, 1024).unwrap(), -1);
+        assert_eq!(convert_memory_swap_to_v2(2048, 1024).unwrap(), 1024);
+        assert!(convert_memory_swap_to_v2(1024, 0).is_err());
+        assert!(convert_memory_swap_to_v2(512, 1024).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::Path;
+
+use containerd_shim::{api::CheckpointTaskRequest, error::Result};
+use futures::future::BoxFuture;
+
+pub const ANNOTATION_KEY_CHECKPOINT_ARCHIVE: &str = "io.kuasar.checkpoint.archive";
+
+// Checkpoint is implemented by the containers that can be checkpointed by CRIU.
+pub(crate) trait Checkpoint {
+    // checkpoint checks that the container can be checkpointed, and returns the future that
+    // dumps it into the image path, and writes the dump files of the checkpoint archive into
+    // the dump dir if it is given. The dump may take a long time, so it is awaited after the
+    // lock of the containers is released.
+    fn checkpoint(
+        &self,
+        req: &CheckpointTaskRequest,
+        image_path: &Path,
+        dump_dir: Option<&Path>,
+    ) -> Result<BoxFuture<'static, Result<()>>>;
+}
@@ -33,6 +33,7 @@ use containerd_shim::{
     util::IntoOption,
     Error,
 };
+use kuasar_common::checkpoint::{runc_root, DEFAULT_COMMAND};
 use log::{debug, warn};
 use nix::{
     cmsg_space,
@@ -132,9 +133,6 @@ pub fn check_kill_error(emsg: String) -> Error {
     }
 }
 
-const DEFAULT_RUNC_ROOT: &str = "/run/containerd/runc";
-const DEFAULT_COMMAND: &str = "runc";
-
 pub fn create_runc(
     runtime: &str,
     namespace: &str,
@@ -147,13 +145,7 @@ pub fn create_runc(
     } else {
         runtime
     };
-    let root = opts.root.as_str();
-    let root = Path::new(if root.is_empty() {
-        DEFAULT_RUNC_ROOT
-    } else {
-        root
-    })
-    .join(namespace);
+    let root = runc_root(namespace, opts);
 
     let log = bundle.as_ref().join("log.json");
     let mut gopts = GlobalOpts::default()
@@ -50,6 +50,8 @@ use crate::{
 };
 
 mod args;
+mod cgroup;
+mod checkpoint;
 mod common;
 mod runc;
 mod sandbox;
@@ -75,7 +77,7 @@ fn main() {
     let sandbox_parent = fork_sandbox_parent().unwrap();
 
     let task_socket = format!("{}/task-{}.sock", &args.dir, Uuid::new_v4());
-    fork_task_server(&task_socket, &args.dir).unwrap();
+    fork_task_server(&task_socket, &args.dir, &args.checkpoint_dir).unwrap();
     let runtime = tokio::runtime::Runtime::new().unwrap();
     runtime.block_on(async move {
         start_sandboxer(sandbox_parent, task_socket, &args.listen, &args.dir)
@@ -31,7 +31,7 @@ use std::{
 use async_trait::async_trait;
 use containerd_sandbox::spec::{get_sandbox_id, JsonSpec};
 use containerd_shim::{
-    api::{CreateTaskRequest, ExecProcessRequest, Options, Status},
+    api::{CheckpointTaskRequest, CreateTaskRequest, ExecProcessRequest, Options, Status},
     asynchronous::{
         console::ConsoleSocket,
         container::{ContainerFactory, ContainerTemplate, ProcessFactory},
@@ -54,6 +54,10 @@ use containerd_shim::{
     },
     Console, Error, ExitSignal, Result,
 };
+use futures::future::BoxFuture;
+use kuasar_common::checkpoint::{
+    extract_archive, write_dump_files, CheckpointOpts, RestoreOpts, RuncCommand,
+};
 use log::{debug, error};
 use nix::{sys::signal::kill, unistd::Pid};
 use oci_spec::runtime::{LinuxResources, Process};
@@ -65,9 +69,13 @@ use tokio::{
     sync::Mutex,
 };
 
-use crate::common::{
-    check_kill_error, create_io, create_runc, get_spec_from_request, receive_socket, CreateConfig,
-    ProcessIO, ShimExecutor, INIT_PID_FILE,
+use crate::{
+    checkpoint::{Checkpoint, ANNOTATION_KEY_CHECKPOINT_ARCHIVE},
+    common::{
+        check_kill_error, create_io, create_runc, get_spec_from_request, receive_socket,
+        CreateConfig, ProcessIO, ShimExecutor, INIT_PID_FILE,
+    },
+    sandbox::RuncSandbox,
 };
 
 pub type ExecProcess = ProcessTemplate<RuncExecLifecycle>;
@@ -78,6 +86,7 @@ pub type RuncContainer = ContainerTemplate<InitProcess, ExecProcess, RuncExecFac
 #[derive(Clone)]
 pub(crate) struct RuncFactory {
     sandbox_parent_dir: String,
+    checkpoint_dir: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -130,7 +139,7 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
         write_options(bundle, &opts).await?;
         write_runtime(bundle, runtime).await?;
 
-        self.rewrite_spec(bundle).await?;
+        let spec = self.rewrite_spec(req.id(), bundle).await?;
 
         let rootfs_vec = req.rootfs().to_vec();
         let rootfs = if !rootfs_vec.is_empty() {
@@ -154,13 +163,22 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
         )?;
 
         let id = req.id();
+        let runc_command = RuncCommand::new(runtime, ns, bundle, &opts);
+        let checkpoint = match spec.annotations.get(ANNOTATION_KEY_CHECKPOINT_ARCHIVE) {
+            Some(archive) => {
+                debug!("restore container {} from checkpoint {}", id, archive);
+                Some(extract_archive(archive, bundle, &self.checkpoint_dir).await?)
+            }
+            None => None,
+        };
         let stdio = Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal());
         write_stdio(bundle, &stdio).await?;
 
         let mut init = InitProcess::new(
             id,
             stdio,
-            RuncInitLifecycle::new(runc.clone(), opts.clone(), bundle),
+            RuncInitLifecycle::new(runc.clone(), runc_command, opts.clone(), bundle)
+                .with_checkpoint(checkpoint),
         );
 
         let config = CreateConfig::default();
@@ -186,13 +204,18 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
 }
 
 impl RuncFactory {
-    pub fn new(sandbox_parent_dir: &str) -> Self {
+    pub fn new(sandbox_parent_dir: &str, checkpoint_dir: &str) -> Self {
         Self {
             sandbox_parent_dir: sandbox_parent_dir.to_string(),
+            checkpoint_dir: checkpoint_dir.to_string(),
         }
     }
 
     async fn do_create(&self, init: &mut InitProcess, _config: CreateConfig) -> Result<()> {
+        // the container will be restored from the checkpoint images when it starts
+        if init.lifecycle.checkpoint.is_some() {
+            return Ok(());
+        }
         let id = init.id.to_string();
         let stdio = &init.stdio;
         let opts = &init.lifecycle.opts;
@@ -231,13 +254,19 @@ impl RuncFactory {
         Ok(())
     }
 
-    async fn rewrite_spec(&self, bundle: &str) -> Result<()> {
+    async fn rewrite_spec(&self, id: &str, bundle: &str) -> Result<JsonSpec> {
         let mut spec: JsonSpec = read_spec(bundle).await?;
         let sandbox_id = if let Some(id) = get_sandbox_id(&spec.annotations) {
             id
         } else {
-            return Ok(());
+            return Ok(spec);
         };
+        let sandbox_dir = format!("{}/{}", self.sandbox_parent_dir, sandbox_id);
+        // the container must not be left out of the pod cgroup if the sandbox can not be read
+        let sandbox_cgroup = RuncSandbox::recover(&sandbox_dir)
+            .await
+            .map(|sb| sb.cgroup)
+            .map_err(|e| other!("failed to load sandbox {}, {}", sandbox_id, e))?;
 
         if let Some(l) = &mut spec.linux {
             for ns in &mut l.namespaces {
@@ -251,12 +280,17 @@ impl RuncFactory {
                     ns.path = format!("{}/{}/net", self.sandbox_parent_dir, sandbox_id);
                 }
             }
+            // put the container in the pod cgroup, the cgroups path in the format of
+            // "slice:prefix:name" is managed by systemd and is left unchanged.
+            if !sandbox_cgroup.cgroup_path.is_empty() && !l.cgroups_path.contains(':') {
+                l.cgroups_path = sandbox_cgroup.container_cgroup_path(id);
+            }
         }
         let config_file_path = format!("{}/{}", bundle, CONFIG_FILE_NAME);
         let spec_content_new = serde_json::to_string(&spec)
             .map_err(other_error!(e, "failed to marshal spec to string"))?;
         write_str_to_file(&config_file_path, &spec_content_new).await?;
-        Ok(())
+        Ok(spec)
     }
 }
 
@@ -301,14 +335,20 @@ impl ProcessFactory<ExecProcess> for RuncExecFactory {
 
 pub struct RuncInitLifecycle {
     runtime: Runc,
+    runc_command: RuncCommand,
     opts: Options,
     bundle: String,
+    // the checkpoint images to restore the container from when it starts
+    checkpoint: Option<String>,
     exit_signal: Arc<ExitSignal>,
 }
 
 #[async_trait]
 impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
     async fn start(&self, p: &mut InitProcess) -> containerd_shim::Result<()> {
+        if let Some(image_path) = &self.checkpoint {
+            return self.restore(p, image_path).await;
+        }
         self.runtime
             .start(p.id.as_str())
             .await
@@ -401,7 +441,7 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
 }
 
 impl RuncInitLifecycle {
-    pub fn new(runtime: Runc, opts: Options, bundle: &str) -> Self {
+    pub fn new(runtime: Runc, runc_command: RuncCommand, opts: Options, bundle: &str) -> Self {
         let work_dir = Path::new(bundle).join("work");
         let mut opts = opts;
         if opts.criu_path().is_empty() {
@@ -409,11 +449,119 @@ impl RuncInitLifecycle {
         }
         Self {
             runtime,
+            runc_command,
             opts,
             bundle: bundle.to_string(),
+            checkpoint: None,
             exit_signal: Default::default(),
         }
     }
+
+    pub fn with_checkpoint(mut self, checkpoint: Option<String>) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    async fn restore(&self, p: &mut InitProcess, image_path: &str) -> Result<()> {
+        let pid_path = Path::new(&self.bundle).join(INIT_PID_FILE);
+        let mut restore_opts = RestoreOpts {
+            image_path: image_path.to_string(),
+            work_path: self.opts.criu_path.to_string(),
+            bundle: self.bundle.to_string(),
+            pid_file: pid_path.to_string_lossy().to_string(),
+            console_socket: None,
+            no_pivot: self.opts.no_pivot_root,
+        };
+        let (socket, pio) = if p.stdio.terminal {
+            let s = ConsoleSocket::new().await?;
+            restore_opts.console_socket = Some(s.path.to_string_lossy().to_string());
+            (Some(s), None)
+        } else {
+            let pio = create_io(&p.id, self.opts.io_uid, self.opts.io_gid, &p.stdio)?;
+            (None, Some(pio))
+        };
+
+        let mut cmd = self.runc_command.restore(&p.id, &restore_opts);
+        if let Some(io) = pio.as_ref().and_then(|x| x.io.as_ref()) {
+            io.set(&mut cmd)
+                .map_err(other_error!(e, "failed to set io for restore"))?;
+        }
+        let res = ShimExecutor::default()
+            .execute(cmd, Box::new(|| {}), true)
+            .await;
+        if let Some(io) = pio.as_ref().and_then(|x| x.io.as_ref()) {
+            io.close_after_start();
+        }
+        let res = match res {
+            Ok((status, _, _, stderr)) if !status.success() => Err(other!(
+                "failed to restore container: {}",
+                last_runtime_error(&self.bundle).await.unwrap_or(stderr)
+            )),
+            Err(e) => Err(runtime_error(e, &self.bundle).await),
+            Ok(_) => Ok(()),
+        };
+        if let Err(e) = res {
+            if let Some(s) = socket {
+                s.clean().await;
+            }
+            return Err(e);
+        }
+        copy_io_or_console(p, socket, pio, self.exit_signal.clone()).await?;
+        let pid = read_file_to_str(pid_path).await?.parse::<i32>()?;
+        p.pid = pid;
+        p.state = Status::RUNNING;
+        Ok(())
+    }
+}
+
+impl Checkpoint for RuncContainer {
+    fn checkpoint(
+        &self,
+        req: &CheckpointTaskRequest,
+        image_path: &Path,
+        dump_dir: Option<&Path>,
+    ) -> Result<BoxFuture<'static, Result<()>>> {
+        if self.init.state != Status::RUNNING && self.init.state != Status::PAUSED {
+            return Err(Error::FailedPreconditionError(format!(
+                "container {} is {:?}, can not be checkpointed",
+                self.id, self.init.state
+            )));
+        }
+        let lifecycle = self.init.lifecycle.clone();
+        let opts = CheckpointOpts::from_request(
+            req,
+            &image_path.to_string_lossy(),
+            &lifecycle.opts.criu_path,
+        )?;
+        let id = self.id.to_string();
+        let bundle = self.bundle.to_string();
+        let image_path = image_path.to_path_buf();
+        let dump_dir = dump_dir.map(Path::to_path_buf);
+        Ok(Box::pin(async move {
+            tokio::fs::create_dir_all(&image_path)
+                .await
+                .map_err(io_error!(e, "failed to create checkpoint dir"))?;
+            let cmd = lifecycle.runc_command.checkpoint(&id, &opts);
+            debug!("checkpoint container {} to {}", id, image_path.display());
+            match ShimExecutor::default()
+                .execute(cmd, Box::new(|| {}), true)
+                .await
+            {
+                Ok((status, _, _, stderr)) if !status.success() => {
+                    return Err(other!(
+                        "failed to checkpoint container: {}",
+                        last_runtime_error(&bundle).await.unwrap_or(stderr)
+                    ));
+                }
+                Err(e) => return Err(runtime_error(e, &bundle).await),
+                Ok(_) => {}
+            }
+            if let Some(dump_dir) = dump_dir {
+                write_dump_files(&id, &bundle, &lifecycle.opts.binary_name, &dump_dir).await?;
+            }
+            Ok(())
+        }))
+    }
 }
 
 pub struct RuncExecLifecycle {
@@ -445,7 +593,8 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
             exec_opts.io = pio.io.as_ref().cloned();
             (None, Some(pio))
         };
-        //TODO  checkpoint support
+        // exec processes are not checkpointed on their own, `runc checkpoint` of the
+        // container dumps them together with the init process.
         let exec_result = self
             .runtime
             .exec(&self.container_id, &self.spec, Some(&exec_opts))
@@ -779,6 +928,14 @@ struct Log {
 
 // runtime_error will read the OCI runtime logfile collecting runtime error
 async fn runtime_error(e: runc::error::Error, bundle: &str) -> Error {
+    match last_runtime_error(bundle).await {
+        Some(msg) => other!("{}", msg),
+        None => other!("unable to retrieve OCI runtime error {:?}", e),
+    }
+}
+
+// last_runtime_error returns the last error in the OCI runtime logfile
+async fn last_runtime_error(bundle: &str) -> Option<String> {
     let mut msg = String::new();
     if let Ok(file) = File::open(Path::new(bundle).join("log.json")).await {
         let mut lines = BufReader::new(file).lines();
@@ -792,9 +949,9 @@ async fn runtime_error(e: runc::error::Error, bundle: &str) -> Error {
         }
     }
     if !msg.is_empty() {
-        other!("{}", msg)
+        Some(msg)
     } else {
-        other!("unable to retrieve OCI runtime error {:?}", e)
+        None
     }
 }
 
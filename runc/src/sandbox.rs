@@ -44,7 +44,7 @@ use tokio::{
     sync::{Mutex, RwLock},
 };
 
-use crate::{read_count, write_all};
+use crate::{cgroup::SandboxCgroup, read_count, write_all};
 
 pub struct RuncSandboxer {
     #[allow(clippy::type_complexity)]
@@ -62,6 +62,8 @@ pub struct RuncSandbox {
     #[serde(skip, default)]
     pub(crate) exit_signal: Arc<ExitSignal>,
     pub(crate) containers: HashMap<String, RuncContainerData>,
+    #[serde(default)]
+    pub(crate) cgroup: SandboxCgroup,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -114,7 +116,10 @@ impl RuncSandboxer {
                 if t.is_dir() {
                     let path = Path::new(dir).join(entry.file_name());
                     match RuncSandbox::recover(&path).await {
-                        Ok(sb) => {
+                        Ok(mut sb) => {
+                            sb.cgroup.load().unwrap_or_else(|e| {
+                                warn!("failed to load cgroup of sandbox {}, {:?}", sb.id, e);
+                            });
                             if let SandboxStatus::Running(pid) = sb.status {
                                 // TODO need to check if the sandbox process is still running.
                                 pids.push(pid);
@@ -143,6 +148,7 @@ impl Sandboxer for RuncSandboxer {
     type Sandbox = RuncSandbox;
 
     async fn create(&self, id: &str, s: SandboxOption) -> Result<()> {
+        let cgroup = SandboxCgroup::create_sandbox_cgroup(&s.sandbox)?;
         let sandbox = RuncSandbox {
             id: id.to_string(),
             base_dir: s.base_dir,
@@ -150,11 +156,25 @@ impl Sandboxer for RuncSandboxer {
             status: SandboxStatus::Created,
             exit_signal: Arc::new(Default::default()),
             containers: Default::default(),
+            cgroup,
         };
+        let cleanup_cgroup = |sandbox: &RuncSandbox| {
+            sandbox.cgroup.remove().unwrap_or_else(|e| {
+                warn!("failed to remove cgroup of sandbox {}, {:?}", sandbox.id, e);
+            });
+        };
+        sandbox
+            .cgroup
+            .update_resources(&sandbox.data)
+            .inspect_err(|_| cleanup_cgroup(&sandbox))?;
         create_dir_all(&sandbox.base_dir)
             .await
-            .map_err(|e| anyhow!("failed to create {}, {}", sandbox.base_dir, e))?;
-        sandbox.dump().await?;
+            .map_err(|e| anyhow!("failed to create {}, {}", sandbox.base_dir, e))
+            .inspect_err(|_| cleanup_cgroup(&sandbox))?;
+        sandbox
+            .dump()
+            .await
+            .inspect_err(|_| cleanup_cgroup(&sandbox))?;
         let mut sandboxes = self.sandboxes.write().await;
         sandboxes.insert(id.to_string(), Arc::new(Mutex::new(sandbox)));
         Ok(())
@@ -165,6 +185,12 @@ impl Sandboxer for RuncSandboxer {
         let mut sandbox = sandbox.lock().await;
         let mut sandbox_parent = self.sandbox_parent.lock().await;
         let sandbox_pid = sandbox_parent.fork_sandbox_process(id, &sandbox.data.netns)?;
+        sandbox
+            .cgroup
+            .add_process(sandbox_pid as u32)
+            .inspect_err(|_| {
+                kill(Pid::from_raw(sandbox_pid), Signal::SIGKILL).unwrap_or_default();
+            })?;
         sandbox
             .prepare_sandbox_ns(sandbox_pid)
             .await
@@ -184,7 +210,11 @@ impl Sandboxer for RuncSandboxer {
 
     async fn update(&self, id: &str, data: SandboxData) -> Result<()> {
         let sandbox = self.sandbox(id).await?;
-        sandbox.lock().await.data = data;
+        let mut sandbox = sandbox.lock().await;
+        // resize the pod cgroup, as the pod level resources may be changed
+        sandbox.cgroup.update_resources(&data)?;
+        sandbox.data = data;
+        sandbox.dump().await?;
         Ok(())
     }
 
@@ -205,7 +235,10 @@ impl Sandboxer for RuncSandboxer {
     }
 
     async fn delete(&self, id: &str) -> Result<()> {
-        self.sandboxes.write().await.remove(id);
+        let sandbox = self.sandboxes.write().await.remove(id);
+        if let Some(sandbox) = sandbox {
+            sandbox.lock().await.cgroup.remove()?;
+        }
         Ok(())
     }
 }
@@ -230,7 +263,7 @@ impl RuncSandbox {
         Ok(())
     }
 
-    async fn recover<P: AsRef<Path>>(base_dir: P) -> Result<Self> {
+    pub(crate) async fn recover<P: AsRef<Path>>(base_dir: P) -> Result<Self> {
         let dump_path = base_dir.as_ref().join("sandbox.json");
         let mut dump_file = OpenOptions::new()
             .read(true)
@@ -251,17 +284,24 @@ impl RuncSandbox {
         let dump_data =
             serde_json::to_vec(&self).map_err(|e| anyhow!("failed to serialize sandbox, {}", e))?;
         let dump_path = format!("{}/sandbox.json", self.base_dir);
+        // write to a temp file and rename it, as the dump is read by the task server
+        // when containers are created, which should never see a partially written one.
+        let tmp_path = format!("{}/.sandbox.json", self.base_dir);
         let mut dump_file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(&dump_path)
+            .open(&tmp_path)
             .await
             .map_err(Error::IO)?;
         dump_file
             .write_all(dump_data.as_slice())
             .await
             .map_err(Error::IO)?;
+        dump_file.sync_data().await.map_err(Error::IO)?;
+        tokio::fs::rename(&tmp_path, &dump_path)
+            .await
+            .map_err(Error::IO)?;
         Ok(())
     }
 
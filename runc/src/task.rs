@@ -21,14 +21,17 @@ use std::{
         fd::{AsRawFd, OwnedFd},
         unix::net::UnixListener,
     },
+    path::Path,
     process::exit,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::anyhow;
+use async_trait::async_trait;
 use containerd_sandbox::error;
 use containerd_shim::{
+    api::*,
     asynchronous::{
         monitor::{monitor_subscribe, monitor_unsubscribe},
         task::TaskService,
@@ -37,7 +40,17 @@ use containerd_shim::{
     container::Container,
     monitor::{Subject, Topic},
     processes::Process,
-    protos::{shim::shim_ttrpc_async::create_task, ttrpc::asynchronous::Server},
+    protos::{
+        events::task::TaskCheckpointed, protobuf::MessageField,
+        shim::shim_ttrpc_async::create_task, topics::TASK_CHECKPOINTED_EVENT_TOPIC,
+        ttrpc::asynchronous::Server,
+    },
+    util::convert_to_any,
+    DeleteResponse, Error, Task, TtrpcContext, TtrpcResult,
+};
+use kuasar_common::{
+    cgroup::collect_metrics,
+    checkpoint::{create_archive, CHECKPOINT_DIR_NAME},
 };
 use log::{debug, error};
 use nix::{
@@ -51,12 +64,18 @@ use tokio::{
 };
 
 use crate::{
+    checkpoint::Checkpoint,
     common::{has_shared_pid_namespace, prepare_unix_socket},
     handle_signals, read_count,
     runc::{RuncContainer, RuncFactory},
+    sandbox::RuncSandbox,
 };
 
-pub fn fork_task_server(task_socket: &str, sandbox_parent_dir: &str) -> Result<(), anyhow::Error> {
+pub fn fork_task_server(
+    task_socket: &str,
+    sandbox_parent_dir: &str,
+    checkpoint_dir: &str,
+) -> Result<(), anyhow::Error> {
     prepare_unix_socket(task_socket)?;
 
     let task_listener = UnixListener::bind(task_socket)?;
@@ -74,7 +93,9 @@ pub fn fork_task_server(task_socket: &str, sandbox_parent_dir: &str) -> Result<(
             // TODO set thread count
             let runtime = tokio::runtime::Runtime::new().unwrap();
             runtime.block_on(async move {
-                if let Err(e) = run_task_server(task_listener, pipe_r, sandbox_parent_dir).await {
+                if let Err(e) =
+                    run_task_server(task_listener, pipe_r, sandbox_parent_dir, checkpoint_dir).await
+                {
                     error!("run task server failed {}", e);
                     exit(-1);
                 }
@@ -88,9 +109,15 @@ async fn run_task_server(
     listener: UnixListener,
     exit_pipe: OwnedFd,
     sandbox_parent_dir: &str,
+    checkpoint_dir: &str,
 ) -> error::Result<()> {
-    let task = start_task_service(sandbox_parent_dir).await?;
+    let task = start_task_service(sandbox_parent_dir, checkpoint_dir).await?;
     let containers = task.containers.clone();
+    let task = RuncTaskService {
+        task,
+        sandbox_parent_dir: sandbox_parent_dir.to_string(),
+        checkpoint_dir: checkpoint_dir.to_string(),
+    };
     let task_service: HashMap<String, containerd_shim::protos::ttrpc::asynchronous::Service> =
         create_task(Arc::new(Box::new(task)));
     let mut server = Server::new().register_service(task_service);
@@ -177,6 +204,7 @@ async fn process_exits<F>(task: &TaskService<F, RuncContainer>) {
 
 async fn start_task_service(
     sandbox_parent_dir: &str,
+    checkpoint_dir: &str,
 ) -> error::Result<TaskService<RuncFactory, RuncContainer>> {
     tokio::spawn(async move {
         let signals = Signals::new([libc::SIGTERM, libc::SIGINT, libc::SIGPIPE, libc::SIGCHLD])
@@ -184,7 +212,7 @@ async fn start_task_service(
         handle_signals(signals).await;
     });
     let (tx, mut rx) = channel(128);
-    let factory = RuncFactory::new(sandbox_parent_dir);
+    let factory = RuncFactory::new(sandbox_parent_dir, checkpoint_dir);
     let task = TaskService {
         factory,
         containers: Arc::new(Mutex::new(HashMap::new())),
@@ -215,3 +243,182 @@ async fn should_kill_all_on_exit(bundle_path: &str) -> bool {
         }
     }
 }
+
+// RuncTaskService serves the tasks of the containers, the checkpoint of containers,
+// and the stats of the sandboxes, which are collected from the pod cgroups.
+struct RuncTaskService {
+    task: TaskService<RuncFactory, RuncContainer>,
+    sandbox_parent_dir: String,
+    checkpoint_dir: String,
+}
+
+impl RuncTaskService {
+    // dump checkpoints the container into the image path, the lock of the containers
+    // is released before the dump, which may take a long time.
+    async fn dump(
+        &self,
+        req: &CheckpointTaskRequest,
+        image_path: &Path,
+        dump_dir: Option<&Path>,
+    ) -> containerd_shim::Result<()> {
+        let dump = {
+            let containers = self.task.containers.lock().await;
+            let container = containers.get(req.id()).ok_or_else(|| {
+                Error::NotFoundError(format!("can not find container by id {}", req.id()))
+            })?;
+            container.checkpoint(req, image_path, dump_dir)?
+        };
+        dump.await
+    }
+
+    // checkpoint_to_archive dumps the container into a work dir in the checkpoint dir,
+    // and packs it into the archive.
+    async fn checkpoint_to_archive(
+        &self,
+        req: &CheckpointTaskRequest,
+        archive: &str,
+    ) -> containerd_shim::Result<()> {
+        let work_dir = Path::new(&self.checkpoint_dir).join(req.id());
+        tokio::fs::remove_dir_all(&work_dir)
+            .await
+            .unwrap_or_default();
+        let res = async {
+            self.dump(req, &work_dir.join(CHECKPOINT_DIR_NAME), Some(&work_dir))
+                .await?;
+            create_archive(&work_dir, archive).await
+        }
+        .await;
+        tokio::fs::remove_dir_all(&work_dir)
+            .await
+            .unwrap_or_default();
+        res
+    }
+}
+
+#[async_trait]
+impl Task for RuncTaskService {
+    async fn state(&self, ctx: &TtrpcContext, req: StateRequest) -> TtrpcResult<StateResponse> {
+        self.task.state(ctx, req).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &TtrpcContext,
+        req: CreateTaskRequest,
+    ) -> TtrpcResult<CreateTaskResponse> {
+        self.task.create(ctx, req).await
+    }
+
+    async fn start(&self, ctx: &TtrpcContext, req: StartRequest) -> TtrpcResult<StartResponse> {
+        self.task.start(ctx, req).await
+    }
+
+    async fn delete(&self, ctx: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
+        self.task.delete(ctx, req).await
+    }
+
+    async fn pids(&self, ctx: &TtrpcContext, req: PidsRequest) -> TtrpcResult<PidsResponse> {
+        self.task.pids(ctx, req).await
+    }
+
+    async fn pause(&self, ctx: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        self.task.pause(ctx, req).await
+    }
+
+    async fn resume(&self, ctx: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        self.task.resume(ctx, req).await
+    }
+
+    async fn checkpoint(
+        &self,
+        _ctx: &TtrpcContext,
+        req: CheckpointTaskRequest,
+    ) -> TtrpcResult<Empty> {
+        debug!("checkpoint request for {}", req.id());
+        // the path in request is the dir created by containerd for the checkpoint images,
+        // if it is not given, the container is checkpointed into an archive in the format of
+        // the Kubernetes checkpoint API, which is put in the checkpoint dir.
+        let checkpoint = if req.path.is_empty() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let archive = format!(
+                "{}/checkpoint-{}-{}.tar",
+                self.checkpoint_dir,
+                req.id(),
+                now
+            );
+            self.checkpoint_to_archive(&req, &archive).await?;
+            archive
+        } else {
+            self.dump(&req, Path::new(&req.path), None).await?;
+            req.path.to_string()
+        };
+        let event = TaskCheckpointed {
+            container_id: req.id().to_string(),
+            checkpoint,
+            ..Default::default()
+        };
+        self.task
+            .tx
+            .send((TASK_CHECKPOINTED_EVENT_TOPIC.to_string(), Box::new(event)))
+            .await
+            .unwrap_or_else(|e| error!("failed to send checkpointed event: {}", e));
+        Ok(Empty::new())
+    }
+
+    async fn kill(&self, ctx: &TtrpcContext, req: KillRequest) -> TtrpcResult<Empty> {
+        self.task.kill(ctx, req).await
+    }
+
+    async fn exec(&self, ctx: &TtrpcContext, req: ExecProcessRequest) -> TtrpcResult<Empty> {
+        self.task.exec(ctx, req).await
+    }
+
+    async fn resize_pty(&self, ctx: &TtrpcContext, req: ResizePtyRequest) -> TtrpcResult<Empty> {
+        self.task.resize_pty(ctx, req).await
+    }
+
+    async fn close_io(&self, ctx: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        self.task.close_io(ctx, req).await
+    }
+
+    async fn update(&self, ctx: &TtrpcContext, req: UpdateTaskRequest) -> TtrpcResult<Empty> {
+        self.task.update(ctx, req).await
+    }
+
+    async fn wait(&self, ctx: &TtrpcContext, req: WaitRequest) -> TtrpcResult<WaitResponse> {
+        self.task.wait(ctx, req).await
+    }
+
+    async fn stats(&self, ctx: &TtrpcContext, req: StatsRequest) -> TtrpcResult<StatsResponse> {
+        if self.task.containers.lock().await.contains_key(&req.id) {
+            return self.task.stats(ctx, req).await;
+        }
+        // the id is not a container, return the stats of the pod if it is a sandbox
+        let sandbox_dir = Path::new(&self.sandbox_parent_dir).join(&req.id);
+        let sandbox = RuncSandbox::recover(&sandbox_dir)
+            .await
+            .map_err(|_| Error::NotFoundError(req.id.to_string()))?;
+        if sandbox.cgroup.cgroup_path.is_empty() {
+            return Err(Error::NotFoundError(format!("cgroup of sandbox {}", req.id)).into());
+        }
+        let metrics = collect_metrics(&sandbox.cgroup.cgroup_path);
+        let mut resp = StatsResponse::new();
+        resp.stats = MessageField::some(convert_to_any(Box::new(metrics))?);
+        Ok(resp)
+    }
+
+    async fn connect(
+        &self,
+        ctx: &TtrpcContext,
+        req: ConnectRequest,
+    ) -> TtrpcResult<ConnectResponse> {
+        self.task.connect(ctx, req).await
+    }
+
+    async fn shutdown(&self, ctx: &TtrpcContext, req: ShutdownRequest) -> TtrpcResult<Empty> {
+        self.task.shutdown(ctx, req).await
+    }
+}
@@ -27,6 +27,18 @@ pub const KUASAR_STATE_DIR: &str = "/run/kuasar/state";
 pub const IO_FILE_PREFIX: &str = "io";
 pub const STORAGE_FILE_PREFIX: &str = "storage";
 pub const SHARED_DIR_SUFFIX: &str = "shared";
+pub const CHECKPOINT_DIR_NAME: &str = "checkpoint";
+
+// The checkpoint archive on the host that a container is restored from.
+pub const ANNOTATION_KEY_CHECKPOINT_ARCHIVE: &str = "io.kuasar.checkpoint.archive";
+// The checkpoint images in the guest that a container is restored from.
+pub const ANNOTATION_KEY_CHECKPOINT_PATH: &str = "io.kuasar.checkpoint.path";
+
+// The rate limits of each block and network device of the pod,
+// which can only lower the default ones in the config of the sandboxer.
+pub const ANNOTATION_KEY_BLOCK_IOPS: &str = "io.kuasar.block.iops";
+pub const ANNOTATION_KEY_BLOCK_BANDWIDTH: &str = "io.kuasar.block.bandwidth";
+pub const ANNOTATION_KEY_NET_BANDWIDTH: &str = "io.kuasar.net.bandwidth";
 
 pub const ETC_HOSTS: &str = "/etc/hosts";
 pub const ETC_HOSTNAME: &str = "/etc/hostname";
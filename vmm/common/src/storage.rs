@@ -30,6 +30,12 @@ pub const DRIVERNVDIMMTYPE: &str = "nvdimm";
 pub const DRIVEREPHEMERALTYPE: &str = "ephemeral";
 pub const DRIVERLOCALTYPE: &str = "local";
 
+// The sharefs type of the hypervisors which have no shared filesystem, the files of a container
+// in the sandbox shared dir are packed into an ext4 image and attached as a block device.
+pub const SHAREFS_TYPE_BLOCK: &str = "block";
+// The dir in guest where the block share images are mounted before bound to the state dir.
+pub const BLOCK_SHARE_DIR: &str = "/run/kuasar/block";
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Storage {
     pub host_source: String,
@@ -68,3 +74,12 @@ impl Storage {
         self.ref_container.remove(container_id);
     }
 }
+
+// block_share_label returns the ext4 label of the block share image of a container, as the label
+// is at most 16 bytes, the container id is hashed by FNV-1a, which is stable across builds.
+pub fn block_share_label(container_id: &str) -> String {
+    let hash = container_id.bytes().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
 
 use anyhow::{anyhow, Ok, Result};
 use cgroups_rs::{
@@ -32,6 +32,8 @@ use crate::{
 pub const DEFAULT_CGROUP_PARENT_PATH: &str = "kuasar-vmm";
 pub const VCPU_CGROUP_NAME: &str = "vcpu";
 pub const POD_OVERHEAD_CGROUP_NAME: &str = "pod_overhead";
+const CGROUP_V2_MOUNTPOINT: &str = "/sys/fs/cgroup";
+const CGROUP_V2_TYPE_THREADED: &str = "threaded";
 
 /// `SandboxCgroup` represents a set of cgroups for a sandbox.
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -68,6 +70,14 @@ impl SandboxCgroup {
             .set_specified_controllers(vec!["cpu".to_string()])
             .build(cgroups_rs::hierarchies::auto())?;
 
+        // In cgroup v2 all threads of a process must stay in the same cgroup unless they are in
+        // a threaded subtree, so make the vcpu and pod_overhead cgroups threaded to be able to
+        // move the vcpu threads apart from the other threads of the vmm process.
+        if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+            set_cgroup_threaded(&vcpu_cgroup)?;
+            set_cgroup_threaded(&pod_overhead_cgroup)?;
+        }
+
         Ok(SandboxCgroup {
             cgroup_parent_path: cgroup_parent_path.to_string(),
             sandbox_cgroup,
@@ -132,6 +142,22 @@ fn apply_cpu_resource(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<
         .controller_of()
         .ok_or_else(|| anyhow!("No cpu controller attached!"))?;
 
+    if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+        // cpu.max holds both quota and period, so write them in one go
+        let quota = (res.cpu_quota != 0).then_some(res.cpu_quota);
+        let period = match res.cpu_period {
+            0 => None,
+            p => Some(p.try_into()?),
+        };
+        if quota.is_some() || period.is_some() {
+            cpu_controller.set_cfs_quota_and_period(quota, period)?;
+        }
+        if res.cpu_shares != 0 {
+            cpu_controller.set_shares(convert_cpu_shares_to_weight(res.cpu_shares.try_into()?))?;
+        }
+        return Ok(());
+    }
+
     if res.cpu_period != 0 {
         cpu_controller.set_cfs_period(res.cpu_period.try_into()?)?;
     }
@@ -150,6 +176,26 @@ fn apply_memory_resource(cgroup: &Cgroup, res: &LinuxContainerResources) -> Resu
         .controller_of()
         .ok_or_else(|| anyhow!("No memory controller attached!"))?;
 
+    if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+        // memory.max and memory.swap.max default to "max", which can not be written as -1
+        if res.memory_limit_in_bytes > 0 {
+            mem_controller.set_limit(res.memory_limit_in_bytes)?;
+        }
+        // memory.swap.max is absent if swap accounting is disabled in the kernel
+        if res.memory_swap_limit_in_bytes != 0
+            && cgroup_v2_path(cgroup).join("memory.swap.max").exists()
+        {
+            let swap = convert_memory_swap_to_v2(
+                res.memory_swap_limit_in_bytes,
+                res.memory_limit_in_bytes,
+            )?;
+            if swap >= 0 {
+                mem_controller.set_memswap_limit(swap)?;
+            }
+        }
+        return Ok(());
+    }
+
     if res.memory_limit_in_bytes != 0 {
         mem_controller.set_limit(res.memory_limit_in_bytes)?;
     }
@@ -176,6 +222,10 @@ fn apply_cpuset_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Res
 }
 
 fn apply_hugetlb_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
+    // hugetlb controller is optional in cgroup v2, only require it when limits are specified
+    if res.hugepage_limits.is_empty() {
+        return Ok(());
+    }
     let hugetlb_controller: &HugeTlbController = cgroup
         .controller_of()
         .ok_or_else(|| anyhow!("No hugetlb controller attached!"))?;
@@ -185,6 +235,54 @@ fn apply_hugetlb_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Re
     Ok(())
 }
 
+// convert_cpu_shares_to_weight maps cpu.shares in [2, 262144] to cpu.weight in [1, 10000],
+// which is the same conversion as runc and the kubelet do.
+fn convert_cpu_shares_to_weight(shares: u64) -> u64 {
+    if shares == 0 {
+        return 0;
+    }
+    1 + (shares.saturating_sub(2) * 9999) / 262142
+}
+
+// convert_memory_swap_to_v2 converts the CRI memory swap limit, which is the total of memory and
+// swap, to the value of memory.swap.max, which only limits the swap usage.
+fn convert_memory_swap_to_v2(memory_swap: i64, memory: i64) -> Result<i64> {
+    if memory_swap < 0 {
+        return Ok(-1);
+    }
+    if memory <= 0 {
+        return Err(anyhow!(
+            "memory swap limit {} is set without a memory limit",
+            memory_swap
+        ));
+    }
+    if memory_swap < memory {
+        return Err(anyhow!(
+            "memory swap limit {} should not be less than memory limit {}",
+            memory_swap,
+            memory
+        ));
+    }
+    Ok(memory_swap - memory)
+}
+
+fn cgroup_v2_path(cgroup: &Cgroup) -> PathBuf {
+    PathBuf::from(CGROUP_V2_MOUNTPOINT).join(cgroup.path())
+}
+
+fn set_cgroup_threaded(cgroup: &Cgroup) -> Result<()> {
+    let type_path = cgroup_v2_path(cgroup).join("cgroup.type");
+    let cgroup_type = std::fs::read_to_string(&type_path)
+        .map_err(|e| anyhow!("failed to read {}: {}", type_path.display(), e))?;
+    // the cgroup may already be threaded if it is created again in recovery
+    if cgroup_type.trim() == CGROUP_V2_TYPE_THREADED {
+        return Ok(());
+    }
+    std::fs::write(&type_path, CGROUP_V2_TYPE_THREADED)
+        .map_err(|e| anyhow!("failed to write {}: {}", type_path.display(), e))?;
+    Ok(())
+}
+
 fn remove_sandbox_cgroup(cgroup: &Cgroup) -> Result<()> {
     // get the tids in the current cgroup and then move the tids to parent cgroup
     let tids = cgroup.tasks();
@@ -261,7 +359,7 @@ mod tests {
 
     #[test]
     fn test_create_sandbox_cgroups() {
-        // Currently only support cgroup V1, cgroup V2 is not supported now
+        // This case checks the cgroup v1 paths, see test_create_sandbox_cgroups_v2 for cgroup v2
         if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
             return;
         }
@@ -335,7 +433,7 @@ mod tests {
 
     #[test]
     fn test_update_res_for_sandbox_cgroups_success() {
-        // Currently only support cgroup V1, cgroup V2 is not supported now
+        // This case checks the cgroup v1 interface files
         if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
             return;
         }
@@ -418,4 +516,51 @@ mod tests {
 
         assert_eq!(sandbox_cgroups.remove_sandbox_cgroups().is_ok(), true);
     }
+
+    #[test]
+    fn test_create_sandbox_cgroups_v2() {
+        if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+            return;
+        }
+
+        let mut sandbox_data = SandboxData::default();
+        sandbox_data.id = String::from("test_sandbox_v2");
+        sandbox_data.config = Some(create_mock_pod_sandbox_config());
+        let sandbox_cgroup_path = get_sandbox_cgroup_parent_path(&sandbox_data).unwrap();
+        let sandbox_cgroups =
+            SandboxCgroup::create_sandbox_cgroups(&sandbox_cgroup_path, &sandbox_data.id).unwrap();
+
+        let sandbox_path =
+            PathBuf::from(CGROUP_V2_MOUNTPOINT).join("kubepods/burstable/podxxx/test_sandbox_v2");
+        assert!(sandbox_path.exists());
+        for name in [VCPU_CGROUP_NAME, POD_OVERHEAD_CGROUP_NAME] {
+            let cgroup_type =
+                std::fs::read_to_string(sandbox_path.join(name).join("cgroup.type")).unwrap();
+            assert_eq!(cgroup_type.trim(), CGROUP_V2_TYPE_THREADED);
+        }
+
+        // Create again as in recovery should not fail
+        let sandbox_cgroups_again =
+            SandboxCgroup::create_sandbox_cgroups(&sandbox_cgroup_path, &sandbox_data.id);
+        assert!(sandbox_cgroups_again.is_ok());
+
+        assert!(sandbox_cgroups.remove_sandbox_cgroups().is_ok());
+    }
+
+    #[test]
+    fn test_convert_cpu_shares_to_weight() {
+        assert_eq!(convert_cpu_shares_to_weight(0), 0);
+        assert_eq!(convert_cpu_shares_to_weight(2), 1);
+        assert_eq!(convert_cpu_shares_to_weight(1024), 39);
+        assert_eq!(convert_cpu_shares_to_weight(262144), 10000);
+    }
+
+    #[test]
+    fn test_convert_memory_swap_to_v2() {
+        assert_eq!(convert_memory_swap_to_v2(-1, 1024).unwrap(), -1);
+        assert_eq!(convert_memory_swap_to_v2(1024, 1024).unwrap(), 0);
+        assert_eq!(convert_memory_swap_to_v2(2048, 1024).unwrap(), 1024);
+        assert!(convert_memory_swap_to_v2(1024, 2048).is_err());
+        assert!(convert_memory_swap_to_v2(1024, 0).is_err());
+    }
 }
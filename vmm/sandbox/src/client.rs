@@ -48,10 +48,15 @@ use ttrpc::{
     r#async::{Client, TtrpcContext},
 };
 use vmm_common::api::{
-    sandbox::{CheckRequest, SetupSandboxRequest, SyncClockPacket},
+    sandbox::{
+        CheckRequest, SetupSandboxRequest, SyncClockPacket, UpdateInterfacesRequest,
+        UpdateNeighboursRequest, UpdateRoutesRequest,
+    },
     sandbox_ttrpc::SandboxServiceClient,
 };
 
+use crate::vm::HealthError;
+
 const HVSOCK_RETRY_TIMEOUT_IN_MS: u64 = 10;
 // TODO: reduce to 10s
 const NEW_TTRPC_CLIENT_TIMEOUT: u64 = 45;
@@ -239,6 +244,17 @@ async fn do_check_agent(client: &SandboxServiceClient, timeout: u64) {
     }
 }
 
+// client_ping does a single check round trip to the agent, unlike client_check,
+// it will not retry as it is used to probe the liveness of a running sandbox.
+pub(crate) async fn client_ping(client: &SandboxServiceClient, t: Duration) -> Result<()> {
+    let req = CheckRequest::new();
+    timeout(t, client.check(with_timeout(t.as_nanos() as i64), &req))
+        .await
+        .map_err(|_| HealthError::AgentUnresponsive(format!("check timeout after {:?}", t)))?
+        .map_err(|e| HealthError::AgentUnresponsive(format!("check failed: {}", e)))?;
+    Ok(())
+}
+
 pub(crate) async fn client_setup_sandbox(
     client: &SandboxServiceClient,
     config: &SetupSandboxRequest,
@@ -253,6 +269,39 @@ pub(crate) async fn client_setup_sandbox(
     Ok(())
 }
 
+pub(crate) async fn client_update_interfaces(
+    client: &SandboxServiceClient,
+    req: &UpdateInterfacesRequest,
+) -> Result<()> {
+    client
+        .update_interfaces(with_timeout(Duration::from_secs(10).as_nanos() as i64), req)
+        .await
+        .map_err(|e| anyhow!("failed to update interfaces: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_update_routes(
+    client: &SandboxServiceClient,
+    req: &UpdateRoutesRequest,
+) -> Result<()> {
+    client
+        .update_routes(with_timeout(Duration::from_secs(10).as_nanos() as i64), req)
+        .await
+        .map_err(|e| anyhow!("failed to update routes: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_update_neighbours(
+    client: &SandboxServiceClient,
+    req: &UpdateNeighboursRequest,
+) -> Result<()> {
+    client
+        .update_neighbours(with_timeout(Duration::from_secs(10).as_nanos() as i64), req)
+        .await
+        .map_err(|e| anyhow!("failed to update neighbours: {}", e))?;
+    Ok(())
+}
+
 pub(crate) fn client_sync_clock(
     client: &SandboxServiceClient,
     id: &str,
@@ -335,10 +384,18 @@ fn checked_compute_delta(c_send: i64, c_arrive: i64, s_send: i64, s_arrive: i64)
     Ok(delta)
 }
 
-pub(crate) async fn publish_event(envelope: Envelope) -> Result<()> {
-    let publisher = RemotePublisher::new("/run/containerd/containerd.sock.ttrpc")
-        .await
-        .map_err(|e| anyhow!("publisher connects to containerd: {}", e))?;
+// publish_event forwards the event to containerd with the given publisher, the connection
+// is established lazily and is reset on failure so that the next event reconnects.
+pub(crate) async fn publish_event(
+    publisher: &mut Option<RemotePublisher>,
+    envelope: Envelope,
+) -> Result<()> {
+    if publisher.is_none() {
+        let p = RemotePublisher::new("/run/containerd/containerd.sock.ttrpc")
+            .await
+            .map_err(|e| anyhow!("publisher connects to containerd: {}", e))?;
+        *publisher = Some(p);
+    }
 
     let mut req = events::ForwardRequest::new();
     req.set_envelope(envelope);
@@ -349,10 +406,12 @@ pub(crate) async fn publish_event(envelope: Envelope) -> Result<()> {
         metadata: Default::default(),
         timeout_nano: 0,
     };
-    publisher
-        .forward(&ctx, req)
-        .await
-        .map_err(|e| anyhow!("forward event to containerd: {}", e))?;
+    if let Some(p) = publisher.as_ref() {
+        if let Err(e) = p.forward(&ctx, req).await {
+            *publisher = None;
+            return Err(anyhow!("forward event to containerd: {}", e).into());
+        }
+    }
     Ok(())
 }
 
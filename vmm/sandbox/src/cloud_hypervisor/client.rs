@@ -15,23 +15,74 @@ limitations under the License.
 */
 
 use std::{
-    os::unix::net::UnixStream,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    },
     thread::sleep,
     time::{Duration, SystemTime},
 };
 
 use anyhow::anyhow;
-use api_client::{simple_api_command, simple_api_full_command_with_fds_and_response};
-use containerd_sandbox::error::Result;
+use api_client::{
+    simple_api_command, simple_api_full_command_and_response,
+    simple_api_full_command_with_fds_and_response,
+};
+use containerd_sandbox::error::{Error, Result};
 use log::{debug, error, trace};
+use serde::{Deserialize, Serialize};
 use tokio::task::spawn_blocking;
 
 use crate::{
-    cloud_hypervisor::devices::{block::DiskConfig, AddDeviceResponse, RemoveDeviceRequest},
+    cloud_hypervisor::devices::{
+        block::DiskConfig,
+        vfio::{DeviceConfig, VfioDevice},
+        vhost_user::{is_vhost_user_blk, NetConfig},
+        AddDeviceResponse, RateLimiterConfig, RemoveDeviceRequest,
+    },
     device::DeviceInfo,
+    vm::{HealthError, IoLimits},
 };
 
 pub(crate) const CLOUD_HYPERVISOR_START_TIMEOUT_IN_SEC: u64 = 10;
+pub(crate) const VM_STATE_RUNNING: &str = "Running";
+
+#[derive(Deserialize, Debug)]
+struct VmInfo {
+    state: String,
+}
+
+#[derive(Serialize, Debug)]
+struct VmResize {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    desired_vcpus: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    desired_ram: Option<u64>,
+}
+
+// query_vm_state gets the vm state through a new connection rather than the one of ChClient,
+// so that a request which is timed out can not leave its response to the following requests.
+pub(crate) async fn query_vm_state(socket_path: &str, timeout: Duration) -> Result<String> {
+    let socket_path = socket_path.to_string();
+    spawn_blocking(move || -> Result<String> {
+        let mut socket = UnixStream::connect(&socket_path).map_err(|e| {
+            HealthError::VmmUnresponsive(format!("failed to connect {}, {}", socket_path, e))
+        })?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+        let response_body =
+            simple_api_full_command_and_response(&mut socket, "GET", "vm.info", None)
+                .map_err(|e| HealthError::VmmUnresponsive(format!("failed to get vm.info, {}", e)))?
+                .ok_or_else(|| {
+                    HealthError::VmmUnresponsive("no response body of vm.info".to_string())
+                })?;
+        let info = serde_json::from_str::<VmInfo>(&response_body)
+            .map_err(|e| anyhow!("failed to unmarshal response {}, {}", response_body, e))?;
+        Ok(info.state)
+    })
+    .await
+    .map_err(|e| anyhow!("failed to join thread {}", e))?
+}
 
 pub struct ChClient {
     socket: UnixStream,
@@ -66,7 +117,7 @@ impl ChClient {
         Ok(Self { socket })
     }
 
-    pub fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<String> {
+    pub fn hot_attach(&mut self, device_info: DeviceInfo, limits: &IoLimits) -> Result<String> {
         match device_info {
             DeviceInfo::Block(blk) => {
                 let disk_config = DiskConfig {
@@ -76,42 +127,103 @@ impl ChClient {
                     vhost_user: false,
                     vhost_socket: None,
                     id: blk.id,
+                    rate_limiter_config: RateLimiterConfig::new(
+                        limits.block_bandwidth,
+                        limits.block_iops,
+                    ),
                 };
                 let request_body = serde_json::to_string(&disk_config)
                     .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", disk_config, e))?;
-                let response_opt = simple_api_full_command_with_fds_and_response(
-                    &mut self.socket,
-                    "PUT",
-                    "vm.add-disk",
-                    Some(&request_body),
-                    vec![],
-                )
-                .map_err(|e| anyhow!("failed to hotplug disk {}, {}", request_body, e))?;
-                if let Some(response_body) = response_opt {
-                    let response = serde_json::from_str::<AddDeviceResponse>(&response_body)
-                        .map_err(|e| {
-                            anyhow!("failed to unmarshal response {}, {}", response_body, e)
-                        })?;
-                    Ok(response.bdf)
+                self.add_device("vm.add-disk", &request_body)
+            }
+            DeviceInfo::VhostUser(vhost_user) => {
+                if is_vhost_user_blk(&vhost_user.r#type) {
+                    let disk_config = DiskConfig {
+                        path: "".to_string(),
+                        readonly: false,
+                        direct: false,
+                        vhost_user: true,
+                        vhost_socket: Some(vhost_user.socket_path),
+                        id: vhost_user.id,
+                        rate_limiter_config: None,
+                    };
+                    let request_body = serde_json::to_string(&disk_config).map_err(|e| {
+                        anyhow!("failed to marshal {:?} to json, {}", disk_config, e)
+                    })?;
+                    self.add_device("vm.add-disk", &request_body)
                 } else {
-                    Err(anyhow!("no response body from server").into())
+                    let net_config = NetConfig::vhost_user(
+                        &vhost_user.id,
+                        &vhost_user.socket_path,
+                        &vhost_user.mac_address,
+                    );
+                    let request_body = serde_json::to_string(&net_config).map_err(|e| {
+                        anyhow!("failed to marshal {:?} to json, {}", net_config, e)
+                    })?;
+                    self.add_device("vm.add-net", &request_body)
                 }
             }
-            DeviceInfo::Tap(_) => {
-                todo!()
-            }
-            DeviceInfo::Physical(_) => {
-                todo!()
+            DeviceInfo::Tap(tap) => {
+                let mut net_config =
+                    NetConfig::tap(&tap.id, &tap.name, &tap.mac_address, tap.fds.len());
+                net_config.rate_limiter_config = RateLimiterConfig::new(limits.net_bandwidth, 0);
+                let request_body = serde_json::to_string(&net_config)
+                    .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", net_config, e))?;
+                // the fds are closed after they are sent, cloud-hypervisor receives its own copies
+                let fds = tap.fds.iter().map(|fd| fd.as_raw_fd()).collect();
+                self.add_device_with_fds("vm.add-net", &request_body, fds)
             }
-            DeviceInfo::VhostUser(_) => {
-                todo!()
-            }
-            DeviceInfo::Char(_) => {
-                unimplemented!()
+            DeviceInfo::Physical(vfio) => {
+                let device_config = DeviceConfig::from(VfioDevice::new(&vfio.id, &vfio.bdf));
+                let request_body = serde_json::to_string(&device_config)
+                    .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", device_config, e))?;
+                self.add_device("vm.add-device", &request_body)
             }
+            DeviceInfo::Char(_) => Err(Error::Unimplemented(
+                "hot attach for char device".to_string(),
+            )),
+        }
+    }
+
+    fn add_device(&mut self, command: &str, request_body: &str) -> Result<String> {
+        self.add_device_with_fds(command, request_body, vec![])
+    }
+
+    fn add_device_with_fds(
+        &mut self,
+        command: &str,
+        request_body: &str,
+        fds: Vec<RawFd>,
+    ) -> Result<String> {
+        let response_opt = simple_api_full_command_with_fds_and_response(
+            &mut self.socket,
+            "PUT",
+            command,
+            Some(request_body),
+            fds,
+        )
+        .map_err(|e| anyhow!("failed to hotplug device {}, {}", request_body, e))?;
+        if let Some(response_body) = response_opt {
+            let response = serde_json::from_str::<AddDeviceResponse>(&response_body)
+                .map_err(|e| anyhow!("failed to unmarshal response {}, {}", response_body, e))?;
+            Ok(response.bdf)
+        } else {
+            Err(anyhow!("no response body from server").into())
         }
     }
 
+    pub fn resize(&mut self, desired_vcpus: Option<u32>, desired_ram: Option<u64>) -> Result<()> {
+        let request = VmResize {
+            desired_vcpus,
+            desired_ram,
+        };
+        let request_body = serde_json::to_string(&request)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", request, e))?;
+        simple_api_command(&mut self.socket, "PUT", "resize", Some(&request_body))
+            .map_err(|e| anyhow!("failed to resize vm {}, {}", request_body, e))?;
+        Ok(())
+    }
+
     pub fn hot_detach(&mut self, device_id: &str) -> Result<()> {
         let request = RemoveDeviceRequest {
             id: device_id.to_string(),
@@ -24,6 +24,7 @@ root=/dev/pmem0p1 \
 rootflags=data=ordered,errors=remount-ro \
 ro rootfstype=ext4 \
 task.sharefs_type=virtiofs";
+const MEMORY_HOTPLUG_METHOD_VIRTIO_MEM: &str = "virtio-mem";
 
 #[derive(Deserialize)]
 pub struct CloudHypervisorVMConfig {
@@ -135,6 +136,10 @@ pub struct Memory {
     pub(crate) prefault: Option<bool>,
     #[property(generator = "crate::utils::bool_to_on_off")]
     pub(crate) thp: Option<bool>,
+    #[property(key = "hotplug_method")]
+    pub(crate) hotplug_method: Option<String>,
+    #[property(key = "hotplug_size")]
+    pub(crate) hotplug_size: Option<u64>,
 }
 
 impl Memory {
@@ -146,18 +151,32 @@ impl Memory {
             hugepage_size: None,
             prefault: None,
             thp: None,
+            hotplug_method: None,
+            hotplug_size: None,
         }
     }
 }
 
 impl CloudHypervisorConfig {
     pub fn from(vm_config: &CloudHypervisorVMConfig) -> Self {
-        let cpus = Cpus::new(vm_config.common.vcpus);
-        let memory = Memory::new(
+        let mut cpus = Cpus::new(vm_config.common.vcpus);
+        if vm_config.common.max_vcpus > vm_config.common.vcpus {
+            cpus.max = Some(vm_config.common.max_vcpus);
+        }
+        let mut memory = Memory::new(
             (vm_config.common.memory_in_mb as u64) * 1024 * 1024,
             true,
             vm_config.hugepages,
         );
+        // virtio-mem is used so that the hotplugged memory can also be unplugged
+        if vm_config.common.max_memory_in_mb > vm_config.common.memory_in_mb {
+            memory.hotplug_method = Some(MEMORY_HOTPLUG_METHOD_VIRTIO_MEM.to_string());
+            memory.hotplug_size = Some(
+                ((vm_config.common.max_memory_in_mb - vm_config.common.memory_in_mb) as u64)
+                    * 1024
+                    * 1024,
+            );
+        }
         let mut cmdline = format!(
             "{} {}",
             DEFAULT_KERNEL_PARAMS, vm_config.common.kernel_params
@@ -213,6 +232,8 @@ mod tests {
                 hugepage_size: Some("2M".to_string()),
                 prefault: None,
                 thp: None,
+                hotplug_method: None,
+                hotplug_size: None,
             },
             kernel: "/path/to/kernel".to_string(),
             cmdline: "task.sharefs_type=virtiofs".to_string(),
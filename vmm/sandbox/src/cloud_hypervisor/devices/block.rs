@@ -17,6 +17,8 @@ limitations under the License.
 use sandbox_derive::CmdLineParams;
 use serde_derive::Serialize;
 
+use crate::cloud_hypervisor::devices::RateLimiterConfig;
+
 #[derive(CmdLineParams, Debug, Clone)]
 pub struct Disk {
     path: String,
@@ -25,10 +27,14 @@ pub struct Disk {
     direct: Option<bool>,
     iommu: Option<bool>,
     num_queues: Option<u32>,
-    bw_refill_time: Option<u32>,
-    ops_size: Option<u32>,
-    ope_one_time_burst: Option<u32>,
-    ops_refill_time: Option<u32>,
+    #[property(key = "bw_size")]
+    bw_size: Option<u64>,
+    #[property(key = "bw_refill_time")]
+    bw_refill_time: Option<u64>,
+    #[property(key = "ops_size")]
+    ops_size: Option<u64>,
+    #[property(key = "ops_refill_time")]
+    ops_refill_time: Option<u64>,
     pci_segment: Option<String>,
 }
 
@@ -43,13 +49,23 @@ impl Disk {
             direct: Some(direct),
             iommu: None,
             num_queues: None,
+            bw_size: None,
             bw_refill_time: None,
             ops_size: None,
-            ope_one_time_burst: None,
             ops_refill_time: None,
             pci_segment: None,
         }
     }
+
+    pub fn rate_limiter(mut self, config: Option<RateLimiterConfig>) -> Self {
+        if let Some(c) = config {
+            self.bw_size = c.bandwidth.as_ref().map(|b| b.size);
+            self.bw_refill_time = c.bandwidth.as_ref().map(|b| b.refill_time);
+            self.ops_size = c.ops.as_ref().map(|o| o.size);
+            self.ops_refill_time = c.ops.as_ref().map(|o| o.refill_time);
+        }
+        self
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -60,4 +76,28 @@ pub struct DiskConfig {
     pub vhost_user: bool,
     pub vhost_socket: Option<String>,
     pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_config: Option<RateLimiterConfig>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cloud_hypervisor::devices::{block::Disk, RateLimiterConfig},
+        param::ToCmdLineParams,
+    };
+
+    #[test]
+    fn test_disk_rate_limiter_params() {
+        let disk = Disk::new("blk-1", "/dev/sdb", false, true)
+            .rate_limiter(RateLimiterConfig::new(1048576, 100));
+        assert_eq!(
+            disk.to_cmdline_params("--"),
+            vec![
+                "--disk",
+                "path=/dev/sdb,id=blk-1,readonly=false,direct=true,bw_size=1048576,bw_refill_time=1000,ops_size=100,ops_refill_time=1000"
+            ]
+        );
+        assert!(RateLimiterConfig::new(0, 0).is_none());
+    }
 }
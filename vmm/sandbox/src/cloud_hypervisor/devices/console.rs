@@ -35,3 +35,25 @@ impl Console {
         }
     }
 }
+
+// Serial is the serial port of cloud-hypervisor, it only supports one serial port,
+// and a unix socket or a file as its backend.
+#[derive(CmdLineParams, Debug, Clone)]
+pub struct Serial {
+    #[property(ignore)]
+    id: String,
+    socket: Option<String>,
+    file: Option<String>,
+}
+
+impl_device_no_bus!(Serial);
+
+impl Serial {
+    pub fn new_with_socket(path: &str, id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            socket: Some(path.to_string()),
+            file: None,
+        }
+    }
+}
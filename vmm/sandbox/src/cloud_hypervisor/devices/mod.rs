@@ -25,6 +25,7 @@ pub mod fs;
 pub mod pmem;
 pub mod rng;
 pub mod vfio;
+pub mod vhost_user;
 pub mod virtio_net;
 pub mod vsock;
 
@@ -42,3 +43,37 @@ pub struct AddDeviceResponse {
 pub struct RemoveDeviceRequest {
     pub id: String,
 }
+
+// the token buckets are refilled every second, so that their sizes are the rates per second
+const RATE_LIMITER_REFILL_TIME_IN_MS: u64 = 1000;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenBucketConfig {
+    pub size: u64,
+    pub refill_time: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RateLimiterConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<TokenBucketConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ops: Option<TokenBucketConfig>,
+}
+
+impl RateLimiterConfig {
+    // new returns None if neither the bandwidth nor the ops is limited.
+    pub fn new(bandwidth: u64, ops: u64) -> Option<Self> {
+        let bucket = |size: u64| {
+            (size > 0).then_some(TokenBucketConfig {
+                size,
+                refill_time: RATE_LIMITER_REFILL_TIME_IN_MS,
+            })
+        };
+        let config = Self {
+            bandwidth: bucket(bandwidth),
+            ops: bucket(ops),
+        };
+        (config.bandwidth.is_some() || config.ops.is_some()).then_some(config)
+    }
+}
@@ -15,6 +15,7 @@ limitations under the License.
 */
 
 use sandbox_derive::CmdLineParams;
+use serde_derive::Serialize;
 
 const VFIO_DEVICE_SYSFS_PATH: &str = "/sys/bus/pci/devices";
 
@@ -37,6 +38,21 @@ impl VfioDevice {
     }
 }
 
+#[derive(Serialize, Debug)]
+pub struct DeviceConfig {
+    pub id: String,
+    pub path: String,
+}
+
+impl From<VfioDevice> for DeviceConfig {
+    fn from(d: VfioDevice) -> Self {
+        Self {
+            id: d.id,
+            path: d.path,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{cloud_hypervisor::devices::vfio::VfioDevice, param::ToParams};
@@ -0,0 +1,178 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use sandbox_derive::CmdLineParams;
+use serde_derive::Serialize;
+
+use crate::cloud_hypervisor::devices::RateLimiterConfig;
+
+// the backend of vhost-user device is the server, cloud-hypervisor connects to it as a client
+const VHOST_MODE_CLIENT: &str = "client";
+const VHOST_USER_BLK_TYPE: &str = "blk";
+
+// is_vhost_user_blk tells if a vhost-user device is a block device by its type,
+// which is the device driver like "vhost-user-blk-pci" or "virtio-net-pci".
+pub fn is_vhost_user_blk(r#type: &str) -> bool {
+    r#type.contains(VHOST_USER_BLK_TYPE)
+}
+
+#[derive(CmdLineParams, Debug, Clone)]
+#[params("net")]
+pub struct VhostUserNet {
+    id: String,
+    #[property(key = "vhost_user")]
+    vhost_user: bool,
+    socket: String,
+    #[property(key = "vhost_mode")]
+    vhost_mode: String,
+    mac: String,
+    #[property(key = "num_queues")]
+    num_queues: Option<u32>,
+}
+
+impl_device_no_bus!(VhostUserNet);
+
+impl VhostUserNet {
+    pub fn new(id: &str, socket: &str, mac: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            vhost_user: true,
+            socket: socket.to_string(),
+            vhost_mode: VHOST_MODE_CLIENT.to_string(),
+            mac: mac.to_string(),
+            num_queues: None,
+        }
+    }
+}
+
+#[derive(CmdLineParams, Debug, Clone)]
+#[params("disk")]
+pub struct VhostUserBlk {
+    id: String,
+    #[property(key = "vhost_user")]
+    vhost_user: bool,
+    socket: String,
+    #[property(key = "num_queues")]
+    num_queues: Option<u32>,
+}
+
+impl_device_no_bus!(VhostUserBlk);
+
+impl VhostUserBlk {
+    pub fn new(id: &str, socket: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            vhost_user: true,
+            socket: socket.to_string(),
+            num_queues: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct NetConfig {
+    pub id: String,
+    pub mac: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tap: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_queues: Option<usize>,
+    pub vhost_user: bool,
+    pub vhost_socket: Option<String>,
+    pub vhost_mode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_config: Option<RateLimiterConfig>,
+}
+
+impl NetConfig {
+    pub fn vhost_user(id: &str, socket: &str, mac: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            mac: mac.to_string(),
+            tap: None,
+            num_queues: None,
+            vhost_user: true,
+            vhost_socket: Some(socket.to_string()),
+            vhost_mode: VHOST_MODE_CLIENT.to_string(),
+            rate_limiter_config: None,
+        }
+    }
+
+    // tap is the config of the net device backed by a tap, which is opened by cloud-hypervisor
+    // with the name if no fds of it is sent along with the config.
+    pub fn tap(id: &str, name: &str, mac: &str, fd_num: usize) -> Self {
+        Self {
+            id: id.to_string(),
+            mac: mac.to_string(),
+            tap: if fd_num == 0 {
+                Some(name.to_string())
+            } else {
+                None
+            },
+            // a pair of rx and tx queues for each fd
+            num_queues: if fd_num == 0 { None } else { Some(fd_num * 2) },
+            vhost_user: false,
+            vhost_socket: None,
+            vhost_mode: VHOST_MODE_CLIENT.to_string(),
+            rate_limiter_config: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cloud_hypervisor::devices::vhost_user::{
+            is_vhost_user_blk, NetConfig, VhostUserBlk, VhostUserNet,
+        },
+        param::ToCmdLineParams,
+    };
+
+    #[test]
+    fn test_vhost_user_net_params() {
+        let device = VhostUserNet::new("intf-1", "/tmp/vhost.sock", "00:11:22:33:44:55");
+        assert_eq!(
+            device.to_cmdline_params("--"),
+            vec![
+                "--net",
+                "id=intf-1,vhost_user=true,socket=/tmp/vhost.sock,vhost_mode=client,mac=00:11:22:33:44:55"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vhost_user_blk_params() {
+        let device = VhostUserBlk::new("blk-1", "/tmp/blk.sock");
+        assert_eq!(
+            device.to_cmdline_params("--"),
+            vec!["--disk", "id=blk-1,vhost_user=true,socket=/tmp/blk.sock"]
+        );
+        assert!(is_vhost_user_blk("vhost-user-blk-pci"));
+        assert!(!is_vhost_user_blk("virtio-net-pci"));
+    }
+
+    #[test]
+    fn test_tap_net_config() {
+        let config = NetConfig::tap("intf-1", "tap_kua_1", "00:11:22:33:44:55", 2);
+        assert_eq!(
+            serde_json::to_string(&config).unwrap(),
+            r#"{"id":"intf-1","mac":"00:11:22:33:44:55","num_queues":4,"vhost_user":false,"vhost_socket":null,"vhost_mode":"client"}"#
+        );
+        let config = NetConfig::tap("intf-1", "tap_kua_1", "00:11:22:33:44:55", 0);
+        assert_eq!(config.tap.as_deref(), Some("tap_kua_1"));
+        assert!(config.num_queues.is_none());
+    }
+}
@@ -18,6 +18,8 @@ use std::os::unix::io::RawFd;
 
 use sandbox_derive::CmdLineParams;
 
+use crate::cloud_hypervisor::devices::RateLimiterConfig;
+
 #[derive(CmdLineParams, Debug, Clone)]
 #[params("net")]
 pub struct VirtioNetDevice {
@@ -37,6 +39,12 @@ pub struct VirtioNetDevice {
 
     #[property(key = "num_queues", predicate = "self.fds.len()>0")]
     pub(crate) num_queues: u32,
+
+    #[property(key = "bw_size")]
+    pub(crate) bw_size: Option<u64>,
+
+    #[property(key = "bw_refill_time")]
+    pub(crate) bw_refill_time: Option<u64>,
 }
 
 impl_device_no_bus!(VirtioNetDevice);
@@ -49,7 +57,17 @@ impl VirtioNetDevice {
             num_queues: (fds.len() * 2) as u32,
             mac: mac.to_string(),
             fds,
+            bw_size: None,
+            bw_refill_time: None,
+        }
+    }
+
+    pub fn rate_limiter(mut self, config: Option<RateLimiterConfig>) -> Self {
+        if let Some(bandwidth) = config.and_then(|c| c.bandwidth) {
+            self.bw_size = Some(bandwidth.size);
+            self.bw_refill_time = Some(bandwidth.refill_time);
         }
+        self
     }
 }
 
@@ -34,16 +34,21 @@ use vmm_common::SHARED_DIR_SUFFIX;
 
 use crate::{
     cloud_hypervisor::{
-        client::ChClient,
+        client::{query_vm_state, ChClient, VM_STATE_RUNNING},
         config::{CloudHypervisorConfig, CloudHypervisorVMConfig, VirtiofsdConfig},
         devices::{
-            block::Disk, vfio::VfioDevice, virtio_net::VirtioNetDevice, CloudHypervisorDevice,
+            block::Disk,
+            console::Serial,
+            vfio::VfioDevice,
+            vhost_user::{is_vhost_user_blk, VhostUserBlk, VhostUserNet},
+            virtio_net::VirtioNetDevice,
+            CloudHypervisorDevice, RateLimiterConfig,
         },
     },
-    device::{BusType, DeviceInfo},
+    device::{BusType, CharBackendType, DeviceInfo},
     param::ToCmdLineParams,
     utils::{read_std, set_cmd_fd, set_cmd_netns, wait_channel, wait_pid, write_file_atomic},
-    vm::{Pids, VcpuThreads, VM},
+    vm::{HealthError, IoLimits, Pids, VcpuThreads, VmCapabilities, VmResources, VM},
 };
 
 mod client;
@@ -71,6 +76,10 @@ pub struct CloudHypervisorVM {
     #[serde(skip)]
     fds: Vec<OwnedFd>,
     pids: Pids,
+    #[serde(default)]
+    resources: VmResources,
+    #[serde(default)]
+    io_limits: IoLimits,
 }
 
 impl CloudHypervisorVM {
@@ -96,6 +105,8 @@ impl CloudHypervisorVM {
             client: None,
             fds: vec![],
             pids: Pids::default(),
+            resources: VmResources::default(),
+            io_limits: IoLimits::default(),
         }
     }
 
@@ -164,6 +175,10 @@ impl VM for CloudHypervisorVM {
         let virtiofsd_pid = self.start_virtiofsd().await?;
         // TODO: add child virtiofsd process
         self.pids.affiliated_pids.push(virtiofsd_pid);
+        self.resources = VmResources {
+            vcpus: self.config.cpus.boot,
+            memory_in_mb: self.config.memory.size / 1024 / 1024,
+        };
         let mut params = self.config.to_cmdline_params("--");
         for d in self.devices.iter() {
             params.extend(d.to_cmdline_params("--"));
@@ -253,7 +268,11 @@ impl VM for CloudHypervisorVM {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = Disk::new(&blk_info.id, &blk_info.path, blk_info.read_only, true);
+                let device = Disk::new(&blk_info.id, &blk_info.path, blk_info.read_only, true)
+                    .rate_limiter(RateLimiterConfig::new(
+                        self.io_limits.block_bandwidth,
+                        self.io_limits.block_iops,
+                    ));
                 self.add_device(device);
             }
             DeviceInfo::Tap(tap_info) => {
@@ -267,27 +286,48 @@ impl VM for CloudHypervisorVM {
                     Some(tap_info.name),
                     &tap_info.mac_address,
                     fd_ints,
-                );
+                )
+                .rate_limiter(RateLimiterConfig::new(self.io_limits.net_bandwidth, 0));
                 self.add_device(device);
             }
             DeviceInfo::Physical(vfio_info) => {
                 let device = VfioDevice::new(&vfio_info.id, &vfio_info.bdf);
                 self.add_device(device);
             }
-            DeviceInfo::VhostUser(_vhost_user_info) => {
-                todo!()
-            }
-            DeviceInfo::Char(_char_info) => {
-                unimplemented!()
+            DeviceInfo::VhostUser(vhost_user_info) => {
+                if is_vhost_user_blk(&vhost_user_info.r#type) {
+                    let device =
+                        VhostUserBlk::new(&vhost_user_info.id, &vhost_user_info.socket_path);
+                    self.add_device(device);
+                } else {
+                    let device = VhostUserNet::new(
+                        &vhost_user_info.id,
+                        &vhost_user_info.socket_path,
+                        &vhost_user_info.mac_address,
+                    );
+                    self.add_device(device);
+                }
             }
+            DeviceInfo::Char(char_info) => match &char_info.backend {
+                CharBackendType::Socket(path) => {
+                    let device = Serial::new_with_socket(path, &char_info.id);
+                    self.add_device(device);
+                }
+                CharBackendType::Pipe(_) => {
+                    return Err(Error::Unimplemented(
+                        "pipe backend of char device for cloud hypervisor".to_string(),
+                    ));
+                }
+            },
         };
         Ok(())
     }
 
     #[instrument(skip_all)]
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
+        let limits = self.io_limits;
         let client = self.get_client()?;
-        let addr = client.hot_attach(device_info)?;
+        let addr = client.hot_attach(device_info, &limits)?;
         Ok((BusType::PCI, addr))
     }
 
@@ -299,8 +339,11 @@ impl VM for CloudHypervisorVM {
     }
 
     #[instrument(skip_all)]
-    async fn ping(&self) -> Result<()> {
-        // TODO
+    async fn ping(&self, timeout: Duration) -> Result<()> {
+        let state = query_vm_state(&self.config.api_socket, timeout).await?;
+        if state != VM_STATE_RUNNING {
+            return Err(HealthError::GuestNotRunning(format!("vm state is {}", state)).into());
+        }
         Ok(())
     }
 
@@ -340,6 +383,55 @@ impl VM for CloudHypervisorVM {
     fn pids(&self) -> Pids {
         self.pids.clone()
     }
+
+    fn resources(&self) -> VmResources {
+        self.resources
+    }
+
+    fn boot_vcpus(&self) -> u32 {
+        self.config.cpus.boot
+    }
+
+    #[instrument(skip_all)]
+    async fn resize(&mut self, resources: VmResources) -> Result<VmResources> {
+        let boot_vcpus = self.config.cpus.boot;
+        let max_vcpus = self.config.cpus.max.unwrap_or(boot_vcpus);
+        let boot_memory_in_mb = self.config.memory.size / 1024 / 1024;
+        let max_memory_in_mb =
+            boot_memory_in_mb + self.config.memory.hotplug_size.unwrap_or_default() / 1024 / 1024;
+        let target = VmResources {
+            vcpus: resources.vcpus.clamp(boot_vcpus, max_vcpus),
+            memory_in_mb: resources
+                .memory_in_mb
+                .clamp(boot_memory_in_mb, max_memory_in_mb),
+        };
+        if target == self.resources {
+            return Ok(target);
+        }
+
+        let desired_vcpus = (target.vcpus != self.resources.vcpus).then_some(target.vcpus);
+        let desired_ram = (target.memory_in_mb != self.resources.memory_in_mb)
+            .then_some(target.memory_in_mb * 1024 * 1024);
+        debug!("resize vm {} to {:?}", self.id, target);
+        self.get_client()?.resize(desired_vcpus, desired_ram)?;
+        self.resources = target;
+        Ok(target)
+    }
+
+    #[instrument(skip_all)]
+    async fn set_io_limits(&mut self, limits: IoLimits) -> Result<()> {
+        self.io_limits = limits;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> VmCapabilities {
+        VmCapabilities {
+            // the rate limiters of cloud-hypervisor are only configured when the devices are added
+            block_io_limits_update: false,
+            net_io_limits_update: false,
+            ..Default::default()
+        }
+    }
 }
 
 #[async_trait]
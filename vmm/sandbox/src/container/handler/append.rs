@@ -49,6 +49,8 @@ where
             data: self.option.container.clone(),
             io_devices: vec![],
             processes: vec![],
+            checkpoint_archive: None,
+            block_share: None,
         };
         let bundle = format!(
             "{}/{}",
@@ -0,0 +1,266 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::{debug, warn};
+use nix::libc::MNT_DETACH;
+use tokio::process::Command;
+use vmm_common::{
+    mount::{bind_mount, unmount, MNT_NOFOLLOW},
+    storage::block_share_label,
+    HOSTNAME_FILENAME, HOSTS_FILENAME, RESOLV_FILENAME,
+};
+
+use crate::{
+    container::handler::Handler,
+    device::{BlockDeviceInfo, DeviceInfo},
+    sandbox::KuasarSandbox,
+    vm::VM,
+};
+
+const BLOCK_SHARE_DIR_NAME: &str = "block-share";
+// the free space left in the image for the writes of container, the image file is sparse.
+const BLOCK_SHARE_EXTRA_SIZE_IN_MB: u64 = 256;
+
+// BlockShareHandler is the fallback of the hypervisors without a shared filesystem. It packs
+// the files in the sandbox shared dir that the container needs, including its bundle and the
+// bind or overlay storages, into an ext4 image, and attaches the image as a block device.
+// The guest finds the device by its label and binds the files into the same paths in the state
+// dir, so the writes in the guest are not seen by the host.
+pub struct BlockShareHandler {
+    container_id: String,
+}
+
+impl BlockShareHandler {
+    pub fn new(container_id: &str) -> Self {
+        Self {
+            container_id: container_id.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl<T> Handler<KuasarSandbox<T>> for BlockShareHandler
+where
+    T: VM + Sync + Send,
+{
+    async fn handle(&self, sandbox: &mut KuasarSandbox<T>) -> Result<()> {
+        let shared_path = sandbox.get_sandbox_shared_path();
+        let mut entries = vec![self.container_id.to_string()];
+        for s in sandbox.storages.iter() {
+            if s.ref_container.contains_key(&self.container_id)
+                && !s.need_guest_handle
+                && s.fstype == "bind"
+            {
+                entries.push(s.id.to_string());
+            }
+        }
+        for f in [HOSTNAME_FILENAME, HOSTS_FILENAME, RESOLV_FILENAME] {
+            if Path::new(&shared_path).join(f).exists() {
+                entries.push(f.to_string());
+            }
+        }
+
+        let share_dir = format!("{}/{}", sandbox.base_dir, BLOCK_SHARE_DIR_NAME);
+        let staging = format!("{}/{}", share_dir, self.container_id);
+        let image = block_share_image(&sandbox.base_dir, &self.container_id);
+        tokio::fs::create_dir_all(&staging).await?;
+        let mut targets = vec![];
+        let res = make_image(
+            &shared_path,
+            &staging,
+            &entries,
+            &image,
+            &self.container_id,
+            &mut targets,
+        )
+        .await;
+        let res = match (res, remove_staging(&staging, &targets).await) {
+            (Ok(_), res) => res,
+            (Err(e), Err(re)) => {
+                warn!("failed to remove staging dir {}, {}", staging, re);
+                Err(e)
+            }
+            (Err(e), Ok(_)) => Err(e),
+        };
+        if let Err(e) = res {
+            remove_block_share_image(&sandbox.base_dir, &self.container_id).await;
+            return Err(e);
+        }
+
+        let device_id = format!("blockshare{}", sandbox.increment_and_get_id());
+        sandbox
+            .vm
+            .hot_attach(DeviceInfo::Block(BlockDeviceInfo {
+                id: device_id.to_string(),
+                path: image.to_string(),
+                read_only: false,
+            }))
+            .await?;
+        let container = sandbox.container_mut(&self.container_id)?;
+        container.block_share = Some(device_id);
+        Ok(())
+    }
+
+    async fn rollback(&self, sandbox: &mut KuasarSandbox<T>) -> Result<()> {
+        if let Some(device_id) = sandbox
+            .container_mut(&self.container_id)
+            .ok()
+            .and_then(|c| c.block_share.take())
+        {
+            sandbox.vm.hot_detach(&device_id).await?;
+        }
+        remove_block_share_image(&sandbox.base_dir, &self.container_id).await;
+        Ok(())
+    }
+}
+
+pub fn block_share_image(base_dir: &str, container_id: &str) -> String {
+    format!("{}/{}/{}.img", base_dir, BLOCK_SHARE_DIR_NAME, container_id)
+}
+
+pub async fn remove_block_share_image(base_dir: &str, container_id: &str) {
+    let image = block_share_image(base_dir, container_id);
+    if Path::new(&image).exists() {
+        tokio::fs::remove_file(&image).await.unwrap_or_else(|e| {
+            warn!("failed to remove block share image {}, {}", image, e);
+        });
+    }
+}
+
+async fn make_image(
+    shared_path: &str,
+    staging: &str,
+    entries: &[String],
+    image: &str,
+    container_id: &str,
+    targets: &mut Vec<String>,
+) -> Result<()> {
+    for e in entries {
+        let source = Path::new(shared_path).join(e);
+        let target = format!("{}/{}", staging, e);
+        if source.is_dir() {
+            tokio::fs::create_dir(&target).await?;
+        } else {
+            tokio::fs::File::create(&target).await?;
+        }
+        if let Err(err) = bind_mount(&source, &target, &["rbind".to_string()]) {
+            remove_target(&target).await.unwrap_or_else(|re| {
+                warn!("failed to remove {}, {}", target, re);
+            });
+            return Err(err.into());
+        }
+        targets.push(target);
+    }
+
+    let staging_path = staging.to_string();
+    let size = tokio::task::spawn_blocking(move || dir_size(Path::new(&staging_path)))
+        .await
+        .map_err(|e| anyhow!("failed to join the thread of counting size, {}", e))??;
+    let size_in_mb = size / 1024 / 1024 + BLOCK_SHARE_EXTRA_SIZE_IN_MB;
+    debug!(
+        "make block share image {} of {}M for container {}",
+        image, size_in_mb, container_id
+    );
+    let output = Command::new("mkfs.ext4")
+        .args([
+            "-q",
+            "-F",
+            "-L",
+            &block_share_label(container_id),
+            "-d",
+            staging,
+            image,
+            &format!("{}M", size_in_mb),
+        ])
+        .output()
+        .await
+        .map_err(|e| anyhow!("failed to execute mkfs.ext4, {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to make block share image {}, {}",
+            image,
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// remove_staging unmounts the bound targets in the staging dir and removes them and the dir.
+// Nothing is removed recursively and nothing is removed at all if any unmount fails,
+// so that the files of the container are never removed through a bind that is still there.
+async fn remove_staging(staging: &str, targets: &[String]) -> Result<()> {
+    for t in targets {
+        unmount(t, MNT_DETACH | MNT_NOFOLLOW)?;
+    }
+    for t in targets {
+        remove_target(t).await?;
+    }
+    tokio::fs::remove_dir(staging)
+        .await
+        .map_err(|e| anyhow!("failed to remove staging dir {}, {}", staging, e))?;
+    Ok(())
+}
+
+// remove_target removes the empty dir or file that was the target of a bind.
+async fn remove_target(target: &str) -> Result<()> {
+    let metadata = tokio::fs::symlink_metadata(target).await?;
+    if metadata.is_dir() {
+        tokio::fs::remove_dir(target).await?;
+    } else {
+        tokio::fs::remove_file(target).await?;
+    }
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use crate::container::handler::block_share::remove_staging;
+
+    #[tokio::test]
+    async fn test_remove_staging_keeps_files_if_unmount_fails() {
+        let staging = TempDir::new().unwrap();
+        let target = staging.child("container1");
+        std::fs::create_dir(&target).unwrap();
+        std::fs::write(target.join("config.json"), "{}").unwrap();
+
+        // the target is not a mount point, so unmounting it fails
+        let targets = vec![target.to_str().unwrap().to_string()];
+        assert!(remove_staging(staging.path().to_str().unwrap(), &targets)
+            .await
+            .is_err());
+        assert!(target.join("config.json").exists());
+    }
+}
@@ -0,0 +1,162 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use kuasar_common::checkpoint::{extract_archive, RESTORE_DIR_NAME};
+use log::debug;
+use vmm_common::{
+    ANNOTATION_KEY_CHECKPOINT_ARCHIVE, ANNOTATION_KEY_CHECKPOINT_PATH, KUASAR_STATE_DIR,
+};
+
+use crate::{container::handler::Handler, sandbox::KuasarSandbox, vm::VM};
+
+// CheckpointHandler extracts the checkpoint archive of a container into its bundle,
+// which is shared with the guest, so that vmm-task restores the container from the
+// checkpoint images instead of creating a new one.
+pub struct CheckpointHandler {
+    container_id: String,
+}
+
+impl CheckpointHandler {
+    pub fn new(container_id: &str) -> Self {
+        Self {
+            container_id: container_id.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl<T> Handler<KuasarSandbox<T>> for CheckpointHandler
+where
+    T: VM + Sync + Send,
+{
+    async fn handle(&self, sandbox: &mut KuasarSandbox<T>) -> Result<()> {
+        let checkpoint_dir = sandbox.checkpoint_dir.clone();
+        let container = sandbox.container_mut(&self.container_id)?;
+        let archive = match container
+            .data
+            .spec
+            .as_ref()
+            .and_then(|s| s.annotations.get(ANNOTATION_KEY_CHECKPOINT_ARCHIVE))
+        {
+            None => return Ok(()),
+            Some(a) => a.to_string(),
+        };
+        debug!(
+            "extract checkpoint archive {} of container {}",
+            archive, self.container_id
+        );
+        let guest_image_path = extract_checkpoint(
+            &archive,
+            &container.data.bundle,
+            &checkpoint_dir,
+            &self.container_id,
+        )
+        .await?;
+        if let Some(spec) = &mut container.data.spec {
+            spec.annotations
+                .insert(ANNOTATION_KEY_CHECKPOINT_PATH.to_string(), guest_image_path);
+        }
+        container.checkpoint_archive = Some(archive);
+        Ok(())
+    }
+
+    async fn rollback(&self, sandbox: &mut KuasarSandbox<T>) -> Result<()> {
+        let container = sandbox.container_mut(&self.container_id)?;
+        if container.checkpoint_archive.take().is_none() {
+            return Ok(());
+        }
+        if let Some(spec) = &mut container.data.spec {
+            spec.annotations.remove(ANNOTATION_KEY_CHECKPOINT_PATH);
+        }
+        let restore_dir = Path::new(&container.data.bundle).join(RESTORE_DIR_NAME);
+        tokio::fs::remove_dir_all(&restore_dir)
+            .await
+            .unwrap_or_default();
+        Ok(())
+    }
+}
+
+// extract_checkpoint extracts the checkpoint archive into the bundle of the container,
+// and returns the path of the checkpoint images in the guest, where the bundle is
+// shared to the state dir of the container.
+async fn extract_checkpoint(
+    archive: &str,
+    bundle: &str,
+    checkpoint_dir: &str,
+    container_id: &str,
+) -> Result<String> {
+    let image_path = extract_archive(archive, bundle, checkpoint_dir)
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    let relative_path = Path::new(&image_path)
+        .strip_prefix(bundle)
+        .map_err(|e| anyhow!("checkpoint images {} out of bundle, {}", image_path, e))?;
+    Ok(Path::new(KUASAR_STATE_DIR)
+        .join(container_id)
+        .join(relative_path)
+        .to_string_lossy()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+    use tokio::process::Command;
+
+    use crate::container::handler::checkpoint::extract_checkpoint;
+
+    #[tokio::test]
+    async fn test_extract_checkpoint() {
+        // the archive of the Kubernetes checkpoint API has the images in a checkpoint dir
+        let checkpoint_dir = TempDir::new().unwrap();
+        let work_dir = checkpoint_dir.child("work");
+        tokio::fs::create_dir_all(work_dir.join("checkpoint"))
+            .await
+            .unwrap();
+        tokio::fs::write(work_dir.join("checkpoint/pages-1.img"), "pages")
+            .await
+            .unwrap();
+        tokio::fs::write(work_dir.join("config.dump"), "{}")
+            .await
+            .unwrap();
+        let archive = checkpoint_dir.child("checkpoint.tar");
+        let archive = archive.to_str().unwrap();
+        let status = Command::new("tar")
+            .args(["-cf", archive, "-C", work_dir.to_str().unwrap()])
+            .args(["checkpoint", "config.dump"])
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let bundle = TempDir::new().unwrap();
+        let image_path = extract_checkpoint(
+            archive,
+            bundle.path().to_str().unwrap(),
+            checkpoint_dir.path().to_str().unwrap(),
+            "c1",
+        )
+        .await
+        .unwrap();
+        assert_eq!(image_path, "/run/kuasar/state/c1/restore/checkpoint");
+        assert!(bundle.child("restore/checkpoint/pages-1.img").exists());
+    }
+}
@@ -15,12 +15,17 @@ limitations under the License.
 */
 
 use async_trait::async_trait;
-use containerd_sandbox::{error::Result, ContainerOption, Sandbox};
+use containerd_sandbox::{
+    error::{Error, Result},
+    ContainerOption, Sandbox,
+};
 use log::warn;
 
 use crate::{
     container::handler::{
         append::MetadataAddHandler,
+        block_share::BlockShareHandler,
+        checkpoint::CheckpointHandler,
         io::IoHandler,
         mount::MountHandler,
         ns::NamespaceHandler,
@@ -33,6 +38,8 @@ use crate::{
 };
 
 pub mod append;
+pub(crate) mod block_share;
+mod checkpoint;
 mod io;
 mod mount;
 mod ns;
@@ -133,8 +140,21 @@ where
             let io_handler = IoHandler::new(id, io);
             handlers.push(Box::new(io_handler));
         }
+        let checkpoint_handler = CheckpointHandler::new(id);
+        handlers.push(Box::new(checkpoint_handler));
         let spec_handler = SpecHandler::new(id);
         handlers.push(Box::new(spec_handler));
+        if !self.vm.capabilities().fs_sharing {
+            if !self.enable_block_share {
+                return Err(Error::Unimplemented(format!(
+                    "no filesystem is shared with the vm of sandbox {} for container {}, \
+                     set enable_block_share to copy its files into a block device",
+                    self.id, id
+                )));
+            }
+            let block_share_handler = BlockShareHandler::new(id);
+            handlers.push(Box::new(block_share_handler));
+        }
 
         Ok(HandlerChain::from(handlers))
     }
@@ -20,6 +20,8 @@ use containerd_sandbox::{
 };
 use serde::{Deserialize, Serialize};
 
+pub(crate) use self::handler::block_share::remove_block_share_image;
+
 mod handler;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -28,6 +30,13 @@ pub struct KuasarContainer {
     pub(crate) data: ContainerData,
     pub(crate) io_devices: Vec<String>,
     pub(crate) processes: Vec<KuasarProcess>,
+    // The checkpoint archive on the host that the container is restored from.
+    #[serde(default)]
+    pub(crate) checkpoint_archive: Option<String>,
+    // The block device that shares the files of container to the guest,
+    // if the hypervisor has no shared filesystem.
+    #[serde(default)]
+    pub(crate) block_share: Option<String>,
 }
 
 impl Container for KuasarContainer {
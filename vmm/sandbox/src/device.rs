@@ -17,6 +17,7 @@ limitations under the License.
 use std::os::fd::OwnedFd;
 
 use containerd_sandbox::error::{Error, Result};
+use serde::{Deserialize, Serialize};
 
 macro_rules! impl_device_no_bus {
     ($ty:ty) => {
@@ -51,7 +52,7 @@ pub trait Device {
     fn bus(&mut self) -> Option<&mut Bus>;
 }
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum BusType {
     PCI,
@@ -63,7 +64,7 @@ pub enum BusType {
     NULL,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bus {
     pub(crate) r#type: BusType,
     pub(crate) id: String,
@@ -122,7 +123,7 @@ impl Bus {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Slot {
     pub(crate) status: SlotStatus,
 }
@@ -135,7 +136,7 @@ impl Default for Slot {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SlotStatus {
     Empty,
     Occupied(String),
@@ -0,0 +1,309 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    thread::sleep,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use log::{debug, error, trace};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+
+use crate::vm::HealthError;
+
+pub(crate) const FIRECRACKER_START_TIMEOUT_IN_SEC: u64 = 10;
+const FIRECRACKER_REQUEST_TIMEOUT_IN_SEC: u64 = 10;
+pub(crate) const VM_STATE_RUNNING: &str = "Running";
+pub(crate) const ACTION_INSTANCE_START: &str = "InstanceStart";
+// the token buckets are refilled every second, so that their sizes are the rates per second
+const RATE_LIMITER_REFILL_TIME_IN_MS: u64 = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MachineConfig {
+    pub vcpu_count: u32,
+    pub mem_size_mib: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BootSource {
+    pub kernel_image_path: String,
+    pub boot_args: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initrd_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenBucket {
+    pub size: u64,
+    pub refill_time: u64,
+}
+
+// RateLimiter limits the bytes and the operations per second,
+// the token bucket of size 0 is unlimited, which also disables the limit when it is updated.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimiter {
+    pub bandwidth: TokenBucket,
+    pub ops: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(bandwidth: u64, ops: u64) -> Self {
+        let bucket = |size: u64| TokenBucket {
+            size,
+            refill_time: RATE_LIMITER_REFILL_TIME_IN_MS,
+        };
+        Self {
+            bandwidth: bucket(bandwidth),
+            ops: bucket(ops),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Drive {
+    pub drive_id: String,
+    pub path_on_host: String,
+    pub is_root_device: bool,
+    pub is_read_only: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PartialDrive {
+    pub drive_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_on_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NetworkInterface {
+    pub iface_id: String,
+    pub host_dev_name: String,
+    pub guest_mac: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_rate_limiter: Option<RateLimiter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_rate_limiter: Option<RateLimiter>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PartialNetworkInterface {
+    pub iface_id: String,
+    pub rx_rate_limiter: RateLimiter,
+    pub tx_rate_limiter: RateLimiter,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Vsock {
+    pub guest_cid: u32,
+    pub uds_path: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InstanceActionInfo {
+    pub action_type: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct InstanceInfo {
+    state: String,
+}
+
+// query_vm_state gets the vm state with the given timeout, the state of a booted vm is "Running".
+pub(crate) async fn query_vm_state(socket_path: &str, timeout: Duration) -> Result<String> {
+    let socket_path = socket_path.to_string();
+    spawn_blocking(move || -> Result<String> {
+        let mut socket = UnixStream::connect(&socket_path).map_err(|e| {
+            HealthError::VmmUnresponsive(format!("failed to connect {}, {}", socket_path, e))
+        })?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+        let (_, response_body) = send_request(&mut socket, "GET", "/", None)
+            .map_err(|e| HealthError::VmmUnresponsive(format!("failed to get info, {}", e)))?;
+        let info = serde_json::from_str::<InstanceInfo>(&response_body)
+            .map_err(|e| anyhow!("failed to unmarshal response {}, {}", response_body, e))?;
+        Ok(info.state)
+    })
+    .await
+    .map_err(|e| anyhow!("failed to join thread {}", e))?
+}
+
+// FcClient talks to the REST api of firecracker, a new connection is created for every request,
+// as firecracker closes the connection when the request is failed.
+#[derive(Debug, Clone)]
+pub struct FcClient {
+    socket_path: String,
+}
+
+impl FcClient {
+    pub async fn new(socket_path: String) -> Result<Self> {
+        let s = socket_path.to_string();
+        let start_time = SystemTime::now();
+        spawn_blocking(move || -> Result<()> {
+            loop {
+                match UnixStream::connect(&socket_path) {
+                    Ok(_) => {
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        trace!("failed to create client: {:?}", e);
+                        if start_time.elapsed().unwrap().as_secs()
+                            > FIRECRACKER_START_TIMEOUT_IN_SEC
+                        {
+                            error!("failed to create client: {:?}", e);
+                            return Err(anyhow!("timeout connect client, {}", e).into());
+                        }
+                        sleep(Duration::from_millis(10));
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|e| anyhow!("failed to join thread {}", e))??;
+        debug!("connected to api server {}", s);
+        Ok(Self { socket_path: s })
+    }
+
+    pub async fn put<T: Serialize>(&self, path: &str, body: &T) -> Result<()> {
+        self.request("PUT", path, body).await
+    }
+
+    pub async fn patch<T: Serialize>(&self, path: &str, body: &T) -> Result<()> {
+        self.request("PATCH", path, body).await
+    }
+
+    async fn request<T: Serialize>(&self, method: &str, path: &str, body: &T) -> Result<()> {
+        let request_body = serde_json::to_string(body)
+            .map_err(|e| anyhow!("failed to marshal request of {}, {}", path, e))?;
+        let socket_path = self.socket_path.to_string();
+        let method = method.to_string();
+        let path = path.to_string();
+        debug!("send request {} {}: {}", method, path, request_body);
+        spawn_blocking(move || -> Result<()> {
+            let mut socket = UnixStream::connect(&socket_path)
+                .map_err(|e| anyhow!("failed to connect {}, {}", socket_path, e))?;
+            let timeout = Duration::from_secs(FIRECRACKER_REQUEST_TIMEOUT_IN_SEC);
+            socket.set_read_timeout(Some(timeout))?;
+            socket.set_write_timeout(Some(timeout))?;
+            let (status, response_body) =
+                send_request(&mut socket, &method, &path, Some(&request_body))?;
+            if status >= 300 {
+                return Err(anyhow!(
+                    "failed to request {} {}, status: {}, response: {}",
+                    method,
+                    path,
+                    status,
+                    response_body
+                )
+                .into());
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| anyhow!("failed to join thread {}", e))?
+    }
+}
+
+fn send_request(
+    socket: &mut UnixStream,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+) -> Result<(u16, String)> {
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+    if let Some(b) = body {
+        request.push_str("Content-Type: application/json\r\n");
+        request.push_str(&format!("Content-Length: {}\r\n\r\n{}", b.len(), b));
+    } else {
+        request.push_str("\r\n");
+    }
+    socket
+        .write_all(request.as_bytes())
+        .map_err(|e| anyhow!("failed to write request {} {}, {}", method, path, e))?;
+    read_response(BufReader::new(socket))
+}
+
+// read_response parses the http response, returns the status code and the body.
+fn read_response<R: BufRead>(mut reader: R) -> Result<(u16, String)> {
+    let mut status_line = String::new();
+    reader
+        .read_line(&mut status_line)
+        .map_err(|e| anyhow!("failed to read response, {}", e))?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("invalid status line of response: {:?}", status_line))?;
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map_err(|e| anyhow!("failed to read response header, {}", e))?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            if key.trim().eq_ignore_ascii_case("Content-Length") {
+                content_length = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|e| anyhow!("invalid content length {}, {}", value, e))?;
+            }
+        }
+    }
+
+    let mut body = vec![0u8; content_length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| anyhow!("failed to read response body, {}", e))?;
+    Ok((status, String::from_utf8_lossy(&body).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::firecracker::client::read_response;
+
+    #[test]
+    fn test_read_response() {
+        let response = "HTTP/1.1 200 \r\n\
+            Server: Firecracker API\r\n\
+            Connection: keep-alive\r\n\
+            Content-Type: application/json\r\n\
+            Content-Length: 19\r\n\r\n\
+            {\"state\":\"Running\"}";
+        let (status, body) = read_response(response.as_bytes()).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, "{\"state\":\"Running\"}");
+
+        let response = "HTTP/1.1 204 \r\nServer: Firecracker API\r\n\r\n";
+        let (status, body) = read_response(response.as_bytes()).unwrap();
+        assert_eq!(status, 204);
+        assert!(body.is_empty());
+
+        assert!(read_response("invalid".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use serde::Deserialize;
+use vmm_common::storage::SHAREFS_TYPE_BLOCK;
+
+use crate::vm::HypervisorCommonConfig;
+
+const DEFAULT_KERNEL_PARAMS: &str = "console=ttyS0 \
+reboot=k panic=1 pci=off \
+root=/dev/vda1 \
+rootflags=data=ordered,errors=remount-ro \
+ro rootfstype=ext4";
+const DEFAULT_BLOCK_DEVICE_POOL_SIZE: u32 = 8;
+
+#[derive(Deserialize)]
+pub struct FirecrackerVMConfig {
+    pub path: String,
+    #[serde(flatten)]
+    pub common: HypervisorCommonConfig,
+    // firecracker can not hotplug devices, so the drives for the block devices of containers
+    // are attached with placeholder files before boot, and their backing files are updated later
+    #[serde(default = "default_block_device_pool_size")]
+    pub block_device_pool_size: u32,
+    pub task: TaskConfig,
+}
+
+impl Default for FirecrackerVMConfig {
+    fn default() -> Self {
+        Self {
+            path: "/usr/local/bin/firecracker".to_string(),
+            common: HypervisorCommonConfig::default(),
+            block_device_pool_size: DEFAULT_BLOCK_DEVICE_POOL_SIZE,
+            task: TaskConfig::default(),
+        }
+    }
+}
+
+fn default_block_device_pool_size() -> u32 {
+    DEFAULT_BLOCK_DEVICE_POOL_SIZE
+}
+
+#[derive(Deserialize, Default)]
+pub struct TaskConfig {
+    pub debug: bool,
+    pub enable_tracing: bool,
+}
+
+impl FirecrackerVMConfig {
+    pub fn boot_args(&self) -> String {
+        let mut boot_args = format!(
+            "{} task.sharefs_type={} {}",
+            DEFAULT_KERNEL_PARAMS, SHAREFS_TYPE_BLOCK, self.common.kernel_params
+        );
+        if self.task.debug {
+            boot_args.push_str(" task.log_level=debug");
+        }
+        boot_args.push_str(&format!(
+            " task.enable_tracing={}",
+            self.task.enable_tracing
+        ));
+        boot_args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::firecracker::config::FirecrackerVMConfig;
+
+    #[test]
+    fn test_boot_args() {
+        let mut config = FirecrackerVMConfig::default();
+        config.common.kernel_params = "quiet".to_string();
+        config.task.debug = true;
+        let boot_args = config.boot_args();
+        assert!(boot_args.starts_with("console=ttyS0 "));
+        assert!(boot_args.contains("root=/dev/vda1"));
+        assert!(boot_args.ends_with(
+            "task.sharefs_type=block quiet task.log_level=debug task.enable_tracing=false"
+        ));
+    }
+}
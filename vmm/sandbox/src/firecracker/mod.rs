@@ -0,0 +1,711 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{path::Path, process::Stdio, time::Duration};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::error::{Error, Result};
+use log::{debug, error, info, warn};
+use nix::{errno::Errno::ESRCH, sys::signal, unistd::Pid};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::{
+    fs::create_dir_all,
+    process::Child,
+    sync::watch::{channel, Receiver, Sender},
+    task::JoinHandle,
+};
+use tracing::instrument;
+
+use crate::{
+    device::{BusType, DeviceInfo},
+    firecracker::{
+        client::{
+            query_vm_state, BootSource, Drive, FcClient, InstanceActionInfo, MachineConfig,
+            NetworkInterface, PartialDrive, PartialNetworkInterface, RateLimiter, Vsock,
+            ACTION_INSTANCE_START, VM_STATE_RUNNING,
+        },
+        config::FirecrackerVMConfig,
+    },
+    utils::{
+        check_process_cmdline, read_std, set_cmd_netns, wait_channel, wait_pid, write_file_atomic,
+    },
+    vm::{HealthError, IoLimits, Pids, VcpuThreads, VmCapabilities, VmResources, VM},
+};
+
+mod client;
+pub mod config;
+pub mod factory;
+pub mod hooks;
+
+const VCPU_PREFIX: &str = "fc_vcpu ";
+const PLACEHOLDER_SIZE: u64 = 4096;
+
+// PoolDrive is a drive attached before boot with a placeholder file,
+// the placeholder is replaced by the block device when it is "hot attached".
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PoolDrive {
+    drive_id: String,
+    placeholder: String,
+    // the device name in guest, like /dev/vdb
+    guest_device: String,
+    // the id of device attached to this drive
+    device_id: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct FirecrackerVM {
+    id: String,
+    path: String,
+    debug: bool,
+    netns: String,
+    base_dir: String,
+    api_socket: String,
+    agent_socket: String,
+    machine_config: MachineConfig,
+    boot_source: BootSource,
+    drives: Vec<Drive>,
+    pool: Vec<PoolDrive>,
+    net_interfaces: Vec<NetworkInterface>,
+    vsock: Option<Vsock>,
+    #[serde(skip)]
+    wait_chan: Option<Receiver<(u32, i128)>>,
+    #[serde(skip)]
+    client: Option<FcClient>,
+    pids: Pids,
+    #[serde(default)]
+    resources: VmResources,
+    #[serde(default)]
+    io_limits: IoLimits,
+}
+
+impl FirecrackerVM {
+    pub fn new(id: &str, netns: &str, base_dir: &str, vm_config: &FirecrackerVMConfig) -> Self {
+        let initrd_path = if vm_config.common.initrd_path.is_empty() {
+            None
+        } else {
+            Some(vm_config.common.initrd_path.to_string())
+        };
+        Self {
+            id: id.to_string(),
+            path: vm_config.path.to_string(),
+            debug: vm_config.common.debug,
+            netns: netns.to_string(),
+            base_dir: base_dir.to_string(),
+            api_socket: format!("{}/api.sock", base_dir),
+            agent_socket: "".to_string(),
+            machine_config: MachineConfig {
+                vcpu_count: vm_config.common.vcpus,
+                mem_size_mib: vm_config.common.memory_in_mb,
+            },
+            boot_source: BootSource {
+                kernel_image_path: vm_config.common.kernel_path.to_string(),
+                boot_args: vm_config.boot_args(),
+                initrd_path,
+            },
+            drives: vec![],
+            pool: vec![],
+            net_interfaces: vec![],
+            vsock: None,
+            wait_chan: None,
+            client: None,
+            pids: Pids::default(),
+            resources: VmResources::default(),
+            io_limits: IoLimits::default(),
+        }
+    }
+
+    // add_drive adds a drive before boot, and returns its device name in guest,
+    // as the virtio-mmio devices are probed in the order of the drives.
+    pub fn add_drive(&mut self, drive: Drive) -> String {
+        self.drives.push(drive);
+        guest_device_name(self.drives.len() - 1)
+    }
+
+    pub fn add_pool_drive(&mut self, index: u32) {
+        let drive_id = format!("pool{}", index);
+        let placeholder = format!("{}/{}.img", self.base_dir, drive_id);
+        // the drive is always writable, as it can not be changed after boot,
+        // the read only block devices are mounted read only in guest.
+        let guest_device = self.add_drive(Drive {
+            drive_id: drive_id.to_string(),
+            path_on_host: placeholder.to_string(),
+            is_root_device: false,
+            is_read_only: false,
+            rate_limiter: self.block_rate_limiter(),
+        });
+        self.pool.push(PoolDrive {
+            drive_id,
+            placeholder,
+            guest_device,
+            device_id: None,
+        });
+    }
+
+    fn block_rate_limiter(&self) -> Option<RateLimiter> {
+        self.io_limits
+            .block_limited()
+            .then(|| RateLimiter::new(self.io_limits.block_bandwidth, self.io_limits.block_iops))
+    }
+
+    fn net_rate_limiter(&self) -> Option<RateLimiter> {
+        self.io_limits
+            .net_limited()
+            .then(|| RateLimiter::new(self.io_limits.net_bandwidth, 0))
+    }
+
+    // apply_io_limits sets the rate limits of the vm to the drives and network interfaces,
+    // and updates them if the vm is running.
+    async fn apply_io_limits(&mut self) -> Result<()> {
+        let block_limiter = self.block_rate_limiter();
+        let net_limiter = self.net_rate_limiter();
+        for d in self.drives.iter_mut().filter(|d| !d.is_root_device) {
+            d.rate_limiter.clone_from(&block_limiter);
+        }
+        for n in self.net_interfaces.iter_mut() {
+            n.rx_rate_limiter.clone_from(&net_limiter);
+            n.tx_rate_limiter.clone_from(&net_limiter);
+        }
+        if self.client.is_none() {
+            return Ok(());
+        }
+
+        // update the rate limiters of the running vm, the unlimited ones are disabled
+        let block_limiter = block_limiter.unwrap_or_default();
+        let net_limiter = net_limiter.unwrap_or_default();
+        let client = self.get_client()?;
+        for d in self.drives.iter().filter(|d| !d.is_root_device) {
+            debug!(
+                "update rate limiter of drive {} to {:?}",
+                d.drive_id, block_limiter
+            );
+            client
+                .patch(
+                    &format!("/drives/{}", d.drive_id),
+                    &PartialDrive {
+                        drive_id: d.drive_id.to_string(),
+                        path_on_host: None,
+                        rate_limiter: Some(block_limiter.clone()),
+                    },
+                )
+                .await?;
+        }
+        for n in &self.net_interfaces {
+            debug!(
+                "update rate limiter of network interface {} to {:?}",
+                n.iface_id, net_limiter
+            );
+            client
+                .patch(
+                    &format!("/network-interfaces/{}", n.iface_id),
+                    &PartialNetworkInterface {
+                        iface_id: n.iface_id.to_string(),
+                        rx_rate_limiter: net_limiter.clone(),
+                        tx_rate_limiter: net_limiter.clone(),
+                    },
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn pid(&self) -> Result<u32> {
+        match self.pids.vmm_pid {
+            None => Err(anyhow!("empty pid from vmm_pid").into()),
+            Some(pid) => Ok(pid),
+        }
+    }
+
+    async fn create_client(&self) -> Result<FcClient> {
+        FcClient::new(self.api_socket.to_string()).await
+    }
+
+    fn get_client(&self) -> Result<&FcClient> {
+        self.client
+            .as_ref()
+            .ok_or(Error::NotFound("firecracker client not inited".to_string()))
+    }
+
+    async fn create_placeholders(&self) -> Result<()> {
+        for p in &self.pool {
+            let file = tokio::fs::File::create(&p.placeholder)
+                .await
+                .map_err(|e| anyhow!("failed to create placeholder {}, {}", p.placeholder, e))?;
+            file.set_len(PLACEHOLDER_SIZE).await?;
+        }
+        Ok(())
+    }
+
+    async fn configure_and_boot(&self) -> Result<()> {
+        let client = self.get_client()?;
+        client.put("/machine-config", &self.machine_config).await?;
+        client.put("/boot-source", &self.boot_source).await?;
+        for d in &self.drives {
+            client.put(&format!("/drives/{}", d.drive_id), d).await?;
+        }
+        for n in &self.net_interfaces {
+            client
+                .put(&format!("/network-interfaces/{}", n.iface_id), n)
+                .await?;
+        }
+        if let Some(v) = &self.vsock {
+            client.put("/vsock", v).await?;
+        }
+        client
+            .put(
+                "/actions",
+                &InstanceActionInfo {
+                    action_type: ACTION_INSTANCE_START.to_string(),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn wait_stop(&mut self, t: Duration) -> Result<()> {
+        if let Some(rx) = self.wait_channel().await {
+            let (_, ts) = *rx.borrow();
+            if ts == 0 {
+                wait_channel(t, rx).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VM for FirecrackerVM {
+    #[instrument(skip_all)]
+    async fn start(&mut self) -> Result<u32> {
+        create_dir_all(&self.base_dir).await?;
+        self.create_placeholders().await?;
+        // firecracker fails to start if the sockets exist
+        for socket in [
+            Some(&self.api_socket),
+            self.vsock.as_ref().map(|v| &v.uds_path),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if Path::new(socket).exists() {
+                tokio::fs::remove_file(socket).await?;
+            }
+        }
+        self.resources = VmResources {
+            vcpus: self.machine_config.vcpu_count,
+            memory_in_mb: self.machine_config.mem_size_mib as u64,
+        };
+
+        let mut params = vec![
+            "--api-sock".to_string(),
+            self.api_socket.to_string(),
+            "--id".to_string(),
+            self.id.to_string(),
+        ];
+        if self.debug {
+            params.push("--level".to_string());
+            params.push("Debug".to_string());
+        }
+
+        let child = {
+            let mut cmd = tokio::process::Command::new(&self.path);
+            cmd.args(params.as_slice());
+            set_cmd_netns(&mut cmd, self.netns.to_string())?;
+            cmd.stdout(Stdio::piped());
+            cmd.stderr(Stdio::piped());
+            info!("start firecracker with cmdline: {:?}", cmd);
+            cmd.spawn()
+                .map_err(|e| anyhow!("failed to spawn firecracker command: {}", e))?
+        };
+        let pid = child.id();
+        info!(
+            "firecracker for {} is running with pid {}",
+            self.id,
+            pid.unwrap_or_default()
+        );
+        self.pids.vmm_pid = pid;
+        let pid_file = format!("{}/pid", self.base_dir);
+        let (tx, rx) = channel((0u32, 0i128));
+        self.wait_chan = Some(rx);
+        spawn_wait(
+            child,
+            format!("firecracker {}", self.id),
+            Some(pid_file),
+            Some(tx),
+        );
+
+        let res = match self.create_client().await {
+            Ok(client) => {
+                self.client = Some(client);
+                self.configure_and_boot().await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            if let Err(re) = self.stop(true).await {
+                warn!("roll back in start firecracker: {}", re);
+            }
+            return Err(e);
+        }
+        Ok(pid.unwrap_or_default())
+    }
+
+    #[instrument(skip_all)]
+    async fn stop(&mut self, force: bool) -> Result<()> {
+        let signal = if force {
+            signal::SIGKILL
+        } else {
+            signal::SIGTERM
+        };
+
+        let pids = self.pids();
+        if let Some(vmm_pid) = pids.vmm_pid {
+            if vmm_pid > 0 {
+                // TODO: Consider pid reused
+                match signal::kill(Pid::from_raw(vmm_pid as i32), signal) {
+                    Err(e) => {
+                        if e != ESRCH {
+                            return Err(anyhow!("kill vmm process {}: {}", vmm_pid, e).into());
+                        }
+                    }
+                    Ok(_) => self.wait_stop(Duration::from_secs(10)).await?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
+        match device_info {
+            DeviceInfo::Block(blk_info) => {
+                let rate_limiter = self.block_rate_limiter();
+                self.add_drive(Drive {
+                    drive_id: blk_info.id,
+                    path_on_host: blk_info.path,
+                    is_root_device: false,
+                    is_read_only: blk_info.read_only,
+                    rate_limiter,
+                });
+            }
+            DeviceInfo::Tap(tap_info) => {
+                // firecracker opens the tap device by name,
+                // the fds are closed here as the tap device is persistent.
+                let rate_limiter = self.net_rate_limiter();
+                self.net_interfaces.push(NetworkInterface {
+                    iface_id: tap_info.id,
+                    host_dev_name: tap_info.name,
+                    guest_mac: tap_info.mac_address,
+                    rx_rate_limiter: rate_limiter.clone(),
+                    tx_rate_limiter: rate_limiter,
+                });
+            }
+            DeviceInfo::Physical(_) => {
+                return Err(Error::Unimplemented(
+                    "firecracker does not support vfio device".to_string(),
+                ));
+            }
+            DeviceInfo::VhostUser(_) => {
+                return Err(Error::Unimplemented(
+                    "vhost user device for firecracker".to_string(),
+                ));
+            }
+            DeviceInfo::Char(_) => {
+                return Err(Error::Unimplemented(
+                    "char device for firecracker".to_string(),
+                ));
+            }
+        };
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
+        match device_info {
+            DeviceInfo::Block(blk_info) => {
+                let index = self
+                    .pool
+                    .iter()
+                    .position(|p| p.device_id.is_none())
+                    .ok_or_else(|| {
+                        Error::ResourceExhausted(format!(
+                            "no free drive for block device {}",
+                            blk_info.id
+                        ))
+                    })?;
+                let drive_id = self.pool[index].drive_id.to_string();
+                debug!(
+                    "attach block device {} of {} to drive {}",
+                    blk_info.id, blk_info.path, drive_id
+                );
+                self.get_client()?
+                    .patch(
+                        &format!("/drives/{}", drive_id),
+                        &PartialDrive {
+                            drive_id: drive_id.to_string(),
+                            path_on_host: Some(blk_info.path),
+                            rate_limiter: None,
+                        },
+                    )
+                    .await?;
+                let drive = &mut self.pool[index];
+                drive.device_id = Some(blk_info.id);
+                Ok((BusType::MMIO, drive.guest_device.to_string()))
+            }
+            _ => Err(Error::Unimplemented(
+                "firecracker only supports hot attaching block devices".to_string(),
+            )),
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn hot_detach(&mut self, id: &str) -> Result<()> {
+        let index = self
+            .pool
+            .iter()
+            .position(|p| p.device_id.as_deref() == Some(id))
+            .ok_or_else(|| Error::NotFound(format!("no drive attached by device {}", id)))?;
+        let drive = self.pool[index].clone();
+        self.get_client()?
+            .patch(
+                &format!("/drives/{}", drive.drive_id),
+                &PartialDrive {
+                    drive_id: drive.drive_id.to_string(),
+                    path_on_host: Some(drive.placeholder.to_string()),
+                    rate_limiter: None,
+                },
+            )
+            .await?;
+        self.pool[index].device_id = None;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn ping(&self, timeout: Duration) -> Result<()> {
+        let state = query_vm_state(&self.api_socket, timeout).await?;
+        if state != VM_STATE_RUNNING {
+            return Err(HealthError::GuestNotRunning(format!("vm state is {}", state)).into());
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    fn socket_address(&self) -> String {
+        self.agent_socket.to_string()
+    }
+
+    #[instrument(skip_all)]
+    async fn wait_channel(&self) -> Option<Receiver<(u32, i128)>> {
+        self.wait_chan.clone()
+    }
+
+    #[instrument(skip_all)]
+    async fn vcpus(&self) -> Result<VcpuThreads> {
+        // the vcpu threads of firecracker are named as "fc_vcpu <index>"
+        Ok(VcpuThreads {
+            vcpus: procfs::process::Process::new(self.pid()? as i32)
+                .map_err(|e| anyhow!("failed to get process {}", e))?
+                .tasks()
+                .map_err(|e| anyhow!("failed to get tasks {}", e))?
+                .flatten()
+                .filter_map(|t| {
+                    t.stat()
+                        .map_err(|e| anyhow!("failed to get stat {}", e))
+                        .ok()?
+                        .comm
+                        .strip_prefix(VCPU_PREFIX)
+                        .and_then(|comm| comm.parse().ok())
+                        .map(|index| (index, t.tid as i64))
+                })
+                .collect(),
+        })
+    }
+
+    #[instrument(skip_all)]
+    fn pids(&self) -> Pids {
+        self.pids.clone()
+    }
+
+    fn resources(&self) -> VmResources {
+        self.resources
+    }
+
+    fn boot_vcpus(&self) -> u32 {
+        self.machine_config.vcpu_count
+    }
+
+    #[instrument(skip_all)]
+    async fn resize(&mut self, resources: VmResources) -> Result<VmResources> {
+        if resources != self.resources {
+            warn!(
+                "firecracker does not support cpu or memory hotplug, vm {} keeps {:?}",
+                self.id, self.resources
+            );
+        }
+        Ok(self.resources)
+    }
+
+    #[instrument(skip_all)]
+    async fn set_io_limits(&mut self, limits: IoLimits) -> Result<()> {
+        let old = std::mem::replace(&mut self.io_limits, limits);
+        if let Err(e) = self.apply_io_limits().await {
+            // roll back the devices which are already updated
+            self.io_limits = old;
+            self.apply_io_limits()
+                .await
+                .unwrap_or_else(|re| warn!("roll back in set io limits of vm {}: {}", self.id, re));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> VmCapabilities {
+        VmCapabilities {
+            fs_sharing: false,
+            net_multi_queue: false,
+            // the network interfaces are only configured before the vm boots
+            net_hot_plug: false,
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl crate::vm::Recoverable for FirecrackerVM {
+    #[instrument(skip_all)]
+    async fn recover(&mut self) -> Result<()> {
+        // make sure the process is still the one we started before adopting it
+        let pid = self.pid()?;
+        check_process_cmdline(pid, &self.path)?;
+        self.client = Some(self.create_client().await?);
+        let (tx, rx) = channel((0u32, 0i128));
+        tokio::spawn(async move {
+            let wait_result = wait_pid(pid as i32).await;
+            tx.send(wait_result).unwrap_or_default();
+        });
+        self.wait_chan = Some(rx);
+        Ok(())
+    }
+}
+
+// guest_device_name names the virtio block devices the way the kernel does,
+// vda to vdz, then vdaa to vdzz, vdaaa and so on.
+fn guest_device_name(index: usize) -> String {
+    let mut index = index;
+    let mut name = vec![];
+    loop {
+        name.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    name.reverse();
+    format!("/dev/vd{}", String::from_utf8_lossy(&name))
+}
+
+macro_rules! read_stdio {
+    ($stdio:expr, $cmd_name:ident) => {
+        if let Some(std) = $stdio {
+            let cmd_name_clone = $cmd_name.clone();
+            tokio::spawn(async move {
+                read_std(std, &cmd_name_clone).await.unwrap_or_default();
+            });
+        }
+    };
+}
+
+fn spawn_wait(
+    child: Child,
+    cmd_name: String,
+    pid_file_path: Option<String>,
+    exit_chan: Option<Sender<(u32, i128)>>,
+) -> JoinHandle<()> {
+    let mut child = child;
+    tokio::spawn(async move {
+        if let Some(pid_file) = pid_file_path {
+            if let Some(pid) = child.id() {
+                write_file_atomic(&pid_file, &pid.to_string())
+                    .await
+                    .unwrap_or_default();
+            }
+        }
+
+        read_stdio!(child.stdout.take(), cmd_name);
+        read_stdio!(child.stderr.take(), cmd_name);
+
+        match child.wait().await {
+            Ok(status) => {
+                if !status.success() {
+                    error!("{} exit {}", cmd_name, status);
+                }
+                let now = OffsetDateTime::now_utc();
+                if let Some(tx) = exit_chan {
+                    tx.send((
+                        status.code().unwrap_or_default() as u32,
+                        now.unix_timestamp_nanos(),
+                    ))
+                    .unwrap_or_default();
+                }
+            }
+            Err(e) => {
+                error!("{} wait error {}", cmd_name, e);
+                let now = OffsetDateTime::now_utc();
+                if let Some(tx) = exit_chan {
+                    tx.send((0, now.unix_timestamp_nanos())).unwrap_or_default();
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::firecracker::{config::FirecrackerVMConfig, guest_device_name, FirecrackerVM};
+
+    #[test]
+    fn test_guest_device_name() {
+        assert_eq!(guest_device_name(0), "/dev/vda");
+        assert_eq!(guest_device_name(25), "/dev/vdz");
+        assert_eq!(guest_device_name(26), "/dev/vdaa");
+        assert_eq!(guest_device_name(27), "/dev/vdab");
+        assert_eq!(guest_device_name(701), "/dev/vdzz");
+        assert_eq!(guest_device_name(702), "/dev/vdaaa");
+    }
+
+    #[test]
+    fn test_pool_drive_guest_device() {
+        let mut vm = FirecrackerVM::new(
+            "sandbox1",
+            "",
+            "/run/kuasar",
+            &FirecrackerVMConfig::default(),
+        );
+        vm.add_drive(crate::firecracker::client::Drive {
+            drive_id: "rootfs".to_string(),
+            path_on_host: "/var/lib/kuasar/kuasar.img".to_string(),
+            is_root_device: true,
+            is_read_only: true,
+            rate_limiter: None,
+        });
+        vm.add_pool_drive(0);
+        vm.add_pool_drive(1);
+        assert_eq!(vm.pool[0].guest_device, "/dev/vdb");
+        assert_eq!(vm.pool[1].guest_device, "/dev/vdc");
+        assert_eq!(vm.pool[1].placeholder, "/run/kuasar/pool1.img");
+    }
+}
@@ -30,6 +30,7 @@ mod vm;
 pub mod args;
 pub mod cloud_hypervisor;
 pub mod config;
+pub mod firecracker;
 pub mod kata_config;
 pub mod qemu;
 pub mod sandbox;
@@ -15,10 +15,10 @@ limitations under the License.
 */
 
 use netlink_packet_route::AddressFamily;
-use protobuf::{EnumOrUnknown, SpecialFields};
-use vmm_common::api::sandbox::{IPAddress, IPFamily, Interface, Route};
+use protobuf::{EnumOrUnknown, MessageField, SpecialFields};
+use vmm_common::api::sandbox::{IPAddress, IPFamily, IPv6Config, Interface, Neighbour, Route};
 
-use crate::network::{IpNet, NetworkInterface};
+use crate::network::{link::Ipv6Config, IpNet, NetworkInterface};
 
 impl From<&NetworkInterface> for Interface {
     fn from(interface: &NetworkInterface) -> Self {
@@ -29,7 +29,20 @@ impl From<&NetworkInterface> for Interface {
             mtu: interface.mtu as u64,
             hwAddr: interface.mac_address.to_string(),
             raw_flags: interface.flags,
-            type_: "".to_string(),
+            type_: interface.r#type.to_string(),
+            ipv6: MessageField::from_option(interface.ipv6.as_ref().map(|c| c.into())),
+            special_fields: SpecialFields::default(),
+        }
+    }
+}
+
+impl From<&Ipv6Config> for IPv6Config {
+    fn from(c: &Ipv6Config) -> Self {
+        Self {
+            disabled: c.disabled,
+            accept_ra: c.accept_ra,
+            autoconf: c.autoconf,
+            addr_gen_mode: c.addr_gen_mode,
             special_fields: SpecialFields::default(),
         }
     }
@@ -68,3 +81,19 @@ impl From<&crate::network::Route> for Route {
         }
     }
 }
+
+impl From<&crate::network::Neighbour> for Neighbour {
+    fn from(n: &crate::network::Neighbour) -> Self {
+        Self {
+            destination: n.destination.to_string(),
+            device: n.device.to_string(),
+            lladdr: n.lladdr.to_string(),
+            state: n.state as u32,
+            family: EnumOrUnknown::from(match AddressFamily::from(n.family) {
+                AddressFamily::Inet6 => IPFamily::v6,
+                _ => IPFamily::v4,
+            }),
+            special_fields: Default::default(),
+        }
+    }
+}
@@ -28,6 +28,7 @@ use anyhow::anyhow;
 use containerd_sandbox::error::Result;
 use futures_util::TryStreamExt;
 use libc::{IFF_MULTI_QUEUE, IFF_NO_PI, IFF_TAP, IFF_VNET_HDR};
+use log::warn;
 use netlink_packet_route::link::{
     InfoData, InfoIpVlan, InfoKind, InfoMacVlan, InfoMacVtap, InfoVlan, InfoVxlan, LinkFlag,
     LinkInfo, LinkMessage,
@@ -44,7 +45,9 @@ use crate::{
     device::{DeviceInfo, PhysicalDeviceInfo, TapDeviceInfo, VhostUserDeviceInfo},
     network::{
         address::{CniIPAddress, IpNet, MacAddress},
-        create_netlink_handle, execute_in_netns, run_in_new_netns,
+        create_netlink_handle,
+        netlink::{add_ingress_redirect, del_ingress_qdisc},
+        run_in_new_netns,
     },
     sandbox::KuasarSandbox,
     utils::write_file_async,
@@ -53,6 +56,10 @@ use crate::{
 
 const DEVICE_DRIVER_VFIO: &str = "vfio-pci";
 
+const IPV6_CONF_DIR: &str = "/proc/sys/net/ipv6/conf";
+
+const MACVTAP_DEVICE_PREFIX: &str = "/dev/tap";
+
 const SIOCETHTOOL: u64 = 0x8946;
 const ETHTOOL_GDRVINFO: u32 = 0x00000003;
 
@@ -132,6 +139,21 @@ impl Display for LinkType {
     }
 }
 
+impl LinkType {
+    // bridged_by_tap returns true if the interface is bridged to the vm by a tap device,
+    // with the packets redirected between them by tc.
+    pub fn bridged_by_tap(&self) -> bool {
+        matches!(
+            self,
+            LinkType::Veth
+                | LinkType::Macvlan(_)
+                | LinkType::Ipvlan(_)
+                | LinkType::Vlan(_)
+                | LinkType::Bond
+        )
+    }
+}
+
 impl From<InfoData> for LinkType {
     fn from(d: InfoData) -> Self {
         match d {
@@ -204,6 +226,20 @@ pub struct NetworkInterface {
     pub fds: Vec<OwnedFd>,
     #[serde(skip)]
     pub queue: u32,
+    #[serde(skip)]
+    pub multi_queue: bool,
+    #[serde(default)]
+    pub ipv6: Option<Ipv6Config>,
+}
+
+// Ipv6Config is the IPv6 sysctls of an interface in /proc/sys/net/ipv6/conf/<interface>/,
+// it is applied to the interface in guest so that IPv6 behaves the same as in the netns.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct Ipv6Config {
+    pub disabled: bool,
+    pub accept_ra: i32,
+    pub autoconf: bool,
+    pub addr_gen_mode: u32,
 }
 
 // netlink-packet-route-0.19.0/src/link/link_flag.rs:26
@@ -229,6 +265,7 @@ impl NetworkInterface {
         let mut intf = NetworkInterface {
             flags: u32::from(&VecLinkFlag(msg.header.flags)),
             index: msg.header.index,
+            queue,
             ..NetworkInterface::default()
         };
         use netlink_packet_route::link::LinkAttribute;
@@ -241,7 +278,6 @@ impl NetworkInterface {
                         } else if let LinkInfo::Kind(InfoKind::Veth) = info {
                             // for veth, there is no Info::Data, but SlaveKind and SlaveData,
                             // so we have to get the type from Info::Kind
-                            intf.queue = queue;
                             intf.r#type = LinkType::Veth;
                         }
                     }
@@ -289,6 +325,18 @@ impl NetworkInterface {
         Ok(intf)
     }
 
+    // read_ipv6_config reads the IPv6 sysctls of the interface in the netns,
+    // it is None if IPv6 is not supported by the kernel.
+    pub async fn read_ipv6_config(&mut self, netns: &str) -> Result<()> {
+        let name = self.name.to_string();
+        self.ipv6 = if !netns.is_empty() {
+            run_in_new_netns(netns, move || read_ipv6_config(IPV6_CONF_DIR, &name)).await?
+        } else {
+            read_ipv6_config(IPV6_CONF_DIR, &name)
+        };
+        Ok(())
+    }
+
     pub async fn init_cni_interface(&mut self) -> Result<()> {
         // TODO: cni_link_type to LinkType
         // TODO: Impl From<String> for LinkType
@@ -311,126 +359,195 @@ impl NetworkInterface {
 
     pub async fn prepare_attaching(&mut self, netns: &str) -> Result<()> {
         match &self.r#type {
-            LinkType::Veth => {
+            t if t.bridged_by_tap() => {
                 let handle = create_netlink_handle(netns).await?;
-                let tap_name = format!("tap_kua_{}", self.index);
-                let tap_intf =
-                    create_tap_in_netns(netns, &tap_name, self.queue, self.mtu, &handle).await?;
-                tap_intf.add_qdisc_ingress(netns, &handle).await?;
-                self.add_qdisc_ingress(netns, &handle).await?;
-                tap_intf.add_redirect_tc_filter(netns, &self.name).await?;
-                self.add_redirect_tc_filter(netns, &tap_intf.name).await?;
+                let tap_name = tap_name(self.index);
+                let tap_intf = create_tap_in_netns(
+                    netns,
+                    &tap_name,
+                    self.queue,
+                    self.multi_queue,
+                    self.mtu,
+                    &handle,
+                )
+                .await?;
+                redirect_between(&handle, self, &tap_intf).await?;
                 self.twin = Some(Box::new(tap_intf));
             }
+            LinkType::Macvtap(_) => {
+                self.fds = open_macvtap_fds(self.index, self.queue)?;
+            }
             LinkType::Physical(bdf, _driver) => {
                 bind_device_to_driver(DEVICE_DRIVER_VFIO, bdf).await?
             }
-            _ => {}
+            LinkType::VhostUser(_) | LinkType::Tap | LinkType::Loopback => {}
+            t => {
+                return Err(anyhow!(
+                    "interface {} of type \"{}\" is not supported to attach to the vm",
+                    self.name,
+                    t
+                )
+                .into());
+            }
         }
         Ok(())
     }
 
     pub async fn attach_to<V: VM>(&mut self, sandbox: &mut KuasarSandbox<V>) -> Result<()> {
-        let id = format!("intf-{}", self.index);
-        match &self.r#type {
-            LinkType::Veth => {
-                if let Some(intf) = self.twin.as_mut() {
-                    sandbox
-                        .vm
-                        .attach(DeviceInfo::Tap(TapDeviceInfo {
-                            id,
-                            index: self.index,
-                            name: intf.name.to_string(),
-                            mac_address: self.mac_address.to_string(),
-                            fds: intf.fds.drain(..).collect(),
-                        }))
-                        .await?;
-                } else {
-                    return Err(anyhow!("no tap interface created for veth {}", self.name).into());
-                }
-            }
-            LinkType::VhostUser(sock) => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::VhostUser(VhostUserDeviceInfo {
-                        id,
-                        socket_path: sock.to_string(),
-                        mac_address: self.mac_address.to_string(),
-                        r#type: "virtio-net-pci".to_string(),
-                    }))
-                    .await?;
-            }
-            LinkType::Physical(bdf, _driver) => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::Physical(PhysicalDeviceInfo {
-                        id,
-                        bdf: bdf.to_string(),
-                    }))
-                    .await?;
-            }
-            LinkType::Tap => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::Tap(TapDeviceInfo {
-                        id,
-                        index: self.index,
-                        name: self.name.to_string(),
-                        mac_address: self.mac_address.to_string(),
-                        fds: vec![],
-                    }))
-                    .await?;
-            }
-            LinkType::Loopback => {}
-            _ => {}
+        if let Some(device) = self.device_info()? {
+            sandbox.vm.attach(device).await?;
         }
         Ok(())
     }
 
-    pub async fn after_detach(&mut self, _netns: &str) -> Result<()> {
-        if let LinkType::Physical(bdf, driver) = &self.r#type {
-            bind_device_to_driver(driver, bdf).await?
+    // hot_attach_to attaches the interface to the running vm,
+    // prepare_attaching should be called before it.
+    pub async fn hot_attach_to<V: VM>(&mut self, vm: &mut V) -> Result<()> {
+        if let Some(device) = self.device_info()? {
+            vm.hot_attach(device).await?;
         }
         Ok(())
     }
 
-    async fn add_qdisc_ingress(&self, netns: &str, _handle: &Handle) -> Result<()> {
-        // TODO use netlink to add ingress
-        let mut cmd = std::process::Command::new("tc");
-        cmd.args(["qdisc", "add", "dev", &*self.name, "ingress"]);
-        execute_in_netns(netns, cmd).await?;
+    // hot_detach_from detaches the interface from the running vm,
+    // and recycles the resources of it in the netns.
+    pub async fn hot_detach_from<V: VM>(&mut self, vm: &mut V, netns: &str) -> Result<()> {
+        if !matches!(self.r#type, LinkType::Loopback) {
+            if let Err(e) = vm.hot_detach(&self.device_id()).await {
+                // recycle the resources anyway, as the interface is removed from the network
+                // and they would be leaked otherwise.
+                self.after_detach(netns).await.unwrap_or_else(|re| {
+                    warn!("failed to recycle interface {}: {}", self.name, re)
+                });
+                return Err(e);
+            }
+        }
+        self.after_detach(netns).await
+    }
+
+    fn device_id(&self) -> String {
+        format!("intf-{}", self.index)
+    }
+
+    fn device_info(&mut self) -> Result<Option<DeviceInfo>> {
+        let id = self.device_id();
+        let device = match &self.r#type {
+            t if t.bridged_by_tap() => {
+                let intf = self.twin.as_mut().ok_or(anyhow!(
+                    "no tap interface created for {} {}",
+                    self.r#type,
+                    self.name
+                ))?;
+                DeviceInfo::Tap(TapDeviceInfo {
+                    id,
+                    index: self.index,
+                    name: intf.name.to_string(),
+                    mac_address: self.mac_address.to_string(),
+                    fds: intf.fds.drain(..).collect(),
+                })
+            }
+            LinkType::Macvtap(_) => DeviceInfo::Tap(TapDeviceInfo {
+                id,
+                index: self.index,
+                name: self.name.to_string(),
+                mac_address: self.mac_address.to_string(),
+                fds: self.fds.drain(..).collect(),
+            }),
+            LinkType::VhostUser(sock) => DeviceInfo::VhostUser(VhostUserDeviceInfo {
+                id,
+                socket_path: sock.to_string(),
+                mac_address: self.mac_address.to_string(),
+                r#type: "virtio-net-pci".to_string(),
+            }),
+            LinkType::Physical(bdf, _driver) => DeviceInfo::Physical(PhysicalDeviceInfo {
+                id,
+                bdf: bdf.to_string(),
+            }),
+            LinkType::Tap => DeviceInfo::Tap(TapDeviceInfo {
+                id,
+                index: self.index,
+                name: self.name.to_string(),
+                mac_address: self.mac_address.to_string(),
+                fds: vec![],
+            }),
+            _ => return Ok(None),
+        };
+        Ok(Some(device))
+    }
+
+    pub async fn after_detach(&mut self, netns: &str) -> Result<()> {
+        match &self.r#type {
+            t if t.bridged_by_tap() => self.remove_twin(netns).await?,
+            LinkType::Physical(bdf, driver) => bind_device_to_driver(driver, bdf).await?,
+            _ => {}
+        }
         Ok(())
     }
 
-    async fn add_redirect_tc_filter(&self, netns: &str, dest: &str) -> Result<()> {
-        // TODO do this with netlink library
-        let mut cmd = std::process::Command::new("tc");
-        cmd.args([
-            "filter",
-            "add",
-            "dev",
-            &*self.name,
-            "parent",
-            "ffff:",
-            "protocol",
-            "all",
-            "u32",
-            "match",
-            "u8",
-            "0",
-            "0",
-            "action",
-            "mirred",
-            "egress",
-            "redirect",
-            "dev",
-            dest,
-        ]);
-        execute_in_netns(netns, cmd).await?;
+    // remove_twin deletes the tap of the interface and the ingress qdisc, with the redirect
+    // filters on it, of the interface, nothing to do if the netns is already removed.
+    async fn remove_twin(&mut self, netns: &str) -> Result<()> {
+        self.twin = None;
+        if !netns.is_empty() && !Path::new(netns).exists() {
+            return Ok(());
+        }
+        let handle = create_netlink_handle(netns).await?;
+        del_ingress_qdisc(&handle, self.index)
+            .await
+            .map_err(|e| anyhow!("failed to delete ingress qdisc of {}: {}", self.name, e))?;
+        let tap_name = tap_name(self.index);
+        let mut links = handle.link().get().match_name(tap_name.clone()).execute();
+        // the tap may not exist if the sandbox failed before it is created
+        if let Ok(Some(msg)) = links.try_next().await {
+            handle
+                .link()
+                .del(msg.header.index)
+                .execute()
+                .await
+                .map_err(|e| anyhow!("failed to delete tap {}: {}", tap_name, e))?;
+        }
         Ok(())
     }
 }
 
+fn tap_name(index: u32) -> String {
+    format!("tap_kua_{}", index)
+}
+
+// redirect_between redirects the packets received by each of the two interfaces
+// to the other one, so that they are bridged.
+async fn redirect_between(
+    handle: &Handle,
+    intf: &NetworkInterface,
+    twin: &NetworkInterface,
+) -> Result<()> {
+    add_ingress_redirect(handle, twin.index, intf.index)
+        .await
+        .map_err(|e| anyhow!("failed to redirect {} to {}: {}", twin.name, intf.name, e))?;
+    add_ingress_redirect(handle, intf.index, twin.index)
+        .await
+        .map_err(|e| anyhow!("failed to redirect {} to {}: {}", intf.name, twin.name, e))?;
+    Ok(())
+}
+
+fn read_ipv6_config<P: AsRef<Path>>(conf_dir: P, if_name: &str) -> Option<Ipv6Config> {
+    let dir = conf_dir.as_ref().join(if_name);
+    let read = |key: &str| -> Option<i32> {
+        std::fs::read_to_string(dir.join(key))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    Some(Ipv6Config {
+        disabled: read("disable_ipv6")? != 0,
+        accept_ra: read("accept_ra")?,
+        autoconf: read("autoconf")? != 0,
+        // addr_gen_mode is not supported by the kernels before 4.11
+        addr_gen_mode: read("addr_gen_mode").unwrap_or_default() as u32,
+    })
+}
+
 fn get_bdf_for_eth(if_name: &str) -> Result<String> {
     if if_name.len() > 16 {
         return Err(anyhow!("the interface name length is larger than 16").into());
@@ -484,11 +601,15 @@ async fn create_tap_in_netns(
     netns: &str,
     tap_name: &str,
     queue: u32,
+    multi_queue: bool,
     mtu: u32,
     handle: &Handle,
 ) -> Result<NetworkInterface> {
     let tap_name_move = tap_name.to_string();
-    let fds = run_in_new_netns(netns, move || create_tap_device(&tap_name_move, queue)).await??;
+    let fds = run_in_new_netns(netns, move || {
+        create_tap_device(&tap_name_move, queue, multi_queue)
+    })
+    .await??;
 
     // getLinkByName
     let mut link = handle
@@ -523,7 +644,7 @@ pub struct ifreq {
     ifr_pad: [u8; 22],
 }
 
-fn create_tap_device(tap_name: &str, mut queue: u32) -> Result<Vec<OwnedFd>> {
+fn create_tap_device(tap_name: &str, mut queue: u32, multi_queue: bool) -> Result<Vec<OwnedFd>> {
     if tap_name.len() > 15 {
         return Err(anyhow!("tap name {} length should less than 15", tap_name).into());
     }
@@ -535,7 +656,10 @@ fn create_tap_device(tap_name: &str, mut queue: u32) -> Result<Vec<OwnedFd>> {
     };
 
     // TODO: Remove IFF_VNET_HDR
-    let flags = IFF_TAP | IFF_NO_PI | IFF_MULTI_QUEUE | IFF_VNET_HDR;
+    let mut flags = IFF_TAP | IFF_NO_PI | IFF_VNET_HDR;
+    if multi_queue {
+        flags |= IFF_MULTI_QUEUE;
+    }
 
     let req = ifreq {
         ifr_name: if_name_arr,
@@ -564,6 +688,25 @@ fn create_tap_device(tap_name: &str, mut queue: u32) -> Result<Vec<OwnedFd>> {
     Ok(fds)
 }
 
+// open_macvtap_fds opens the character device of the macvtap for each queue,
+// the device is created by udev with the name of the interface index.
+fn open_macvtap_fds(index: u32, mut queue: u32) -> Result<Vec<OwnedFd>> {
+    let path = format!("{}{}", MACVTAP_DEVICE_PREFIX, index);
+    if queue == 0 {
+        queue = 1
+    };
+    let mut fds: Vec<OwnedFd> = Vec::new();
+    for _i in 0..queue {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| anyhow!("failed to open macvtap device {}: {}", path, e))?;
+        fds.push(OwnedFd::from(file));
+    }
+    Ok(fds)
+}
+
 async fn get_pci_driver(bdf: &str) -> Result<String> {
     let driver_path = format!("/sys/bus/pci/devices/{}/driver", bdf);
     let driver_dest = tokio::fs::read_link(&driver_path)
@@ -618,19 +761,21 @@ async fn bind_device_to_driver(driver: &str, bdf: &str) -> Result<()> {
 mod tests {
     use std::process::Command;
 
-    use crate::network::link::create_tap_device;
+    use temp_dir::TempDir;
+
+    use crate::network::link::{create_tap_device, open_macvtap_fds, read_ipv6_config, LinkType};
 
     #[test]
     fn add_tap_device_with_long_name() {
         let tap_name = "add_tap_device_with_long_name";
-        let res = create_tap_device(tap_name, 1);
+        let res = create_tap_device(tap_name, 1, true);
         assert!(res.is_err());
     }
 
     #[test]
     fn add_and_remove_tap_device() {
         let tap_name = "test-kuasar";
-        let _ = create_tap_device(tap_name, 1).expect("failed to create tap dev");
+        let _ = create_tap_device(tap_name, 1, true).expect("failed to create tap dev");
 
         // ip tuntap list to show tap device
         let stdout = Command::new("ip")
@@ -648,4 +793,47 @@ mod tests {
             .output()
             .expect("failed to delete tap dev");
     }
+
+    #[test]
+    fn test_bridged_by_tap() {
+        assert!(LinkType::Veth.bridged_by_tap());
+        assert!(LinkType::Ipvlan(0).bridged_by_tap());
+        assert!(LinkType::Vlan(100).bridged_by_tap());
+        assert!(LinkType::Bond.bridged_by_tap());
+        assert!(!LinkType::Macvtap(0).bridged_by_tap());
+        assert!(!LinkType::Tap.bridged_by_tap());
+        assert!(!LinkType::Vxlan(1).bridged_by_tap());
+    }
+
+    #[test]
+    fn open_macvtap_fds_of_nonexistent_device() {
+        assert!(open_macvtap_fds(u32::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn test_read_ipv6_config() {
+        let dir = TempDir::new().unwrap();
+        let conf = dir.child("eth0");
+        std::fs::create_dir(&conf).unwrap();
+        // the kernel does not support IPv6 if there is no conf of the interface
+        assert!(read_ipv6_config(dir.path(), "eth0").is_none());
+
+        std::fs::write(conf.join("disable_ipv6"), "0\n").unwrap();
+        std::fs::write(conf.join("accept_ra"), "2\n").unwrap();
+        std::fs::write(conf.join("autoconf"), "1\n").unwrap();
+        let ipv6 = read_ipv6_config(dir.path(), "eth0").unwrap();
+        assert!(!ipv6.disabled);
+        assert_eq!(ipv6.accept_ra, 2);
+        assert!(ipv6.autoconf);
+        // addr_gen_mode is missing in the kernels before 4.11
+        assert_eq!(ipv6.addr_gen_mode, 0);
+
+        std::fs::write(conf.join("disable_ipv6"), "1\n").unwrap();
+        std::fs::write(conf.join("addr_gen_mode"), "1\n").unwrap();
+        let ipv6 = read_ipv6_config(dir.path(), "eth0").unwrap();
+        assert!(ipv6.disabled);
+        assert_eq!(ipv6.addr_gen_mode, 1);
+
+        assert!(read_ipv6_config(dir.path(), "eth1").is_none());
+    }
 }
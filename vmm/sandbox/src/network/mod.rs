@@ -32,21 +32,35 @@ use nix::{
 use rtnetlink::{new_connection, Handle, IpVersion};
 use serde_derive::{Deserialize, Serialize};
 use tokio::task::spawn_blocking;
+use vmm_common::api::sandbox::{
+    Interface, UpdateInterfacesRequest, UpdateNeighboursRequest, UpdateRoutesRequest,
+};
 
-pub use crate::network::{address::IpNet, link::NetworkInterface, route::Route};
+pub use crate::network::{
+    address::IpNet, link::NetworkInterface, neighbour::Neighbour, route::Route,
+    watcher::NetnsWatcher,
+};
 use crate::{network::link::LinkType, sandbox::KuasarSandbox, utils::safe_open_file, vm::VM};
 
 pub mod address;
 mod convert;
 pub mod link;
+pub mod neighbour;
 mod netlink;
 pub mod route;
+mod watcher;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Network {
     pub(crate) config: NetworkConfig,
     pub(crate) intfs: Vec<NetworkInterface>,
     routes: Vec<Route>,
+    #[serde(default)]
+    neighbours: Vec<Neighbour>,
+    // the indexes of the interfaces that failed to be hot attached,
+    // they are not attached again as it would fail the same way.
+    #[serde(default)]
+    failed_intfs: Vec<u32>,
 }
 
 async fn get_route(
@@ -72,6 +86,21 @@ async fn get_route(
     Ok(())
 }
 
+async fn get_neighbours(handle: &Handle, intfs: &[NetworkInterface]) -> Result<Vec<Neighbour>> {
+    let mut neighbours = vec![];
+    let mut neigh_msgs = handle.neighbours().get().execute();
+    while let Some(neigh_msg) = neigh_msgs.try_next().await.map_err(|e| anyhow!("{}", e))? {
+        match Neighbour::parse_from_message(neigh_msg, intfs) {
+            Ok(n) => neighbours.push(n),
+            Err(e) => {
+                // ignore those neighbours that are not set by CNI
+                debug!("can not parse the neighbour message to neighbour {}", e);
+            }
+        }
+    }
+    Ok(neighbours)
+}
+
 impl Network {
     pub async fn new(config: NetworkConfig) -> Result<Self> {
         debug!("create network with config: {:?}", config);
@@ -111,17 +140,26 @@ impl Network {
             }
             intfs.push(network_interface);
         }
-        let intfs = Self::filter_intfs(intfs);
+        let mut intfs = Self::filter_intfs(intfs);
+        for intf in &mut intfs {
+            intf.read_ipv6_config(&config.netns).await?;
+            intf.multi_queue = config.multi_queue;
+        }
 
         // get all routes from netns
         let mut routes = vec![];
         get_route(IpVersion::V4, &handle, &intfs, &mut routes).await?;
         get_route(IpVersion::V6, &handle, &intfs, &mut routes).await?;
 
+        // get the ARP/NDP neighbours from netns
+        let neighbours = get_neighbours(&handle, &intfs).await?;
+
         Ok(Network {
             config,
             intfs,
             routes,
+            neighbours,
+            failed_intfs: vec![],
         })
     }
 
@@ -136,6 +174,117 @@ impl Network {
         Ok(())
     }
 
+    // sync rescans the netns, hot attaches the new interfaces to the vm and hot detaches the
+    // removed ones, returns the requests to update the guest with only the added or changed
+    // interfaces, as the guest takes the link down to update it, or None if nothing changed.
+    pub async fn sync<V: VM>(&mut self, vm: &mut V) -> Result<Option<NetworkChanges>> {
+        let netns = self.config.netns.to_string();
+        let latest = Self::new_in_netns(self.config.clone()).await?;
+        let (_, routes_before, neighbours_before) = self.to_requests();
+
+        let mut intfs = vec![];
+        let mut updated = vec![];
+        for mut intf in latest.intfs {
+            if self.failed_intfs.contains(&intf.index) {
+                continue;
+            }
+            match self.intfs.iter().position(|x| x.index == intf.index) {
+                Some(i) => {
+                    // keep the tap attached to the vm, and take the latest configs
+                    let old = self.intfs.remove(i);
+                    if Interface::from(&old) != Interface::from(&intf) {
+                        updated.push(Interface::from(&intf));
+                    }
+                    intf.twin = old.twin;
+                    intfs.push(intf);
+                }
+                None => {
+                    info!("hot attach interface {} to sandbox", intf.name);
+                    if let Err(e) = Self::hot_attach(&mut intf, vm, &netns).await {
+                        error!("failed to hot attach interface {}: {}", intf.name, e);
+                        self.failed_intfs.push(intf.index);
+                        continue;
+                    }
+                    updated.push(Interface::from(&intf));
+                    intfs.push(intf);
+                }
+            }
+        }
+        let mut detached = false;
+        for mut intf in self.intfs.drain(..) {
+            // the physical interface disappears from the netns once it is bound to vfio
+            if let LinkType::Physical(_, _) = intf.r#type {
+                intfs.push(intf);
+                continue;
+            }
+            info!("hot detach interface {} from sandbox", intf.name);
+            if let Err(e) = intf.hot_detach_from(vm, &netns).await {
+                error!("failed to hot detach interface {}: {}", intf.name, e);
+            }
+            detached = true;
+        }
+        self.intfs = intfs;
+        self.routes = latest.routes;
+        self.neighbours = latest.neighbours;
+
+        let (_, routes, neighbours) = self.to_requests();
+        let mut changes = NetworkChanges::default();
+        // the routes and neighbours on the links are flushed when the guest takes them down
+        // to update them, so they are set again if any interface is updated.
+        let relinked = !updated.is_empty();
+        if relinked {
+            changes.interfaces = Some(UpdateInterfacesRequest {
+                interfaces: updated,
+                ..Default::default()
+            });
+        }
+        if relinked || routes != routes_before {
+            changes.routes = Some(routes);
+        }
+        if relinked || neighbours != neighbours_before {
+            changes.neighbours = Some(neighbours);
+        }
+        if !detached && changes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(changes))
+    }
+
+    async fn hot_attach<V: VM>(intf: &mut NetworkInterface, vm: &mut V, netns: &str) -> Result<()> {
+        intf.prepare_attaching(netns).await?;
+        if let Err(e) = intf.hot_attach_to(vm).await {
+            intf.after_detach(netns).await.unwrap_or_else(|re| {
+                warn!("roll back in hot attach interface {}: {}", intf.name, re)
+            });
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // to_requests converts the network to the requests to update the network of the guest.
+    pub fn to_requests(
+        &self,
+    ) -> (
+        UpdateInterfacesRequest,
+        UpdateRoutesRequest,
+        UpdateNeighboursRequest,
+    ) {
+        (
+            UpdateInterfacesRequest {
+                interfaces: self.intfs.iter().map(|x| x.into()).collect(),
+                ..Default::default()
+            },
+            UpdateRoutesRequest {
+                routes: self.routes.iter().map(|x| x.into()).collect(),
+                ..Default::default()
+            },
+            UpdateNeighboursRequest {
+                neighbours: self.neighbours.iter().map(|x| x.into()).collect(),
+                ..Default::default()
+            },
+        )
+    }
+
     pub async fn destroy(&mut self) {
         for intf in &mut self.intfs {
             if let Err(e) = intf.after_detach(&self.config.netns).await {
@@ -155,11 +304,16 @@ impl Network {
         self.routes.as_ref()
     }
 
+    pub fn neighbours(&self) -> &Vec<Neighbour> {
+        self.neighbours.as_ref()
+    }
+
     fn filter_intfs(intfs: Vec<NetworkInterface>) -> Vec<NetworkInterface> {
         intfs
             .into_iter()
             .filter(|intf| match intf.r#type {
-                LinkType::Veth => true,
+                ref t if t.bridged_by_tap() => true,
+                LinkType::Macvtap(_) => true,
                 LinkType::VhostUser(_) => true,
                 LinkType::Physical(_, _) => true,
                 LinkType::Tap => true,
@@ -167,17 +321,44 @@ impl Network {
                     // do we have to drop loopback?
                     true
                 }
-                _ => false,
+                // the taps created by kuasar for the interfaces
+                LinkType::Tun => false,
+                // keep the unsupported ones configured by CNI, so that the sandbox fails to
+                // start rather than runs without the network.
+                _ => !intf.ip_addresses.is_empty(),
             })
             .collect::<Vec<NetworkInterface>>()
     }
 }
 
+// NetworkChanges are the requests to update the network of the guest after a sync,
+// each of them is None if there is nothing to update.
+#[derive(Debug, Default)]
+pub struct NetworkChanges {
+    pub interfaces: Option<UpdateInterfacesRequest>,
+    pub routes: Option<UpdateRoutesRequest>,
+    pub neighbours: Option<UpdateNeighboursRequest>,
+}
+
+impl NetworkChanges {
+    pub fn is_empty(&self) -> bool {
+        self.interfaces.is_none() && self.routes.is_none() && self.neighbours.is_none()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
     pub(crate) netns: String,
     pub(crate) sandbox_id: String,
     pub(crate) queue: u32,
+    // the taps are created without IFF_MULTI_QUEUE if the hypervisor does not support it,
+    // as it opens the tap by name with single queue.
+    #[serde(default = "default_multi_queue")]
+    pub(crate) multi_queue: bool,
+}
+
+fn default_multi_queue() -> bool {
+    true
 }
 
 async fn run_in_new_netns<P: AsRef<Path>, F, T>(netns: P, f: F) -> Result<T>
@@ -211,27 +392,6 @@ pub(crate) async fn create_netlink_handle(netns: &str) -> Result<Handle> {
     Ok(handle)
 }
 
-pub async fn execute_in_netns(netns: &str, mut cmd: std::process::Command) -> Result<String> {
-    let output = if !netns.is_empty() {
-        run_in_new_netns(netns, move || cmd.output()).await?
-    } else {
-        cmd.output()
-    }?;
-    if !output.status.success() {
-        Err(anyhow!(
-            "failed to execute command, command return {:?}, stdout: {}, stderr: {}",
-            output.status.code(),
-            String::from_utf8_lossy(output.stdout.as_slice()),
-            String::from_utf8_lossy(output.stderr.as_slice())
-        )
-        .into())
-    } else {
-        let stdout = String::from_utf8(output.stdout)
-            .map_err(|e| anyhow!("failed to execute command: {}", e))?;
-        Ok(stdout)
-    }
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NetType {
     Tap,
@@ -256,7 +416,95 @@ impl Display for NetType {
 
 #[cfg(test)]
 mod tests {
-    use crate::network::{Network, NetworkConfig};
+    use std::{path::Path, time::Duration};
+
+    use async_trait::async_trait;
+    use containerd_sandbox::error::{Error, Result};
+    use futures_util::TryStreamExt;
+    use nix::{
+        sched::{unshare, CloneFlags},
+        unistd::{getpid, gettid},
+    };
+    use serde_derive::Serialize;
+    use tokio::sync::watch::Receiver;
+
+    use crate::{
+        device::{BusType, DeviceInfo},
+        network::{create_netlink_handle, Network, NetworkConfig},
+        vm::{IoLimits, Pids, VcpuThreads, VmResources, VM},
+    };
+
+    #[derive(Default, Serialize)]
+    struct FakeVM {
+        attached: Vec<String>,
+        fail_detach: bool,
+    }
+
+    #[async_trait]
+    impl VM for FakeVM {
+        async fn start(&mut self) -> Result<u32> {
+            Ok(0)
+        }
+
+        async fn stop(&mut self, _force: bool) -> Result<()> {
+            Ok(())
+        }
+
+        async fn attach(&mut self, _device_info: DeviceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
+            if let DeviceInfo::Tap(tap) = device_info {
+                self.attached.push(tap.id);
+            }
+            Ok((BusType::PCI, "".to_string()))
+        }
+
+        async fn hot_detach(&mut self, id: &str) -> Result<()> {
+            if self.fail_detach {
+                return Err(Error::Unimplemented(format!("detach {}", id)));
+            }
+            self.attached.retain(|x| x != id);
+            Ok(())
+        }
+
+        async fn ping(&self, _timeout: Duration) -> Result<()> {
+            Ok(())
+        }
+
+        fn socket_address(&self) -> String {
+            "".to_string()
+        }
+
+        async fn wait_channel(&self) -> Option<Receiver<(u32, i128)>> {
+            None
+        }
+
+        async fn vcpus(&self) -> Result<VcpuThreads> {
+            Err(Error::Unimplemented("vcpus".to_string()))
+        }
+
+        fn pids(&self) -> Pids {
+            Pids::default()
+        }
+
+        fn resources(&self) -> VmResources {
+            VmResources::default()
+        }
+
+        fn boot_vcpus(&self) -> u32 {
+            1
+        }
+
+        async fn resize(&mut self, resources: VmResources) -> Result<VmResources> {
+            Ok(resources)
+        }
+
+        async fn set_io_limits(&mut self, _limits: IoLimits) -> Result<()> {
+            Ok(())
+        }
+    }
 
     #[tokio::test]
     async fn test_new() {
@@ -264,6 +512,7 @@ mod tests {
             netns: "".to_string(),
             sandbox_id: "".to_string(),
             queue: 1,
+            multi_queue: true,
         })
         .await
         .unwrap();
@@ -274,5 +523,81 @@ mod tests {
         for route in network.routes {
             println!("route: {:?}", route);
         }
+        for neigh in network.neighbours {
+            println!("neighbour: {:?}", neigh);
+        }
+    }
+
+    #[test]
+    fn test_sync() {
+        std::thread::spawn(|| {
+            // run in a new netns of the thread to keep the links of the host untouched,
+            // skip the test if there is no permission to create a netns or taps.
+            if !Path::new("/dev/net/tun").exists() || unshare(CloneFlags::CLONE_NEWNET).is_err() {
+                return;
+            }
+            let netns = format!("/proc/{}/task/{}/ns/net", getpid(), gettid());
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async move {
+                    let handle = create_netlink_handle("").await.unwrap();
+                    let mut network = Network::new_from_netns(NetworkConfig {
+                        netns,
+                        sandbox_id: "".to_string(),
+                        queue: 1,
+                        multi_queue: true,
+                    })
+                    .await
+                    .unwrap();
+                    let mut vm = FakeVM::default();
+                    assert!(network.sync(&mut vm).await.unwrap().is_none());
+
+                    handle
+                        .link()
+                        .add()
+                        .veth("kuasar-veth0".to_string(), "kuasar-veth1".to_string())
+                        .execute()
+                        .await
+                        .unwrap();
+                    let changes = network.sync(&mut vm).await.unwrap().unwrap();
+                    assert_eq!(changes.interfaces.unwrap().interfaces.len(), 2);
+                    assert_eq!(network.interfaces().len(), 2);
+                    assert_eq!(vm.attached.len(), 2);
+                    // the taps created for the interfaces are not synced
+                    assert!(network.sync(&mut vm).await.unwrap().is_none());
+
+                    let index = network.interfaces()[0].index;
+                    handle.link().set(index).mtu(1400).execute().await.unwrap();
+                    let changes = network.sync(&mut vm).await.unwrap().unwrap();
+                    let interfaces = changes.interfaces.unwrap().interfaces;
+                    assert_eq!(interfaces.len(), 1);
+                    assert_eq!(interfaces[0].mtu, 1400);
+                    // the routes and neighbours are set again after the link is updated
+                    assert!(changes.routes.is_some());
+                    assert!(changes.neighbours.is_some());
+                    assert_eq!(vm.attached.len(), 2);
+
+                    // deleting one end of the veth pair deletes both of them
+                    vm.fail_detach = true;
+                    handle.link().del(index).execute().await.unwrap();
+                    let changes = network.sync(&mut vm).await.unwrap().unwrap();
+                    assert!(changes.interfaces.is_none());
+                    assert!(network.interfaces().is_empty());
+                    // the taps are deleted even if the vmm fails to detach them,
+                    // only the loopback is left in the netns.
+                    let links = handle
+                        .link()
+                        .get()
+                        .execute()
+                        .try_collect::<Vec<_>>()
+                        .await
+                        .unwrap();
+                    assert_eq!(links.len(), 1);
+                });
+        })
+        .join()
+        .unwrap();
     }
 }
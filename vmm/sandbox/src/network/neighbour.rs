@@ -0,0 +1,168 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::net::IpAddr;
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use netlink_packet_route::neighbour::{
+    NeighbourAddress, NeighbourAttribute, NeighbourMessage, NeighbourState,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::network::{address::MacAddress, link::NetworkInterface};
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Neighbour {
+    pub device: String,
+    pub destination: String,
+    #[serde(default)]
+    pub lladdr: String,
+    #[serde(default)]
+    pub state: u16,
+    #[serde(default)]
+    pub family: u8,
+}
+
+impl Neighbour {
+    // parse_from_message parses the permanent neighbour entries, which are the ones set by CNI,
+    // the dynamic ones are learned by the guest itself.
+    pub fn parse_from_message(msg: NeighbourMessage, intfs: &[NetworkInterface]) -> Result<Self> {
+        if msg.header.state != NeighbourState::Permanent {
+            return Err(anyhow!("ignore neighbours that are not permanent").into());
+        }
+        let mut neigh = Neighbour {
+            state: msg.header.state.into(),
+            family: msg.header.family.into(),
+            ..Neighbour::default()
+        };
+        neigh.device = intfs
+            .iter()
+            .find(|&x| x.index == msg.header.ifindex)
+            .map(|x| x.name.to_string())
+            .ok_or(anyhow!(
+                "can not find the device by index {}",
+                msg.header.ifindex
+            ))?;
+        for attribute in msg.attributes.into_iter() {
+            match attribute {
+                NeighbourAttribute::Destination(d) => {
+                    neigh.destination = convert_to_ip_address(d)?.to_string();
+                }
+                NeighbourAttribute::LinkLocalAddress(a) => {
+                    neigh.lladdr = MacAddress(a).to_string();
+                }
+                _ => {}
+            }
+        }
+        if neigh.destination.is_empty() {
+            return Err(anyhow!("no destination in neighbour of {}", neigh.device).into());
+        }
+        Ok(neigh)
+    }
+}
+
+fn convert_to_ip_address(address: NeighbourAddress) -> Result<IpAddr> {
+    match address {
+        NeighbourAddress::Inet(addr) => Ok(IpAddr::V4(addr)),
+        NeighbourAddress::Inet6(addr) => Ok(IpAddr::V6(addr)),
+        _ => Err(anyhow!("unsupported neighbour address {:?}", address).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use netlink_packet_route::{
+        neighbour::{NeighbourAddress, NeighbourAttribute, NeighbourMessage, NeighbourState},
+        AddressFamily,
+    };
+
+    use crate::network::{link::NetworkInterface, neighbour::Neighbour};
+
+    fn neighbour_message(ifindex: u32, state: NeighbourState) -> NeighbourMessage {
+        let mut msg = NeighbourMessage::default();
+        msg.header.ifindex = ifindex;
+        msg.header.state = state;
+        msg.header.family = AddressFamily::Inet;
+        msg.attributes
+            .push(NeighbourAttribute::Destination(NeighbourAddress::Inet(
+                Ipv4Addr::new(10, 0, 0, 1),
+            )));
+        msg.attributes
+            .push(NeighbourAttribute::LinkLocalAddress(vec![
+                0x02, 0x42, 0xac, 0x11, 0x00, 0x02,
+            ]));
+        msg
+    }
+
+    fn interfaces() -> Vec<NetworkInterface> {
+        vec![NetworkInterface {
+            index: 2,
+            name: "eth0".to_string(),
+            ..NetworkInterface::default()
+        }]
+    }
+
+    #[test]
+    fn test_parse_from_message() {
+        let intfs = interfaces();
+        let neigh =
+            Neighbour::parse_from_message(neighbour_message(2, NeighbourState::Permanent), &intfs)
+                .unwrap();
+        assert_eq!(neigh.device, "eth0");
+        assert_eq!(neigh.destination, "10.0.0.1");
+        assert_eq!(neigh.lladdr, "02:42:ac:11:00:02");
+        assert_eq!(neigh.state, u16::from(NeighbourState::Permanent));
+        assert_eq!(neigh.family, u8::from(AddressFamily::Inet));
+
+        let mut msg = NeighbourMessage::default();
+        msg.header.ifindex = 2;
+        msg.header.state = NeighbourState::Permanent;
+        msg.header.family = AddressFamily::Inet6;
+        msg.attributes
+            .push(NeighbourAttribute::Destination(NeighbourAddress::Inet6(
+                Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1),
+            )));
+        let neigh = Neighbour::parse_from_message(msg, &intfs).unwrap();
+        assert_eq!(neigh.destination, "fd00::1");
+        assert!(neigh.lladdr.is_empty());
+        assert_eq!(neigh.family, u8::from(AddressFamily::Inet6));
+    }
+
+    #[test]
+    fn test_parse_from_message_ignored() {
+        let intfs = interfaces();
+        // the dynamic neighbours are learned by the guest itself
+        assert!(Neighbour::parse_from_message(
+            neighbour_message(2, NeighbourState::Reachable),
+            &intfs
+        )
+        .is_err());
+        // the neighbours of the interfaces not in the sandbox
+        assert!(Neighbour::parse_from_message(
+            neighbour_message(3, NeighbourState::Permanent),
+            &intfs
+        )
+        .is_err());
+
+        let mut msg = neighbour_message(2, NeighbourState::Permanent);
+        msg.attributes
+            .retain(|a| !matches!(a, NeighbourAttribute::Destination(_)));
+        assert!(Neighbour::parse_from_message(msg, &intfs).is_err());
+    }
+}
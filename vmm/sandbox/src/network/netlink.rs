@@ -14,20 +14,28 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use futures_util::StreamExt;
-use netlink_packet_core::{NetlinkMessage, NLM_F_ACK, NLM_F_CREATE, NLM_F_EXCL, NLM_F_REQUEST};
-use netlink_packet_route::{tc::TcMessage, RouteNetlinkMessage};
+use futures_util::{StreamExt, TryStreamExt};
+use netlink_packet_core::{
+    ErrorMessage, NetlinkMessage, NLM_F_ACK, NLM_F_CREATE, NLM_F_EXCL, NLM_F_REQUEST,
+};
+use netlink_packet_route::{
+    tc::{TcAttribute, TcMessage},
+    RouteNetlinkMessage,
+};
+use nix::errno::Errno;
 use rtnetlink::{try_nl, Error, Handle};
 
+// the parent of the ingress qdisc, TC_H_INGRESS
 const HANDLE_INGRESS: u32 = 0xfffffff1;
+// the handle of the ingress qdisc, which is the parent of the filters on it, "ffff:" in tc
 const HANDLE_TC_FILTER: u32 = 0xffff0000;
+const ETH_P_ALL: u16 = 0x0003;
 
 pub struct QDiscAddRequest {
     handle: Handle,
     message: TcMessage,
 }
 
-#[allow(dead_code)]
 impl QDiscAddRequest {
     pub(crate) fn new(handle: Handle) -> Self {
         QDiscAddRequest {
@@ -41,10 +49,8 @@ impl QDiscAddRequest {
             mut handle,
             message,
         } = self;
-
         let mut req = NetlinkMessage::from(RouteNetlinkMessage::NewQueueDiscipline(message));
         req.header.flags = NLM_F_REQUEST | NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK;
-
         let mut response = handle.request(req)?;
         while let Some(message) = response.next().await {
             try_nl!(message);
@@ -59,34 +65,34 @@ impl QDiscAddRequest {
 
     pub fn ingress(mut self) -> Self {
         self.message.header.parent = HANDLE_INGRESS.into();
+        self.message.header.handle = HANDLE_TC_FILTER.into();
+        self.message
+            .attributes
+            .push(TcAttribute::Kind("ingress".to_string()));
         self
     }
 }
 
-pub struct TrafficFilterSetRequest {
+pub struct QDiscDelRequest {
     handle: Handle,
     message: TcMessage,
 }
 
-#[allow(dead_code)]
-impl TrafficFilterSetRequest {
-    pub(crate) fn new(handle: Handle, ifindex: i32) -> Self {
-        let mut message = TcMessage::default();
-        message.header.index = ifindex;
-        message.header.parent = HANDLE_TC_FILTER.into();
-
-        Self { handle, message }
+impl QDiscDelRequest {
+    pub(crate) fn new(handle: Handle) -> Self {
+        QDiscDelRequest {
+            handle,
+            message: TcMessage::default(),
+        }
     }
 
     pub async fn execute(self) -> Result<(), Error> {
-        let TrafficFilterSetRequest {
+        let QDiscDelRequest {
             mut handle,
             message,
         } = self;
-
-        let mut req = NetlinkMessage::from(RouteNetlinkMessage::NewTrafficFilter(message));
-        req.header.flags = NLM_F_REQUEST | NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK;
-
+        let mut req = NetlinkMessage::from(RouteNetlinkMessage::DelQueueDiscipline(message));
+        req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
         let mut response = handle.request(req)?;
         while let Some(message) = response.next().await {
             try_nl!(message);
@@ -94,8 +100,157 @@ impl TrafficFilterSetRequest {
         Ok(())
     }
 
-    pub fn mirror(self, _dest_index: i32) -> Self {
-        //TODO
+    pub fn if_index(mut self, if_index: i32) -> Self {
+        self.message.header.index = if_index;
+        self
+    }
+
+    pub fn ingress(mut self) -> Self {
+        self.message.header.parent = HANDLE_INGRESS.into();
+        self.message.header.handle = HANDLE_TC_FILTER.into();
         self
     }
 }
+
+// add_ingress_redirect redirects all the packets received by the link to the egress of
+// the dest link, the same as `tc qdisc add dev <link> ingress` and `tc filter add dev <link>
+// parent ffff: protocol all u32 match u8 0 0 action mirred egress redirect dev <dest>`.
+// The ingress qdisc is recreated with the filter, so it is safe to be called again.
+pub async fn add_ingress_redirect(
+    handle: &Handle,
+    index: u32,
+    dest_index: u32,
+) -> Result<(), Error> {
+    del_ingress_qdisc(handle, index).await?;
+    QDiscAddRequest::new(handle.clone())
+        .if_index(index as i32)
+        .ingress()
+        .execute()
+        .await?;
+    handle
+        .traffic_filter(index as i32)
+        .add()
+        .parent(HANDLE_TC_FILTER)
+        .protocol(ETH_P_ALL)
+        .redirect(dest_index)?
+        .execute()
+        .await
+}
+
+// del_ingress_qdisc deletes the ingress qdisc of the link with all the filters on it,
+// it is not an error if there is no ingress qdisc on the link.
+pub async fn del_ingress_qdisc(handle: &Handle, index: u32) -> Result<(), Error> {
+    // the kernel returns EINVAL if there is no ingress qdisc to delete, which can not be
+    // told apart from the other invalid requests, so look it up before deleting.
+    if !has_ingress_qdisc(handle, index).await? {
+        return Ok(());
+    }
+    match QDiscDelRequest::new(handle.clone())
+        .if_index(index as i32)
+        .ingress()
+        .execute()
+        .await
+    {
+        Err(Error::NetlinkError(e)) if is_not_found(&e) => Ok(()),
+        res => res,
+    }
+}
+
+async fn has_ingress_qdisc(handle: &Handle, index: u32) -> Result<bool, Error> {
+    let mut qdiscs = handle.qdisc().get().execute();
+    while let Some(msg) = qdiscs.try_next().await? {
+        if msg.header.index == index as i32
+            && msg
+                .attributes
+                .iter()
+                .any(|a| matches!(a, TcAttribute::Kind(k) if k == "ingress"))
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// is_not_found tells if the qdisc or the link is already gone when it is deleted.
+fn is_not_found(e: &ErrorMessage) -> bool {
+    matches!(
+        e.code.map(|c| Errno::from_i32(c.get().abs())),
+        Some(Errno::ENOENT) | Some(Errno::ENODEV)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+    use nix::sched::{unshare, CloneFlags};
+    use rtnetlink::{new_connection, Handle};
+
+    use crate::network::netlink::{add_ingress_redirect, del_ingress_qdisc, has_ingress_qdisc};
+
+    async fn add_dummy_link(handle: &Handle, name: &str) -> u32 {
+        handle
+            .link()
+            .add()
+            .dummy(name.to_string())
+            .execute()
+            .await
+            .unwrap();
+        let link = handle
+            .link()
+            .get()
+            .match_name(name.to_string())
+            .execute()
+            .try_next()
+            .await
+            .unwrap()
+            .unwrap();
+        link.header.index
+    }
+
+    #[test]
+    fn add_and_del_ingress_redirect() {
+        std::thread::spawn(|| {
+            // run in a new netns of the thread to keep the links of the host untouched,
+            // skip the test if there is no permission to create a netns.
+            if unshare(CloneFlags::CLONE_NEWNET).is_err() {
+                return;
+            }
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let (conn, handle, _) = new_connection().unwrap();
+                    tokio::spawn(conn);
+                    let index = add_dummy_link(&handle, "kuasar-test0").await;
+                    let dest_index = add_dummy_link(&handle, "kuasar-test1").await;
+
+                    // it is not an error if there is no ingress qdisc
+                    del_ingress_qdisc(&handle, index).await.unwrap();
+                    assert!(!has_ingress_qdisc(&handle, index).await.unwrap());
+
+                    add_ingress_redirect(&handle, index, dest_index)
+                        .await
+                        .unwrap();
+                    assert!(has_ingress_qdisc(&handle, index).await.unwrap());
+                    assert!(!has_ingress_qdisc(&handle, dest_index).await.unwrap());
+                    // the ingress qdisc is recreated if it is redirected again
+                    add_ingress_redirect(&handle, index, dest_index)
+                        .await
+                        .unwrap();
+                    assert!(has_ingress_qdisc(&handle, index).await.unwrap());
+
+                    del_ingress_qdisc(&handle, index).await.unwrap();
+                    assert!(!has_ingress_qdisc(&handle, index).await.unwrap());
+                    del_ingress_qdisc(&handle, index).await.unwrap();
+
+                    // the errors other than a missing qdisc are returned
+                    assert!(add_ingress_redirect(&handle, index, u32::MAX)
+                        .await
+                        .is_err());
+                });
+        })
+        .join()
+        .unwrap();
+    }
+}
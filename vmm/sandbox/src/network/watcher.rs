@@ -0,0 +1,82 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use futures_util::{stream::BoxStream, StreamExt};
+use rtnetlink::{
+    constants::{
+        RTMGRP_IPV4_IFADDR, RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_IFADDR, RTMGRP_IPV6_ROUTE, RTMGRP_LINK,
+    },
+    new_connection,
+    sys::{AsyncSocket, SocketAddr},
+};
+use tokio::{task::JoinHandle, time::timeout};
+
+use crate::network::run_in_new_netns;
+
+// CNI configures the link, addresses and routes one by one,
+// so the changes within the interval are merged into one.
+const MERGE_INTERVAL: Duration = Duration::from_millis(500);
+
+// NetnsWatcher subscribes the changes of the links, addresses and routes in the netns.
+pub struct NetnsWatcher {
+    messages: BoxStream<'static, ()>,
+    connection: JoinHandle<()>,
+}
+
+impl NetnsWatcher {
+    pub async fn new(netns: &str) -> Result<Self> {
+        let (mut connection, _, messages) = run_in_new_netns(netns, new_connection).await??;
+        let groups = RTMGRP_LINK
+            | RTMGRP_IPV4_IFADDR
+            | RTMGRP_IPV6_IFADDR
+            | RTMGRP_IPV4_ROUTE
+            | RTMGRP_IPV6_ROUTE;
+        connection
+            .socket_mut()
+            .socket_mut()
+            .bind(&SocketAddr::new(0, groups))
+            .map_err(|e| anyhow!("failed to subscribe netlink groups in {}: {}", netns, e))?;
+        Ok(Self {
+            messages: messages.map(|_| ()).boxed(),
+            connection: tokio::spawn(connection),
+        })
+    }
+
+    // changed waits until the network in the netns is changed,
+    // returns false if no more changes can be received.
+    pub async fn changed(&mut self) -> bool {
+        if self.messages.next().await.is_none() {
+            return false;
+        }
+        loop {
+            match timeout(MERGE_INTERVAL, self.messages.next()).await {
+                Ok(Some(_)) => continue,
+                _ => return true,
+            }
+        }
+    }
+}
+
+impl Drop for NetnsWatcher {
+    fn drop(&mut self) {
+        // the netlink socket holds the netns, so it has to be closed with the watcher
+        self.connection.abort();
+    }
+}
@@ -27,7 +27,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     param::ToCmdLineParams,
-    utils::{bool_to_on_off, get_host_memory_in_mb},
+    utils::bool_to_on_off,
     vm::{BlockDriver, HypervisorCommonConfig, ShareFsType},
 };
 
@@ -100,6 +100,9 @@ pub struct QemuVMConfig {
     pub share_fs: ShareFsType,
     pub use_vsock: bool,
     pub virtiofsd: Option<VirtiofsdConfig>,
+    // offload the data plane of the tap devices to the vhost-net kernel module
+    #[serde(default)]
+    pub vhost_net: bool,
 }
 
 impl Default for QemuVMConfig {
@@ -115,7 +118,7 @@ impl Default for QemuVMConfig {
             default_bridges: 1,
             default_max_vcpus: 0,
             entropy_source: "/dev/urandom".to_string(),
-            mem_slots: 1,
+            mem_slots: 10,
             mem_offset: 0,
             memory_path: "".to_string(),
             file_backend_mem_path: "".to_string(),
@@ -132,6 +135,7 @@ impl Default for QemuVMConfig {
             share_fs: ShareFsType::Virtio9P,
             use_vsock: false,
             virtiofsd: None,
+            vhost_net: false,
         }
     }
 }
@@ -194,12 +198,17 @@ impl QemuVMConfig {
         if !self.firmware_path.is_empty() {
             result.bios = Some(self.firmware_path.to_string());
         }
+        let max_vcpus = if self.default_max_vcpus > 0 {
+            self.default_max_vcpus
+        } else {
+            self.common.max_vcpus
+        };
         result.smp = SMP {
             cpus: self.common.vcpus,
             cores: 1,
             threads: 1,
-            sockets: self.default_max_vcpus,
-            max_cpus: self.default_max_vcpus,
+            sockets: max_vcpus,
+            max_cpus: max_vcpus,
         };
         if self.enable_vhost_user_store && !self.hugepages {
             return Err(Error::InvalidArgument(
@@ -208,14 +217,20 @@ impl QemuVMConfig {
         }
         result.memory = Memory {
             size: format!("{}M", self.common.memory_in_mb),
-            slots: self.mem_slots,
-            max_mem: format!("{}M", get_host_memory_in_mb().await?),
+            slots: 0,
+            max_mem: "".to_string(),
             backend_type: MemoryBackend::Ram,
             pre_alloc: self.mem_prealloc,
             shared: self.enable_vhost_user_store,
             enable_numa: self.machine_type != MACHINE_TYPE_MICROVM_PCI,
         };
 
+        // leave no room for memory hotplug if the max memory is not above the boot memory
+        if self.common.max_memory_in_mb > self.common.memory_in_mb && self.mem_slots > 0 {
+            result.memory.slots = self.mem_slots;
+            result.memory.max_mem = format!("{}M", self.common.max_memory_in_mb);
+        }
+
         if !self.memory_path.is_empty() {
             result.memory.backend_type = MemoryBackend::File(self.memory_path.to_string());
         } else if self.hugepages {
@@ -333,10 +348,14 @@ impl ToCmdLineParams for Memory {
         let mut params = vec![];
         if !self.size.is_empty() {
             params.push(format!("{}m", hyphen));
-            params.push(format!(
-                "{},slots={},maxmem={}",
-                self.size, self.slots, self.max_mem
-            ));
+            if self.slots > 0 && !self.max_mem.is_empty() {
+                params.push(format!(
+                    "{},slots={},maxmem={}",
+                    self.size, self.slots, self.max_mem
+                ));
+            } else {
+                params.push(self.size.to_string());
+            }
         }
         // -machine with memory-backend is only supported by qemu with version higher than 5.0,
         // so we return directly here if numa is not supported.
@@ -557,4 +576,25 @@ mod tests {
         eprintln!("params: {:?}", params);
         // TODO asserts
     }
+
+    #[tokio::test]
+    async fn test_qemu_memory_params() {
+        let mut vmconfig = QemuVMConfig::default();
+        vmconfig.common.memory_in_mb = 2048;
+        vmconfig.common.max_memory_in_mb = 0;
+        let config = vmconfig.to_qemu_config().await.unwrap();
+        let params = config.memory.to_cmdline_params("-");
+        assert_eq!(params[0..2], ["-m".to_string(), "2048M".to_string()]);
+
+        vmconfig.common.max_memory_in_mb = 4096;
+        let config = vmconfig.to_qemu_config().await.unwrap();
+        let params = config.memory.to_cmdline_params("-");
+        assert_eq!(
+            params[0..2],
+            [
+                "-m".to_string(),
+                format!("2048M,slots={},maxmem=4096M", vmconfig.mem_slots)
+            ]
+        );
+    }
 }
@@ -25,16 +25,18 @@ use qapi::{
     Dictionary,
 };
 use sandbox_derive::CmdLineParams;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
     device::{BusType, Device, Transport},
     qemu::{devices::HotAttachable, qmp_client::QmpClient},
+    vm::IoLimits,
 };
 
 pub const VIRTIO_BLK_DRIVER: &str = "virtio-blk";
 
-#[derive(CmdLineParams, Debug, Clone)]
+#[derive(CmdLineParams, Debug, Clone, Serialize, Deserialize)]
 #[params("device", "drive")]
 pub struct VirtioBlockDevice {
     #[property(param = "device", ignore_key)]
@@ -42,6 +44,8 @@ pub struct VirtioBlockDevice {
     #[property(param = "drive")]
     #[property(param = "device", key = "drive")]
     pub id: String,
+    #[property(param = "device", key = "id")]
+    pub qdev_id: Option<String>,
     #[property(param = "drive")]
     pub file: Option<String>,
     #[property(param = "drive")]
@@ -84,6 +88,10 @@ pub struct VirtioBlockDevice {
     pub share_rw: bool,
     #[property(param = "drive", generator = "crate::utils::bool_to_on_off")]
     pub readonly: bool,
+    #[property(param = "drive", key = "throttling.iops-total")]
+    pub iops_total: Option<u64>,
+    #[property(param = "drive", key = "throttling.bps-total")]
+    pub bps_total: Option<u64>,
 }
 
 impl_device_no_bus!(VirtioBlockDevice);
@@ -93,6 +101,7 @@ impl VirtioBlockDevice {
         Self {
             driver: driver.to_string(),
             id: id.to_string(),
+            qdev_id: None,
             file,
             r#if: None,
             aio: None,
@@ -110,8 +119,24 @@ impl VirtioBlockDevice {
             disable_legacy: true,
             #[cfg(feature = "virtcca")]
             iommu_platform: true,
+            iops_total: None,
+            bps_total: None,
         }
     }
+
+    // throttle limits the drive when it is attached by the command line,
+    // the hot attached ones are limited by block_set_io_throttle.
+    pub fn throttle(mut self, limits: &IoLimits) -> Self {
+        self.iops_total = (limits.block_iops > 0).then_some(limits.block_iops);
+        self.bps_total = (limits.block_bandwidth > 0).then_some(limits.block_bandwidth);
+        self
+    }
+
+    // device_id is the qdev id of the device which is hot attached by device_add,
+    // or the qdev_id of the one attached by the command line.
+    pub fn device_id(&self) -> String {
+        format!("virtio-{}", self.id())
+    }
 }
 
 #[async_trait]
@@ -145,8 +170,7 @@ impl HotAttachable for VirtioBlockDevice {
 
     async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
         debug!("hot detach device {}", self.id);
-        let device_id = format!("virtio-{}", self.id());
-        client.delete_device(&device_id).await?;
+        client.delete_device(&self.device_id()).await?;
         client.execute(self.to_blockdev_del()).await?;
         Ok(())
     }
@@ -215,7 +239,7 @@ impl VirtioBlockDevice {
         device_add {
             driver,
             bus,
-            id: Some(format!("virtio-{}", self.id())),
+            id: Some(self.device_id()),
             arguments: args,
         }
     }
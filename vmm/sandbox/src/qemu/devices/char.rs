@@ -23,6 +23,7 @@ use qapi::{
     Dictionary,
 };
 use sandbox_derive::CmdLineParams;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
@@ -36,7 +37,7 @@ pub const VIRT_SERIAL_PORT_DRIVER: &str = "virtserialport";
 pub const CHARBACKEND_SOCKET: &str = "socket";
 pub const CHARBACKEND_PIPE: &str = "pipe";
 
-#[derive(CmdLineParams, Debug, Clone)]
+#[derive(CmdLineParams, Debug, Clone, Serialize, Deserialize)]
 #[params("device", "chardev")]
 pub struct CharDevice {
     #[property(param = "chardev", ignore_key)]
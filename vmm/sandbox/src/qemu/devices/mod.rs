@@ -16,11 +16,15 @@ limitations under the License.
 
 use async_trait::async_trait;
 use containerd_sandbox::error::Result;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     device::{Bus, BusType, Device, Slot},
     param::ToCmdLineParams,
-    qemu::{devices::bridge::Bridge, qmp_client::QmpClient},
+    qemu::{
+        devices::{block::VirtioBlockDevice, bridge::Bridge, char::CharDevice},
+        qmp_client::QmpClient,
+    },
 };
 
 pub mod block;
@@ -59,6 +63,68 @@ pub trait QemuHotAttachable: Device + HotAttachable {}
 
 impl<T> QemuHotAttachable for T where T: Device + HotAttachable {}
 
+// HotAttachedDevice keeps the hot attached devices in a serializable form,
+// so that they can still be hot detached after the sandboxer restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HotAttachedDevice {
+    Block(VirtioBlockDevice),
+    Char(CharDevice),
+}
+
+impl From<VirtioBlockDevice> for HotAttachedDevice {
+    fn from(d: VirtioBlockDevice) -> Self {
+        Self::Block(d)
+    }
+}
+
+impl From<CharDevice> for HotAttachedDevice {
+    fn from(d: CharDevice) -> Self {
+        Self::Char(d)
+    }
+}
+
+impl Device for HotAttachedDevice {
+    fn id(&self) -> String {
+        match self {
+            Self::Block(d) => d.id(),
+            Self::Char(d) => d.id(),
+        }
+    }
+
+    fn bus(&mut self) -> Option<&mut Bus> {
+        None
+    }
+}
+
+#[async_trait]
+impl HotAttachable for HotAttachedDevice {
+    async fn execute_hot_attach(
+        &self,
+        client: &QmpClient,
+        bus_type: &BusType,
+        bus_id: &str,
+        slot_index: usize,
+    ) -> Result<()> {
+        match self {
+            Self::Block(d) => {
+                d.execute_hot_attach(client, bus_type, bus_id, slot_index)
+                    .await
+            }
+            Self::Char(d) => {
+                d.execute_hot_attach(client, bus_type, bus_id, slot_index)
+                    .await
+            }
+        }
+    }
+
+    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
+        match self {
+            Self::Block(d) => d.execute_hot_detach(client).await,
+            Self::Char(d) => d.execute_hot_detach(client).await,
+        }
+    }
+}
+
 pub fn create_bridges(count: u32, machine_type: &str) -> Vec<Bridge> {
     let (r#type, driver, bus) = match machine_type {
         crate::qemu::config::MACHINE_TYPE_VIRT => ("pcie", "pcie-pci-bridge", "pcie.0"),
@@ -122,7 +122,7 @@ impl VirtioNetDevice {
             transport,
             driver,
             id: id.to_string(),
-            vhost: false,
+            vhost: !vhostfds.is_empty(),
             vhostfds,
             fds,
             ifname: name,
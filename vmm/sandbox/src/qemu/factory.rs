@@ -68,6 +68,7 @@ impl VMFactory for QemuVMFactory {
         vm.config.name = format!("sandbox-{}", id);
         vm.config.pid_file = format!("{}/sandbox-{}.pid", s.base_dir, id);
         vm.block_driver = self.default_config.block_device_driver.clone();
+        vm.vhost_net = self.default_config.vhost_net;
 
         // set qmp socket
         vm.config.qmp_socket = Some(QmpSocket {
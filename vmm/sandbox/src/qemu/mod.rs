@@ -30,72 +30,98 @@ use containerd_sandbox::error::{Error, Result};
 use futures_util::TryFutureExt;
 use log::{debug, error, trace, warn};
 use nix::{fcntl::OFlag, libc::kill, sys::stat::Mode};
-use qapi::qmp::quit;
+use qapi::{
+    qmp::{device_add, quit},
+    Dictionary,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::OffsetDateTime;
 use tokio::{
     net::UnixStream,
     process::Child,
     sync::watch::{channel, Receiver, Sender},
     task::{spawn_blocking, JoinHandle},
-    time::sleep,
+    time::{sleep, timeout},
 };
 use unshare::Fd;
 
 use crate::{
-    device::{BusType, DeviceInfo, SlotStatus, Transport},
-    impl_recoverable,
+    device::{Bus, BusType, DeviceInfo, SlotStatus, Transport},
     param::ToCmdLineParams,
     qemu::{
-        config::{QemuConfig, VirtiofsdConfig},
+        config::{MemoryBackend, QemuConfig, VirtiofsdConfig},
         devices::{
             block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
             char::{CharDevice, VIRT_SERIAL_PORT_DRIVER},
             vfio::VfioDevice,
             vhost_user::{VhostNetDevice, VhostUserType},
             virtio_net::VirtioNetDevice,
-            QemuDevice, QemuHotAttachable,
+            HotAttachedDevice, QemuDevice, QemuHotAttachable,
         },
+        qmp::{BlockSetIoThrottle, ObjectAdd, ObjectDel, QueryCpusFast, QueryHotpluggableCpus},
         qmp_client::QmpClient,
-        utils::detect_pid,
+        utils::{detect_pid, parse_memory_in_mb},
+    },
+    utils::{
+        check_process_cmdline, open_vhost_net_fds, read_std, set_cmd_netns, wait_channel, wait_pid,
+        write_file_atomic,
     },
-    utils::{read_std, set_cmd_netns, wait_channel, wait_pid, write_file_atomic},
-    vm::{BlockDriver, Pids, VcpuThreads, VM},
+    vm::{BlockDriver, HealthError, IoLimits, Pids, VcpuThreads, VmCapabilities, VmResources, VM},
 };
 
 pub mod config;
 mod devices;
 pub mod factory;
 pub mod hooks;
+mod qmp;
 mod qmp_client;
 mod utils;
 
 pub(crate) const QEMU_START_TIMEOUT_IN_SEC: u64 = 10;
+const VCPU_UNPLUG_TIMEOUT_IN_SEC: u64 = 10;
+const MEMORY_HOTPLUG_DRIVER: &str = "pc-dimm";
 
-// restart recovery is not supported yet,
-// so we annotate the QemuVM with Serialize and Deserlize,
-// but skip all the fields serialization.
+// The devices are only used to build the qemu command line, so they are not serialized,
+// the slots of their buses are kept in `buses` once the vm is launched instead.
 #[derive(Default, Serialize, Deserialize)]
 pub struct QemuVM {
     id: String,
     config: QemuConfig,
     #[serde(skip)]
     devices: Vec<Box<dyn QemuDevice + Sync + Send>>,
-    #[serde(skip)]
-    hot_attached_devices: Vec<Box<dyn QemuHotAttachable + Sync + Send>>,
+    #[serde(default)]
+    buses: Vec<Bus>,
+    #[serde(default)]
+    hot_attached_devices: Vec<HotAttachedDevice>,
+    // qdev ids of the block devices attached by the command line
+    #[serde(default)]
+    block_devices: Vec<String>,
     #[serde(skip)]
     fds: Vec<OwnedFd>,
     console_socket: String,
     agent_socket: String,
     netns: String,
     pids: Pids,
-    #[serde(skip)]
+    #[serde(default)]
     block_driver: BlockDriver,
+    #[serde(default)]
+    vhost_net: bool,
     #[serde(skip)]
     wait_chan: Option<Receiver<(u32, i128)>>,
     #[serde(skip)]
     client: Option<QmpClient>,
     virtiofsd_config: Option<VirtiofsdConfig>,
+    #[serde(default)]
+    resources: VmResources,
+    // ids of the hotplugged cpu devices, each of them has one vcpu as smp has one thread per core
+    #[serde(default)]
+    hotplugged_vcpus: Vec<String>,
+    // ids of the hotplugged pc-dimm devices
+    #[serde(default)]
+    hotplugged_memory: Vec<String>,
+    #[serde(default)]
+    io_limits: IoLimits,
 }
 
 #[async_trait]
@@ -108,6 +134,15 @@ impl VM for QemuVM {
         }
         let wait_chan = self.launch().await?;
         self.wait_chan = Some(wait_chan);
+        self.resources = VmResources {
+            vcpus: self.config.smp.cpus,
+            memory_in_mb: parse_memory_in_mb(&self.config.memory.size).unwrap_or_default(),
+        };
+        self.buses = self
+            .devices
+            .iter_mut()
+            .filter_map(|d| d.bus().cloned())
+            .collect();
         let start_time = SystemTime::now();
         loop {
             match self.create_client().await {
@@ -126,20 +161,11 @@ impl VM for QemuVM {
             }
         }
 
-        let console_socket = self.console_socket.clone();
-        tokio::spawn(async move {
-            UnixStream::connect(&*console_socket)
-                .map_err(|e| e.into())
-                .and_then(|s| read_std(s, "console"))
-                .await
-                .unwrap_or_else(|e| {
-                    error!("failed to read console log, {}", e);
-                });
-        });
+        self.read_console();
         // update vmm related pids
         let vmm_pid = detect_pid(self.config.pid_file.as_str(), self.config.path.as_str()).await?;
         self.pids.vmm_pid = Some(vmm_pid);
-        Ok(0)
+        Ok(vmm_pid)
     }
 
     async fn stop(&mut self, force: bool) -> Result<()> {
@@ -169,16 +195,33 @@ impl VM for QemuVM {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
+                let mut device = VirtioBlockDevice::new(
                     &Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
                     &blk_info.id,
                     Some(blk_info.path),
                     blk_info.read_only,
-                );
+                )
+                .throttle(&self.io_limits);
+                // name it as the hot attached ones, so that its limits can be updated by qmp
+                device.qdev_id = Some(device.device_id());
+                self.block_devices.push(device.device_id());
                 self.attach_device(device);
             }
             DeviceInfo::Tap(tap_info) => {
+                if self.io_limits.net_limited() {
+                    warn!(
+                        "qemu does not support rate limits of network devices, {} of vm {} is not limited",
+                        tap_info.id, self.id
+                    );
+                }
                 let mut fd_ints = vec![];
+                let mut vhostfd_ints = vec![];
+                if self.vhost_net {
+                    for fd in open_vhost_net_fds(tap_info.fds.len() as u32)? {
+                        let index = self.append_fd(fd);
+                        vhostfd_ints.push(index as i32);
+                    }
+                }
                 for fd in tap_info.fds {
                     let index = self.append_fd(fd);
                     fd_ints.push(index as i32);
@@ -189,7 +232,7 @@ impl VM for QemuVM {
                     &tap_info.mac_address,
                     Transport::Pci,
                     fd_ints,
-                    vec![],
+                    vhostfd_ints,
                 );
                 self.attach_device(device);
             }
@@ -229,9 +272,16 @@ impl VM for QemuVM {
                     Some(blk_info.path),
                     blk_info.read_only,
                 );
+                let device_id = device.device_id();
                 let (bus_addr, index) = self
                     .hot_attach_device(device, self.block_driver.to_bus_type())
                     .await?;
+                if self.io_limits.block_limited() {
+                    if let Err(e) = self.throttle_block_device(&device_id).await {
+                        self.hot_detach(&blk_info.id).await?;
+                        return Err(e);
+                    }
+                }
                 let addr = match self.block_driver {
                     BlockDriver::VirtioBlk => {
                         format!("0000:{}:{:02x}.0", bus_addr, index)
@@ -298,9 +348,19 @@ impl VM for QemuVM {
         Ok(())
     }
 
-    async fn ping(&self) -> Result<()> {
+    async fn ping(&self, t: Duration) -> Result<()> {
         let client = self.get_client()?;
-        let _res = client.execute(qapi::qmp::query_status {}).await?;
+        let status = timeout(t, client.execute(qapi::qmp::query_status {}))
+            .await
+            .map_err(|_| {
+                HealthError::VmmUnresponsive(format!("query-status timeout after {:?}", t))
+            })?
+            .map_err(|e| HealthError::VmmUnresponsive(format!("query-status failed: {}", e)))?;
+        if !status.running {
+            return Err(
+                HealthError::GuestNotRunning(format!("vm status is {:?}", status.status)).into(),
+            );
+        }
         Ok(())
     }
 
@@ -313,15 +373,77 @@ impl VM for QemuVM {
     }
 
     async fn vcpus(&self) -> Result<VcpuThreads> {
-        // TODO: support get vcpu threads id
-        Ok(VcpuThreads {
-            vcpus: HashMap::new(),
-        })
+        let client = self.get_client()?;
+        let result = client.execute(QueryCpusFast {}).await?;
+        let vcpus: HashMap<i64, i64> = result
+            .iter()
+            .map(|cpu| (cpu.cpu_index, cpu.thread_id))
+            .collect();
+        Ok(VcpuThreads { vcpus })
     }
 
     fn pids(&self) -> Pids {
-        // TODO: support get all vmm related pids
-        Pids::default()
+        self.pids.clone()
+    }
+
+    fn resources(&self) -> VmResources {
+        self.resources
+    }
+
+    fn boot_vcpus(&self) -> u32 {
+        self.config.smp.cpus
+    }
+
+    async fn resize(&mut self, resources: VmResources) -> Result<VmResources> {
+        let VmResources {
+            vcpus,
+            memory_in_mb,
+        } = self.target_resources(resources);
+        if vcpus > self.resources.vcpus {
+            self.hotplug_vcpus(vcpus - self.resources.vcpus).await?;
+        } else if vcpus < self.resources.vcpus {
+            self.unplug_vcpus(self.resources.vcpus - vcpus).await?;
+        }
+
+        if memory_in_mb > self.resources.memory_in_mb {
+            self.hotplug_memory(memory_in_mb - self.resources.memory_in_mb)
+                .await?;
+        } else if memory_in_mb < self.resources.memory_in_mb {
+            warn!(
+                "memory of vm {} can not be reduced from {}M to {}M",
+                self.id, self.resources.memory_in_mb, memory_in_mb
+            );
+        }
+        Ok(self.resources)
+    }
+
+    async fn set_io_limits(&mut self, limits: IoLimits) -> Result<()> {
+        let old = std::mem::replace(&mut self.io_limits, limits);
+        if self.client.is_none()
+            || (limits.block_iops == old.block_iops
+                && limits.block_bandwidth == old.block_bandwidth)
+        {
+            return Ok(());
+        }
+        if let Err(e) = self.throttle_block_devices().await {
+            // roll back the devices which are already throttled
+            self.io_limits = old;
+            self.throttle_block_devices()
+                .await
+                .unwrap_or_else(|re| warn!("roll back in set io limits of vm {}: {}", self.id, re));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> VmCapabilities {
+        VmCapabilities {
+            // tap and vfio devices are not hot plugged by `hot_attach` yet
+            net_hot_plug: false,
+            // the network devices are not rate limited by qemu
+            net_io_limits_update: false,
+            ..Default::default()
+        }
     }
 }
 
@@ -331,16 +453,23 @@ impl QemuVM {
             id: id.to_string(),
             config: QemuConfig::default(),
             devices: vec![],
+            buses: vec![],
             hot_attached_devices: vec![],
+            block_devices: vec![],
             fds: vec![],
             console_socket: format!("{}/console.sock", base_dir),
             agent_socket: "".to_string(),
             netns: netns.to_string(),
             pids: Pids::default(),
             block_driver: BlockDriver::default(),
+            vhost_net: false,
             wait_chan: None,
             client: None,
             virtiofsd_config: None,
+            resources: VmResources::default(),
+            hotplugged_vcpus: vec![],
+            hotplugged_memory: vec![],
+            io_limits: IoLimits::default(),
         }
     }
 
@@ -353,6 +482,19 @@ impl QemuVM {
         self.fds.len() - 1 + 3
     }
 
+    fn read_console(&self) {
+        let console_socket = self.console_socket.clone();
+        tokio::spawn(async move {
+            UnixStream::connect(&*console_socket)
+                .map_err(|e| e.into())
+                .and_then(|s| read_std(s, "console"))
+                .await
+                .unwrap_or_else(|e| {
+                    error!("failed to read console log, {}", e);
+                });
+        });
+    }
+
     async fn launch(&mut self) -> Result<Receiver<(u32, i128)>> {
         let mut params = self.config.to_cmdline_params("-");
         for d in self.devices.iter() {
@@ -481,7 +623,138 @@ impl QemuVM {
         Ok(pid)
     }
 
-    async fn hot_attach_device<T: QemuHotAttachable + Sync + Send + 'static>(
+    // target_resources clamps the resources that the pod asks for between
+    // the boot and the max resources of the vm.
+    fn target_resources(&self, resources: VmResources) -> VmResources {
+        let boot_vcpus = self.config.smp.cpus;
+        let max_vcpus = self.config.smp.max_cpus.max(boot_vcpus);
+        let boot_memory_in_mb = parse_memory_in_mb(&self.config.memory.size).unwrap_or_default();
+        let max_memory_in_mb =
+            parse_memory_in_mb(&self.config.memory.max_mem).unwrap_or(boot_memory_in_mb);
+        VmResources {
+            vcpus: resources.vcpus.clamp(boot_vcpus, max_vcpus),
+            memory_in_mb: resources
+                .memory_in_mb
+                .clamp(boot_memory_in_mb, max_memory_in_mb.max(boot_memory_in_mb)),
+        }
+    }
+
+    async fn hotplug_vcpus(&mut self, count: u32) -> Result<()> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(|| anyhow!("qmp client is not init"))?;
+        let cpus = client.execute(QueryHotpluggableCpus {}).await?;
+        let mut index = self.hotplugged_vcpus.len();
+        for cpu in cpus
+            .into_iter()
+            .filter(|c| c.qom_path.is_none())
+            .take(count as usize)
+        {
+            let id = loop {
+                let id = format!("vcpu-hp{}", index);
+                index += 1;
+                if !self.hotplugged_vcpus.contains(&id) {
+                    break id;
+                }
+            };
+            debug!("hotplug vcpu {} to vm {}", id, self.id);
+            client
+                .execute(device_add {
+                    driver: cpu.driver,
+                    bus: None,
+                    id: Some(id.clone()),
+                    arguments: cpu.props,
+                })
+                .await?;
+            self.hotplugged_vcpus.push(id);
+            self.resources.vcpus += 1;
+        }
+        Ok(())
+    }
+
+    async fn unplug_vcpus(&mut self, count: u32) -> Result<()> {
+        for _ in 0..count {
+            let id = match self.hotplugged_vcpus.last() {
+                None => break,
+                Some(id) => id.to_string(),
+            };
+            debug!("unplug vcpu {} from vm {}", id, self.id);
+            let client = self.get_client()?;
+            timeout(
+                Duration::from_secs(VCPU_UNPLUG_TIMEOUT_IN_SEC),
+                client.delete_device(&id),
+            )
+            .await
+            .map_err(|_| anyhow!("timeout to unplug vcpu {}", id))??;
+            self.hotplugged_vcpus.pop();
+            self.resources.vcpus -= 1;
+        }
+        Ok(())
+    }
+
+    async fn hotplug_memory(&mut self, size_in_mb: u64) -> Result<()> {
+        if self.hotplugged_memory.len() >= self.config.memory.slots as usize {
+            return Err(Error::ResourceExhausted(format!(
+                "memory slots of vm {}",
+                self.id
+            )));
+        }
+        let id = format!("dimm-hp{}", self.hotplugged_memory.len());
+        let backend_id = format!("{}-backend", id);
+        let mut props = Dictionary::new();
+        props.insert("size".to_string(), Value::from(size_in_mb * 1024 * 1024));
+        props.insert("share".to_string(), Value::from(self.config.memory.shared));
+        props.insert(
+            "prealloc".to_string(),
+            Value::from(self.config.memory.pre_alloc),
+        );
+        let qom_type = match &self.config.memory.backend_type {
+            MemoryBackend::Ram => "memory-backend-ram",
+            MemoryBackend::File(f) => {
+                props.insert("mem-path".to_string(), Value::from(f.to_string()));
+                "memory-backend-file"
+            }
+        };
+
+        debug!("hotplug memory {}M to vm {}", size_in_mb, self.id);
+        let client = self.get_client()?;
+        client
+            .execute(ObjectAdd {
+                qom_type: qom_type.to_string(),
+                id: backend_id.clone(),
+                props,
+            })
+            .await?;
+        let mut arguments = Dictionary::new();
+        arguments.insert("memdev".to_string(), Value::from(backend_id.clone()));
+        if let Err(e) = client
+            .execute(device_add {
+                driver: MEMORY_HOTPLUG_DRIVER.to_string(),
+                bus: None,
+                id: Some(id.clone()),
+                arguments,
+            })
+            .await
+        {
+            client
+                .execute(ObjectDel { id: backend_id })
+                .await
+                .unwrap_or_else(|e| {
+                    error!(
+                        "failed to delete memory backend after device_add failed, {}",
+                        e
+                    );
+                    qapi::Empty {}
+                });
+            return Err(e);
+        }
+        self.hotplugged_memory.push(id);
+        self.resources.memory_in_mb += size_in_mb;
+        Ok(())
+    }
+
+    async fn hot_attach_device<T: QemuHotAttachable + Into<HotAttachedDevice> + Sync + Send>(
         &mut self,
         device: T,
         bus_type: BusType,
@@ -501,16 +774,41 @@ impl QemuVM {
                 return Err(e);
             }
         };
-        self.hot_attached_devices.push(Box::new(device));
+        self.hot_attached_devices.push(device.into());
         Ok((bus_addr, index))
     }
 
+    // throttle_block_devices sets the rate limits of the vm to all the block devices,
+    // both the ones attached by the command line and the hot attached ones.
+    async fn throttle_block_devices(&self) -> Result<()> {
+        let hot_attached = self.hot_attached_devices.iter().filter_map(|d| match d {
+            HotAttachedDevice::Block(b) => Some(b.device_id()),
+            _ => None,
+        });
+        for id in self.block_devices.iter().cloned().chain(hot_attached) {
+            self.throttle_block_device(&id).await?;
+        }
+        Ok(())
+    }
+
+    // throttle_block_device sets the rate limits of the vm to the block device
+    async fn throttle_block_device(&self, device_id: &str) -> Result<()> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(|| anyhow!("qmp client is not init"))?;
+        debug!(
+            "set io limits {:?} of block device {}",
+            self.io_limits, device_id
+        );
+        client
+            .execute(BlockSetIoThrottle::new(device_id, &self.io_limits))
+            .await?;
+        Ok(())
+    }
+
     fn empty_slot(&mut self, bus_type: BusType) -> Result<(String, usize)> {
-        for b in self
-            .devices
-            .iter_mut()
-            .filter_map(|x| x.bus().filter(|b| b.r#type == bus_type))
-        {
+        for b in self.buses.iter_mut().filter(|b| b.r#type == bus_type) {
             let res = b.empty_slot();
             if let Some(index) = res {
                 return Ok((b.id.to_string(), index));
@@ -526,9 +824,8 @@ impl QemuVM {
         device_id: &str,
     ) -> Result<(String, usize)> {
         let bus = self
-            .devices
+            .buses
             .iter_mut()
-            .filter_map(|x| x.bus())
             .find(|b| b.id == bus_id)
             .ok_or_else(|| anyhow!("can not get bus by id {}", bus_id))?;
         if let Some(s) = bus.slots.get_mut(index) {
@@ -538,21 +835,18 @@ impl QemuVM {
     }
 
     fn detach_from_bus(&mut self, device_id: &str) {
-        self.devices
-            .iter_mut()
-            .filter_map(|x| x.bus())
-            .for_each(|b| {
-                if let Some(x) = b.slots.iter_mut().find(|s| {
-                    if let SlotStatus::Occupied(id) = &s.status {
-                        if id == device_id {
-                            return true;
-                        }
+        self.buses.iter_mut().for_each(|b| {
+            if let Some(x) = b.slots.iter_mut().find(|s| {
+                if let SlotStatus::Occupied(id) = &s.status {
+                    if id == device_id {
+                        return true;
                     }
-                    false
-                }) {
-                    x.status = SlotStatus::Empty;
                 }
-            });
+                false
+            }) {
+                x.status = SlotStatus::Empty;
+            }
+        });
     }
 }
 
@@ -611,4 +905,95 @@ fn spawn_wait(
     })
 }
 
-impl_recoverable!(QemuVM);
+#[async_trait]
+impl crate::vm::Recoverable for QemuVM {
+    async fn recover(&mut self) -> Result<()> {
+        // make sure the processes are still the ones we started before adopting them
+        let pid = self.pid()?;
+        check_process_cmdline(pid, &self.config.path)?;
+        if let Some(virtiofsd_config) = &self.virtiofsd_config {
+            for affiliated_pid in self.pids.affiliated_pids.iter() {
+                check_process_cmdline(*affiliated_pid, &virtiofsd_config.path)?;
+            }
+        }
+
+        self.client = Some(self.create_client().await?);
+        let (tx, rx) = channel((0u32, 0i128));
+        tokio::spawn(async move {
+            let wait_result = wait_pid(pid as i32).await;
+            tx.send(wait_result).unwrap_or_default();
+        });
+        self.wait_chan = Some(rx);
+        self.read_console();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        device::{BlockDeviceInfo, DeviceInfo},
+        param::ToCmdLineParams,
+        qemu::QemuVM,
+        vm::{IoLimits, VmResources, VM},
+    };
+
+    #[test]
+    fn test_target_resources() {
+        let mut vm = QemuVM::default();
+        vm.config.smp.cpus = 1;
+        vm.config.smp.max_cpus = 4;
+        vm.config.memory.size = "2048M".to_string();
+        vm.config.memory.max_mem = "4G".to_string();
+
+        let cases = [
+            ((0, 0), (1, 2048)),
+            ((2, 3072), (2, 3072)),
+            ((8, 8192), (4, 4096)),
+        ];
+        for ((vcpus, memory_in_mb), (expected_vcpus, expected_memory_in_mb)) in cases {
+            let res = vm.target_resources(VmResources {
+                vcpus,
+                memory_in_mb,
+            });
+            assert_eq!(res.vcpus, expected_vcpus);
+            assert_eq!(res.memory_in_mb, expected_memory_in_mb);
+        }
+
+        // no hotplug if the max resources are not set
+        vm.config.smp.max_cpus = 0;
+        vm.config.memory.max_mem = "".to_string();
+        let res = vm.target_resources(VmResources {
+            vcpus: 2,
+            memory_in_mb: 3072,
+        });
+        assert_eq!(res.vcpus, 1);
+        assert_eq!(res.memory_in_mb, 2048);
+    }
+
+    #[tokio::test]
+    async fn test_attach_throttled_block_device() {
+        let mut vm = QemuVM::default();
+        vm.set_io_limits(IoLimits {
+            block_iops: 1000,
+            block_bandwidth: 0,
+            net_bandwidth: 0,
+        })
+        .await
+        .unwrap();
+        vm.attach(DeviceInfo::Block(BlockDeviceInfo {
+            id: "blk1".to_string(),
+            path: "/dev/test".to_string(),
+            read_only: false,
+        }))
+        .await
+        .unwrap();
+
+        // the device attached by the command line is throttled by its qdev id later
+        assert_eq!(vm.block_devices, vec!["virtio-blk1".to_string()]);
+        let params = vm.devices[0].to_cmdline_params("-").join(" ");
+        assert!(params.contains("drive=blk1,id=virtio-blk1"));
+        assert!(params.contains("throttling.iops-total=1000"));
+        assert!(!params.contains("throttling.bps-total"));
+    }
+}
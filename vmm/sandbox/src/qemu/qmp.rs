@@ -0,0 +1,217 @@
+/*
+Copyright 2022 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use qapi::{qmp::QmpCommand, Dictionary};
+use serde::{Deserialize, Serialize};
+
+use crate::vm::IoLimits;
+
+// The target specific fields of `CpuInfoFast` differ between qemu versions,
+// only the common fields are declared here so that the response can always be parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryCpusFast {}
+
+impl QmpCommand for QueryCpusFast {}
+impl ::qapi_spec::Command for QueryCpusFast {
+    const NAME: &'static str = "query-cpus-fast";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = Vec<CpuInfoFast>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpuInfoFast {
+    #[serde(rename = "cpu-index")]
+    pub cpu_index: i64,
+    #[serde(rename = "qom-path")]
+    pub qom_path: String,
+    #[serde(rename = "thread-id")]
+    pub thread_id: i64,
+    #[serde(rename = "target", default)]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryHotpluggableCpus {}
+
+impl QmpCommand for QueryHotpluggableCpus {}
+impl ::qapi_spec::Command for QueryHotpluggableCpus {
+    const NAME: &'static str = "query-hotpluggable-cpus";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = Vec<HotpluggableCpu>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotpluggableCpu {
+    #[serde(rename = "type")]
+    pub driver: String,
+    #[serde(rename = "vcpus-count")]
+    pub vcpus_count: i64,
+    // the properties differ between architectures, they are passed to device_add as they are
+    #[serde(rename = "props")]
+    pub props: Dictionary,
+    // the cpu is plugged if qom-path is present
+    #[serde(rename = "qom-path", default)]
+    pub qom_path: Option<String>,
+}
+
+// object-add with the properties of the object flattened, which is supported since qemu 6.0
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectAdd {
+    #[serde(rename = "qom-type")]
+    pub qom_type: String,
+    #[serde(rename = "id")]
+    pub id: String,
+    #[serde(flatten)]
+    pub props: Dictionary,
+}
+
+impl QmpCommand for ObjectAdd {}
+impl ::qapi_spec::Command for ObjectAdd {
+    const NAME: &'static str = "object-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectDel {
+    #[serde(rename = "id")]
+    pub id: String,
+}
+
+impl QmpCommand for ObjectDel {}
+impl ::qapi_spec::Command for ObjectDel {
+    const NAME: &'static str = "object-del";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+// block_set_io_throttle limits the block device found by its qdev id,
+// the device is not throttled anymore if all the limits are zero.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockSetIoThrottle {
+    #[serde(rename = "id")]
+    pub id: String,
+    #[serde(rename = "bps")]
+    pub bps: u64,
+    #[serde(rename = "bps_rd")]
+    pub bps_rd: u64,
+    #[serde(rename = "bps_wr")]
+    pub bps_wr: u64,
+    #[serde(rename = "iops")]
+    pub iops: u64,
+    #[serde(rename = "iops_rd")]
+    pub iops_rd: u64,
+    #[serde(rename = "iops_wr")]
+    pub iops_wr: u64,
+}
+
+impl BlockSetIoThrottle {
+    pub fn new(id: &str, limits: &IoLimits) -> Self {
+        Self {
+            id: id.to_string(),
+            bps: limits.block_bandwidth,
+            bps_rd: 0,
+            bps_wr: 0,
+            iops: limits.block_iops,
+            iops_rd: 0,
+            iops_wr: 0,
+        }
+    }
+}
+
+impl QmpCommand for BlockSetIoThrottle {}
+impl ::qapi_spec::Command for BlockSetIoThrottle {
+    const NAME: &'static str = "block_set_io_throttle";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[cfg(test)]
+mod tests {
+    use qapi::Dictionary;
+    use serde_json::Value;
+
+    use super::{BlockSetIoThrottle, CpuInfoFast, HotpluggableCpu, ObjectAdd};
+    use crate::vm::IoLimits;
+
+    #[test]
+    fn test_deserialize_cpu_info_fast() {
+        let resp = r#"[
+            {"thread-id": 25627, "props": {"core-id": 0, "thread-id": 0, "socket-id": 0},
+             "qom-path": "/machine/unattached/device[0]", "cpu-index": 0, "target": "x86_64"},
+            {"thread-id": 25628, "props": {"core-id": 0, "thread-id": 0, "socket-id": 1},
+             "qom-path": "/machine/unattached/device[2]", "cpu-index": 1, "target": "x86_64"}
+        ]"#;
+        let cpus: Vec<CpuInfoFast> = serde_json::from_str(resp).unwrap();
+        assert_eq!(cpus.len(), 2);
+        assert_eq!(cpus[0].cpu_index, 0);
+        assert_eq!(cpus[0].thread_id, 25627);
+        assert_eq!(cpus[1].cpu_index, 1);
+        assert_eq!(cpus[1].thread_id, 25628);
+        assert_eq!(cpus[1].target.as_deref(), Some("x86_64"));
+    }
+
+    #[test]
+    fn test_deserialize_hotpluggable_cpus() {
+        let resp = r#"[
+            {"props": {"core-id": 0, "thread-id": 0, "socket-id": 1}, "vcpus-count": 1,
+             "type": "host-x86_64-cpu"},
+            {"props": {"core-id": 0, "thread-id": 0, "socket-id": 0}, "vcpus-count": 1,
+             "qom-path": "/machine/unattached/device[0]", "type": "host-x86_64-cpu"}
+        ]"#;
+        let cpus: Vec<HotpluggableCpu> = serde_json::from_str(resp).unwrap();
+        assert_eq!(cpus.len(), 2);
+        assert_eq!(cpus[0].driver, "host-x86_64-cpu");
+        assert!(cpus[0].qom_path.is_none());
+        assert_eq!(cpus[0].props.get("socket-id"), Some(&Value::from(1)));
+        assert!(cpus[1].qom_path.is_some());
+    }
+
+    #[test]
+    fn test_serialize_object_add() {
+        let mut props = Dictionary::new();
+        props.insert("size".to_string(), Value::from(1073741824u64));
+        let cmd = ObjectAdd {
+            qom_type: "memory-backend-ram".to_string(),
+            id: "mem0-backend".to_string(),
+            props,
+        };
+        let value = serde_json::to_value(&cmd).unwrap();
+        assert_eq!(value["qom-type"], "memory-backend-ram");
+        assert_eq!(value["id"], "mem0-backend");
+        assert_eq!(value["size"], 1073741824u64);
+    }
+
+    #[test]
+    fn test_serialize_block_set_io_throttle() {
+        let limits = IoLimits {
+            block_iops: 1000,
+            block_bandwidth: 1048576,
+            net_bandwidth: 0,
+        };
+        let value = serde_json::to_value(BlockSetIoThrottle::new("virtio-blk1", &limits)).unwrap();
+        assert_eq!(value["id"], "virtio-blk1");
+        assert_eq!(value["iops"], 1000);
+        assert_eq!(value["bps"], 1048576);
+        assert_eq!(value["iops_rd"], 0);
+        assert_eq!(value["bps_wr"], 0);
+    }
+}
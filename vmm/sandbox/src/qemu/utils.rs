@@ -21,6 +21,18 @@ use containerd_sandbox::error::{Error, Result};
 
 use crate::utils::read_file;
 
+// parse_memory_in_mb parses the memory size in qemu command line, like "2048M" or "2G".
+pub(crate) fn parse_memory_in_mb(size: &str) -> Option<u64> {
+    let size = size.trim();
+    if let Some(s) = size.strip_suffix(['G', 'g']) {
+        return s.parse::<u64>().ok().map(|x| x * 1024);
+    }
+    size.strip_suffix(['M', 'm'])
+        .unwrap_or(size)
+        .parse::<u64>()
+        .ok()
+}
+
 pub(crate) async fn detect_pid(path: &str, bin_path: &str) -> Result<u32> {
     let mut err = None;
     for _i in 0..1000 {
@@ -60,3 +72,16 @@ pub(crate) async fn detect_pid(path: &str, bin_path: &str) -> Result<u32> {
 
     Err(anyhow!("timeout waiting for the pid file, err: {:?}", err).into())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::qemu::utils::parse_memory_in_mb;
+
+    #[test]
+    fn test_parse_memory_in_mb() {
+        assert_eq!(parse_memory_in_mb("2048M"), Some(2048));
+        assert_eq!(parse_memory_in_mb("2G"), Some(2048));
+        assert_eq!(parse_memory_in_mb("512"), Some(512));
+        assert_eq!(parse_memory_in_mb("abcM"), None);
+    }
+}
@@ -14,13 +14,13 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, io::ErrorKind, path::Path, sync::Arc};
+use std::{collections::HashMap, io::ErrorKind, path::Path, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
 use containerd_sandbox::{
     cri::api::v1::NamespaceMode,
-    data::SandboxData,
+    data::{ContainerData, SandboxData},
     error::{Error, Result},
     signal::ExitSignal,
     utils::cleanup_mounts,
@@ -33,31 +33,40 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::{
     fs::{copy, create_dir_all, remove_dir_all, OpenOptions},
     io::{AsyncReadExt, AsyncWriteExt},
-    sync::{Mutex, RwLock},
+    sync::{mpsc::channel, Mutex, RwLock},
 };
 use tracing::instrument;
 use ttrpc::context::with_timeout;
 use vmm_common::{
     api::{empty::Empty, sandbox::SetupSandboxRequest, sandbox_ttrpc::SandboxServiceClient},
     storage::Storage,
+    ANNOTATION_KEY_BLOCK_BANDWIDTH, ANNOTATION_KEY_BLOCK_IOPS, ANNOTATION_KEY_NET_BANDWIDTH,
     ETC_HOSTS, ETC_RESOLV, HOSTNAME_FILENAME, HOSTS_FILENAME, RESOLV_FILENAME, SHARED_DIR_SUFFIX,
 };
 
 use crate::{
     cgroup::{SandboxCgroup, DEFAULT_CGROUP_PARENT_PATH},
-    client::{client_check, client_setup_sandbox, client_sync_clock, new_sandbox_client},
-    container::KuasarContainer,
-    network::{Network, NetworkConfig},
+    client::{
+        client_check, client_ping, client_setup_sandbox, client_sync_clock,
+        client_update_interfaces, client_update_neighbours, client_update_routes,
+        new_sandbox_client, publish_event,
+    },
+    container::{remove_block_share_image, KuasarContainer},
+    network::{NetnsWatcher, Network, NetworkConfig},
     utils::{get_dns_config, get_hostname, get_resources, get_sandbox_cgroup_parent_path},
-    vm::{Hooks, Recoverable, VMFactory, VM},
+    vm::{Hooks, IoLimits, Recoverable, VMFactory, VmResources, VM},
 };
 
 pub const KUASAR_GUEST_SHARE_DIR: &str = "/run/kuasar/storage/containers/";
+// The max number of events fetched from the guest but not yet published to containerd,
+// the sandboxer stops fetching events from the guest until they are published.
+const EVENT_FORWARD_BUFFER_SIZE: usize = 128;
+const DEFAULT_PING_TIMEOUT_IN_MS: u64 = 3000;
+const DEFAULT_CHECKPOINT_DIR: &str = "/var/lib/kuasar/checkpoints";
 
 pub struct KuasarSandboxer<F: VMFactory, H: Hooks<F::VM>> {
     factory: F,
     hooks: H,
-    #[allow(dead_code)]
     config: SandboxConfig,
     #[allow(clippy::type_complexity)]
     sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<F::VM>>>>>>,
@@ -102,13 +111,19 @@ where
                 debug!("recovering sandbox {:?}", entry.file_name());
                 let path = Path::new(dir).join(entry.file_name());
                 match KuasarSandbox::recover(&path).await {
-                    Ok(sb) => {
+                    Ok(mut sb) => {
+                        sb.ping_timeout = self.config.ping_timeout();
+                        sb.net_queues = self.config.net_queues;
+                        sb.default_io_limits = self.config.io_limits;
+                        sb.checkpoint_dir = self.config.checkpoint_dir.clone();
+                        sb.enable_block_share = self.config.enable_block_share;
                         let status = sb.status.clone();
                         let sb_mutex = Arc::new(Mutex::new(sb));
                         // Only running sandbox should be monitored.
                         if let SandboxStatus::Running(_) = status {
                             let sb_clone = sb_mutex.clone();
                             monitor(sb_clone);
+                            watch_network(sb_mutex.clone());
                         }
                         self.sandboxes
                             .write()
@@ -145,6 +160,32 @@ pub struct KuasarSandbox<V: VM> {
     pub(crate) exit_signal: Arc<ExitSignal>,
     #[serde(default)]
     pub(crate) sandbox_cgroups: SandboxCgroup,
+    #[serde(skip, default)]
+    pub(crate) ping_timeout: PingTimeout,
+    #[serde(skip, default)]
+    pub(crate) net_queues: u32,
+    #[serde(skip, default)]
+    pub(crate) default_io_limits: IoLimits,
+    #[serde(skip, default)]
+    pub(crate) checkpoint_dir: String,
+    #[serde(skip, default)]
+    pub(crate) enable_block_share: bool,
+}
+
+/// `PingTimeout` is the timeouts of the liveness probe of a sandbox.
+#[derive(Debug, Clone, Copy)]
+pub struct PingTimeout {
+    pub vm: Duration,
+    pub agent: Duration,
+}
+
+impl Default for PingTimeout {
+    fn default() -> Self {
+        Self {
+            vm: Duration::from_millis(DEFAULT_PING_TIMEOUT_IN_MS),
+            agent: Duration::from_millis(DEFAULT_PING_TIMEOUT_IN_MS),
+        }
+    }
 }
 
 #[async_trait]
@@ -165,22 +206,21 @@ where
         let mut sandbox_cgroups = SandboxCgroup::default();
         let cgroup_parent_path = get_sandbox_cgroup_parent_path(&s.sandbox)
             .unwrap_or(DEFAULT_CGROUP_PARENT_PATH.to_string());
-        // Currently only support cgroup V1, cgroup V2 is not supported now
-        if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
-            // Create sandbox's cgroup and apply sandbox's resources limit
-            let create_and_update_sandbox_cgroup = (|| {
-                sandbox_cgroups =
-                    SandboxCgroup::create_sandbox_cgroups(&cgroup_parent_path, &s.sandbox.id)?;
-                sandbox_cgroups.update_res_for_sandbox_cgroups(&s.sandbox)?;
-                Ok(())
-            })();
-            // If create and update sandbox cgroup failed, do rollback operation
-            if let Err(e) = create_and_update_sandbox_cgroup {
-                let _ = sandbox_cgroups.remove_sandbox_cgroups();
-                return Err(e);
-            }
+        // Create sandbox's cgroup and apply sandbox's resources limit
+        let create_and_update_sandbox_cgroup = (|| {
+            sandbox_cgroups =
+                SandboxCgroup::create_sandbox_cgroups(&cgroup_parent_path, &s.sandbox.id)?;
+            sandbox_cgroups.update_res_for_sandbox_cgroups(&s.sandbox)?;
+            Ok(())
+        })();
+        // If create and update sandbox cgroup failed, do rollback operation
+        if let Err(e) = create_and_update_sandbox_cgroup {
+            let _ = sandbox_cgroups.remove_sandbox_cgroups();
+            return Err(e);
         }
-        let vm = self.factory.create_vm(id, &s).await?;
+        let mut vm = self.factory.create_vm(id, &s).await?;
+        vm.set_io_limits(get_io_limits(&s.sandbox, &self.config.io_limits))
+            .await?;
         let mut sandbox = KuasarSandbox {
             vm,
             id: id.to_string(),
@@ -194,6 +234,11 @@ where
             client: Arc::new(Mutex::new(None)),
             exit_signal: Arc::new(ExitSignal::default()),
             sandbox_cgroups,
+            ping_timeout: self.config.ping_timeout(),
+            net_queues: self.config.net_queues,
+            default_io_limits: self.config.io_limits,
+            checkpoint_dir: self.config.checkpoint_dir.clone(),
+            enable_block_share: self.config.enable_block_share,
         };
 
         // setup sandbox files: hosts, hostname and resolv.conf for guest
@@ -225,6 +270,7 @@ where
 
         let sandbox_clone = sandbox_mutex.clone();
         monitor(sandbox_clone);
+        watch_network(sandbox_mutex.clone());
 
         if let Err(e) = sandbox.add_to_cgroup().await {
             if let Err(re) = sandbox.stop(true).await {
@@ -260,6 +306,9 @@ where
     async fn update(&self, id: &str, data: SandboxData) -> Result<()> {
         let sandbox_mutex = self.sandbox(id).await?;
         let mut sandbox = sandbox_mutex.lock().await;
+        // the io limits may be rejected, update them first so that nothing is changed then
+        sandbox.update_io_limits(&data).await?;
+        sandbox.update_resources(&data).await;
         sandbox.data = data;
         sandbox.dump().await?;
         Ok(())
@@ -294,11 +343,8 @@ where
             let mut sb = sb_mutex.lock().await;
             sb.stop(true).await?;
 
-            // Currently only support cgroup V1, cgroup V2 is not supported now
-            if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
-                // remove the sandbox cgroups
-                sb.sandbox_cgroups.remove_sandbox_cgroups()?;
-            }
+            // remove the sandbox cgroups
+            sb.sandbox_cgroups.remove_sandbox_cgroups()?;
 
             cleanup_mounts(&sb.base_dir).await?;
             // Should Ignore the NotFound error of base dir as it may be already deleted.
@@ -327,7 +373,14 @@ where
 
     #[instrument(skip_all)]
     async fn ping(&self) -> Result<()> {
-        self.vm.ping().await
+        self.vm.ping(self.ping_timeout.vm).await?;
+        // the ping may take as long as the timeout, so do not hold the lock of the client
+        // while waiting, or all the other requests to the agent are blocked.
+        let client = self.client.lock().await.clone();
+        if let Some(client) = client {
+            client_ping(&client, self.ping_timeout.agent).await?;
+        }
+        Ok(())
     }
 
     #[instrument(skip_all)]
@@ -349,8 +402,35 @@ where
 
     #[instrument(skip_all)]
     async fn update_container(&mut self, id: &str, options: ContainerOption) -> Result<()> {
+        let old = get_container_resources(&self.container(id).await?.data);
+        let new = get_container_resources(&options.container);
+        let mut new_resources = options
+            .container
+            .spec
+            .as_ref()
+            .and_then(|s| s.linux.as_ref())
+            .and_then(|l| l.resources.clone());
         let handler_chain = self.container_update_handlers(id, options).await?;
         handler_chain.handle(self).await?;
+        // the pod resources are the sum of the resources of its containers,
+        // so resizing a container resizes the pod and the vm by the same difference.
+        if old != new {
+            if let Some(r) = new_resources.as_mut().and_then(|r| r.cpu.as_mut()) {
+                r.cpus = "".to_string();
+            }
+            if let Some(l) = self
+                .containers
+                .get_mut(id)
+                .and_then(|c| c.data.spec.as_mut())
+                .and_then(|s| s.linux.as_mut())
+            {
+                l.resources = new_resources;
+            }
+            let mut data = self.data.clone();
+            resize_pod_resources(&mut data, old, new);
+            self.update_resources(&data).await;
+            self.data = data;
+        }
         self.dump().await?;
         Ok(())
     }
@@ -373,6 +453,10 @@ where
                 for device_id in c.io_devices {
                     self.vm.hot_detach(&device_id).await?;
                 }
+                if let Some(device_id) = c.block_share {
+                    self.vm.hot_detach(&device_id).await?;
+                    remove_block_share_image(&self.base_dir, id).await;
+                }
             }
         }
         self.dump().await?;
@@ -582,6 +666,9 @@ where
 
                 // Set routes
                 req.routes = network.routes().iter().map(|x| x.into()).collect();
+
+                // Set neighbours
+                req.neighbours = network.neighbours().iter().map(|x| x.into()).collect();
             }
 
             client_setup_sandbox(client, &req).await?;
@@ -652,28 +739,144 @@ where
         format!("{}/{}", self.base_dir, SHARED_DIR_SUFFIX)
     }
 
+    // update_resources resizes the vm and the sandbox cgroups by the difference between
+    // the current pod resources and the new ones, which makes in-place pod resize possible.
+    // It is best-effort, the hypervisor may not hot plug all or any of the resources,
+    // and the vm is left with what it has if it fails, so that the pod is still updated.
     #[instrument(skip_all)]
-    pub async fn prepare_network(&mut self) -> Result<()> {
-        // get vcpu for interface queue, at least one vcpu
-        let mut vcpu = 1;
-        if let Some(resources) = get_resources(&self.data) {
-            if resources.cpu_period > 0 && resources.cpu_quota > 0 {
-                // get ceil of cpus if it is not integer
-                let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil();
-                vcpu = base as u32;
+    async fn update_resources(&mut self, data: &SandboxData) {
+        let old = get_vm_resources(&self.data);
+        let new = get_vm_resources(data);
+        if old == new {
+            return;
+        }
+        let current = self.vm.resources();
+        let running = matches!(self.status, SandboxStatus::Running(_)) && current.vcpus > 0;
+        let target = VmResources {
+            vcpus: (current.vcpus + new.vcpus).saturating_sub(old.vcpus),
+            memory_in_mb: (current.memory_in_mb + new.memory_in_mb)
+                .saturating_sub(old.memory_in_mb),
+        };
+        // grow the cgroups before hotplug and shrink them after unplug,
+        // so that the vmm threads are never limited below what the vm has.
+        let grow = new.vcpus >= old.vcpus && new.memory_in_mb >= old.memory_in_mb;
+        if grow {
+            self.update_sandbox_cgroups(data);
+        }
+        if running {
+            match self.vm.resize(target).await {
+                Ok(resized) => info!(
+                    "resize vm of sandbox {} from {:?} to {:?}",
+                    self.id, current, resized
+                ),
+                Err(e) => warn!(
+                    "failed to resize vm of sandbox {} from {:?} to {:?}, it has {:?}: {}",
+                    self.id,
+                    current,
+                    target,
+                    self.vm.resources(),
+                    e
+                ),
+            }
+        }
+        if !grow {
+            self.update_sandbox_cgroups(data);
+        }
+    }
+
+    fn update_sandbox_cgroups(&self, data: &SandboxData) {
+        if let Err(e) = self.sandbox_cgroups.update_res_for_sandbox_cgroups(data) {
+            warn!(
+                "failed to update resources of sandbox {} cgroups: {}",
+                self.id, e
+            );
+        }
+    }
+
+    // update_io_limits updates the rate limits of the devices of the vm if the ones that the
+    // pod asks for are changed, the change is rejected if the hypervisor can not update the
+    // limits of the devices attached to the running vm.
+    #[instrument(skip_all)]
+    async fn update_io_limits(&mut self, data: &SandboxData) -> Result<()> {
+        let old = get_io_limits(&self.data, &self.default_io_limits);
+        let new = get_io_limits(data, &self.default_io_limits);
+        if old == new {
+            return Ok(());
+        }
+        if let SandboxStatus::Running(_) = self.status {
+            let capabilities = self.vm.capabilities();
+            let block_changed =
+                new.block_iops != old.block_iops || new.block_bandwidth != old.block_bandwidth;
+            if block_changed && !capabilities.block_io_limits_update {
+                return Err(Error::Unimplemented(format!(
+                    "the block io limits of the running sandbox {} can not be updated",
+                    self.id
+                )));
+            }
+            if new.net_bandwidth != old.net_bandwidth && !capabilities.net_io_limits_update {
+                return Err(Error::Unimplemented(format!(
+                    "the network bandwidth of the running sandbox {} can not be updated",
+                    self.id
+                )));
             }
         }
+        info!(
+            "update io limits of sandbox {} from {:?} to {:?}",
+            self.id, old, new
+        );
+        self.vm.set_io_limits(new).await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn prepare_network(&mut self) -> Result<()> {
+        // one queue for each vcpu by default, so that the packets are processed on all vcpus,
+        // and only one queue if the hypervisor does not support multi-queue tap.
+        let multi_queue = self.vm.capabilities().net_multi_queue;
+        let queue = if !multi_queue {
+            1
+        } else if self.net_queues > 0 {
+            self.net_queues
+        } else {
+            self.vm.boot_vcpus().max(1)
+        };
 
         let network_config = NetworkConfig {
             netns: self.data.netns.to_string(),
             sandbox_id: self.id.to_string(),
-            queue: vcpu,
+            queue,
+            multi_queue,
         };
         let network = Network::new(network_config).await?;
         network.attach_to(self).await?;
         Ok(())
     }
 
+    // sync_network hot plugs the changes of the interfaces in the netns to the vm,
+    // and updates the interfaces, routes and neighbours in the guest.
+    #[instrument(skip_all)]
+    pub async fn sync_network(&mut self) -> Result<()> {
+        let network = match self.network.as_mut() {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+        let changes = match network.sync(&mut self.vm).await? {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        if let Some(client) = &*self.client.lock().await {
+            if let Some(interfaces) = &changes.interfaces {
+                client_update_interfaces(client, interfaces).await?;
+            }
+            if let Some(routes) = &changes.routes {
+                client_update_routes(client, routes).await?;
+            }
+            if let Some(neighbours) = &changes.neighbours {
+                client_update_neighbours(client, neighbours).await?;
+            }
+        }
+        self.dump().await
+    }
+
     //  If a sandbox is still running, destroy network may hang with its running
     #[instrument(skip_all)]
     pub async fn destroy_network(&mut self) {
@@ -685,27 +888,24 @@ where
 
     #[instrument(skip_all)]
     pub async fn add_to_cgroup(&self) -> Result<()> {
-        // Currently only support cgroup V1, cgroup V2 is not supported now
-        if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
-            // add vmm process into sandbox cgroup
-            if let SandboxStatus::Running(vmm_pid) = self.status {
-                let vcpu_threads = self.vm.vcpus().await?;
-                debug!(
-                    "vmm process pid: {}, vcpu threads pid: {:?}",
-                    vmm_pid, vcpu_threads
-                );
+        // add vmm process into sandbox cgroup
+        if let SandboxStatus::Running(vmm_pid) = self.status {
+            let vcpu_threads = self.vm.vcpus().await?;
+            debug!(
+                "vmm process pid: {}, vcpu threads pid: {:?}",
+                vmm_pid, vcpu_threads
+            );
+            self.sandbox_cgroups
+                .add_process_into_sandbox_cgroups(vmm_pid, Some(vcpu_threads))?;
+            // move all vmm-related process into sandbox cgroup
+            for pid in self.vm.pids().affiliated_pids {
                 self.sandbox_cgroups
-                    .add_process_into_sandbox_cgroups(vmm_pid, Some(vcpu_threads))?;
-                // move all vmm-related process into sandbox cgroup
-                for pid in self.vm.pids().affiliated_pids {
-                    self.sandbox_cgroups
-                        .add_process_into_sandbox_cgroups(pid, None)?;
-                }
-            } else {
-                return Err(Error::Other(anyhow!(
-                    "sandbox status is not Running after started!"
-                )));
+                    .add_process_into_sandbox_cgroups(pid, None)?;
             }
+        } else {
+            return Err(Error::Other(anyhow!(
+                "sandbox status is not Running after started!"
+            )));
         }
         Ok(())
     }
@@ -714,15 +914,22 @@ where
         if let Some(client) = &*self.client.lock().await {
             let client = client.clone();
             let exit_signal = self.exit_signal.clone();
+            let (tx, mut rx) = channel(EVENT_FORWARD_BUFFER_SIZE);
+            tokio::spawn(async move {
+                let mut publisher = None;
+                while let Some(envelope) = rx.recv().await {
+                    if let Err(e) = publish_event(&mut publisher, envelope).await {
+                        error!("{}", e);
+                    }
+                }
+            });
             tokio::spawn(async move {
                 let fut = async {
                     loop {
                         match client.get_events(with_timeout(0), &Empty::new()).await {
                             Ok(resp) => {
-                                if let Err(e) =
-                                    crate::client::publish_event(convert_envelope(resp)).await
-                                {
-                                    error!("{}", e);
+                                if tx.send(convert_envelope(resp)).await.is_err() {
+                                    break;
                                 }
                             }
                             Err(err) => {
@@ -733,7 +940,7 @@ where
                                         break;
                                     }
                                 }
-                                error!("failed to get oom event error {:?}", err);
+                                error!("failed to get events from guest: {:?}", err);
                                 break;
                             }
                         }
@@ -794,12 +1001,53 @@ fn convert_envelope(envelope: vmm_common::api::events::Envelope) -> Envelope {
     }
 }
 
-#[derive(Default, Debug, Deserialize)]
+#[derive(Debug, Deserialize)]
 pub struct SandboxConfig {
     #[serde(default)]
     pub log_level: String,
     #[serde(default)]
     pub enable_tracing: bool,
+    #[serde(default = "default_ping_timeout_in_ms")]
+    pub vm_ping_timeout_in_ms: u64,
+    #[serde(default = "default_ping_timeout_in_ms")]
+    pub agent_ping_timeout_in_ms: u64,
+    // the number of queues of each network interface, 0 means the number of vcpus
+    #[serde(default)]
+    pub net_queues: u32,
+    // the default rate limits of each block and network device,
+    // which the annotations of the pod can only lower
+    #[serde(default)]
+    pub io_limits: IoLimits,
+    // the containers can only be restored from the checkpoint archives in this dir
+    #[serde(default = "default_checkpoint_dir")]
+    pub checkpoint_dir: String,
+    // copy the files that a container needs into a block device for the hypervisors without
+    // a shared filesystem, it makes an image of the whole rootfs each time a container is created
+    #[serde(default)]
+    pub enable_block_share: bool,
+}
+
+fn default_ping_timeout_in_ms() -> u64 {
+    DEFAULT_PING_TIMEOUT_IN_MS
+}
+
+fn default_checkpoint_dir() -> String {
+    DEFAULT_CHECKPOINT_DIR.to_string()
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            log_level: "".to_string(),
+            enable_tracing: false,
+            vm_ping_timeout_in_ms: DEFAULT_PING_TIMEOUT_IN_MS,
+            agent_ping_timeout_in_ms: DEFAULT_PING_TIMEOUT_IN_MS,
+            net_queues: 0,
+            io_limits: IoLimits::default(),
+            checkpoint_dir: default_checkpoint_dir(),
+            enable_block_share: false,
+        }
+    }
 }
 
 impl SandboxConfig {
@@ -810,6 +1058,13 @@ impl SandboxConfig {
     pub fn enable_tracing(&self) -> bool {
         self.enable_tracing
     }
+
+    pub fn ping_timeout(&self) -> PingTimeout {
+        PingTimeout {
+            vm: Duration::from_millis(self.vm_ping_timeout_in_ms),
+            agent: Duration::from_millis(self.agent_ping_timeout_in_ms),
+        }
+    }
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -872,8 +1127,298 @@ fn monitor<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
     });
 }
 
+// watch_network syncs the network of the sandbox when the netns is changed after it started,
+// like the interfaces attached later by multus, until the sandbox exits. The netns is not
+// watched if the vm can not hot plug network devices, the network of the sandbox is the one
+// when it started then.
+fn watch_network<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
+    tokio::spawn(async move {
+        let (id, netns, exit_signal) = {
+            let sandbox = sandbox_mutex.lock().await;
+            if sandbox.network.is_none() {
+                return;
+            }
+            if !sandbox.vm.capabilities().net_hot_plug {
+                info!(
+                    "network devices can not be hot plugged to sandbox {}, \
+                    the changes of its netns are not synced",
+                    sandbox.id
+                );
+                return;
+            }
+            (
+                sandbox.id.to_string(),
+                sandbox.data.netns.to_string(),
+                sandbox.exit_signal.clone(),
+            )
+        };
+        let mut watcher = match NetnsWatcher::new(&netns).await {
+            Ok(w) => w,
+            Err(e) => {
+                error!("failed to watch netns {} of sandbox {}: {}", netns, id, e);
+                return;
+            }
+        };
+        // sync once for the changes before the watcher is created
+        loop {
+            {
+                let mut sandbox = sandbox_mutex.lock().await;
+                if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+                    return;
+                }
+                if let Err(e) = sandbox.sync_network().await {
+                    error!("failed to sync network of sandbox {}: {}", id, e);
+                }
+            }
+            tokio::select! {
+                changed = watcher.changed() => {
+                    if !changed {
+                        warn!("stop watching netns {} of sandbox {}", netns, id);
+                        return;
+                    }
+                },
+                _ = exit_signal.wait() => return,
+            }
+        }
+    });
+}
+
+// get_vm_resources returns the vcpus and memory that the pod resources ask for,
+// the vcpus is the ceil of cpus if it is not integer.
+fn get_vm_resources(data: &SandboxData) -> VmResources {
+    let mut res = VmResources::default();
+    if let Some(resources) = get_resources(data) {
+        if resources.cpu_period > 0 && resources.cpu_quota > 0 {
+            res.vcpus = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil() as u32;
+        }
+        if resources.memory_limit_in_bytes > 0 {
+            res.memory_in_mb = resources.memory_limit_in_bytes as u64 / (1024 * 1024);
+        }
+    }
+    res
+}
+
+// get_container_resources returns the cpus and the memory limit in bytes
+// that the spec of a container asks for, 0 means unlimited.
+fn get_container_resources(data: &ContainerData) -> (f64, u64) {
+    let resources = match data
+        .spec
+        .as_ref()
+        .and_then(|s| s.linux.as_ref())
+        .and_then(|l| l.resources.as_ref())
+    {
+        Some(r) => r,
+        None => return (0.0, 0),
+    };
+    let cpus = match resources.cpu.as_ref().map(|c| (c.quota, c.period)) {
+        Some((Some(quota), Some(period))) if quota > 0 && period > 0 => {
+            quota as f64 / period as f64
+        }
+        _ => 0.0,
+    };
+    let memory = resources
+        .memory
+        .as_ref()
+        .and_then(|m| m.limit)
+        .unwrap_or_default();
+    (cpus, memory)
+}
+
+// resize_pod_resources changes the cpus and memory limit of the pod by the difference between
+// the old and new resources of one of its containers, if both of them are limited.
+fn resize_pod_resources(data: &mut SandboxData, old: (f64, u64), new: (f64, u64)) {
+    let resources = match data
+        .config
+        .as_mut()
+        .and_then(|c| c.linux.as_mut())
+        .and_then(|l| l.resources.as_mut())
+    {
+        Some(r) => r,
+        None => return,
+    };
+    if resources.cpu_period > 0 && resources.cpu_quota > 0 && old.0 > 0.0 && new.0 > 0.0 {
+        let quota = resources.cpu_quota as f64 + (new.0 - old.0) * resources.cpu_period as f64;
+        if quota >= 1.0 {
+            resources.cpu_quota = quota.round() as i64;
+        }
+    }
+    if resources.memory_limit_in_bytes > 0 && old.1 > 0 && new.1 > 0 {
+        let memory = resources.memory_limit_in_bytes + new.1 as i64 - old.1 as i64;
+        if memory > 0 {
+            resources.memory_limit_in_bytes = memory;
+        }
+    }
+}
+
+// get_io_limits returns the rate limits that the annotations of the pod ask for.
+// A non-zero default is a ceiling that the annotations can only lower, so an
+// annotation that is not set, invalid, 0 or above it falls back to the default.
+fn get_io_limits(data: &SandboxData, defaults: &IoLimits) -> IoLimits {
+    let annotations = match data.config.as_ref() {
+        Some(c) => &c.annotations,
+        None => return *defaults,
+    };
+    let get = |key: &str, default: u64| {
+        let value = match annotations.get(key) {
+            Some(value) => value.trim().parse().unwrap_or_else(|e| {
+                warn!(
+                    "invalid annotation {}={}, use {} instead: {}",
+                    key, value, default, e
+                );
+                default
+            }),
+            None => default,
+        };
+        match (value, default) {
+            (0, _) => default,
+            (_, 0) => value,
+            _ => value.min(default),
+        }
+    };
+    IoLimits {
+        block_iops: get(ANNOTATION_KEY_BLOCK_IOPS, defaults.block_iops),
+        block_bandwidth: get(ANNOTATION_KEY_BLOCK_BANDWIDTH, defaults.block_bandwidth),
+        net_bandwidth: get(ANNOTATION_KEY_NET_BANDWIDTH, defaults.net_bandwidth),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    mod resources {
+        use containerd_sandbox::{
+            cri::api::v1::{LinuxContainerResources, LinuxPodSandboxConfig},
+            data::SandboxData,
+            PodSandboxConfig,
+        };
+
+        use crate::sandbox::{get_vm_resources, resize_pod_resources};
+
+        fn sandbox_data(cpu_period: i64, cpu_quota: i64, memory: i64) -> SandboxData {
+            let config = PodSandboxConfig {
+                linux: Some(LinuxPodSandboxConfig {
+                    resources: Some(LinuxContainerResources {
+                        cpu_period,
+                        cpu_quota,
+                        memory_limit_in_bytes: memory,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            SandboxData {
+                config: Some(config),
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_get_vm_resources() {
+            let res = get_vm_resources(&SandboxData::default());
+            assert_eq!(res.vcpus, 0);
+            assert_eq!(res.memory_in_mb, 0);
+
+            let res = get_vm_resources(&sandbox_data(100000, 150000, 1024 * 1024 * 1024));
+            assert_eq!(res.vcpus, 2);
+            assert_eq!(res.memory_in_mb, 1024);
+
+            let res = get_vm_resources(&sandbox_data(100000, 200000, 0));
+            assert_eq!(res.vcpus, 2);
+            assert_eq!(res.memory_in_mb, 0);
+
+            // unlimited cpu quota asks for no vcpus
+            let res = get_vm_resources(&sandbox_data(100000, -1, 0));
+            assert_eq!(res.vcpus, 0);
+        }
+
+        #[test]
+        fn test_resize_pod_resources() {
+            let mut data = sandbox_data(100000, 150000, 1024 * 1024 * 1024);
+            resize_pod_resources(
+                &mut data,
+                (0.5, 256 * 1024 * 1024),
+                (1.5, 512 * 1024 * 1024),
+            );
+            let res = get_vm_resources(&data);
+            assert_eq!(res.vcpus, 3);
+            assert_eq!(res.memory_in_mb, 1280);
+
+            // an unlimited container does not change the pod
+            resize_pod_resources(&mut data, (0.0, 0), (1.0, 1024 * 1024 * 1024));
+            let res = get_vm_resources(&data);
+            assert_eq!(res.vcpus, 3);
+            assert_eq!(res.memory_in_mb, 1280);
+
+            // neither does any container of an unlimited pod
+            let mut data = sandbox_data(100000, -1, 0);
+            resize_pod_resources(
+                &mut data,
+                (0.5, 256 * 1024 * 1024),
+                (1.5, 512 * 1024 * 1024),
+            );
+            let res = get_vm_resources(&data);
+            assert_eq!(res.vcpus, 0);
+            assert_eq!(res.memory_in_mb, 0);
+        }
+    }
+
+    mod io_limits {
+        use containerd_sandbox::{data::SandboxData, PodSandboxConfig};
+        use vmm_common::{
+            ANNOTATION_KEY_BLOCK_BANDWIDTH, ANNOTATION_KEY_BLOCK_IOPS, ANNOTATION_KEY_NET_BANDWIDTH,
+        };
+
+        use crate::{sandbox::get_io_limits, vm::IoLimits};
+
+        #[test]
+        fn test_get_io_limits() {
+            let defaults = IoLimits {
+                block_iops: 1000,
+                block_bandwidth: 100 * 1024 * 1024,
+                net_bandwidth: 0,
+            };
+            let mut data = SandboxData::default();
+            assert_eq!(get_io_limits(&data, &defaults), defaults);
+
+            let mut config = PodSandboxConfig::default();
+            config
+                .annotations
+                .insert(ANNOTATION_KEY_BLOCK_IOPS.to_string(), "invalid".to_string());
+            config.annotations.insert(
+                ANNOTATION_KEY_NET_BANDWIDTH.to_string(),
+                "10485760".to_string(),
+            );
+            data.config = Some(config);
+            let limits = get_io_limits(&data, &defaults);
+            assert_eq!(limits.block_iops, 1000);
+            assert_eq!(limits.block_bandwidth, 100 * 1024 * 1024);
+            assert_eq!(limits.net_bandwidth, 10485760);
+
+            // the defaults are ceilings, the annotations can only lower them
+            let mut config = PodSandboxConfig::default();
+            config
+                .annotations
+                .insert(ANNOTATION_KEY_BLOCK_IOPS.to_string(), "0".to_string());
+            config.annotations.insert(
+                ANNOTATION_KEY_BLOCK_BANDWIDTH.to_string(),
+                (200 * 1024 * 1024).to_string(),
+            );
+            data.config = Some(config);
+            let limits = get_io_limits(&data, &defaults);
+            assert_eq!(limits.block_iops, 1000);
+            assert_eq!(limits.block_bandwidth, 100 * 1024 * 1024);
+
+            let mut config = PodSandboxConfig::default();
+            config.annotations.insert(
+                ANNOTATION_KEY_BLOCK_BANDWIDTH.to_string(),
+                (50 * 1024 * 1024).to_string(),
+            );
+            data.config = Some(config);
+            let limits = get_io_limits(&data, &defaults);
+            assert_eq!(limits.block_bandwidth, 50 * 1024 * 1024);
+        }
+    }
+
     mod dns {
         use crate::sandbox::parse_dnsoptions;
 
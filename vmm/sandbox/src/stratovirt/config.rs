@@ -39,6 +39,9 @@ pub struct StratoVirtVMConfig {
     #[serde(flatten)]
     pub common: HypervisorCommonConfig,
     pub virtiofsd_conf: VirtiofsdConfig,
+    // offload the data plane of the tap devices to the vhost-net kernel module
+    #[serde(default)]
+    pub vhost_net: bool,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -56,6 +59,7 @@ impl Default for StratoVirtVMConfig {
                 path: DEFAULT_VHOST_USER_FS_BIN_PATH.to_string(),
             },
             block_device_driver: "virtio-blk".to_string(),
+            vhost_net: false,
         }
     }
 }
@@ -76,6 +80,7 @@ impl StratoVirtVMConfig {
 
         result.smp = SMP {
             cpus: self.common.vcpus,
+            max_cpus: self.common.max_vcpus,
         };
 
         result.memory = Memory {
@@ -203,6 +208,8 @@ pub struct Global {
 #[derive(CmdLineParams, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SMP {
     pub cpus: u32,
+    #[property(key = "maxcpus", predicate = "self.max_cpus > self.cpus")]
+    pub max_cpus: u32,
 }
 
 #[derive(CmdLineParams, Debug, Default, Clone, Serialize, Deserialize)]
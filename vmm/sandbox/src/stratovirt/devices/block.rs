@@ -26,16 +26,17 @@ use qapi::{
     Dictionary,
 };
 use sandbox_derive::CmdLineParams;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
     device::Device,
-    stratovirt::{devices::HotAttachable, qmp_client::QmpClient},
+    stratovirt::{devices::HotAttachable, qmp::BlockdevAdd, qmp_client::QmpClient},
 };
 
 pub const VIRTIO_BLK_DRIVER: &str = "virtio-blk";
 
-#[derive(CmdLineParams, Debug, Clone)]
+#[derive(CmdLineParams, Debug, Clone, Serialize, Deserialize)]
 #[params("device", "drive")]
 pub struct VirtioBlockDevice {
     #[property(param = "device", ignore_key)]
@@ -57,6 +58,8 @@ pub struct VirtioBlockDevice {
     pub bus: Option<String>,
     #[property(param = "device", predicate = "self.addr.len()>0")]
     pub addr: String,
+    #[property(param = "drive", key = "throttling.iops-total")]
+    pub iops: Option<u64>,
 }
 
 impl_device_no_bus!(VirtioBlockDevice);
@@ -80,15 +83,26 @@ impl VirtioBlockDevice {
             direct: None,
             bus: None,
             addr: "".to_string(),
+            iops: None,
         }
     }
+
+    pub fn iops(mut self, iops: u64) -> Self {
+        self.iops = (iops > 0).then_some(iops);
+        self
+    }
 }
 
 #[async_trait]
 impl HotAttachable for VirtioBlockDevice {
     async fn execute_hot_attach(&self, client: &QmpClient, rp_id: &str) -> Result<()> {
         debug!("hot attach block device {}", self.id);
-        client.execute(self.to_blockdev_add()).await?;
+        client
+            .execute(BlockdevAdd {
+                options: self.to_blockdev_add().0,
+                iops: self.iops,
+            })
+            .await?;
         match client.execute(self.to_device_add(rp_id)).await {
             Ok(_) => Ok(()),
             Err(e) => {
@@ -185,6 +199,7 @@ mod tests {
     use serde_json::Value;
 
     use super::{VirtioBlockDevice, VIRTIO_BLK_DRIVER};
+    use crate::stratovirt::qmp::BlockdevAdd;
 
     fn compare_json_strings(json_str1: &str, json_str2: &str) -> bool {
         let value1: Value = serde_json::from_str(json_str1).unwrap();
@@ -242,6 +257,30 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_block_device_add_with_iops_qmp_commands() {
+        let virtio_blk_device = VirtioBlockDevice::new(
+            VIRTIO_BLK_DRIVER,
+            "drive-0",
+            "",
+            Some("/dev/dm-8".to_string()),
+            Some(false),
+        )
+        .iops(1000);
+
+        let blockdev_add_qmp_cmd = BlockdevAdd {
+            options: virtio_blk_device.to_blockdev_add().0,
+            iops: virtio_blk_device.iops,
+        };
+        let blockdev_add_qmp_json_str = serde_json::to_string(&blockdev_add_qmp_cmd).unwrap();
+
+        let expected_params_str = r#"{"driver":"raw","read-only":false,"node-name":"drive-0","cache":{"direct":true},"file":{"driver":"file","filename":"/dev/dm-8"},"throttling.iops-total":1000}"#;
+        assert!(compare_json_strings(
+            &blockdev_add_qmp_json_str,
+            expected_params_str,
+        ));
+    }
+
     #[test]
     fn test_block_device_del_qmp_commands() {
         let virtio_blk_device = VirtioBlockDevice::new(
@@ -16,8 +16,9 @@ limitations under the License.
 
 use async_trait::async_trait;
 use containerd_sandbox::error::Result;
+use serde::{Deserialize, Serialize};
 
-use self::pcie_rootbus::PcieRootBus;
+use self::{block::VirtioBlockDevice, pcie_rootbus::PcieRootBus};
 use crate::{
     device::{Bus, BusType, Device, Slot, SlotStatus},
     param::ToCmdLineParams,
@@ -61,6 +62,46 @@ pub trait StratoVirtHotAttachable: Device + HotAttachable {}
 
 impl<T> StratoVirtHotAttachable for T where T: Device + HotAttachable {}
 
+// HotAttachedDevice keeps the hot attached devices in a serializable form,
+// so that they can still be hot detached after the sandboxer restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HotAttachedDevice {
+    Block(VirtioBlockDevice),
+}
+
+impl From<VirtioBlockDevice> for HotAttachedDevice {
+    fn from(d: VirtioBlockDevice) -> Self {
+        Self::Block(d)
+    }
+}
+
+impl Device for HotAttachedDevice {
+    fn id(&self) -> String {
+        match self {
+            Self::Block(d) => d.id(),
+        }
+    }
+
+    fn bus(&mut self) -> Option<&mut Bus> {
+        None
+    }
+}
+
+#[async_trait]
+impl HotAttachable for HotAttachedDevice {
+    async fn execute_hot_attach(&self, client: &QmpClient, bus_id: &str) -> Result<()> {
+        match self {
+            Self::Block(d) => d.execute_hot_attach(client, bus_id).await,
+        }
+    }
+
+    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
+        match self {
+            Self::Block(d) => d.execute_hot_detach(client).await,
+        }
+    }
+}
+
 pub fn create_pcie_root_bus() -> Option<PcieRootBus> {
     let mut pcie_root_bus = PcieRootBus {
         id: "pcie.0".to_string(),
@@ -85,8 +126,8 @@ mod tests {
     pub(crate) const VHOST_USER_FS_ADDR: usize = 4;
     pub(crate) const ROOTPORT_PCI_START_ADDR: usize = 5;
 
-    use super::create_pcie_root_bus;
-    use crate::device::SlotStatus;
+    use super::{block::VirtioBlockDevice, create_pcie_root_bus, HotAttachedDevice};
+    use crate::device::{Device, SlotStatus};
 
     #[test]
     fn test_create_pcie_root_bus() {
@@ -95,4 +136,28 @@ mod tests {
             assert_eq!(s, "reserved");
         }
     }
+
+    #[test]
+    fn test_pcie_root_bus_serialize() {
+        let pcie_root_bus = create_pcie_root_bus().unwrap();
+        let data = serde_json::to_vec(&pcie_root_bus).unwrap();
+        let recovered: super::PcieRootBus = serde_json::from_slice(&data).unwrap();
+        assert_eq!(recovered.bus.slots.len(), super::PCIE_ROOTBUS_CAPACITY);
+        if let SlotStatus::Occupied(s) = &recovered.bus.slots[0].status {
+            assert_eq!(s, "reserved");
+        } else {
+            panic!("slot 0 of the recovered pcie root bus should be occupied");
+        }
+    }
+
+    #[test]
+    fn test_hot_attached_device_serialize() {
+        let device: HotAttachedDevice =
+            VirtioBlockDevice::new("", "blk1", "", Some("/dev/test".to_string()), Some(true))
+                .into();
+        let data = serde_json::to_vec(&vec![device]).unwrap();
+        let recovered: Vec<HotAttachedDevice> = serde_json::from_slice(&data).unwrap();
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].id(), "blk1");
+    }
 }
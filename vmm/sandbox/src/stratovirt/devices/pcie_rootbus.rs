@@ -14,11 +14,13 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use serde::{Deserialize, Serialize};
+
 use crate::device::Bus;
 
 // PcieRootBus is not a actual pci device, which is used to manage the
 // pci slot resources in the pcie.0 root bus
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PcieRootBus {
     pub(crate) id: String,
     pub(crate) bus: Bus,
@@ -15,12 +15,13 @@ limitations under the License.
 */
 
 use sandbox_derive::CmdLineParams;
+use serde::{Deserialize, Serialize};
 
 use crate::device::Bus;
 
 pub(crate) const PCIE_ROOT_PORT_DRIVER: &str = "pcie-root-port";
 
-#[derive(CmdLineParams, Debug, Clone)]
+#[derive(CmdLineParams, Debug, Clone, Serialize, Deserialize)]
 #[params("device")]
 pub struct RootPort {
     #[property(ignore_key)]
@@ -56,7 +57,7 @@ impl RootPort {
 impl_device_no_bus!(RootPort);
 impl_set_device_addr!(RootPort);
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PCIERootPorts {
     pub(crate) id: String,
     pub(crate) bus: Bus,
@@ -68,6 +68,7 @@ impl VMFactory for StratoVirtVMFactory {
         vm.config.name = format!("sandbox-{}", id);
         vm.config.pid_file = format!("{}/sandbox-{}.pid", s.base_dir, id);
         vm.block_driver = BlockDriver::from(self.default_config.block_device_driver.as_str());
+        vm.vhost_net = self.default_config.vhost_net;
         if self.default_config.common.debug {
             vm.config.log_file = Some(format!("{}/sandbox-{}.log", s.base_dir, id));
         }
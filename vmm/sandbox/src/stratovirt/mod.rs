@@ -29,35 +29,38 @@ use containerd_sandbox::error::{Error, Result};
 use futures_util::TryFutureExt;
 use log::{debug, error, trace, warn};
 use nix::{fcntl::OFlag, libc::kill, sys::stat::Mode};
-use qapi::qmp::quit;
+use qapi::{
+    qmp::{device_add, quit},
+    Dictionary,
+};
 use qmp::CpuInfo;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::OffsetDateTime;
 use tokio::{
     net::UnixStream,
     sync::watch::{channel, Receiver},
     task::spawn_blocking,
-    time::sleep,
+    time::{sleep, timeout},
 };
 use unshare::Fd;
 
 use self::devices::{pcie_rootbus::PcieRootBus, rootport::RootPort, PCIE_ROOTBUS_CAPACITY};
 use crate::{
     device::{Bus, BusType, DeviceInfo, Slot, SlotStatus},
-    impl_recoverable,
     param::ToCmdLineParams,
     stratovirt::{
         config::StratoVirtConfig,
         devices::{
             block::VirtioBlockDevice, rootport::PCIERootPorts, virtio_net::VirtioNetDevice,
-            StratoVirtDevice, StratoVirtHotAttachable, DEFAULT_PCIE_BUS,
+            HotAttachedDevice, StratoVirtDevice, StratoVirtHotAttachable, DEFAULT_PCIE_BUS,
         },
         qmp_client::QmpClient,
         utils::detect_pid,
         virtiofs::VirtiofsDaemon,
     },
-    utils::{read_std, wait_channel, wait_pid},
-    vm::{BlockDriver, Pids, VcpuThreads, VM},
+    utils::{check_process_cmdline, open_vhost_net_fds, read_std, wait_channel, wait_pid},
+    vm::{BlockDriver, HealthError, IoLimits, Pids, VcpuThreads, VmCapabilities, VmResources, VM},
 };
 
 pub mod config;
@@ -71,35 +74,43 @@ mod virtiofs;
 
 pub(crate) const STRATOVIRT_START_TIMEOUT_IN_SEC: u64 = 10;
 pub const CONFIG_STRATOVIRT_PATH: &str = "/var/lib/kuasar/config_stratovirt.toml";
+// stratovirt only supports cpu hotplug on x86_64 standard vm
+const STRATOVIRT_CPU_DRIVER: &str = "generic-x86-cpu";
+const VCPU_UNPLUG_TIMEOUT_IN_SEC: u64 = 10;
 
-// restart recovery is not supported yet,
-// so we annotate the StratoVirtVM with Serialize and Deserlize,
-// but skip all the fields serialization.
+// The devices are only used to build the stratovirt command line, so they are not serialized,
+// the slots for hot plugging are kept in `pcie_root_bus` and `pcie_root_ports_pool`.
 #[derive(Default, Serialize, Deserialize)]
 pub struct StratoVirtVM {
     id: String,
     config: StratoVirtConfig,
     #[serde(skip)]
     devices: Vec<Box<dyn StratoVirtDevice + Sync + Send>>,
-    #[serde(skip)]
-    hot_attached_devices: Vec<Box<dyn StratoVirtHotAttachable + Sync + Send>>,
+    #[serde(default)]
+    hot_attached_devices: Vec<HotAttachedDevice>,
     #[serde(skip)]
     fds: Vec<OwnedFd>,
     console_socket: String,
     agent_socket: String,
     netns: String,
     pids: Pids,
-    #[serde(skip)]
+    #[serde(default)]
     block_driver: BlockDriver,
+    #[serde(default)]
+    vhost_net: bool,
     #[serde(skip)]
     wait_chan: Option<Receiver<(u32, i128)>>,
     #[serde(skip)]
     client: Option<QmpClient>,
     virtiofs_daemon: Option<VirtiofsDaemon>,
-    #[serde(skip)]
+    #[serde(default)]
     pcie_root_bus: Option<PcieRootBus>,
-    #[serde(skip)]
+    #[serde(default)]
     pcie_root_ports_pool: Option<PCIERootPorts>,
+    #[serde(default)]
+    resources: VmResources,
+    #[serde(default)]
+    io_limits: IoLimits,
 }
 
 #[async_trait]
@@ -130,20 +141,21 @@ impl VM for StratoVirtVM {
             }
         }
 
-        let console_socket = self.console_socket.clone();
-        tokio::spawn(async move {
-            UnixStream::connect(&*console_socket)
-                .map_err(|e| e.into())
-                .and_then(|s| read_std(s, "console"))
-                .await
-                .unwrap_or_else(|e| {
-                    error!("failed to read console log, {}", e);
-                });
-        });
+        self.read_console();
 
         // update vmm related pids
         let vmm_pid = detect_pid(self.config.pid_file.as_str(), self.config.path.as_str()).await?;
         self.pids.vmm_pid = Some(vmm_pid);
+        self.resources = VmResources {
+            vcpus: self.config.smp.cpus,
+            memory_in_mb: self
+                .config
+                .memory
+                .size
+                .trim_end_matches('M')
+                .parse()
+                .unwrap_or_default(),
+        };
         if let Some(virtiofsd) = &self.virtiofs_daemon {
             if let Some(pid) = virtiofsd.pid {
                 self.pids.affiliated_pids.push(pid);
@@ -189,6 +201,13 @@ impl VM for StratoVirtVM {
         match device_info {
             DeviceInfo::Tap(tap_info) => {
                 let mut fd_ints = vec![];
+                let mut vhostfd_ints = vec![];
+                if self.vhost_net {
+                    for fd in open_vhost_net_fds(tap_info.fds.len() as u32)? {
+                        let index = self.append_fd(fd);
+                        vhostfd_ints.push(index as i32);
+                    }
+                }
                 for fd in tap_info.fds {
                     let index = self.append_fd(fd);
                     fd_ints.push(index as i32);
@@ -200,8 +219,8 @@ impl VM for StratoVirtVM {
                     .mac_address(&tap_info.mac_address)
                     .transport(self.config.machine.transport())
                     .fds(fd_ints)
-                    .vhost(false)
-                    .vhostfds(vec![])
+                    .vhost(!vhostfd_ints.is_empty())
+                    .vhostfds(vhostfd_ints)
                     .bus(Some(DEFAULT_PCIE_BUS.to_string()))
                     .build();
                 self.attach_to_bus(virtio_net_device)?;
@@ -222,7 +241,8 @@ impl VM for StratoVirtVM {
                     "",
                     Some(blk_info.path),
                     Some(blk_info.read_only),
-                );
+                )
+                .iops(self.io_limits.block_iops);
                 let index = self.hot_attach_device(device).await?;
                 let addr = format!("0000:00:{:02x}.0", index);
                 Ok((self.block_driver.to_bus_type(), addr))
@@ -265,13 +285,23 @@ impl VM for StratoVirtVM {
             self.hot_attached_devices.push(device);
             return Err(e);
         }
-        self.detach_from_bus(id);
+        self.detach_from_bus(&device);
         Ok(())
     }
 
-    async fn ping(&self) -> Result<()> {
+    async fn ping(&self, t: Duration) -> Result<()> {
         let client = self.get_client()?;
-        let _res = client.execute(qapi::qmp::query_status {}).await?;
+        let status = timeout(t, client.execute(qapi::qmp::query_status {}))
+            .await
+            .map_err(|_| {
+                HealthError::VmmUnresponsive(format!("query-status timeout after {:?}", t))
+            })?
+            .map_err(|e| HealthError::VmmUnresponsive(format!("query-status failed: {}", e)))?;
+        if !status.running {
+            return Err(
+                HealthError::GuestNotRunning(format!("vm status is {:?}", status.status)).into(),
+            );
+        }
         Ok(())
     }
 
@@ -302,6 +332,83 @@ impl VM for StratoVirtVM {
     fn pids(&self) -> Pids {
         self.pids.clone()
     }
+
+    fn resources(&self) -> VmResources {
+        self.resources
+    }
+
+    fn boot_vcpus(&self) -> u32 {
+        self.config.smp.cpus
+    }
+
+    async fn resize(&mut self, resources: VmResources) -> Result<VmResources> {
+        let boot_vcpus = self.config.smp.cpus;
+        let max_vcpus = self.config.smp.max_cpus.max(boot_vcpus);
+        let vcpus = resources.vcpus.clamp(boot_vcpus, max_vcpus);
+        if vcpus != self.resources.vcpus && !cfg!(target_arch = "x86_64") {
+            return Err(Error::Unimplemented(
+                "cpu hotplug of stratovirt is only supported on x86_64".to_string(),
+            ));
+        }
+        while self.resources.vcpus < vcpus {
+            let cpu_id = self.resources.vcpus;
+            let mut arguments = Dictionary::new();
+            arguments.insert("cpu-id".to_string(), Value::from(cpu_id));
+            debug!("hotplug vcpu {} to vm {}", cpu_id, self.id);
+            self.get_client()?
+                .execute(device_add {
+                    driver: STRATOVIRT_CPU_DRIVER.to_string(),
+                    bus: None,
+                    id: Some(format!("vcpu-{}", cpu_id)),
+                    arguments,
+                })
+                .await?;
+            self.resources.vcpus += 1;
+        }
+        while self.resources.vcpus > vcpus {
+            let id = format!("vcpu-{}", self.resources.vcpus - 1);
+            debug!("unplug vcpu {} from vm {}", id, self.id);
+            let client = self.get_client()?;
+            timeout(
+                Duration::from_secs(VCPU_UNPLUG_TIMEOUT_IN_SEC),
+                client.delete_device(&id),
+            )
+            .await
+            .map_err(|_| anyhow!("timeout to unplug vcpu {}", id))??;
+            self.resources.vcpus -= 1;
+        }
+
+        if resources.memory_in_mb != self.resources.memory_in_mb {
+            warn!(
+                "memory hotplug is not supported by stratovirt, memory of vm {} keeps {}M",
+                self.id, self.resources.memory_in_mb
+            );
+        }
+        Ok(self.resources)
+    }
+
+    async fn set_io_limits(&mut self, limits: IoLimits) -> Result<()> {
+        if limits.block_bandwidth > 0 || limits.net_bandwidth > 0 {
+            warn!(
+                "stratovirt only supports the iops limit of block devices, \
+                the bandwidth of the devices of vm {} is not limited",
+                self.id
+            );
+        }
+        self.io_limits = limits;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> VmCapabilities {
+        VmCapabilities {
+            // tap and vfio devices are not hot plugged by `hot_attach` yet
+            net_hot_plug: false,
+            // the iops limit is only configured when the block devices are added
+            block_io_limits_update: false,
+            net_io_limits_update: false,
+            ..Default::default()
+        }
+    }
 }
 
 impl StratoVirtVM {
@@ -316,12 +423,15 @@ impl StratoVirtVM {
             agent_socket: "".to_string(),
             netns: netns.to_string(),
             block_driver: Default::default(),
+            vhost_net: false,
             wait_chan: None,
             client: None,
             virtiofs_daemon: None,
             pcie_root_ports_pool: None,
             pcie_root_bus: None,
             pids: Pids::default(),
+            resources: VmResources::default(),
+            io_limits: IoLimits::default(),
         }
     }
 
@@ -334,6 +444,19 @@ impl StratoVirtVM {
         self.fds.len() - 1 + 3
     }
 
+    fn read_console(&self) {
+        let console_socket = self.console_socket.clone();
+        tokio::spawn(async move {
+            UnixStream::connect(&*console_socket)
+                .map_err(|e| e.into())
+                .and_then(|s| read_std(s, "console"))
+                .await
+                .unwrap_or_else(|e| {
+                    error!("failed to read console log, {}", e);
+                });
+        });
+    }
+
     async fn launch(&mut self) -> Result<Receiver<(u32, i128)>> {
         let mut params = self.config.to_cmdline_params("-");
         for d in self.devices.iter() {
@@ -445,17 +568,17 @@ impl StratoVirtVM {
         }
     }
 
-    async fn hot_attach_device<T: StratoVirtHotAttachable + Sync + Send + 'static>(
-        &mut self,
-        device: T,
-    ) -> Result<usize> {
+    async fn hot_attach_device<T>(&mut self, device: T) -> Result<usize>
+    where
+        T: StratoVirtHotAttachable + Into<HotAttachedDevice> + Sync + Send,
+    {
         let (rp_id, rp_index) = self.get_empty_rootport_slot(device.id())?;
         let client = self
             .client
             .as_ref()
             .ok_or_else(|| anyhow!("qmp client is not init"))?;
         device.execute_hot_attach(client, &rp_id).await?;
-        self.hot_attached_devices.push(Box::new(device));
+        self.hot_attached_devices.push(device.into());
         Ok(rp_index)
     }
 
@@ -568,23 +691,43 @@ impl StratoVirtVM {
             .map_err(|e| Error::Other(anyhow!("start virtiofs daemon process failed: {}", e)))
     }
 
-    fn detach_from_bus(&mut self, device_id: &str) {
-        self.devices
-            .iter_mut()
-            .filter_map(|x| x.bus())
-            .for_each(|b| {
-                if let Some(x) = b.slots.iter_mut().find(|s| {
-                    if let SlotStatus::Occupied(id) = &s.status {
-                        if id == device_id {
-                            return true;
-                        }
-                    }
-                    false
-                }) {
-                    x.status = SlotStatus::Empty;
-                }
-            });
+    // detach_from_bus releases the root port occupied by the hot attached device,
+    // the hot attached devices and the root ports are both recovered from the dump,
+    // so it still works after the sandboxer restarts.
+    fn detach_from_bus(&mut self, device: &HotAttachedDevice) {
+        let device_id = device.id();
+        if let Some(pool) = self.pcie_root_ports_pool.as_mut() {
+            if let Some(rp) = pool
+                .root_ports
+                .iter_mut()
+                .find(|rp| rp.device_id == device_id)
+            {
+                rp.device_id = "".to_string();
+            }
+        }
     }
 }
 
-impl_recoverable!(StratoVirtVM);
+#[async_trait]
+impl crate::vm::Recoverable for StratoVirtVM {
+    async fn recover(&mut self) -> Result<()> {
+        // make sure the processes are still the ones we started before adopting them
+        let pid = self.pid()?;
+        check_process_cmdline(pid, &self.config.path)?;
+        if let Some(virtiofsd) = &self.virtiofs_daemon {
+            if let Some(virtiofsd_pid) = virtiofsd.pid {
+                check_process_cmdline(virtiofsd_pid, &virtiofsd.path)?;
+            }
+        }
+
+        self.client = Some(self.create_client().await?);
+        let (tx, rx) = channel((0u32, 0i128));
+        tokio::spawn(async move {
+            let wait_result = wait_pid(pid as i32).await;
+            tx.send(wait_result).unwrap_or_default();
+        });
+        self.wait_chan = Some(rx);
+        self.read_console();
+        Ok(())
+    }
+}
@@ -14,9 +14,30 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use qapi::qmp::QmpCommand;
+use qapi::qmp::{BlockdevOptions, QmpCommand};
 use serde::{Deserialize, Serialize};
 
+// blockdev-add of stratovirt has the extra option to limit the iops of the block device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockdevAdd {
+    #[serde(flatten)]
+    pub options: BlockdevOptions,
+    #[serde(
+        rename = "throttling.iops-total",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub iops: Option<u64>,
+}
+
+impl QmpCommand for BlockdevAdd {}
+impl ::qapi_spec::Command for BlockdevAdd {
+    const NAME: &'static str = "blockdev-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryCpus {}
 
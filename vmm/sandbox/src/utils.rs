@@ -317,6 +317,21 @@ pub async fn wait_pid(pid: i32) -> (u32, i128) {
     }
 }
 
+// check_process_cmdline makes sure the process is still running the given binary,
+// because the pid may be reused by another process after the original one exited.
+pub fn check_process_cmdline(pid: u32, bin_path: &str) -> Result<()> {
+    let p = procfs::process::Process::new(pid as i32)
+        .map_err(|e| anyhow!("failed to get process {}, {}", pid, e))?;
+    let cmd = p
+        .cmdline()
+        .map_err(|e| anyhow!("failed to get command of process {}, {}", pid, e))?;
+    if cmd.iter().any(|s| s.contains(bin_path)) {
+        Ok(())
+    } else {
+        Err(Error::NotFound(format!("process {} of {}", pid, bin_path)))
+    }
+}
+
 pub async fn write_file_async<P: AsRef<Path>>(path: P, s: &str) -> Result<()> {
     let path = path.as_ref();
     let mut f = OpenOptions::new()
@@ -398,6 +413,23 @@ pub fn fds_to_vectors<T>(fds: &[T]) -> String {
     (2 * fds.len() + 2).to_string()
 }
 
+const VHOST_NET_DEVICE: &str = "/dev/vhost-net";
+
+// open_vhost_net_fds opens one vhost-net fd for each queue pair of the tap device.
+pub fn open_vhost_net_fds(queue: u32) -> Result<Vec<OwnedFd>> {
+    let mut fds = vec![];
+    for _ in 0..queue {
+        let fd = safe_open_file(
+            VHOST_NET_DEVICE,
+            OFlag::O_CLOEXEC | OFlag::O_RDWR,
+            Mode::empty(),
+        )
+        .map_err(|e| anyhow!("failed to open {}: {}", VHOST_NET_DEVICE, e))?;
+        fds.push(fd);
+    }
+    Ok(fds)
+}
+
 pub async fn wait_channel<T: Copy>(t: Duration, mut rx: Receiver<T>) -> Result<T> {
     let tf = tokio::time::timeout(t, rx.changed());
     tf.await
@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, fmt, str::FromStr, time::Duration};
 
 use async_trait::async_trait;
 use containerd_sandbox::{
@@ -66,31 +66,85 @@ pub trait VM: Serialize + Sync + Send {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()>;
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)>;
     async fn hot_detach(&mut self, id: &str) -> Result<()>;
-    async fn ping(&self) -> Result<()>;
+    async fn ping(&self, timeout: Duration) -> Result<()>;
     fn socket_address(&self) -> String;
     async fn wait_channel(&self) -> Option<Receiver<(u32, i128)>>;
     async fn vcpus(&self) -> Result<VcpuThreads>;
     fn pids(&self) -> Pids;
+    fn resources(&self) -> VmResources;
+    /// The number of vcpus that the vm is configured to boot with.
+    fn boot_vcpus(&self) -> u32;
+    /// Resize the running vm by cpu and memory hotplug, the returned resources are the actual
+    /// ones after resizing, which may differ from the requested ones if the hypervisor can not
+    /// fully satisfy it, e.g. memory can not be unplugged. Firecracker resizes nothing and
+    /// StratoVirt only hot plugs vcpus on x86_64, they keep the rest and only warn about it.
+    async fn resize(&mut self, resources: VmResources) -> Result<VmResources>;
+    /// Set the rate limits of the block and network devices, they are applied to the devices
+    /// attached afterwards, and to the attached ones if the hypervisor can update them live,
+    /// which is told by `block_io_limits_update` and `net_io_limits_update` of the capabilities.
+    async fn set_io_limits(&mut self, limits: IoLimits) -> Result<()>;
+    fn capabilities(&self) -> VmCapabilities {
+        VmCapabilities::default()
+    }
+}
+
+/// `VmCapabilities` are the features that a hypervisor may lack,
+/// so that the sandbox has to fall back to other ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmCapabilities {
+    /// The shared dir of sandbox can be shared to the guest by virtio-fs or 9p,
+    /// otherwise the files of containers are packed into block devices.
+    pub fs_sharing: bool,
+    /// The tap device can be opened with multiple queues.
+    pub net_multi_queue: bool,
+    /// The network devices can be hot plugged after the vm started, otherwise the interfaces
+    /// added to or removed from the netns after the sandbox started are not synced to the guest.
+    pub net_hot_plug: bool,
+    /// The rate limits of the attached block devices can be updated while the vm is running.
+    pub block_io_limits_update: bool,
+    /// The rate limit of the attached network devices can be updated while the vm is running.
+    pub net_io_limits_update: bool,
+}
+
+impl Default for VmCapabilities {
+    fn default() -> Self {
+        Self {
+            fs_sharing: true,
+            net_multi_queue: true,
+            net_hot_plug: true,
+            block_io_limits_update: true,
+            net_io_limits_update: true,
+        }
+    }
+}
+
+/// `HealthError` tells why a sandbox failed the liveness probe.
+#[derive(Debug)]
+pub enum HealthError {
+    /// The vmm process does not answer its api or qmp requests in time.
+    VmmUnresponsive(String),
+    /// The vmm is responsive but the guest is not running, e.g. it is paused or shutdown.
+    GuestNotRunning(String),
+    /// The agent in the guest does not answer the check request in time.
+    AgentUnresponsive(String),
 }
 
-#[macro_export]
-macro_rules! impl_recoverable {
-    ($ty:ty) => {
-        #[async_trait]
-        impl $crate::vm::Recoverable for $ty {
-            async fn recover(&mut self) -> Result<()> {
-                self.client = Some(self.create_client().await?);
-                let pid = self.pid()?;
-                let (tx, rx) = channel((0u32, 0i128));
-                tokio::spawn(async move {
-                    let wait_result = wait_pid(pid as i32).await;
-                    tx.send(wait_result).unwrap_or_default();
-                });
-                self.wait_chan = Some(rx);
-                Ok(())
-            }
+impl fmt::Display for HealthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthError::VmmUnresponsive(s) => write!(f, "vmm is unresponsive: {}", s),
+            HealthError::GuestNotRunning(s) => write!(f, "guest is not running: {}", s),
+            HealthError::AgentUnresponsive(s) => write!(f, "agent is unresponsive: {}", s),
         }
-    };
+    }
+}
+
+impl std::error::Error for HealthError {}
+
+impl From<HealthError> for Error {
+    fn from(e: HealthError) -> Self {
+        Error::Other(anyhow::Error::new(e))
+    }
 }
 
 #[async_trait]
@@ -104,6 +158,12 @@ pub struct HypervisorCommonConfig {
     pub debug: bool,
     pub vcpus: u32,
     pub memory_in_mb: u32,
+    // the max number of vcpus that the vm can be resized to, 0 means no cpu hotplug
+    #[serde(default)]
+    pub max_vcpus: u32,
+    // the max memory that the vm can be resized to, 0 means no memory hotplug
+    #[serde(default)]
+    pub max_memory_in_mb: u32,
     #[serde(default)]
     pub kernel_path: String,
     #[serde(default)]
@@ -124,6 +184,8 @@ impl Default for HypervisorCommonConfig {
             debug: false,
             vcpus: 1,
             memory_in_mb: 1024,
+            max_vcpus: 0,
+            max_memory_in_mb: 0,
             kernel_path: "/var/lib/kuasar/vmlinux.bin".to_string(),
             image_path: "".to_string(),
             initrd_path: "".to_string(),
@@ -134,7 +196,7 @@ impl Default for HypervisorCommonConfig {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[allow(clippy::enum_variant_names)]
 pub enum BlockDriver {
     VirtioBlk,
@@ -202,6 +264,35 @@ impl FromStr for ShareFsType {
     }
 }
 
+/// `VmResources` is the cpu and memory capacity of a running vm.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VmResources {
+    pub vcpus: u32,
+    pub memory_in_mb: u64,
+}
+
+/// `IoLimits` are the rate limits of each block and network device of a vm, 0 means unlimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IoLimits {
+    /// The read and write operations per second of a block device.
+    pub block_iops: u64,
+    /// The read and write bytes per second of a block device.
+    pub block_bandwidth: u64,
+    /// The bytes per second of a network device, which is limited in each direction.
+    pub net_bandwidth: u64,
+}
+
+impl IoLimits {
+    pub fn block_limited(&self) -> bool {
+        self.block_iops > 0 || self.block_bandwidth > 0
+    }
+
+    pub fn net_limited(&self) -> bool {
+        self.net_bandwidth > 0
+    }
+}
+
 #[derive(Debug)]
 pub struct VcpuThreads {
     pub vcpus: HashMap<i64, i64>,
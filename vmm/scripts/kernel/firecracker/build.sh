@@ -0,0 +1,38 @@
+#!/bin/bash
+# Copyright 2025 The Kuasar Authors.
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+# http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+
+set -e
+set -x
+
+readonly version=${1:-6.12.8}
+readonly base_dir="$(dirname $(readlink -f $0))"
+readonly guest_config="https://raw.githubusercontent.com/firecracker-microvm/firecracker/main/resources/guest_configs/microvm-kernel-ci-x86_64-6.1.config"
+
+sudo apt-get update
+sudo apt-get install -y libelf-dev elfutils flex bison bc
+
+# clone kernel from kernel.org, and build it with the guest config of firecracker
+rm -rf /tmp/linux-firecracker
+git clone --depth 1 https://git.kernel.org/pub/scm/linux/kernel/git/stable/linux.git -b v${version} /tmp/linux-firecracker
+pushd /tmp/linux-firecracker
+curl -fsSL ${guest_config} -o .config
+make olddefconfig
+
+# firecracker boots the uncompressed ELF kernel on x86-64
+make vmlinux -j `nproc`
+# TODO support arm
+popd # pushd /tmp/linux-firecracker
+
+cp /tmp/linux-firecracker/vmlinux ${base_dir}/vmlinux.bin
@@ -15,13 +15,18 @@ limitations under the License.
 */
 
 use std::{
-    convert::TryFrom, io::SeekFrom, os::unix::prelude::ExitStatusExt, path::Path,
-    process::ExitStatus, sync::Arc,
+    convert::TryFrom,
+    io::SeekFrom,
+    os::unix::prelude::ExitStatusExt,
+    path::Path,
+    process::ExitStatus,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use async_trait::async_trait;
 use containerd_shim::{
-    api::{CreateTaskRequest, ExecProcessRequest, Status},
+    api::{CheckpointTaskRequest, CreateTaskRequest, ExecProcessRequest, Status},
     asynchronous::{
         console::ConsoleSocket,
         container::{ContainerFactory, ContainerTemplate, ProcessFactory},
@@ -42,6 +47,11 @@ use containerd_shim::{
     util::read_spec,
     ExitSignal,
 };
+use futures::future::BoxFuture;
+use kuasar_common::checkpoint::{
+    create_archive, runc_root, write_dump_files, CheckpointOpts, RestoreOpts, RuncCommand,
+    DEFAULT_COMMAND,
+};
 use log::{debug, error};
 use nix::{sys::signalfd::signal::kill, unistd::Pid};
 use oci_spec::runtime::{LinuxResources, Process, Spec};
@@ -57,13 +67,14 @@ use tracing::instrument;
 use vmm_common::{
     mount::get_mount_type,
     storage::{Storage, ANNOTATION_KEY_STORAGE},
-    KUASAR_STATE_DIR,
+    ANNOTATION_KEY_CHECKPOINT_PATH, CHECKPOINT_DIR_NAME, KUASAR_STATE_DIR,
 };
 
 use crate::{
+    checkpoint::Checkpoint,
     device::rescan_pci_bus,
     io::{convert_stdio, copy_io_or_console, create_io},
-    sandbox::SandboxResources,
+    sandbox::{prepare_bundle, SandboxResources},
     util::{read_io, read_storages, wait_pid},
 };
 
@@ -98,8 +109,11 @@ pub struct KuasarExecLifecycle {
 
 pub struct KuasarInitLifecycle {
     runtime: Runc,
+    runc_command: RuncCommand,
     opts: Options,
     bundle: String,
+    // the checkpoint images to restore the container from when it starts
+    checkpoint: Option<String>,
     exit_signal: Arc<ExitSignal>,
 }
 
@@ -118,6 +132,7 @@ impl ContainerFactory<KuasarContainer> for KuasarFactory {
         req: &CreateTaskRequest,
     ) -> containerd_shim::Result<KuasarContainer> {
         rescan_pci_bus().await?;
+        prepare_bundle(&self.sandbox, &req.id).await?;
         let bundle = format!("{}/{}", KUASAR_STATE_DIR, req.id);
         let spec: Spec = read_spec(&bundle).await?;
         let annotations = spec.annotations().clone().unwrap_or_default();
@@ -152,6 +167,8 @@ impl ContainerFactory<KuasarContainer> for KuasarFactory {
         )?;
 
         let id = req.id();
+        let runc_command = RuncCommand::new(runtime, ns, &bundle, &opts);
+        let checkpoint = annotations.get(ANNOTATION_KEY_CHECKPOINT_PATH).cloned();
 
         let stdio = match read_io(&bundle, req.id(), None).await {
             Ok(io) => Stdio::new(&io.stdin, &io.stdout, &io.stderr, io.terminal),
@@ -165,7 +182,8 @@ impl ContainerFactory<KuasarContainer> for KuasarFactory {
         let mut init = InitProcess::new(
             id,
             stdio,
-            KuasarInitLifecycle::new(runc.clone(), opts.clone(), &bundle),
+            KuasarInitLifecycle::new(runc.clone(), runc_command, opts.clone(), &bundle)
+                .with_checkpoint(checkpoint),
         );
 
         self.do_create(&mut init).await?;
@@ -198,6 +216,10 @@ impl KuasarFactory {
 
     #[instrument(skip_all)]
     async fn do_create(&self, init: &mut InitProcess) -> Result<()> {
+        // the container will be restored from the checkpoint images when it starts
+        if init.lifecycle.checkpoint.is_some() {
+            return Ok(());
+        }
         let id = init.id.to_string();
         let stdio = &init.stdio;
         let opts = &init.lifecycle.opts;
@@ -350,6 +372,9 @@ impl ProcessFactory<ExecProcess> for KuasarExecFactory {
 impl ProcessLifecycle<InitProcess> for KuasarInitLifecycle {
     #[instrument(skip_all)]
     async fn start(&self, p: &mut InitProcess) -> containerd_shim::Result<()> {
+        if let Some(image_path) = &self.checkpoint {
+            return self.restore(p, image_path).await;
+        }
         if let Err(e) = self.runtime.start(p.id.as_str()).await {
             return Err(runtime_error(&p.lifecycle.bundle, e, "OCI runtime start failed").await);
         }
@@ -455,7 +480,7 @@ impl ProcessLifecycle<InitProcess> for KuasarInitLifecycle {
 
 impl KuasarInitLifecycle {
     #[instrument(skip_all)]
-    pub fn new(runtime: Runc, opts: Options, bundle: &str) -> Self {
+    pub fn new(runtime: Runc, runc_command: RuncCommand, opts: Options, bundle: &str) -> Self {
         let work_dir = Path::new(bundle).join("work");
         let mut opts = opts;
         if opts.criu_path().is_empty() {
@@ -463,11 +488,142 @@ impl KuasarInitLifecycle {
         }
         Self {
             runtime,
+            runc_command,
             opts,
             bundle: bundle.to_string(),
+            checkpoint: None,
             exit_signal: Default::default(),
         }
     }
+
+    pub fn with_checkpoint(mut self, checkpoint: Option<String>) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    #[instrument(skip_all)]
+    async fn restore(&self, p: &mut InitProcess, image_path: &str) -> Result<()> {
+        let pid_path = Path::new(&self.bundle).join(INIT_PID_FILE);
+        let no_pivot = self.opts.no_pivot_root
+            || matches!(get_mount_type("/"), Ok(m_type) if m_type == *"rootfs");
+        let mut restore_opts = RestoreOpts {
+            image_path: image_path.to_string(),
+            work_path: self.opts.criu_path.to_string(),
+            bundle: self.bundle.to_string(),
+            pid_file: pid_path.to_string_lossy().to_string(),
+            console_socket: None,
+            no_pivot,
+        };
+        let (socket, pio) = if p.stdio.terminal {
+            let s = ConsoleSocket::new().await?;
+            restore_opts.console_socket = Some(s.path.to_string_lossy().to_string());
+            (Some(s), None)
+        } else {
+            let pio = create_io(&p.id, self.opts.io_uid, self.opts.io_gid, &p.stdio)?;
+            (None, Some(pio))
+        };
+
+        let mut cmd = self.runc_command.restore(&p.id, &restore_opts);
+        if let Some(io) = pio.as_ref().and_then(|x| x.io.as_ref()) {
+            io.set(&mut cmd)
+                .map_err(other_error!(e, "failed to set io for restore"))?;
+        }
+        debug!("restore container {} from {}", p.id, image_path);
+        let res = ShimExecutor::default()
+            .execute(cmd, Box::new(|| {}), true)
+            .await;
+        if let Some(io) = pio.as_ref().and_then(|x| x.io.as_ref()) {
+            io.close_after_start();
+        }
+        match res {
+            Ok((status, _, _, stderr)) if !status.success() => {
+                if let Some(s) = socket {
+                    s.clean().await;
+                }
+                return Err(other!(
+                    "OCI runtime restore failed: {}",
+                    get_last_runtime_error(&self.bundle).await.unwrap_or(stderr)
+                ));
+            }
+            Err(e) => {
+                if let Some(s) = socket {
+                    s.clean().await;
+                }
+                return Err(runtime_error(&self.bundle, e, "OCI runtime restore failed").await);
+            }
+            Ok(_) => {}
+        }
+        copy_io_or_console(p, socket, pio, self.exit_signal.clone()).await?;
+        let pid = read_file_to_str(pid_path).await?.parse::<i32>()?;
+        p.pid = pid;
+        p.state = Status::RUNNING;
+        Ok(())
+    }
+}
+
+impl Checkpoint for KuasarContainer {
+    fn checkpoint(
+        &self,
+        req: &CheckpointTaskRequest,
+    ) -> Result<BoxFuture<'static, Result<String>>> {
+        if self.init.state != Status::RUNNING && self.init.state != Status::PAUSED {
+            return Err(Error::FailedPreconditionError(format!(
+                "container {} is {:?}, can not be checkpointed",
+                self.id, self.init.state
+            )));
+        }
+        let work_dir = Path::new(&self.bundle).join(CHECKPOINT_DIR_NAME);
+        let image_path = work_dir.join(CHECKPOINT_DIR_NAME);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let archive = format!("{}/checkpoint-{}.tar", self.bundle, now);
+        let lifecycle = self.init.lifecycle.clone();
+        let opts = CheckpointOpts::from_request(
+            req,
+            &image_path.to_string_lossy(),
+            &lifecycle.opts.criu_path,
+        )?;
+        let id = self.id.to_string();
+        let bundle = self.bundle.to_string();
+        Ok(Box::pin(async move {
+            tokio::fs::remove_dir_all(&work_dir)
+                .await
+                .unwrap_or_default();
+            let res = async {
+                tokio::fs::create_dir_all(&image_path)
+                    .await
+                    .map_err(other_error!(e, "failed to create checkpoint dir"))?;
+                let cmd = lifecycle.runc_command.checkpoint(&id, &opts);
+                debug!("checkpoint container {} to {}", id, image_path.display());
+                match ShimExecutor::default()
+                    .execute(cmd, Box::new(|| {}), true)
+                    .await
+                {
+                    Ok((status, _, _, stderr)) if !status.success() => {
+                        return Err(other!(
+                            "OCI runtime checkpoint failed: {}",
+                            get_last_runtime_error(&bundle).await.unwrap_or(stderr)
+                        ));
+                    }
+                    Err(e) => {
+                        return Err(
+                            runtime_error(&bundle, e, "OCI runtime checkpoint failed").await
+                        );
+                    }
+                    Ok(_) => {}
+                }
+                write_dump_files(&id, &bundle, &lifecycle.opts.binary_name, &work_dir).await?;
+                create_archive(&work_dir, &archive).await
+            }
+            .await;
+            tokio::fs::remove_dir_all(&work_dir)
+                .await
+                .unwrap_or_default();
+            res.map(|_| archive)
+        }))
+    }
 }
 
 #[async_trait]
@@ -492,7 +648,8 @@ impl ProcessLifecycle<ExecProcess> for KuasarExecLifecycle {
             exec_opts.io = pio.io.as_ref().cloned();
             (None, Some(pio))
         };
-        //TODO  checkpoint support
+        // exec processes are not checkpointed on their own, `runc checkpoint` of the
+        // container dumps them together with the init process.
         let exec_result = self
             .runtime
             .exec(&self.container_id, &self.spec, Some(&exec_opts))
@@ -569,9 +726,6 @@ fn get_spec_from_request(
     }
 }
 
-const DEFAULT_RUNC_ROOT: &str = "/run/containerd/runc";
-const DEFAULT_COMMAND: &str = "runc";
-
 #[instrument(skip_all)]
 pub fn create_runc(
     runtime: &str,
@@ -585,13 +739,7 @@ pub fn create_runc(
     } else {
         runtime
     };
-    let root = opts.root.as_str();
-    let root = Path::new(if root.is_empty() {
-        DEFAULT_RUNC_ROOT
-    } else {
-        root
-    })
-    .join(namespace);
+    let root = runc_root(namespace, opts);
 
     let log = bundle.as_ref().join("log.json");
     let mut gopts = GlobalOpts::default()
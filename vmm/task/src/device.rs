@@ -14,11 +14,20 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, fs::read_link, os::unix::prelude::FromRawFd, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs::read_link,
+    io::Read,
+    os::unix::{
+        fs::OpenOptionsExt,
+        prelude::{AsRawFd, FromRawFd},
+    },
+    sync::Arc,
+};
 
 use containerd_shim::{other, Error, Result};
 use lazy_static::lazy_static;
-use log::debug;
+use log::{debug, warn};
 use netlink_sys::{protocols, SocketAddr, TokioSocket};
 use tokio::sync::{
     mpsc::{channel, Receiver, Sender},
@@ -39,6 +48,8 @@ lazy_static! {
         let converters = vec![
             convert_to_scsi_device as DeviceConverter,
             convert_to_blk_device as DeviceConverter,
+            convert_to_mmio_blk_device as DeviceConverter,
+            convert_to_pmem_device as DeviceConverter,
         ];
         converters
     };
@@ -205,6 +216,21 @@ impl DeviceMonitor {
                 let real_path = read_link(entry.path()).unwrap();
                 let real_path = real_path.to_str().unwrap();
                 let real_path_parts: Vec<&str> = real_path.split('/').collect();
+                // vda -> ../../devices/virtio-mmio-cmdline/virtio-mmio.0/virtio0/block/vda/
+                if is_mmio_blk_path(&real_path_parts) {
+                    let device = Device {
+                        path: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+                        addr: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+                        r#type: DeviceType::MmioBlk,
+                    };
+                    debug!("scan add device {:?} of devpath {}", device, real_path);
+                    self.internal
+                        .lock()
+                        .await
+                        .add_device(real_path.to_string(), device)
+                        .await;
+                    continue;
+                }
                 for part in real_path_parts {
                     if part.starts_with("0000:") {
                         let device = Device {
@@ -221,6 +247,29 @@ impl DeviceMonitor {
                     }
                 }
             }
+            // pmem0 -> ../../devices/LNXSYSTM:00/LNXSYBUS:00/ACPI0012:00/ndbus0/region0/pmem0/block/pmem0
+            if dev_name.starts_with("pmem") && metadata.is_symlink() {
+                let real_path = read_link(entry.path()).unwrap();
+                let real_path = real_path.to_str().unwrap();
+                let real_path_parts: Vec<&str> =
+                    real_path.trim_end_matches('/').split('/').collect();
+                // skip the partitions of the pmem device
+                if real_path_parts.len() > 2
+                    && real_path_parts[real_path_parts.len() - 2] == "block"
+                {
+                    let device = Device {
+                        path: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+                        addr: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+                        r#type: DeviceType::Pmem,
+                    };
+                    debug!("scan add device {:?} of devpath {}", device, real_path);
+                    self.internal
+                        .lock()
+                        .await
+                        .add_device(real_path.to_string(), device)
+                        .await;
+                }
+            }
         }
     }
 }
@@ -241,6 +290,10 @@ impl DeviceMonitorInternal {
     }
 
     async fn handle_add_event(&mut self, event: Uevent) {
+        if let Some((path, state)) = online_state_file(&event) {
+            online_hotplugged(&path, state).await;
+            return;
+        }
         for converter in DEVICE_CONVERTORS.iter() {
             if let Some(device) = converter(&event) {
                 debug!("add device {:?} of devpath {}", device, event.devpath);
@@ -296,12 +349,65 @@ pub const SYSFS_SCSI_HOST_PATH: &str = "/sys/class/scsi_host";
 pub const SYSFS_SCSI_DEVICE_PATH: &str = "/sys/class/scsi_device";
 pub const SYSFS_BLK_DEVICE_PATH: &str = "/sys/class/block";
 pub const SYSFS_PCI_BUS_RESCAN_FILE: &str = "/sys/bus/pci/rescan";
+pub const SYSFS_CPU_ONLINE_FILE: &str = "online";
+pub const SYSFS_MEMORY_STATE_FILE: &str = "state";
 pub const SYSTEM_DEV_PATH: &str = "/dev";
 
+// online_state_file returns the sysfs file of the hot plugged cpu or memory block in the
+// uevent and the state to write to it, as the guest kernel does not online them by default.
+fn online_state_file(event: &Uevent) -> Option<(String, &'static str)> {
+    match &*event.subsystem {
+        "cpu" => Some((
+            format!("/sys{}/{}", event.devpath, SYSFS_CPU_ONLINE_FILE),
+            "1",
+        )),
+        "memory" => Some((
+            format!("/sys{}/{}", event.devpath, SYSFS_MEMORY_STATE_FILE),
+            "online",
+        )),
+        _ => None,
+    }
+}
+
+async fn online_hotplugged(path: &str, state: &str) {
+    // the boot cpu has no online file, and the kernel may have onlined it already
+    let current = match tokio::fs::read_to_string(path).await {
+        Ok(s) => s,
+        Err(e) => {
+            debug!("skip to online {}: {}", path, e);
+            return;
+        }
+    };
+    if current.trim() == state {
+        return;
+    }
+    debug!("online hot plugged {}", path);
+    if let Err(e) = tokio::fs::write(path, state).await {
+        warn!("failed to write {} to {}: {}", state, path, e);
+    }
+}
+
+const EXT4_SUPERBLOCK_OFFSET: usize = 1024;
+const EXT4_MAGIC: u16 = 0xEF53;
+const EXT4_MAGIC_OFFSET: usize = 56;
+const EXT4_LABEL_OFFSET: usize = 120;
+const EXT4_LABEL_LEN: usize = 16;
+const BLKFLSBUF: u32 = 0x1261;
+// the size of the direct read of the head of a device, which holds the ext4 superblock
+const DIRECT_READ_SIZE: usize = 4096;
+
+// DirectReadBuffer is aligned to the page size, as the buffers of O_DIRECT reads have to be.
+#[repr(C, align(4096))]
+struct DirectReadBuffer([u8; DIRECT_READ_SIZE]);
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum DeviceType {
     Blk,
     Scsi,
+    // the virtio-mmio block device, its addr is the device path in guest, like /dev/vdb
+    MmioBlk,
+    // the nvdimm device, its addr is the device path in guest, like /dev/pmem1
+    Pmem,
 }
 
 #[derive(Clone, Debug)]
@@ -365,6 +471,107 @@ pub fn convert_to_blk_device(event: &Uevent) -> Option<Device> {
     }
 }
 
+pub fn convert_to_mmio_blk_device(event: &Uevent) -> Option<Device> {
+    let path_parts: Vec<_> = event.devpath.split('/').collect();
+    if event.subsystem == "block" && !event.devname.is_empty() && is_mmio_blk_path(&path_parts) {
+        let path = format!("{}/{}", SYSTEM_DEV_PATH, &event.devname);
+        Some(Device {
+            path: path.to_string(),
+            addr: path,
+            r#type: DeviceType::MmioBlk,
+        })
+    } else {
+        None
+    }
+}
+
+pub fn convert_to_pmem_device(event: &Uevent) -> Option<Device> {
+    let path_parts: Vec<_> = event.devpath.split('/').collect();
+    let length = path_parts.len();
+    if length > 2
+        && event.subsystem == "block"
+        && event.devname.starts_with("pmem")
+        && path_parts[length - 2] == "block"
+    {
+        let path = format!("{}/{}", SYSTEM_DEV_PATH, &event.devname);
+        Some(Device {
+            path: path.to_string(),
+            addr: path,
+            r#type: DeviceType::Pmem,
+        })
+    } else {
+        None
+    }
+}
+
+// is_mmio_blk_path tells if the sysfs path is of a virtio-mmio block device but not a partition,
+// like /devices/platform/a003c00.virtio_mmio/virtio1/block/vdb
+fn is_mmio_blk_path(path_parts: &[&str]) -> bool {
+    let path_parts: Vec<&str> = path_parts
+        .iter()
+        .copied()
+        .filter(|p| !p.is_empty())
+        .collect();
+    let length = path_parts.len();
+    length > 4
+        && (path_parts[length - 4].contains("virtio-mmio")
+            || path_parts[length - 4].contains("virtio_mmio"))
+        && path_parts[length - 3].starts_with("virtio")
+        && path_parts[length - 2] == "block"
+}
+
+// flush_block_device_buffers drops the cached pages of the block device, which are stale
+// if the hypervisor has changed the backing file of the device, like what firecracker does.
+pub fn flush_block_device_buffers(path: &str) -> Result<()> {
+    let file = std::fs::File::open(path).map_err(|e| other!("failed to open {}, {}", path, e))?;
+    let res = unsafe { libc::ioctl(file.as_raw_fd(), BLKFLSBUF as _) };
+    if res < 0 {
+        return Err(other!(
+            "failed to flush buffers of {}, {}",
+            path,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+// find_block_device_by_label returns the path of the block device with the given ext4 label.
+pub fn find_block_device_by_label(label: &str) -> Option<String> {
+    let entries = std::fs::read_dir(SYSFS_BLK_DEVICE_PATH).ok()?;
+    for entry in entries.flatten() {
+        let path = format!(
+            "{}/{}",
+            SYSTEM_DEV_PATH,
+            entry.file_name().to_string_lossy()
+        );
+        if read_ext4_label(&path).as_deref() == Some(label) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+fn read_ext4_label(path: &str) -> Option<String> {
+    // the device may be a placeholder whose backing file is changed later, so its head is read
+    // with O_DIRECT to bypass the stale page cache, instead of flushing the cache of every device,
+    // or buffered if the filesystem of the path does not support direct io.
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+        .or_else(|_| std::fs::File::open(path))
+        .ok()?;
+    let mut buf = Box::new(DirectReadBuffer([0u8; DIRECT_READ_SIZE]));
+    file.read_exact(&mut buf.0).ok()?;
+    let sb = &buf.0[EXT4_SUPERBLOCK_OFFSET..];
+    if u16::from_le_bytes([sb[EXT4_MAGIC_OFFSET], sb[EXT4_MAGIC_OFFSET + 1]]) != EXT4_MAGIC {
+        return None;
+    }
+    let label = &sb[EXT4_LABEL_OFFSET..EXT4_LABEL_OFFSET + EXT4_LABEL_LEN];
+    let end = label.iter().position(|&b| b == 0).unwrap_or(EXT4_LABEL_LEN);
+    Some(String::from_utf8_lossy(&label[..end]).to_string())
+}
+
 pub async fn scan_scsi_bus(scsi_addr: &str) -> containerd_shim::Result<()> {
     let tokens: Vec<&str> = scsi_addr.split(':').collect();
     if tokens.len() != 2 {
@@ -392,3 +599,114 @@ pub async fn scan_scsi_bus(scsi_addr: &str) -> containerd_shim::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::device::{
+        convert_to_blk_device, convert_to_mmio_blk_device, convert_to_pmem_device,
+        online_state_file, read_ext4_label, DeviceType, Uevent, EXT4_LABEL_OFFSET,
+        EXT4_MAGIC_OFFSET,
+    };
+
+    fn block_event(devpath: &str, devname: &str) -> Uevent {
+        Uevent {
+            action: "add".to_string(),
+            devpath: devpath.to_string(),
+            devname: devname.to_string(),
+            subsystem: "block".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_convert_to_mmio_blk_device() {
+        let event = block_event(
+            "/devices/virtio-mmio-cmdline/virtio-mmio.1/virtio1/block/vdb",
+            "vdb",
+        );
+        let device = convert_to_mmio_blk_device(&event).unwrap();
+        assert_eq!(device.path, "/dev/vdb");
+        assert_eq!(device.addr, "/dev/vdb");
+        assert_eq!(device.r#type, DeviceType::MmioBlk);
+        assert!(convert_to_blk_device(&event).is_none());
+
+        let event = block_event(
+            "/devices/platform/a003c00.virtio_mmio/virtio1/block/vdb",
+            "vdb",
+        );
+        assert!(convert_to_mmio_blk_device(&event).is_some());
+
+        let event = block_event(
+            "/devices/virtio-mmio-cmdline/virtio-mmio.1/virtio1/block/vdb/vdb1",
+            "vdb1",
+        );
+        assert!(convert_to_mmio_blk_device(&event).is_none());
+
+        let event = block_event("/devices/pci0000:00/0000:00:07.0/virtio6/block/vdb", "vdb");
+        assert!(convert_to_mmio_blk_device(&event).is_none());
+    }
+
+    #[test]
+    fn test_convert_to_pmem_device() {
+        let event = block_event(
+            "/devices/LNXSYSTM:00/LNXSYBUS:00/ACPI0012:00/ndbus0/region1/pmem1/block/pmem1",
+            "pmem1",
+        );
+        let device = convert_to_pmem_device(&event).unwrap();
+        assert_eq!(device.addr, "/dev/pmem1");
+        assert_eq!(device.r#type, DeviceType::Pmem);
+
+        let event = block_event(
+            "/devices/LNXSYSTM:00/LNXSYBUS:00/ACPI0012:00/ndbus0/region1/pmem1/block/pmem1/pmem1p1",
+            "pmem1p1",
+        );
+        assert!(convert_to_pmem_device(&event).is_none());
+    }
+
+    #[test]
+    fn test_online_state_file() {
+        let event = Uevent {
+            action: "add".to_string(),
+            devpath: "/devices/system/cpu/cpu2".to_string(),
+            subsystem: "cpu".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            online_state_file(&event),
+            Some(("/sys/devices/system/cpu/cpu2/online".to_string(), "1"))
+        );
+
+        let event = Uevent {
+            action: "add".to_string(),
+            devpath: "/devices/system/memory/memory40".to_string(),
+            subsystem: "memory".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            online_state_file(&event),
+            Some((
+                "/sys/devices/system/memory/memory40/state".to_string(),
+                "online"
+            ))
+        );
+
+        let event = block_event("/devices/pci0000:00/0000:00:07.0/virtio6/block/vdb", "vdb");
+        assert!(online_state_file(&event).is_none());
+    }
+
+    #[test]
+    fn test_read_ext4_label() {
+        let path = "/tmp/kuasar-test_read_ext4_label.img";
+        let mut data = vec![0u8; 4096];
+        data[1024 + EXT4_MAGIC_OFFSET] = 0x53;
+        data[1024 + EXT4_MAGIC_OFFSET + 1] = 0xEF;
+        data[1024 + EXT4_LABEL_OFFSET..1024 + EXT4_LABEL_OFFSET + 6].copy_from_slice(b"kuasar");
+        std::fs::write(path, &data).unwrap();
+        assert_eq!(read_ext4_label(path), Some("kuasar".to_string()));
+
+        data[1024 + EXT4_MAGIC_OFFSET] = 0;
+        std::fs::write(path, &data).unwrap();
+        assert_eq!(read_ext4_label(path), None);
+        std::fs::remove_file(path).unwrap();
+    }
+}
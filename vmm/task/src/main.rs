@@ -45,6 +45,7 @@ use tracing_subscriber::{
 use vmm_common::{
     api::{sandbox_ttrpc::create_sandbox_service, streaming_ttrpc::create_streaming},
     mount::mount,
+    storage::SHAREFS_TYPE_BLOCK,
     trace, ETC_RESOLV, IPC_NAMESPACE, KUASAR_STATE_DIR, PID_NAMESPACE, RESOLV_FILENAME,
     UTS_NAMESPACE,
 };
@@ -57,6 +58,7 @@ use crate::{
     task::create_task_service,
 };
 
+mod checkpoint;
 mod config;
 #[cfg(not(feature = "youki"))]
 mod container;
@@ -164,8 +166,12 @@ async fn initialize() -> anyhow::Result<TaskConfig> {
         "virtiofs" => {
             mount_static_mounts(SHAREFS_VIRTIOFS_MOUNTS.clone()).await?;
         }
+        // the files of containers are in block devices, mounted when containers are created
+        SHAREFS_TYPE_BLOCK => {
+            tokio::fs::create_dir_all(KUASAR_STATE_DIR).await?;
+        }
         _ => {
-            warn!("sharefs_type should be either 9p or virtiofs");
+            warn!("sharefs_type should be either 9p, virtiofs or block");
         }
     }
     if config.debug {
@@ -8,7 +8,9 @@ use std::{
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     ops::Deref,
+    path::Path,
     str::FromStr,
+    time::Duration,
 };
 
 use containerd_shim::{
@@ -16,22 +18,31 @@ use containerd_shim::{
     other, other_error,
     protos::protobuf::EnumOrUnknown,
 };
-use futures::{future, TryStreamExt};
+use futures::{future, StreamExt, TryStreamExt};
 use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use log::warn;
+use netlink_packet_core::{NetlinkMessage, NLM_F_ACK, NLM_F_CREATE, NLM_F_REPLACE, NLM_F_REQUEST};
 use netlink_packet_route::{
     address::{AddressAttribute, AddressMessage},
     link::{LinkAttribute, LinkFlag, LinkMessage},
+    neighbour::{NeighbourAddress, NeighbourAttribute, NeighbourMessage, NeighbourState},
     route::{
         RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteProtocol, RouteScope,
         RouteType,
     },
-    AddressFamily,
+    AddressFamily, RouteNetlinkMessage,
 };
 use nix::errno::Errno;
-use rtnetlink::{new_connection, IpVersion};
-use vmm_common::api::sandbox::{IPAddress, IPFamily, Interface, Route};
+use rtnetlink::{new_connection, try_nl, IpVersion};
+use vmm_common::api::sandbox::{IPAddress, IPFamily, IPv6Config, Interface, Neighbour, Route};
+
+const IPV6_CONF_DIR: &str = "/proc/sys/net/ipv6/conf";
+const NET_CLASS_DIR: &str = "/sys/class/net";
+const LINK_WAIT_RETRIES: u32 = 50;
+const LINK_WAIT_INTERVAL: Duration = Duration::from_millis(100);
 
 /// Search criteria to use when looking for a link in `find_link`.
+#[derive(Clone, Copy)]
 pub enum LinkFilter<'a> {
     /// Find by link name.
     Name(&'a str),
@@ -87,13 +98,19 @@ impl Handle {
             // target link. filter using name or family is supported, but
             // we cannot use that to find target link.
             // let's try if hardware address filter works. -_-
-            let link = self.find_link(LinkFilter::Address(&intf.hwAddr)).await?;
+            let link = self.wait_link(LinkFilter::Address(&intf.hwAddr)).await?;
 
             // Bring down interface if it is UP
             if link.is_up() {
                 self.enable_link(link.index(), false).await?;
             }
 
+            // Apply the IPv6 settings before adding addresses and bringing it up,
+            // so that the link-local address and SLAAC follow the settings on the host.
+            if let Some(ipv6) = intf.ipv6.as_ref() {
+                set_ipv6_config(&link.name(), ipv6).await?;
+            }
+
             // Delete all addresses associated with the link
             let addresses = self
                 .list_addresses(AddressFilter::LinkIndex(link.index()))
@@ -131,13 +148,7 @@ impl Handle {
             if link.name() != intf.name.as_str() {
                 let existed_link = self.find_link(LinkFilter::Name(&intf.name)).await;
                 if let Ok(l) = existed_link {
-                    let mut request = self.handle.link().set(l.index());
-                    request.message_mut().header = l.header.clone();
-                    request
-                        .name(format!("{}-tmp", l.name()))
-                        .execute()
-                        .await
-                        .map_err(other_error!(e, "failed to execute netlink request"))?;
+                    self.rename_link(&l, format!("{}-tmp", l.name())).await?;
                 }
             }
             request
@@ -148,10 +159,37 @@ impl Handle {
                 .execute()
                 .await
                 .map_err(other_error!(e, "failed to execute netlink request"))?;
+
+            if let Err(e) = set_rps_cpus(&intf.name).await {
+                warn!("failed to set rps cpus of {}: {}", intf.name, e);
+            }
         }
         Ok(())
     }
 
+    async fn rename_link(&self, link: &Link, name: String) -> Result<()> {
+        let mut request = self.handle.link().set(link.index());
+        request.message_mut().header = link.header.clone();
+        request
+            .name(name)
+            .execute()
+            .await
+            .map_err(other_error!(e, "failed to execute netlink request"))?;
+        Ok(())
+    }
+
+    // wait_link finds the link, and waits for it for a while if it is not found,
+    // as the link of a hot plugged device shows up after the driver probes it.
+    async fn wait_link(&self, filter: LinkFilter<'_>) -> Result<Link> {
+        for _ in 0..LINK_WAIT_RETRIES {
+            if let Ok(link) = self.find_link(filter).await {
+                return Ok(link);
+            }
+            tokio::time::sleep(LINK_WAIT_INTERVAL).await;
+        }
+        self.find_link(filter).await
+    }
+
     async fn find_link(&self, filter: LinkFilter<'_>) -> Result<Link> {
         let request = self.handle.link().get();
 
@@ -435,6 +473,39 @@ impl Handle {
         Ok(())
     }
 
+    /// Adds or replaces the ARP/NDP neighbour entries, the entries not in the list are kept.
+    pub async fn update_neighbours<I>(&mut self, list: I) -> Result<()>
+    where
+        I: IntoIterator<Item = Neighbour>,
+    {
+        for neigh in list {
+            let link = self.find_link(LinkFilter::Name(&neigh.device)).await?;
+            let message = neighbour_message(link.index(), &neigh)?;
+            self.add_neighbour(message).await.map_err(other_error!(
+                e,
+                format!(
+                    "failed to add neighbour {} on {}",
+                    neigh.destination, neigh.device
+                )
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    async fn add_neighbour(
+        &self,
+        message: NeighbourMessage,
+    ) -> std::result::Result<(), rtnetlink::Error> {
+        let mut req = NetlinkMessage::from(RouteNetlinkMessage::NewNeighbour(message));
+        req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE;
+        let mut response = self.handle.clone().request(req)?;
+        while let Some(message) = response.next().await {
+            try_nl!(message);
+        }
+        Ok(())
+    }
+
     async fn delete_routes<I>(&mut self, routes: I) -> Result<()>
     where
         I: IntoIterator<Item = RouteMessage>,
@@ -567,9 +638,9 @@ impl TryFrom<Address> for IPAddress {
 
     fn try_from(value: Address) -> Result<Self> {
         let family = if value.is_ipv6() {
-            IPFamily::v4
-        } else {
             IPFamily::v6
+        } else {
+            IPFamily::v4
         };
 
         let mut address = value.address();
@@ -622,6 +693,81 @@ impl Address {
     }
 }
 
+// set_ipv6_config writes the IPv6 sysctls of the link, the other settings are
+// not applied if IPv6 is disabled on the link.
+async fn set_ipv6_config(name: &str, config: &IPv6Config) -> Result<()> {
+    let dir = Path::new(IPV6_CONF_DIR).join(name);
+    if !dir.exists() {
+        warn!(
+            "IPv6 is not supported in guest, ignore the IPv6 config of {}",
+            name
+        );
+        return Ok(());
+    }
+    let mut sysctls = vec![("disable_ipv6", (config.disabled as u8).to_string())];
+    if !config.disabled {
+        sysctls.push(("addr_gen_mode", config.addr_gen_mode.to_string()));
+        sysctls.push(("accept_ra", config.accept_ra.to_string()));
+        sysctls.push(("autoconf", (config.autoconf as u8).to_string()));
+    }
+    for (key, value) in sysctls {
+        tokio::fs::write(dir.join(key), value)
+            .await
+            .map_err(other_error!(
+                e,
+                format!("failed to set {} of {}", key, name)
+            ))?;
+    }
+    Ok(())
+}
+
+// set_rps_cpus steers the received packets to all the cpus of the guest when the link
+// has fewer rx queues than cpus, otherwise every cpu already has its own queue.
+async fn set_rps_cpus(name: &str) -> Result<()> {
+    let cpus = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let mut rx_queues = vec![];
+    let mut entries = tokio::fs::read_dir(Path::new(NET_CLASS_DIR).join(name).join("queues"))
+        .await
+        .map_err(other_error!(
+            e,
+            format!("failed to read queues of {}", name)
+        ))?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name().to_string_lossy().starts_with("rx-") {
+            rx_queues.push(entry.path());
+        }
+    }
+    if rx_queues.is_empty() || rx_queues.len() >= cpus {
+        return Ok(());
+    }
+    let mask = cpu_mask(cpus);
+    for queue in rx_queues {
+        tokio::fs::write(queue.join("rps_cpus"), &mask)
+            .await
+            .map_err(other_error!(
+                e,
+                format!("failed to set rps cpus of {}", queue.display())
+            ))?;
+    }
+    Ok(())
+}
+
+// cpu_mask returns the mask of the first `cpus` cpus in the format of sysfs,
+// which is hex words of 32 bits separated by commas, the highest word first.
+fn cpu_mask(cpus: usize) -> String {
+    let mut words = vec![];
+    let mut remaining = cpus;
+    while remaining > 0 {
+        let bits = remaining.min(32);
+        words.push(format!("{:08x}", (u64::MAX >> (64 - bits)) as u32));
+        remaining -= bits;
+    }
+    words.reverse();
+    words.join(",")
+}
+
 fn parse_mac_address(addr: &str) -> Result<[u8; 6]> {
     let mut split = addr.splitn(6, ':');
 
@@ -648,3 +794,106 @@ fn parse_mac_address(addr: &str) -> Result<[u8; 6]> {
 
     Ok(arr)
 }
+
+fn neighbour_message(index: u32, neigh: &Neighbour) -> Result<NeighbourMessage> {
+    let ip = IpAddr::from_str(&neigh.destination).map_err(other_error!(
+        e,
+        format!("invalid neighbour address: {}", neigh.destination)
+    ))?;
+
+    let mut message = NeighbourMessage::default();
+    message.header.ifindex = index;
+    message.header.state = NeighbourState::from(neigh.state as u16);
+    let destination = match ip {
+        IpAddr::V4(v4) => {
+            message.header.family = AddressFamily::Inet;
+            NeighbourAddress::Inet(v4)
+        }
+        IpAddr::V6(v6) => {
+            message.header.family = AddressFamily::Inet6;
+            NeighbourAddress::Inet6(v6)
+        }
+    };
+    message
+        .attributes
+        .push(NeighbourAttribute::Destination(destination));
+    if !neigh.lladdr.is_empty() {
+        let lladdr = parse_mac_address(&neigh.lladdr)?;
+        message
+            .attributes
+            .push(NeighbourAttribute::LinkLocalAddress(lladdr.to_vec()));
+    }
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use netlink_packet_route::{
+        neighbour::{NeighbourAddress, NeighbourAttribute, NeighbourState},
+        AddressFamily,
+    };
+    use vmm_common::api::sandbox::Neighbour;
+
+    use crate::netlink::{cpu_mask, neighbour_message};
+
+    #[test]
+    fn test_cpu_mask() {
+        assert_eq!(cpu_mask(1), "00000001");
+        assert_eq!(cpu_mask(4), "0000000f");
+        assert_eq!(cpu_mask(32), "ffffffff");
+        assert_eq!(cpu_mask(33), "00000001,ffffffff");
+        assert_eq!(cpu_mask(72), "000000ff,ffffffff,ffffffff");
+    }
+
+    #[test]
+    fn test_neighbour_message() {
+        let neigh = Neighbour {
+            destination: "10.0.0.1".to_string(),
+            device: "eth0".to_string(),
+            lladdr: "02:42:ac:11:00:02".to_string(),
+            // NUD_PERMANENT
+            state: 0x80,
+            ..Default::default()
+        };
+        let message = neighbour_message(2, &neigh).unwrap();
+        assert_eq!(message.header.ifindex, 2);
+        assert_eq!(message.header.state, NeighbourState::Permanent);
+        assert_eq!(message.header.family, AddressFamily::Inet);
+        assert_eq!(
+            message.attributes,
+            vec![
+                NeighbourAttribute::Destination(NeighbourAddress::Inet(Ipv4Addr::new(10, 0, 0, 1))),
+                NeighbourAttribute::LinkLocalAddress(vec![0x02, 0x42, 0xac, 0x11, 0x00, 0x02]),
+            ]
+        );
+
+        let neigh = Neighbour {
+            destination: "fd00::1".to_string(),
+            device: "eth0".to_string(),
+            state: 0x80,
+            ..Default::default()
+        };
+        let message = neighbour_message(2, &neigh).unwrap();
+        assert_eq!(message.header.family, AddressFamily::Inet6);
+        assert_eq!(
+            message.attributes,
+            vec![NeighbourAttribute::Destination(NeighbourAddress::Inet6(
+                Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)
+            ))]
+        );
+
+        let invalid = Neighbour {
+            destination: "10.0.0.256".to_string(),
+            ..neigh.clone()
+        };
+        assert!(neighbour_message(2, &invalid).is_err());
+        let invalid = Neighbour {
+            lladdr: "02:42:ac".to_string(),
+            ..neigh
+        };
+        assert!(neighbour_message(2, &invalid).is_err());
+    }
+}
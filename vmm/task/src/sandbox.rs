@@ -14,7 +14,14 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, os::fd::AsRawFd, path::Path, process::exit, time::Duration};
+use std::{
+    collections::HashMap,
+    fs::Permissions,
+    os::{fd::AsRawFd, unix::fs::PermissionsExt},
+    path::Path,
+    process::exit,
+    time::Duration,
+};
 
 use containerd_sandbox::{cri::api::v1::NamespaceMode, PodSandboxConfig};
 use containerd_shim::{
@@ -25,25 +32,38 @@ use containerd_shim::{
 };
 use log::{debug, warn};
 use nix::{
+    libc::MNT_DETACH,
     sched::{unshare, CloneFlags},
     unistd::{fork, getpid, pause, pipe, ForkResult, Pid},
 };
-use tokio::fs::File;
+use tokio::{fs::File, sync::Mutex};
 use vmm_common::{
-    mount::{mount, unmount},
-    storage::{Storage, DRIVERBLKTYPE, DRIVEREPHEMERALTYPE, DRIVERSCSITYPE},
+    mount::{bind_mount, mount, unmount},
+    storage::{
+        block_share_label, Storage, BLOCK_SHARE_DIR, DRIVERBLKTYPE, DRIVEREPHEMERALTYPE,
+        DRIVERLOCALTYPE, DRIVERMMIOBLKTYPE, DRIVERNVDIMMTYPE, DRIVERSCSITYPE,
+    },
     HOSTNAME_FILENAME, IPC_NAMESPACE, KUASAR_STATE_DIR, PID_NAMESPACE, SANDBOX_NS_PATH,
     UTS_NAMESPACE,
 };
 
 use crate::{
-    device::{scan_scsi_bus, Device, DeviceMatcher, DeviceMonitor, DeviceType},
+    device::{
+        find_block_device_by_label, flush_block_device_buffers, scan_scsi_bus, Device,
+        DeviceMatcher, DeviceMonitor, DeviceType,
+    },
     CLONE_FLAG_TABLE,
 };
 
+const BLOCK_SHARE_TIMEOUT_IN_SEC: u64 = 10;
+const LOCAL_STORAGE_MODE_OPTION: &str = "mode=";
+const DAX_FSTYPES: [&str; 2] = ["ext4", "xfs"];
+
 pub struct SandboxResources {
     storages: Vec<Storage>,
     device_monitor: DeviceMonitor,
+    // the mounts of block share of each container, the device mount is the last one.
+    block_shares: HashMap<String, Vec<String>>,
 }
 
 impl SandboxResources {
@@ -53,6 +73,40 @@ impl SandboxResources {
         Self {
             storages: vec![],
             device_monitor,
+            block_shares: HashMap::new(),
+        }
+    }
+
+    async fn mount_block_share(&mut self, container_id: &str, device: &str) -> Result<()> {
+        // the cached pages of the device are stale if it is attached before boot
+        // with a placeholder and its backing file is changed after that
+        flush_block_device_buffers(device)?;
+        let label = block_share_label(container_id);
+        let mount_point = format!("{}/{}", BLOCK_SHARE_DIR, label);
+        mkdir(&mount_point, 0o711).await?;
+        debug!(
+            "mount block share {} of container {} to {}",
+            device, container_id, mount_point
+        );
+        mount(Some("ext4"), Some(device), &[], &mount_point).map_err(other_error!(e, ""))?;
+        let mut mounts = vec![];
+        let res = bind_block_share(&mount_point, &mut mounts).await;
+        mounts.push(mount_point);
+        self.block_shares.insert(container_id.to_string(), mounts);
+        if let Err(e) = res {
+            self.remove_block_share(container_id).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn remove_block_share(&mut self, container_id: &str) {
+        if let Some(mounts) = self.block_shares.remove(container_id) {
+            for m in mounts {
+                unmount(&m, MNT_DETACH).unwrap_or_else(|e| {
+                    warn!("failed to unmount {} of block share, {}", m, e);
+                });
+            }
         }
     }
 
@@ -68,6 +122,7 @@ impl SandboxResources {
             s.defer(container_id);
         }
         self.gc_storages().await?;
+        self.remove_block_share(container_id).await;
         Ok(())
     }
 
@@ -89,8 +144,17 @@ impl SandboxResources {
             DRIVERBLKTYPE => {
                 self.handle_blk_storage(&mut storage).await?;
             }
+            DRIVERMMIOBLKTYPE => {
+                self.handle_mmio_blk_storage(&mut storage).await?;
+            }
+            DRIVERNVDIMMTYPE => {
+                self.handle_nvdimm_storage(&mut storage).await?;
+            }
+            DRIVERLOCALTYPE => {
+                handle_local_storage(&storage).await?;
+            }
             _ => {
-                unimplemented!("storage driver not implemented {}", storage.driver)
+                return Err(other!("storage driver {} not supported", storage.driver));
             }
         }
         self.storages.push(storage);
@@ -140,6 +204,34 @@ impl SandboxResources {
         Ok(())
     }
 
+    async fn handle_mmio_blk_storage(&mut self, storage: &mut Storage) -> Result<()> {
+        // Retrieve the device by its path, which is known by the hypervisor.
+        let device = self
+            .get_device(&storage.source, DeviceType::MmioBlk)
+            .await?;
+        let path = device.path.to_string();
+        // the device may be attached before boot, and its backing file changed after that
+        flush_block_device_buffers(&path)?;
+        storage.source = path;
+
+        mount_storage(storage).await?;
+        Ok(())
+    }
+
+    async fn handle_nvdimm_storage(&mut self, storage: &mut Storage) -> Result<()> {
+        let device = self.get_device(&storage.source, DeviceType::Pmem).await?;
+        storage.source = device.path.to_string();
+        // the pages of the files on pmem are mapped directly, bypassing the guest page cache
+        if DAX_FSTYPES.contains(&storage.fstype.as_str())
+            && !storage.options.iter().any(|o| o.starts_with("dax"))
+        {
+            storage.options.push("dax".to_string());
+        }
+
+        mount_storage(storage).await?;
+        Ok(())
+    }
+
     async fn get_device(&self, addr: &str, ty: DeviceType) -> Result<Device> {
         let mut s = self
             .device_monitor
@@ -156,6 +248,75 @@ impl SandboxResources {
     }
 }
 
+// prepare_bundle makes sure the bundle of container is in the state dir, if not, the
+// hypervisor has no shared filesystem, and the files of container are in a block device,
+// which has to be mounted and bound into the state dir. The device is waited for without
+// holding the sandbox, so that the other containers are not blocked by it.
+pub async fn prepare_bundle(sandbox: &Mutex<SandboxResources>, container_id: &str) -> Result<()> {
+    let bundle = format!("{}/{}", KUASAR_STATE_DIR, container_id);
+    if Path::new(&bundle).exists() {
+        return Ok(());
+    }
+    let label = block_share_label(container_id);
+    let start = std::time::Instant::now();
+    let device = loop {
+        let l = label.clone();
+        let device = tokio::task::spawn_blocking(move || find_block_device_by_label(&l))
+            .await
+            .map_err(other_error!(
+                e,
+                "failed to join the thread of finding device"
+            ))?;
+        if let Some(d) = device {
+            break d;
+        }
+        if start.elapsed() > Duration::from_secs(BLOCK_SHARE_TIMEOUT_IN_SEC) {
+            return Err(other!(
+                "timeout waiting for block share of container {}",
+                container_id
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    sandbox
+        .lock()
+        .await
+        .mount_block_share(container_id, &device)
+        .await
+}
+
+// bind_block_share binds every entry of the block share into the state dir. The same entries,
+// like the hosts file, may be bound by many containers, as they have the same content,
+// it is fine to unmount any of them when a container is removed.
+async fn bind_block_share(mount_point: &str, mounts: &mut Vec<String>) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(mount_point).await.map_err(io_error!(
+        e,
+        "failed to read dir {}",
+        mount_point
+    ))?;
+    while let Some(entry) =
+        entries
+            .next_entry()
+            .await
+            .map_err(io_error!(e, "failed to read dir {}", mount_point))?
+    {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == "lost+found" {
+            continue;
+        }
+        let source = entry.path();
+        let target = format!("{}/{}", KUASAR_STATE_DIR, name);
+        if source.is_dir() {
+            mkdir(&target, 0o711).await?;
+        } else {
+            ensure_destination_file_exists(Path::new(&target)).await?;
+        }
+        bind_mount(&source, &target, &["rbind".to_string()]).map_err(other_error!(e, ""))?;
+        mounts.push(target);
+    }
+    Ok(())
+}
+
 async fn mount_storage(storage: &Storage) -> Result<()> {
     let src_path = Path::new(&storage.source);
     if storage.fstype == "bind" && !src_path.is_dir() {
@@ -176,7 +337,37 @@ async fn mount_storage(storage: &Storage) -> Result<()> {
     Ok(())
 }
 
+// handle_local_storage creates a dir in guest shared by the containers of the sandbox,
+// the permission of the dir can be set by the option like "mode=0777".
+async fn handle_local_storage(storage: &Storage) -> Result<()> {
+    tokio::fs::create_dir_all(&storage.mount_point)
+        .await
+        .map_err(other_error!(
+            e,
+            format!("failed to create dir {}", storage.mount_point)
+        ))?;
+    for o in &storage.options {
+        if let Some(mode) = o.strip_prefix(LOCAL_STORAGE_MODE_OPTION) {
+            let mode = u32::from_str_radix(mode, 8)
+                .map_err(other_error!(e, format!("invalid mode option {}", o)))?;
+            tokio::fs::set_permissions(&storage.mount_point, Permissions::from_mode(mode))
+                .await
+                .map_err(other_error!(
+                    e,
+                    format!("failed to set mode of {}", storage.mount_point)
+                ))?;
+        }
+    }
+    Ok(())
+}
+
 async fn unmount_storage(storage: &Storage) -> Result<()> {
+    if storage.driver == DRIVERLOCALTYPE {
+        tokio::fs::remove_dir_all(&storage.mount_point)
+            .await
+            .map_err(other_error!(e, ""))?;
+        return Ok(());
+    }
     let src_path = Path::new(&storage.source);
     unmount(&storage.mount_point, 0).map_err(other_error!(e, ""))?;
     if storage.fstype == "bind" && !src_path.is_dir() {
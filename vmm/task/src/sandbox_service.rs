@@ -24,20 +24,20 @@ use std::{
 use async_trait::async_trait;
 use containerd_sandbox::PodSandboxConfig;
 use containerd_shim::{
-    error::Result,
-    io_error, other, other_error,
-    protos::{protobuf::MessageDyn, topics::TASK_OOM_EVENT_TOPIC},
-    util::convert_to_any,
-    Error, TtrpcContext, TtrpcResult,
+    error::Result, io_error, other, other_error, protos::protobuf::MessageDyn,
+    util::convert_to_any, Error, TtrpcContext, TtrpcResult,
 };
-use log::debug;
+use log::{debug, error};
 use nix::{
     sys::time::{TimeSpec, TimeValLike},
     time::{clock_gettime, clock_settime, ClockId},
 };
 use tokio::{
     io::AsyncWriteExt,
-    sync::{mpsc::Receiver, Mutex},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
 };
 use vmm_common::{
     api,
@@ -46,27 +46,32 @@ use vmm_common::{
         events::Envelope,
         sandbox::{
             CheckRequest, ExecVMProcessRequest, ExecVMProcessResponse, SetupSandboxRequest,
-            SyncClockPacket, UpdateInterfacesRequest, UpdateRoutesRequest,
+            SyncClockPacket, UpdateInterfacesRequest, UpdateNeighboursRequest, UpdateRoutesRequest,
         },
     },
 };
 
 use crate::{netlink::Handle, sandbox::setup_sandbox, NAMESPACE};
 
+// The max number of events buffered in the guest before the host fetches them.
+const EVENT_BUFFER_SIZE: usize = 1024;
+
 pub struct SandboxService {
     pub namespace: String,
     pub handle: Arc<Mutex<Handle>>,
-    #[allow(clippy::type_complexity)]
-    pub rx: Arc<Mutex<Receiver<(String, Box<dyn MessageDyn>)>>>,
+    pub rx: Arc<Mutex<Receiver<Envelope>>>,
 }
 
 impl SandboxService {
     pub fn new(rx: Receiver<(String, Box<dyn MessageDyn>)>) -> Result<Self> {
         let handle = Handle::new()?;
+        let namespace = NAMESPACE.to_string();
+        let (tx, envelope_rx) = channel(EVENT_BUFFER_SIZE);
+        relay_events(rx, tx, namespace.clone());
         Ok(Self {
-            namespace: NAMESPACE.to_string(),
+            namespace,
             handle: Arc::new(Mutex::new(handle)),
-            rx: Arc::new(Mutex::new(rx)),
+            rx: Arc::new(Mutex::new(envelope_rx)),
         })
     }
 
@@ -99,6 +104,19 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
         Ok(Empty::new())
     }
 
+    async fn update_neighbours(
+        &self,
+        _ctx: &TtrpcContext,
+        req: UpdateNeighboursRequest,
+    ) -> TtrpcResult<Empty> {
+        self.handle
+            .lock()
+            .await
+            .update_neighbours(req.neighbours)
+            .await?;
+        Ok(Empty::new())
+    }
+
     async fn setup_sandbox(
         &self,
         _ctx: &TtrpcContext,
@@ -134,6 +152,13 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
         // Set Routes
         self.handle.lock().await.update_routes(req.routes).await?;
 
+        // Set Neighbours
+        self.handle
+            .lock()
+            .await
+            .update_neighbours(req.neighbours)
+            .await?;
+
         Ok(Empty::new())
     }
 
@@ -181,26 +206,45 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
     }
 
     async fn get_events(&self, _ctx: &TtrpcContext, _: Empty) -> TtrpcResult<Envelope> {
-        while let Some((topic, event)) = self.rx.lock().await.recv().await {
-            debug!("received event {:?}", event);
-            // Only OOM Event is supported.
-            // TODO: Support all topic
-            if topic != TASK_OOM_EVENT_TOPIC {
-                continue;
-            }
-
-            let mut resp = Envelope::new();
-            resp.set_timestamp(SystemTime::now().into());
-            resp.set_namespace(self.namespace.to_string());
-            resp.set_topic(topic);
-            resp.set_event(convert_to_any(event).unwrap());
-            return Ok(resp);
+        if let Some(envelope) = self.rx.lock().await.recv().await {
+            return Ok(envelope);
         }
 
-        Err(ttrpc::Error::Others("internal".to_string()))
+        Err(ttrpc::Error::Others("event channel is closed".to_string()))
     }
 }
 
+// relay_events takes the events out of the task service as soon as they are published,
+// so that the task service is not blocked by a slow host unless EVENT_BUFFER_SIZE events
+// are pending. The envelope is stamped here because it is the time the event happened,
+// rather than the time the host fetches it.
+fn relay_events(
+    mut rx: Receiver<(String, Box<dyn MessageDyn>)>,
+    tx: Sender<Envelope>,
+    namespace: String,
+) {
+    tokio::spawn(async move {
+        while let Some((topic, event)) = rx.recv().await {
+            debug!("received event {} {:?}", topic, event);
+            let event = match convert_to_any(event) {
+                Ok(e) => e,
+                Err(e) => {
+                    error!("failed to convert event of topic {}: {}", topic, e);
+                    continue;
+                }
+            };
+            let mut envelope = Envelope::new();
+            envelope.set_timestamp(SystemTime::now().into());
+            envelope.set_namespace(namespace.to_string());
+            envelope.set_topic(topic);
+            envelope.set_event(event);
+            if tx.send(envelope).await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
 async fn do_execute_cmd(cmd_args: &str, stdin: &[u8]) -> Result<String> {
     let mut cmd = tokio::process::Command::new("/bin/bash");
     cmd.arg("-c");
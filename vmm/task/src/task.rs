@@ -16,7 +16,15 @@
 
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use containerd_shim::{
+    api::{
+        CheckpointTaskRequest, CloseIORequest, ConnectRequest, ConnectResponse, CreateTaskRequest,
+        CreateTaskResponse, DeleteRequest, DeleteResponse, Empty, ExecProcessRequest, KillRequest,
+        PauseRequest, PidsRequest, PidsResponse, ResizePtyRequest, ResumeRequest, ShutdownRequest,
+        StartRequest, StartResponse, StateRequest, StateResponse, StatsRequest, StatsResponse,
+        UpdateTaskRequest, WaitRequest, WaitResponse,
+    },
     asynchronous::{
         container::Container,
         monitor::{monitor_subscribe, monitor_unsubscribe, Subscription},
@@ -25,7 +33,11 @@ use containerd_shim::{
         util::read_spec,
     },
     monitor::{Subject, Topic},
-    protos::protobuf::MessageDyn,
+    protos::{
+        events::task::TaskCheckpointed, protobuf::MessageDyn, shim_async::Task,
+        topics::TASK_CHECKPOINTED_EVENT_TOPIC,
+    },
+    Error, TtrpcContext, TtrpcResult,
 };
 use log::{debug, error};
 use oci_spec::runtime::{LinuxNamespaceType, Spec};
@@ -35,7 +47,7 @@ use tokio::sync::{mpsc::Sender, Mutex};
 use crate::container::{KuasarContainer, KuasarFactory};
 #[cfg(feature = "youki")]
 use crate::youki::{YoukiContainer, YoukiFactory};
-use crate::{sandbox::SandboxResources, NAMESPACE};
+use crate::{checkpoint::Checkpoint, sandbox::SandboxResources, NAMESPACE};
 
 #[cfg(not(feature = "youki"))]
 type Factory = KuasarFactory;
@@ -47,9 +59,129 @@ type Factory = YoukiFactory;
 #[cfg(feature = "youki")]
 type RealContainer = YoukiContainer;
 
+// KuasarTaskService adds the checkpoint support to the task service of containerd-shim,
+// all the other requests are handled by the inner task service.
+pub(crate) struct KuasarTaskService {
+    inner: TaskService<Factory, RealContainer>,
+}
+
+#[async_trait]
+impl Task for KuasarTaskService {
+    async fn state(&self, ctx: &TtrpcContext, req: StateRequest) -> TtrpcResult<StateResponse> {
+        self.inner.state(ctx, req).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &TtrpcContext,
+        req: CreateTaskRequest,
+    ) -> TtrpcResult<CreateTaskResponse> {
+        self.inner.create(ctx, req).await
+    }
+
+    async fn start(&self, ctx: &TtrpcContext, req: StartRequest) -> TtrpcResult<StartResponse> {
+        self.inner.start(ctx, req).await
+    }
+
+    async fn delete(&self, ctx: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
+        self.inner.delete(ctx, req).await
+    }
+
+    async fn pids(&self, ctx: &TtrpcContext, req: PidsRequest) -> TtrpcResult<PidsResponse> {
+        self.inner.pids(ctx, req).await
+    }
+
+    async fn pause(&self, ctx: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        self.inner.pause(ctx, req).await
+    }
+
+    async fn resume(&self, ctx: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        self.inner.resume(ctx, req).await
+    }
+
+    async fn checkpoint(
+        &self,
+        _ctx: &TtrpcContext,
+        req: CheckpointTaskRequest,
+    ) -> TtrpcResult<Empty> {
+        debug!("checkpoint request for {}", req.id());
+        // the path in request is a dir on the host, which can not be written in the guest,
+        // the archive of the checkpoint is put in the bundle shared with the host instead.
+        if !req.path.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "can not checkpoint container {} into {} out of the vm, \
+                 checkpoint it without a path to get the archive in its bundle",
+                req.id(),
+                req.path
+            ))
+            .into());
+        }
+        let dump = {
+            let containers = self.inner.containers.lock().await;
+            let container = containers.get(req.id()).ok_or_else(|| {
+                Error::NotFoundError(format!("can not find container by id {}", req.id()))
+            })?;
+            container.checkpoint(&req)?
+        };
+        // the containers are not locked while dumping, as the dump may take a long time
+        let archive = dump.await?;
+        let event = TaskCheckpointed {
+            container_id: req.id().to_string(),
+            checkpoint: archive,
+            ..Default::default()
+        };
+        self.inner
+            .tx
+            .send((TASK_CHECKPOINTED_EVENT_TOPIC.to_string(), Box::new(event)))
+            .await
+            .unwrap_or_else(|e| error!("failed to send checkpointed event: {}", e));
+        Ok(Empty::new())
+    }
+
+    async fn kill(&self, ctx: &TtrpcContext, req: KillRequest) -> TtrpcResult<Empty> {
+        self.inner.kill(ctx, req).await
+    }
+
+    async fn exec(&self, ctx: &TtrpcContext, req: ExecProcessRequest) -> TtrpcResult<Empty> {
+        self.inner.exec(ctx, req).await
+    }
+
+    async fn resize_pty(&self, ctx: &TtrpcContext, req: ResizePtyRequest) -> TtrpcResult<Empty> {
+        self.inner.resize_pty(ctx, req).await
+    }
+
+    async fn close_io(&self, ctx: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        self.inner.close_io(ctx, req).await
+    }
+
+    async fn update(&self, ctx: &TtrpcContext, req: UpdateTaskRequest) -> TtrpcResult<Empty> {
+        self.inner.update(ctx, req).await
+    }
+
+    async fn wait(&self, ctx: &TtrpcContext, req: WaitRequest) -> TtrpcResult<WaitResponse> {
+        self.inner.wait(ctx, req).await
+    }
+
+    async fn stats(&self, ctx: &TtrpcContext, req: StatsRequest) -> TtrpcResult<StatsResponse> {
+        self.inner.stats(ctx, req).await
+    }
+
+    async fn connect(
+        &self,
+        ctx: &TtrpcContext,
+        req: ConnectRequest,
+    ) -> TtrpcResult<ConnectResponse> {
+        self.inner.connect(ctx, req).await
+    }
+
+    async fn shutdown(&self, ctx: &TtrpcContext, req: ShutdownRequest) -> TtrpcResult<Empty> {
+        self.inner.shutdown(ctx, req).await
+    }
+}
+
 pub(crate) async fn create_task_service(
     tx: Sender<(String, Box<dyn MessageDyn>)>,
-) -> anyhow::Result<TaskService<Factory, RealContainer>> {
+) -> anyhow::Result<KuasarTaskService> {
     let sandbox = Arc::new(Mutex::new(SandboxResources::new().await));
     let task = TaskService {
         factory: Factory::new(sandbox),
@@ -61,7 +193,7 @@ pub(crate) async fn create_task_service(
     let s = monitor_subscribe(Topic::Pid).await?;
     process_exits(s, &task).await;
 
-    Ok(task)
+    Ok(KuasarTaskService { inner: task })
 }
 
 async fn process_exits(s: Subscription, task: &TaskService<Factory, RealContainer>) {
@@ -23,7 +23,7 @@ use std::{
 
 use async_trait::async_trait;
 use containerd_shim::{
-    api::{CreateTaskRequest, ExecProcessRequest, Options, Status},
+    api::{CheckpointTaskRequest, CreateTaskRequest, ExecProcessRequest, Options, Status},
     asynchronous::{
         console::ConsoleSocket,
         container::{ContainerFactory, ContainerTemplate, ProcessFactory},
@@ -39,6 +39,7 @@ use containerd_shim::{
     util::read_spec,
     Error, ExitSignal, Result,
 };
+use futures::future::BoxFuture;
 use libcontainer::{
     container::{builder::ContainerBuilder, Container},
     error::LibcontainerError,
@@ -64,9 +65,10 @@ use vmm_common::{
 };
 
 use crate::{
+    checkpoint::Checkpoint,
     device::rescan_pci_bus,
     io::{convert_stdio, copy_io_or_console, ProcessIO},
-    sandbox::SandboxResources,
+    sandbox::{prepare_bundle, SandboxResources},
     util::{read_io, read_storages},
 };
 
@@ -90,6 +92,7 @@ impl ContainerFactory<YoukiContainer> for YoukiFactory {
         req: &CreateTaskRequest,
     ) -> containerd_shim::Result<YoukiContainer> {
         rescan_pci_bus().await?;
+        prepare_bundle(&self.sandbox, &req.id).await?;
         let bundle = format!("{}/{}", KUASAR_STATE_DIR, req.id);
         let spec: Spec = read_spec(&bundle).await?;
         let annotations = spec.annotations().clone().unwrap_or_default();
@@ -144,6 +147,15 @@ impl ContainerFactory<YoukiContainer> for YoukiFactory {
     }
 }
 
+impl Checkpoint for YoukiContainer {
+    fn checkpoint(
+        &self,
+        _req: &CheckpointTaskRequest,
+    ) -> Result<BoxFuture<'static, Result<String>>> {
+        Err(Error::Unimplemented("checkpoint for youki".to_string()))
+    }
+}
+
 impl YoukiFactory {
     pub fn new(sandbox: Arc<Mutex<SandboxResources>>) -> Self {
         Self { sandbox }
@@ -40,6 +40,23 @@ pub struct Args {
     /// Logging level for sandboxer [trace, debug, info, warn, error, fatal, panic]
     #[arg(long, value_name = "STRING")]
     pub log_level: Option<String>,
+
+    /// Directory of the cache of precompiled modules, default is `/var/lib/kuasar-wasm/module-cache`
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "/var/lib/kuasar-wasm/module-cache"
+    )]
+    pub module_cache_dir: String,
+
+    /// Max size in MiB of the cache of precompiled modules, the cache is disabled if it is 0
+    #[arg(long, value_name = "MiB", default_value_t = 1024)]
+    pub module_cache_size: u64,
+
+    /// Number of threads to compile modules, default is the number of cpus,
+    /// only takes effect with wasmtime
+    #[arg(long, value_name = "NUM")]
+    pub compile_threads: Option<usize>,
 }
 
 #[cfg(test)]
@@ -55,5 +72,8 @@ mod tests {
         assert_eq!(args.dir, "/run/kuasar-wasm");
         assert_eq!(args.listen, "/run/wasm-sandboxer.sock");
         assert!(args.log_level.is_none());
+        assert_eq!(args.module_cache_dir, "/var/lib/kuasar-wasm/module-cache");
+        assert_eq!(args.module_cache_size, 1024);
+        assert!(args.compile_threads.is_none());
     }
 }
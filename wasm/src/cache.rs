@@ -0,0 +1,232 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+
+const STAGING_DIR_NAME: &str = "staging";
+
+static STAGING_ID: AtomicU64 = AtomicU64::new(0);
+
+// ModuleCache is a content-addressed cache of the precompiled modules on disk, the key of a
+// module is the digest of the module with the version and config of the engine compiling it.
+// The least recently used modules are evicted when the cache exceeds the max size.
+#[derive(Debug)]
+pub struct ModuleCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl ModuleCache {
+    pub fn new<P: AsRef<Path>>(dir: P, max_size: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let staging_dir = dir.join(STAGING_DIR_NAME);
+        std::fs::create_dir_all(&staging_dir)
+            .map_err(|e| anyhow!("failed to create module cache dir {}, {}", dir.display(), e))?;
+        clean_staging(&staging_dir)?;
+        Ok(Self { dir, max_size })
+    }
+
+    // key returns the digest of the module and the engine, which is the file name in the cache.
+    pub fn key(module: &[u8], engine: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(engine.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(module);
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    // get returns the path of the precompiled module if it is cached,
+    // and marks it as the most recently used one.
+    pub async fn get(&self, key: &str) -> Option<PathBuf> {
+        let path = self.dir.join(key);
+        let p = path.clone();
+        tokio::task::spawn_blocking(move || {
+            File::options()
+                .append(true)
+                .open(&p)
+                .and_then(|f| f.set_modified(SystemTime::now()))
+        })
+        .await
+        .ok()?
+        .ok()?;
+        Some(path)
+    }
+
+    // staging_path returns a unique path in the cache dir to write the precompiled module to,
+    // which is moved into the cache by `insert`.
+    pub fn staging_path(&self, key: &str) -> PathBuf {
+        let id = STAGING_ID.fetch_add(1, Ordering::SeqCst);
+        self.dir
+            .join(STAGING_DIR_NAME)
+            .join(format!("{}-{}-{}", key, std::process::id(), id))
+    }
+
+    pub async fn put(&self, key: &str, data: &[u8]) -> Result<PathBuf> {
+        let staging = self.staging_path(key);
+        tokio::fs::write(&staging, data)
+            .await
+            .map_err(|e| anyhow!("failed to write {}, {}", staging.display(), e))?;
+        self.insert(key, &staging).await
+    }
+
+    // insert moves the precompiled module file into the cache, and evicts the other modules
+    // if the size of the cache exceeds the max size.
+    pub async fn insert(&self, key: &str, file: &Path) -> Result<PathBuf> {
+        let path = self.dir.join(key);
+        if let Err(e) = tokio::fs::rename(file, &path).await {
+            tokio::fs::remove_file(file).await.unwrap_or_default();
+            return Err(anyhow!(
+                "failed to move {} to {}, {}",
+                file.display(),
+                path.display(),
+                e
+            ));
+        }
+        debug!("cached precompiled module {}", path.display());
+        let dir = self.dir.clone();
+        let max_size = self.max_size;
+        let key = key.to_string();
+        tokio::task::spawn_blocking(move || evict(&dir, max_size, &key))
+            .await
+            .map_err(|e| anyhow!("failed to join the thread of eviction, {}", e))??;
+        Ok(path)
+    }
+}
+
+// clean_staging removes the files left in the staging dir by the crashed sandboxers,
+// the ones being written by the running sandboxers sharing the cache dir are kept.
+fn clean_staging(dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        // the staging file is named as <key>-<pid>-<id> by `staging_path`
+        let pid = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.rsplitn(3, '-').nth(1))
+            .and_then(|pid| pid.parse::<u32>().ok());
+        if let Some(pid) = pid {
+            if Path::new("/proc").join(pid.to_string()).exists() {
+                continue;
+            }
+        }
+        let path = entry.path();
+        debug!("remove staging file {}", path.display());
+        std::fs::remove_file(&path)
+            .unwrap_or_else(|e| warn!("failed to remove {}, {}", path.display(), e));
+    }
+    Ok(())
+}
+
+// evict removes the least recently used modules until the total size is within max_size,
+// except the one of the given key which is just inserted.
+fn evict(dir: &Path, max_size: u64, key: &str) -> Result<()> {
+    let mut entries = vec![];
+    let mut total = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        total += metadata.len();
+        if entry.file_name() != key {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.push((modified, metadata.len(), entry.path()));
+        }
+    }
+    entries.sort_by_key(|(modified, _, _)| *modified);
+    for (_, size, path) in entries {
+        if total <= max_size {
+            break;
+        }
+        debug!("evict precompiled module {}", path.display());
+        match std::fs::remove_file(&path) {
+            Ok(_) => total -= size,
+            Err(e) => warn!("failed to evict {}, {}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use temp_dir::TempDir;
+
+    use crate::cache::ModuleCache;
+
+    #[test]
+    fn test_key() {
+        let key = ModuleCache::key(b"\0asm", "wasmtime-1");
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, ModuleCache::key(b"\0asm", "wasmtime-1"));
+        assert_ne!(key, ModuleCache::key(b"\0asm", "wasmtime-2"));
+        assert_ne!(key, ModuleCache::key(b"\0asm\x01", "wasmtime-1"));
+    }
+
+    #[tokio::test]
+    async fn test_put_get_and_evict() {
+        let dir = TempDir::new().unwrap();
+        let cache = ModuleCache::new(dir.path(), 10).unwrap();
+        assert!(cache.get("a").await.is_none());
+
+        let a = cache.put("a", b"aaaa").await.unwrap();
+        assert_eq!(cache.get("a").await.unwrap(), a);
+        let b = cache.put("b", b"bbbb").await.unwrap();
+        std::fs::File::options()
+            .append(true)
+            .open(&b)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        assert!(cache.get("a").await.is_some());
+
+        // "b" is the least recently used one as "a" is just read
+        cache.put("c", b"cccc").await.unwrap();
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("c").await.is_some());
+    }
+
+    #[test]
+    fn test_clean_staging() {
+        let dir = TempDir::new().unwrap();
+        let staging = dir.child("staging");
+        std::fs::create_dir_all(&staging).unwrap();
+        let crashed = staging.join(format!("a-{}-0", u32::MAX));
+        let running = staging.join(format!("b-{}-0", std::process::id()));
+        std::fs::write(&crashed, b"aaaa").unwrap();
+        std::fs::write(&running, b"bbbb").unwrap();
+
+        ModuleCache::new(dir.path(), 10).unwrap();
+        assert!(!crashed.exists());
+        assert!(running.exists());
+    }
+}
@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::str::FromStr;
+use std::{path::Path, str::FromStr, sync::Arc};
 
 use clap::Parser;
 use containerd_shim::asynchronous::monitor::monitor_notify_by_pid;
@@ -31,9 +31,10 @@ use nix::{
 };
 use signal_hook_tokio::Signals;
 
-use crate::sandbox::WasmSandboxer;
+use crate::{cache::ModuleCache, sandbox::WasmSandboxer};
 
 mod args;
+mod cache;
 mod sandbox;
 mod utils;
 mod version;
@@ -42,8 +43,7 @@ mod wasmedge;
 #[cfg(feature = "wasmtime")]
 mod wasmtime;
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let args = args::Args::parse();
     if args.version {
         version::print_version_info();
@@ -59,14 +59,54 @@ async fn main() {
         .filter_module("wasm_sandboxer", log_level)
         .init();
 
-    // TODO: Support recovery
+    // The modules are compiled by the rayon thread pool in wasmtime, each thread of which has
+    // a stack size of 4MB, limit the number of threads to save the memory on hosts of many cpus.
+    // Build the pool before the tokio runtime starts, as no other thread should be running.
+    #[cfg(feature = "wasmtime")]
+    if let Some(threads) = args.compile_threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap_or_else(|e| warn!("failed to set the number of compile threads, {}", e));
+    }
+    #[cfg(feature = "wasmedge")]
+    if args.compile_threads.is_some() {
+        warn!("--compile-threads only takes effect with wasmtime, ignored by wasmedge");
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to create tokio runtime")
+        .block_on(start(args));
+}
 
+async fn start(args: args::Args) {
     tokio::spawn(async move {
         let signals = Signals::new([libc::SIGPIPE, libc::SIGCHLD]).expect("new signal failed");
         handle_signals(signals).await;
     });
 
-    let sandboxer = WasmSandboxer::default();
+    let module_cache = if args.module_cache_size > 0 {
+        match ModuleCache::new(&args.module_cache_dir, args.module_cache_size * 1024 * 1024) {
+            Ok(cache) => Some(Arc::new(cache)),
+            Err(e) => {
+                warn!("failed to init module cache, {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let sandboxer = WasmSandboxer {
+        module_cache,
+        ..Default::default()
+    };
+    // Do recovery job
+    if Path::new(&args.dir).exists() {
+        sandboxer.recover(&args.dir).await;
+    }
     containerd_sandbox::run(
         "kuasar-wasm-sandboxer-wasmedge",
         &args.listen,
@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, io::ErrorKind, path::Path, sync::Arc};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -25,57 +25,228 @@ use containerd_sandbox::{
     Container, ContainerOption, Sandbox, SandboxOption, SandboxStatus, Sandboxer,
 };
 use containerd_shim::{
+    api::CreateTaskRequest,
     asynchronous::task::TaskService,
-    protos::{shim::shim_ttrpc_async::create_task, ttrpc::asynchronous::Server},
+    container::{ContainerFactory, ContainerTemplate},
+    processes::Process,
+    protos::{
+        events::task::{TaskExecStarted, TaskStart},
+        protobuf::MessageDyn,
+        shim::shim_ttrpc_async::create_task,
+        ttrpc::asynchronous::Server,
+    },
 };
-use log::debug;
+use log::{debug, error, warn};
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
 use tokio::{
-    fs::create_dir_all,
-    sync::{mpsc::channel, Mutex, RwLock},
+    fs::{create_dir_all, remove_dir_all, remove_file, OpenOptions},
+    io::AsyncReadExt,
+    sync::{
+        mpsc::{channel, Receiver},
+        Mutex, RwLock,
+    },
 };
 
 #[cfg(feature = "wasmedge")]
 use crate::wasmedge::{process_exits, WasmEdgeContainer, WasmEdgeContainerFactory};
 #[cfg(feature = "wasmtime")]
 use crate::wasmtime::{exec_exits, WasmtimeContainer, WasmtimeContainerFactory};
+use crate::{cache::ModuleCache, utils::write_file_atomic};
+
+// the exit code of the processes which are running when the sandboxer restarts and are lost,
+// like the wasmtime instances in the sandboxer, the same as the unknown exit status of containerd.
+const EXIT_CODE_RECOVERED: i32 = 255;
+// the exit code of the forked wasmedge processes which are killed when the sandboxer restarts,
+// as the new sandboxer is not their parent and can not wait for them.
+const EXIT_CODE_KILLED: i32 = 128 + 9;
+const PROCESSES_FILE_NAME: &str = "processes.json";
 
 #[derive(Default)]
 pub struct WasmSandboxer {
     #[allow(clippy::type_complexity)]
     pub(crate) sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<WasmSandbox>>>>>,
+    pub(crate) module_cache: Option<Arc<ModuleCache>>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct WasmSandbox {
-    pub(crate) _id: String,
+    pub(crate) id: String,
     pub(crate) base_dir: String,
     pub(crate) data: SandboxData,
     pub(crate) status: SandboxStatus,
+    #[serde(skip, default)]
     pub(crate) exit_signal: Arc<ExitSignal>,
     pub(crate) containers: HashMap<String, WasmContainer>,
+    #[serde(skip, default)]
     pub(crate) server: Option<Server>,
+    #[serde(skip, default)]
+    pub(crate) module_cache: Option<Arc<ModuleCache>>,
+    #[serde(skip, default)]
+    pub(crate) process_states: ProcessStates,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct WasmContainer {
     pub(crate) data: ContainerData,
 }
 
+// ProcessStates are the pids and exit codes of the processes of the containers in a sandbox,
+// which are saved as soon as the task service reports them, so that the sandboxer can tell
+// the created, running and exited containers apart after it restarts.
+#[derive(Clone, Default)]
+pub struct ProcessStates {
+    path: String,
+    states: Arc<Mutex<HashMap<String, ContainerProcesses>>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ContainerProcesses {
+    #[serde(default)]
+    pub(crate) init: Option<ProcessState>,
+    #[serde(default)]
+    pub(crate) execs: HashMap<String, ProcessState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessState {
+    // the pid of the forked wasmedge process, 0 for the wasmtime instances in the sandboxer
+    pub(crate) pid: i32,
+    // the start time of the process since boot, which tells if the pid is reused
+    pub(crate) start_time: u64,
+    pub(crate) exit_code: Option<i32>,
+}
+
+impl ProcessStates {
+    fn new(base_dir: &str) -> Self {
+        Self {
+            path: format!("{}/{}", base_dir, PROCESSES_FILE_NAME),
+            states: Default::default(),
+        }
+    }
+
+    async fn load(base_dir: &str) -> Result<Self> {
+        let process_states = Self::new(base_dir);
+        let content = match tokio::fs::read(&process_states.path).await {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(process_states),
+            Err(e) => return Err(Error::IO(e)),
+        };
+        let states = serde_json::from_slice(&content)
+            .map_err(|e| anyhow!("failed to deserialize process states, {}", e))?;
+        *process_states.states.lock().await = states;
+        Ok(process_states)
+    }
+
+    async fn snapshot(&self) -> HashMap<String, ContainerProcesses> {
+        self.states.lock().await.clone()
+    }
+
+    // started records the process of a container, the exec id of the init process is empty.
+    pub async fn started(&self, container_id: &str, exec_id: &str, pid: i32) {
+        let mut states = self.states.lock().await;
+        let processes = states.entry(container_id.to_string()).or_default();
+        let state = if exec_id.is_empty() {
+            processes.init.get_or_insert_with(Default::default)
+        } else {
+            processes.execs.entry(exec_id.to_string()).or_default()
+        };
+        state.pid = pid;
+        state.start_time = process_start_time(pid).unwrap_or_default();
+        self.dump(&states).await;
+    }
+
+    // exited records the exit code of the process of a container, which may be reported
+    // before its start if it exits immediately.
+    pub async fn exited(&self, container_id: &str, exec_id: &str, exit_code: i32) {
+        let mut states = self.states.lock().await;
+        let processes = states.entry(container_id.to_string()).or_default();
+        let state = if exec_id.is_empty() {
+            processes.init.get_or_insert_with(Default::default)
+        } else {
+            processes.execs.entry(exec_id.to_string()).or_default()
+        };
+        state.exit_code = Some(exit_code);
+        self.dump(&states).await;
+    }
+
+    async fn set(&self, container_id: &str, processes: Option<ContainerProcesses>) {
+        let mut states = self.states.lock().await;
+        match processes {
+            Some(p) => states.insert(container_id.to_string(), p),
+            None => states.remove(container_id),
+        };
+        self.dump(&states).await;
+    }
+
+    async fn dump(&self, states: &HashMap<String, ContainerProcesses>) {
+        let res = match serde_json::to_vec(states) {
+            Ok(data) => write_file_atomic(&self.path, &data).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            warn!("failed to save process states to {}, {}", self.path, e);
+        }
+    }
+}
+
+impl WasmSandboxer {
+    pub async fn recover(&self, dir: &str) {
+        let mut subs = match tokio::fs::read_dir(dir).await {
+            Ok(subs) => subs,
+            Err(e) => {
+                error!("FATAL! read working dir {} for recovery: {}", dir, e);
+                return;
+            }
+        };
+        while let Ok(Some(entry)) = subs.next_entry().await {
+            if let Ok(t) = entry.file_type().await {
+                if !t.is_dir() {
+                    continue;
+                }
+                debug!("recovering sandbox {:?}", entry.file_name());
+                let path = Path::new(dir).join(entry.file_name());
+                match WasmSandbox::recover(&path, self.module_cache.clone()).await {
+                    Ok(sb) => {
+                        self.sandboxes
+                            .write()
+                            .await
+                            .insert(sb.id.to_string(), Arc::new(Mutex::new(sb)));
+                    }
+                    Err(e) => {
+                        warn!("failed to recover sandbox {:?}, {:?}", entry.file_name(), e);
+                        remove_dir_all(&path).await.unwrap_or_default();
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl Sandboxer for WasmSandboxer {
     type Sandbox = WasmSandbox;
 
     async fn create(&self, id: &str, s: SandboxOption) -> Result<()> {
+        let process_states = ProcessStates::new(&s.base_dir);
         let sandbox = WasmSandbox {
-            _id: id.to_string(),
+            id: id.to_string(),
             base_dir: s.base_dir,
             data: s.sandbox,
             status: SandboxStatus::Created,
             exit_signal: Arc::new(Default::default()),
             containers: Default::default(),
             server: None,
+            module_cache: self.module_cache.clone(),
+            process_states,
         };
         create_dir_all(&sandbox.base_dir)
             .await
             .map_err(|e| anyhow!("failed to create {}, {}", sandbox.base_dir, e))?;
+        sandbox.dump().await?;
         let mut sandboxes = self.sandboxes.write().await;
         sandboxes.insert(id.to_string(), Arc::new(Mutex::new(sandbox)));
         Ok(())
@@ -89,7 +260,9 @@ impl Sandboxer for WasmSandboxer {
 
     async fn update(&self, id: &str, data: SandboxData) -> Result<()> {
         let sandbox = self.sandbox(id).await?;
-        sandbox.lock().await.data = data;
+        let mut sandbox = sandbox.lock().await;
+        sandbox.data = data;
+        sandbox.dump().await?;
         Ok(())
     }
 
@@ -118,6 +291,12 @@ impl Sandboxer for WasmSandboxer {
                     .await
                     .map_err(|e| anyhow!("failed to shutdown task server, {}", e))?;
             }
+            // Should Ignore the NotFound error of base dir as it may be already deleted.
+            if let Err(e) = remove_dir_all(&sandbox.base_dir).await {
+                if e.kind() != ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
         }
         Ok(())
     }
@@ -134,12 +313,20 @@ impl WasmSandbox {
         let ts = time::OffsetDateTime::now_utc().unix_timestamp_nanos();
         self.status = SandboxStatus::Stopped(0, ts);
         self.exit_signal.signal();
+        self.dump().await?;
         Ok(())
     }
 
     async fn start(&mut self) -> Result<()> {
         let task = self.start_task_service().await?;
-        let task_address = format!("unix://{}/task.sock", self.base_dir);
+        let task_path = format!("{}/task.sock", self.base_dir);
+        // the socket may be left by the sandboxer before restart
+        if let Err(e) = remove_file(&task_path).await {
+            if e.kind() != ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        let task_address = format!("unix://{}", task_path);
         self.data
             .task_address
             .clone_from(&format!("ttrpc+{}", task_address));
@@ -154,16 +341,103 @@ impl WasmSandbox {
             .map_err(|e| anyhow!("failed to start task server, {}", e))?;
         self.server = Some(server);
         self.status = SandboxStatus::Running(0);
+        self.dump().await?;
         Ok(())
     }
 
+    async fn dump(&self) -> Result<()> {
+        let dump_data =
+            serde_json::to_vec(&self).map_err(|e| anyhow!("failed to serialize sandbox, {}", e))?;
+        let dump_path = format!("{}/sandbox.json", self.base_dir);
+        write_file_atomic(&dump_path, &dump_data)
+            .await
+            .map_err(Error::IO)?;
+        Ok(())
+    }
+
+    // recover loads the sandbox from its dump, the task server of a running sandbox is started
+    // again at the same address, with the containers in it recovered by `recover_containers`.
+    async fn recover<P: AsRef<Path>>(
+        base_dir: P,
+        module_cache: Option<Arc<ModuleCache>>,
+    ) -> Result<Self> {
+        let dump_path = base_dir.as_ref().join("sandbox.json");
+        let mut dump_file = OpenOptions::new()
+            .read(true)
+            .open(&dump_path)
+            .await
+            .map_err(Error::IO)?;
+        let mut content = vec![];
+        dump_file
+            .read_to_end(&mut content)
+            .await
+            .map_err(Error::IO)?;
+        let mut sb = serde_json::from_slice::<WasmSandbox>(content.as_slice())
+            .map_err(|e| anyhow!("failed to deserialize sandbox, {}", e))?;
+        sb.module_cache = module_cache;
+        sb.process_states = ProcessStates::load(&sb.base_dir).await?;
+        match sb.status {
+            SandboxStatus::Running(_) => {
+                if let Err(e) = sb.start().await {
+                    warn!("failed to start task server of sandbox {}: {}", sb.id, e);
+                    sb.stop().await?;
+                }
+            }
+            SandboxStatus::Stopped(_, _) => sb.exit_signal.signal(),
+            _ => {}
+        }
+        Ok(sb)
+    }
+
+    // recover_containers adds the containers of the sandbox to the task service again. The ones
+    // that were only created are kept created, and the exited ones keep their exit codes. The
+    // running processes are marked as exited, as the new sandboxer is not the parent of the forked
+    // wasmedge processes and can not wait for them, they are killed, and the wasmtime instances
+    // in the sandboxer are lost with it.
+    async fn recover_containers<F, T, E, P>(
+        &self,
+        task: &TaskService<F, ContainerTemplate<T, E, P>>,
+    ) where
+        F: ContainerFactory<ContainerTemplate<T, E, P>>,
+        T: Process,
+    {
+        let mut states = self.process_states.snapshot().await;
+        for (id, c) in &self.containers {
+            let mut container = match task
+                .factory
+                .create(&task.namespace, &recover_task_request(id, c))
+                .await
+            {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("failed to recover container {}: {}", id, e);
+                    continue;
+                }
+            };
+            let processes = states.remove(id).map(recover_processes);
+            if let Some(exit_code) = processes
+                .as_ref()
+                .and_then(|p| p.init)
+                .and_then(|s| s.exit_code)
+            {
+                container.init.set_exited(exit_code).await;
+            }
+            self.process_states.set(id, processes).await;
+            task.containers
+                .lock()
+                .await
+                .insert(id.to_string(), container);
+        }
+    }
+
     #[cfg(feature = "wasmedge")]
     async fn start_task_service(
         &self,
     ) -> Result<TaskService<WasmEdgeContainerFactory, WasmEdgeContainer>> {
-        let (tx, mut rx) = channel(128);
+        let (tx, rx) = channel(128);
         let mut factory = WasmEdgeContainerFactory::default();
         factory.netns.clone_from(&self.data.netns);
+        factory.module_cache.clone_from(&self.module_cache);
         let task = TaskService {
             factory,
             containers: Arc::new(Default::default()),
@@ -171,14 +445,11 @@ impl WasmSandbox {
             exit: Arc::new(Default::default()),
             tx: tx.clone(),
         };
+        self.recover_containers(&task).await;
 
-        process_exits(&task).await;
+        process_exits(&task, self.process_states.clone()).await;
 
-        tokio::spawn(async move {
-            while let Some((_topic, e)) = rx.recv().await {
-                debug!("received event {:?}", e);
-            }
-        });
+        record_process_starts(rx, self.process_states.clone());
         Ok(task)
     }
 
@@ -186,9 +457,10 @@ impl WasmSandbox {
     async fn start_task_service(
         &self,
     ) -> Result<TaskService<WasmtimeContainerFactory, WasmtimeContainer>> {
-        let (tx, mut rx) = channel(128);
+        let (tx, rx) = channel(128);
         let factory = WasmtimeContainerFactory {
             netns: self.data.netns.clone(),
+            module_cache: self.module_cache.clone(),
         };
         let task = TaskService {
             factory,
@@ -197,12 +469,9 @@ impl WasmSandbox {
             exit: Arc::new(Default::default()),
             tx: tx.clone(),
         };
-        exec_exits(&task).await;
-        tokio::spawn(async move {
-            while let Some((_topic, e)) = rx.recv().await {
-                debug!("received event {:?}", e);
-            }
-        });
+        self.recover_containers(&task).await;
+        exec_exits(&task, self.process_states.clone()).await;
+        record_process_starts(rx, self.process_states.clone());
         Ok(task)
     }
 }
@@ -230,6 +499,7 @@ impl Sandbox for WasmSandbox {
             data: option.container,
         };
         self.containers.insert(id.to_string(), container);
+        self.dump().await?;
         Ok(())
     }
 
@@ -239,6 +509,8 @@ impl Sandbox for WasmSandbox {
 
     async fn remove_container(&mut self, id: &str) -> Result<()> {
         self.containers.remove(id);
+        self.process_states.set(id, None).await;
+        self.dump().await?;
         Ok(())
     }
 
@@ -256,3 +528,141 @@ impl Container for WasmContainer {
         Ok(self.data.clone())
     }
 }
+
+// record_process_starts saves the pids of the processes in the start events of the task service.
+fn record_process_starts(
+    mut rx: Receiver<(String, Box<dyn MessageDyn>)>,
+    process_states: ProcessStates,
+) {
+    tokio::spawn(async move {
+        while let Some((_topic, e)) = rx.recv().await {
+            debug!("received event {:?}", e);
+            if let Some(start) = e.downcast_ref::<TaskStart>() {
+                process_states
+                    .started(&start.container_id, "", start.pid as i32)
+                    .await;
+            } else if let Some(start) = e.downcast_ref::<TaskExecStarted>() {
+                process_states
+                    .started(&start.container_id, &start.exec_id, start.pid as i32)
+                    .await;
+            }
+        }
+    });
+}
+
+// recover_task_request is the request to create the container in the task service again when
+// the sandbox is recovered, the containers are only known by the task service after they are
+// created, so the containers of the sandbox at the start of the task service are all recovered.
+// The io of the container is kept so that the containers that were only created can be started.
+fn recover_task_request(id: &str, c: &WasmContainer) -> CreateTaskRequest {
+    let mut req = CreateTaskRequest::new();
+    req.id = id.to_string();
+    req.bundle.clone_from(&c.data.bundle);
+    if let Some(io) = &c.data.io {
+        req.stdin.clone_from(&io.stdin);
+        req.stdout.clone_from(&io.stdout);
+        req.stderr.clone_from(&io.stderr);
+        req.terminal = io.terminal;
+    }
+    req
+}
+
+// recover_processes marks the running processes of a container as exited when the sandbox is
+// recovered, and kills the forked ones. The exec processes are dropped, as the task service does
+// not know them anymore, so the exited container only keeps its init process.
+fn recover_processes(mut processes: ContainerProcesses) -> ContainerProcesses {
+    for s in processes
+        .init
+        .iter_mut()
+        .chain(processes.execs.values_mut())
+    {
+        if s.exit_code.is_some() {
+            continue;
+        }
+        s.exit_code = Some(EXIT_CODE_RECOVERED);
+        if s.pid > 0 && process_start_time(s.pid) == Some(s.start_time) {
+            match kill(Pid::from_raw(s.pid), Signal::SIGKILL) {
+                Ok(_) => s.exit_code = Some(EXIT_CODE_KILLED),
+                Err(e) => warn!("failed to kill process {}, {}", s.pid, e),
+            }
+        }
+    }
+    processes.execs.clear();
+    processes
+}
+
+// process_start_time returns the start time of the process in clock ticks since boot,
+// which is the 22nd field of /proc/<pid>/stat, counted after the command in parentheses.
+fn process_start_time(pid: i32) -> Option<u64> {
+    if pid <= 0 {
+        return None;
+    }
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_sandbox::{data::SandboxData, SandboxStatus};
+    use temp_dir::TempDir;
+
+    use crate::sandbox::{
+        recover_processes, ContainerProcesses, ProcessState, ProcessStates, WasmSandbox,
+        EXIT_CODE_RECOVERED,
+    };
+
+    #[tokio::test]
+    async fn test_dump_and_recover_stopped_sandbox() {
+        let dir = TempDir::new().unwrap();
+        let base_dir = dir.path().to_str().unwrap();
+        let sandbox = WasmSandbox {
+            id: "sb1".to_string(),
+            base_dir: base_dir.to_string(),
+            data: SandboxData::default(),
+            status: SandboxStatus::Stopped(0, 1),
+            exit_signal: Default::default(),
+            containers: Default::default(),
+            server: None,
+            module_cache: None,
+            process_states: ProcessStates::new(base_dir),
+        };
+        sandbox.dump().await.unwrap();
+        sandbox.process_states.started("c1", "", 0).await;
+        sandbox.process_states.exited("c1", "", 3).await;
+
+        let recovered = WasmSandbox::recover(base_dir, None).await.unwrap();
+        assert_eq!(recovered.id, "sb1");
+        assert!(matches!(recovered.status, SandboxStatus::Stopped(0, 1)));
+        assert!(recovered.server.is_none());
+        let states = recovered.process_states.snapshot().await;
+        assert_eq!(states["c1"].init.unwrap().exit_code, Some(3));
+    }
+
+    #[test]
+    fn test_recover_processes() {
+        let exited = ProcessState {
+            pid: 0,
+            start_time: 0,
+            exit_code: Some(3),
+        };
+        // a process that is not running any more, as its start time is not the recorded one
+        let running = ProcessState {
+            pid: std::process::id() as i32,
+            start_time: u64::MAX,
+            exit_code: None,
+        };
+        let processes = recover_processes(ContainerProcesses {
+            init: Some(exited),
+            execs: [("e1".to_string(), running)].into(),
+        });
+        assert_eq!(processes.init, Some(exited));
+        assert!(processes.execs.is_empty());
+
+        let processes = recover_processes(ContainerProcesses {
+            init: Some(running),
+            execs: Default::default(),
+        });
+        assert_eq!(processes.init.unwrap().exit_code, Some(EXIT_CODE_RECOVERED));
+    }
+}
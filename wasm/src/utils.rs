@@ -14,7 +14,11 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use oci_spec::runtime::Spec;
+use std::path::Path;
+
+use containerd_shim::{api::ExecProcessRequest, error::Error};
+use oci_spec::runtime::{Process, Spec};
+use tokio::io::AsyncWriteExt;
 
 #[cfg(feature = "wasmedge")]
 pub fn get_preopens(spec: &Spec) -> Vec<String> {
@@ -58,19 +62,72 @@ pub fn get_kv_envs(spec: &Spec) -> Vec<(String, String)> {
         .unwrap()
         .env()
         .as_ref()
-        .map(|x| {
-            x.iter()
-                .map(|e| {
-                    e.split_once('=')
-                        .map(|(k, v)| (k.to_string(), v.to_string()))
-                        .unwrap_or((e.to_string(), "".to_string()))
-                })
-                .collect::<Vec<(String, String)>>()
-        })
+        .map(|x| to_kv_envs(x))
         .unwrap_or(empty_envs);
     envs.to_vec()
 }
 
+#[cfg(feature = "wasmtime")]
+pub fn to_kv_envs(envs: &[String]) -> Vec<(String, String)> {
+    envs.iter()
+        .map(|e| {
+            e.split_once('=')
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .unwrap_or((e.to_string(), "".to_string()))
+        })
+        .collect()
+}
+
+// get_exec_envs returns the envs of the container overridden by the ones of the exec process.
+pub fn get_exec_envs(spec: &Spec, process: &Process) -> Vec<String> {
+    let mut envs = spec
+        .process()
+        .as_ref()
+        .and_then(|p| p.env().clone())
+        .unwrap_or_default();
+    for e in process.env().as_ref().unwrap_or(&vec![]) {
+        let key = e.split_once('=').map(|(k, _)| k).unwrap_or(e);
+        envs.retain(|x| x.split_once('=').map(|(k, _)| k).unwrap_or(x) != key);
+        envs.push(e.to_string());
+    }
+    envs
+}
+
+// get_exec_args returns the args of the exec process, the first of which is the module to run.
+// If the first arg is not a file in rootfs, the module of the container is run with the args.
+pub fn get_exec_args(spec: &Spec, process: &Process) -> Vec<String> {
+    let args = process.args().clone().unwrap_or_default();
+    let init_args = get_args(spec);
+    let (Some(first), Some(init_module)) = (args.first(), init_args.first()) else {
+        return init_args;
+    };
+    let is_module = get_rootfs(spec)
+        .map(|rootfs| {
+            Path::new(&rootfs)
+                .join(first.trim_start_matches(std::path::MAIN_SEPARATOR))
+                .is_file()
+        })
+        .unwrap_or_default();
+    if is_module {
+        args
+    } else {
+        let mut exec_args = vec![init_module.to_string()];
+        exec_args.extend(args);
+        exec_args
+    }
+}
+
+pub fn get_spec_from_request(req: &ExecProcessRequest) -> containerd_shim::Result<Process> {
+    if let Some(val) = req.spec.as_ref() {
+        let mut p = serde_json::from_slice::<Process>(&val.value)
+            .map_err(|e| Error::InvalidArgument(format!("failed to parse exec spec: {}", e)))?;
+        p.set_terminal(Some(req.terminal));
+        Ok(p)
+    } else {
+        Err(Error::InvalidArgument("no spec in request".to_string()))
+    }
+}
+
 pub fn get_args(spec: &Spec) -> Vec<String> {
     let empty_args = vec![];
     let args = spec
@@ -83,13 +140,43 @@ pub fn get_args(spec: &Spec) -> Vec<String> {
     args.to_vec()
 }
 
-#[cfg(feature = "wasmtime")]
+// get_memory_limit returns the memory limit in bytes of the container, None if it is unlimited.
 pub fn get_memory_limit(spec: &Spec) -> Option<i64> {
     spec.linux()
         .as_ref()
         .and_then(|x| x.resources().as_ref())
         .and_then(|x| x.memory().as_ref())
         .and_then(|x| x.limit())
+        .filter(|x| *x > 0)
+}
+
+// WasiMount is a bind mount of the container, which is preopened as a directory in WASI.
+#[cfg(feature = "wasmtime")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasiMount {
+    pub source: String,
+    pub destination: String,
+    pub read_only: bool,
+}
+
+#[cfg(feature = "wasmtime")]
+pub fn get_wasi_mounts(spec: &Spec) -> Vec<WasiMount> {
+    let mut wasi_mounts = vec![];
+    if let Some(mounts) = spec.mounts() {
+        for m in mounts {
+            let options = m.options().clone().unwrap_or_default();
+            let is_bind = m.typ().as_deref() == Some("bind")
+                || options.iter().any(|o| o == "bind" || o == "rbind");
+            if let (true, Some(source)) = (is_bind, m.source()) {
+                wasi_mounts.push(WasiMount {
+                    source: source.display().to_string(),
+                    destination: m.destination().display().to_string(),
+                    read_only: options.iter().any(|o| o == "ro"),
+                });
+            }
+        }
+    }
+    wasi_mounts
 }
 
 pub(crate) fn get_rootfs(spec: &Spec) -> Option<String> {
@@ -105,3 +192,100 @@ pub(crate) fn get_cgroup_path(spec: &Spec) -> Option<String> {
         .and_then(|linux| linux.cgroups_path().as_ref())
         .map(|path| path.display().to_string())
 }
+
+// write_file_atomic writes the data to a temporary file next to the path and renames it to the
+// path, so that the file is never left half written if the sandboxer crashes.
+pub async fn write_file_atomic(path: &str, data: &[u8]) -> std::io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    file.write_all(data).await?;
+    file.sync_data().await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{ProcessBuilder, RootBuilder, SpecBuilder};
+    use temp_dir::TempDir;
+
+    use crate::utils::{get_exec_args, get_exec_envs};
+
+    #[test]
+    fn test_get_exec_args_and_envs() {
+        let rootfs = TempDir::new().unwrap();
+        std::fs::write(rootfs.child("tool.wasm"), b"").unwrap();
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path(rootfs.path()).build().unwrap())
+            .process(
+                ProcessBuilder::default()
+                    .args(vec!["/app.wasm".to_string(), "serve".to_string()])
+                    .env(vec!["A=1".to_string(), "B=2".to_string()])
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        let exec = ProcessBuilder::default()
+            .args(vec!["/tool.wasm".to_string(), "-v".to_string()])
+            .env(vec!["B=3".to_string(), "C=4".to_string()])
+            .build()
+            .unwrap();
+        assert_eq!(get_exec_args(&spec, &exec), vec!["/tool.wasm", "-v"]);
+        assert_eq!(get_exec_envs(&spec, &exec), vec!["A=1", "B=3", "C=4"]);
+
+        let exec = ProcessBuilder::default()
+            .args(vec!["health".to_string()])
+            .build()
+            .unwrap();
+        assert_eq!(get_exec_args(&spec, &exec), vec!["/app.wasm", "health"]);
+    }
+}
+
+#[cfg(all(test, feature = "wasmtime"))]
+mod wasmtime_tests {
+    use oci_spec::runtime::{MountBuilder, SpecBuilder};
+
+    use crate::utils::{get_wasi_mounts, WasiMount};
+
+    #[test]
+    fn test_get_wasi_mounts() {
+        let mounts = vec![
+            MountBuilder::default()
+                .destination("/data")
+                .typ("bind")
+                .source("/var/lib/data")
+                .options(vec!["rbind".to_string(), "ro".to_string()])
+                .build()
+                .unwrap(),
+            MountBuilder::default()
+                .destination("/cache")
+                .source("/var/lib/cache")
+                .options(vec!["rbind".to_string(), "rw".to_string()])
+                .build()
+                .unwrap(),
+            MountBuilder::default()
+                .destination("/proc")
+                .typ("proc")
+                .source("proc")
+                .build()
+                .unwrap(),
+        ];
+        let spec = SpecBuilder::default().mounts(mounts).build().unwrap();
+        assert_eq!(
+            get_wasi_mounts(&spec),
+            vec![
+                WasiMount {
+                    source: "/var/lib/data".to_string(),
+                    destination: "/data".to_string(),
+                    read_only: true,
+                },
+                WasiMount {
+                    source: "/var/lib/cache".to_string(),
+                    destination: "/cache".to_string(),
+                    read_only: false,
+                },
+            ]
+        );
+    }
+}
@@ -17,6 +17,7 @@ limitations under the License.
 use std::{
     fs::OpenOptions,
     os::unix::prelude::{IntoRawFd, RawFd},
+    path::{Path, PathBuf},
     process::exit,
     sync::Arc,
 };
@@ -39,7 +40,8 @@ use containerd_shim::{
     protos::{cgroups::metrics::Metrics, shim::oci::Options, types::task::ProcessInfo},
     ExitSignal,
 };
-use log::debug;
+use kuasar_common::cgroup::collect_metrics;
+use log::{debug, warn};
 use nix::{
     errno::Errno,
     fcntl::OFlag,
@@ -49,24 +51,51 @@ use nix::{
 };
 use oci_spec::runtime::Spec;
 use wasmedge_sdk::{
-    config::{CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions},
+    config::{
+        CommonConfigOptions, Config, ConfigBuilder, HostRegistrationConfigOptions,
+        RuntimeConfigOptions,
+    },
     error::WasmEdgeError,
     params,
     plugin::PluginManager,
     wasi::WasiInstance,
-    Vm, VmBuilder,
+    Compiler, Vm, VmBuilder,
+};
+
+use crate::{
+    cache::ModuleCache,
+    sandbox::ProcessStates,
+    utils::{
+        get_args, get_cgroup_path, get_envs, get_exec_args, get_exec_envs, get_memory_limit,
+        get_preopens, get_rootfs, get_spec_from_request,
+    },
+    version::built_info,
 };
 
-use crate::utils::{get_args, get_cgroup_path, get_envs, get_preopens, get_rootfs};
+const WASM_PAGE_SIZE: i64 = 64 * 1024;
 
 pub type ExecProcess = ProcessTemplate<WasmEdgeExecLifecycle>;
 pub type InitProcess = ProcessTemplate<WasmEdgeInitLifecycle>;
 
 pub type WasmEdgeContainer = ContainerTemplate<InitProcess, ExecProcess, ExecFactory>;
 
-pub struct ExecFactory {}
+pub struct ExecFactory {
+    spec: Spec,
+    prototype_vm: Vm,
+    netns: String,
+    module_cache: Option<Arc<ModuleCache>>,
+}
 
-pub struct WasmEdgeExecLifecycle {}
+// WasmEdgeExecLifecycle runs the module in a forked process like the init process,
+// with the same preopens and cgroup of the container.
+pub struct WasmEdgeExecLifecycle {
+    spec: Spec,
+    prototype_vm: Vm,
+    netns: String,
+    module_cache: Option<Arc<ModuleCache>>,
+    args: Vec<String>,
+    envs: Vec<String>,
+}
 
 pub struct WasmEdgeInitLifecycle {
     _opts: Options,
@@ -74,12 +103,14 @@ pub struct WasmEdgeInitLifecycle {
     spec: Spec,
     prototype_vm: Vm,
     netns: String,
+    module_cache: Option<Arc<ModuleCache>>,
     _exit_signal: Arc<ExitSignal>,
 }
 
 pub struct WasmEdgeContainerFactory {
     prototype_vm: Vm,
     pub(crate) netns: String,
+    pub(crate) module_cache: Option<Arc<ModuleCache>>,
 }
 
 impl Default for WasmEdgeContainerFactory {
@@ -99,6 +130,7 @@ impl Default for WasmEdgeContainerFactory {
         Self {
             prototype_vm: vm,
             netns: "".to_string(),
+            module_cache: None,
         }
     }
 }
@@ -123,6 +155,12 @@ impl ContainerFactory<WasmEdgeContainer> for WasmEdgeContainerFactory {
         let stdio = Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal);
         let exit_signal = Arc::new(Default::default());
         let netns = self.netns.clone();
+        let process_factory = ExecFactory {
+            spec: spec.clone(),
+            prototype_vm: self.prototype_vm.clone(),
+            netns: netns.to_string(),
+            module_cache: self.module_cache.clone(),
+        };
         let init_process = InitProcess::new(
             req.id(),
             stdio,
@@ -133,13 +171,14 @@ impl ContainerFactory<WasmEdgeContainer> for WasmEdgeContainerFactory {
                 spec,
                 prototype_vm: self.prototype_vm.clone(),
                 netns,
+                module_cache: self.module_cache.clone(),
             },
         );
         Ok(WasmEdgeContainer {
             id: req.id.to_string(),
             bundle: req.id.to_string(),
             init: init_process,
-            process_factory: ExecFactory {},
+            process_factory,
             processes: Default::default(),
         })
     }
@@ -153,92 +192,20 @@ impl ContainerFactory<WasmEdgeContainer> for WasmEdgeContainerFactory {
 impl ProcessLifecycle<InitProcess> for WasmEdgeInitLifecycle {
     async fn start(&self, p: &mut InitProcess) -> containerd_shim::Result<()> {
         let spec = &p.lifecycle.spec;
-        // Allow vm to be mutable since we change it in wasmedge_wasi_nn feature
-        #[allow(unused_mut)]
-        #[allow(unused_assignments)]
-        let mut vm = p.lifecycle.prototype_vm.clone();
         let args = get_args(spec);
         let envs = get_envs(spec);
-        let rootfs = get_rootfs(spec).ok_or_else(|| {
-            Error::InvalidArgument("rootfs is not set in runtime spec".to_string())
-        })?;
-        let mut preopens = vec![format!("/:{}", rootfs)];
-        preopens.append(&mut get_preopens(spec));
-
-        debug!(
-            "start wasm with args: {:?}, envs: {:?}, preopens: {:?}",
-            args, envs, preopens
-        );
-        match unsafe {
-            fork().map_err(other_error!(
-                e,
-                format!("failed to fork process for {}", p.id)
-            ))?
-        } {
-            ForkResult::Parent { child } => {
-                let init_pid = child.as_raw();
-                p.state = Status::RUNNING;
-                p.pid = init_pid;
-            }
-            ForkResult::Child => {
-                if let Some(cgroup_path) = get_cgroup_path(spec) {
-                    // Add child process to Cgroup
-                    Cgroup::new(
-                        cgroups_rs::hierarchies::auto(),
-                        cgroup_path.trim_start_matches('/'),
-                    )
-                    .and_then(|cgroup| cgroup.add_task(CgroupPid::from(std::process::id() as u64)))
-                    .map_err(other_error!(
-                        e,
-                        format!("failed to add task to cgroup: {}", cgroup_path)
-                    ))?;
-                }
-                // Only create new VM instance on wasmedge_wasi_nn feature
-                #[cfg(all(
-                    target_os = "linux",
-                    feature = "wasmedge_wasi_nn",
-                    target_arch = "x86_64"
-                ))]
-                {
-                    const NN_PRELOAD_KEY: &str = "io.kuasar.wasm.nn_preload";
-                    if let Some(process) = p.lifecycle.spec.process() {
-                        if let Some(env) = process.env() {
-                            if let Some(v) =
-                                env.iter().find(|k| k.contains(&NN_PRELOAD_KEY.to_string()))
-                            {
-                                if let Some(nn_preload) =
-                                    v.strip_prefix::<&str>(format!("{}=", NN_PRELOAD_KEY).as_ref())
-                                {
-                                    log::info!("found nn_pre_load: {}", nn_preload);
-                                    if let Some(rootfs) = spec.root().as_ref() {
-                                        pre_load_with_new_rootfs(nn_preload, rootfs.path())
-                                            .unwrap();
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    let host_options = HostRegistrationConfigOptions::default().wasi(true);
-                    let config = ConfigBuilder::new(CommonConfigOptions::default())
-                        .with_host_registration_config(host_options)
-                        .build()
-                        .map_err(other_error!(e, "generate default wasmedge config"))?;
-
-                    vm = VmBuilder::new()
-                        .with_config(config)
-                        .with_plugin_wasi_nn()
-                        .with_plugin("wasi_logging", None)
-                        .build()
-                        .unwrap();
-                }
-                match run_wasi_func(vm, args, envs, preopens, p) {
-                    Ok(_) => exit(0),
-                    // TODO add a pipe? to return detailed error message
-                    Err(e) => exit(e.to_exit_code()),
-                }
-            }
-        }
+        let module_path = load_module(self.module_cache.as_deref(), spec, &args).await?;
+        let pid = fork_wasi_process(
+            &p.lifecycle.prototype_vm,
+            spec,
+            &module_path,
+            args,
+            envs,
+            &p.lifecycle.netns,
+            &p.stdio,
+        )?;
+        p.state = Status::RUNNING;
+        p.pid = pid;
         Ok(())
     }
 
@@ -297,7 +264,12 @@ impl ProcessLifecycle<InitProcess> for WasmEdgeInitLifecycle {
         // Because Wasm Applications execute the instructions inside the host Wasm
         // Runtime, we should read the metrics from Cgroup for the CPU, memory,
         // and filesystem usage.
-        containerd_shim::cgroup::collect_metrics(p.pid as u32)
+        // The processes of the container are all added to the cgroup in the spec if it is set,
+        // otherwise they share the cgroup of the shim and the metrics is of the whole cgroup.
+        match get_cgroup_path(&self.spec) {
+            Some(cgroup_path) => Ok(collect_metrics(&cgroup_path)),
+            None => containerd_shim::cgroup::collect_metrics(p.pid as u32),
+        }
     }
 
     async fn ps(&self, p: &InitProcess) -> containerd_shim::Result<Vec<ProcessInfo>> {
@@ -309,21 +281,37 @@ impl ProcessLifecycle<InitProcess> for WasmEdgeInitLifecycle {
 
 #[async_trait::async_trait]
 impl ProcessLifecycle<ExecProcess> for WasmEdgeExecLifecycle {
-    async fn start(&self, _p: &mut ExecProcess) -> containerd_shim::Result<()> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasm containers".to_string(),
-        ))
+    async fn start(&self, p: &mut ExecProcess) -> containerd_shim::Result<()> {
+        let module_path = load_module(self.module_cache.as_deref(), &self.spec, &self.args).await?;
+        let pid = fork_wasi_process(
+            &self.prototype_vm,
+            &self.spec,
+            &module_path,
+            self.args.clone(),
+            self.envs.clone(),
+            &self.netns,
+            &p.stdio,
+        )?;
+        p.state = Status::RUNNING;
+        p.pid = pid;
+        Ok(())
     }
 
     async fn kill(
         &self,
-        _p: &mut ExecProcess,
-        _signal: u32,
+        p: &mut ExecProcess,
+        signal: u32,
         _all: bool,
     ) -> containerd_shim::Result<()> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasm containers".to_string(),
-        ))
+        debug!("start kill exec process {}", p.pid);
+        if p.state == Status::RUNNING && p.pid > 0 {
+            kill(
+                Pid::from_raw(p.pid),
+                nix::sys::signal::Signal::try_from(signal as i32).unwrap(),
+            )
+            .map_err(other_error!(e, "failed to kill process"))?;
+        }
+        Ok(())
     }
 
     async fn delete(&self, _p: &mut ExecProcess) -> containerd_shim::Result<()> {
@@ -336,32 +324,204 @@ impl ProcessLifecycle<ExecProcess> for WasmEdgeExecLifecycle {
         _resources: &oci_spec::runtime::LinuxResources,
     ) -> containerd_shim::Result<()> {
         Err(Error::Unimplemented(
-            "exec not supported for wasm containers".to_string(),
+            "update not supported for wasm exec processes".to_string(),
         ))
     }
 
     async fn stats(&self, _p: &ExecProcess) -> containerd_shim::Result<Metrics> {
         Err(Error::Unimplemented(
-            "exec not supported for wasm containers".to_string(),
+            "stats not supported for wasm exec processes".to_string(),
         ))
     }
 
-    async fn ps(&self, _p: &ExecProcess) -> containerd_shim::Result<Vec<ProcessInfo>> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasm containers".to_string(),
-        ))
+    async fn ps(&self, p: &ExecProcess) -> containerd_shim::Result<Vec<ProcessInfo>> {
+        let mut process_info = ProcessInfo::new();
+        process_info.pid = p.pid as u32;
+        Ok(vec![process_info])
     }
 }
 
 #[async_trait::async_trait]
 impl ProcessFactory<ExecProcess> for ExecFactory {
-    async fn create(&self, _req: &ExecProcessRequest) -> containerd_shim::Result<ExecProcess> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasm containers".to_string(),
-        ))
+    async fn create(&self, req: &ExecProcessRequest) -> containerd_shim::Result<ExecProcess> {
+        let process = get_spec_from_request(req)?;
+        let stdio = Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal);
+        let lifecycle = WasmEdgeExecLifecycle {
+            spec: self.spec.clone(),
+            prototype_vm: self.prototype_vm.clone(),
+            netns: self.netns.to_string(),
+            module_cache: self.module_cache.clone(),
+            args: get_exec_args(&self.spec, &process),
+            envs: get_exec_envs(&self.spec, &process),
+        };
+        Ok(ExecProcess::new(req.exec_id(), stdio, lifecycle))
     }
 }
 
+// fork_wasi_process runs the module of args[0] in a forked process,
+// which joins the cgroup and netns of the container, returns the pid of the process.
+fn fork_wasi_process(
+    prototype_vm: &Vm,
+    spec: &Spec,
+    module_path: &Path,
+    args: Vec<String>,
+    envs: Vec<String>,
+    netns: &str,
+    stdio: &Stdio,
+) -> containerd_shim::Result<i32> {
+    // Allow vm to be mutable since we change it in wasmedge_wasi_nn feature
+    #[allow(unused_mut)]
+    #[allow(unused_assignments)]
+    let mut vm = prototype_vm.clone();
+    let rootfs = get_rootfs(spec)
+        .ok_or_else(|| Error::InvalidArgument("rootfs is not set in runtime spec".to_string()))?;
+    let mut preopens = vec![format!("/:{}", rootfs)];
+    preopens.append(&mut get_preopens(spec));
+
+    debug!(
+        "start wasm with args: {:?}, envs: {:?}, preopens: {:?}",
+        args, envs, preopens
+    );
+    match unsafe {
+        fork().map_err(other_error!(
+            e,
+            format!("failed to fork process for {}", module_path.display())
+        ))?
+    } {
+        ForkResult::Parent { child } => Ok(child.as_raw()),
+        ForkResult::Child => {
+            if let Some(cgroup_path) = get_cgroup_path(spec) {
+                // Add child process to Cgroup
+                Cgroup::new(
+                    cgroups_rs::hierarchies::auto(),
+                    cgroup_path.trim_start_matches('/'),
+                )
+                .and_then(|cgroup| cgroup.add_task(CgroupPid::from(std::process::id() as u64)))
+                .map_err(other_error!(
+                    e,
+                    format!("failed to add task to cgroup: {}", cgroup_path)
+                ))?;
+            }
+            // Create new VM instance with the memory limit of the container
+            if get_memory_limit(spec).is_some() {
+                vm = VmBuilder::new()
+                    .with_config(wasi_config(spec)?)
+                    .build()
+                    .map_err(other_error!(e, "failed to build wasmedge vm"))?;
+            }
+            // Only create new VM instance with plugins on wasmedge_wasi_nn feature
+            #[cfg(all(
+                target_os = "linux",
+                feature = "wasmedge_wasi_nn",
+                target_arch = "x86_64"
+            ))]
+            {
+                const NN_PRELOAD_KEY: &str = "io.kuasar.wasm.nn_preload";
+                if let Some(v) = envs
+                    .iter()
+                    .find(|k| k.contains(&NN_PRELOAD_KEY.to_string()))
+                {
+                    if let Some(nn_preload) =
+                        v.strip_prefix::<&str>(format!("{}=", NN_PRELOAD_KEY).as_ref())
+                    {
+                        log::info!("found nn_pre_load: {}", nn_preload);
+                        if let Some(rootfs) = spec.root().as_ref() {
+                            pre_load_with_new_rootfs(nn_preload, rootfs.path()).unwrap();
+                        }
+                    }
+                }
+
+                vm = VmBuilder::new()
+                    .with_config(wasi_config(spec)?)
+                    .with_plugin_wasi_nn()
+                    .with_plugin("wasi_logging", None)
+                    .build()
+                    .unwrap();
+            }
+            match run_wasi_func(vm, args, envs, preopens, netns, module_path, stdio) {
+                Ok(_) => exit(0),
+                // TODO add a pipe? to return detailed error message
+                Err(e) => exit(e.to_exit_code()),
+            }
+        }
+    }
+}
+
+// load_module returns the path of the module of args[0] in rootfs, or the path of
+// its AOT compiled one in the cache if the module cache is enabled.
+async fn load_module(
+    cache: Option<&ModuleCache>,
+    spec: &Spec,
+    args: &[String],
+) -> containerd_shim::Result<PathBuf> {
+    let rootfs = get_rootfs(spec)
+        .ok_or_else(|| Error::InvalidArgument("rootfs is not set in runtime spec".to_string()))?;
+    let cmd = args
+        .first()
+        .ok_or_else(|| Error::InvalidArgument("args is empty in runtime spec".to_string()))?;
+    let module_path = Path::new(&rootfs).join(cmd.trim_start_matches(std::path::MAIN_SEPARATOR));
+    let Some(cache) = cache else {
+        return Ok(module_path);
+    };
+    match aot_compile(cache, &module_path).await {
+        Ok(path) => Ok(path),
+        Err(e) => {
+            warn!(
+                "failed to aot compile {}, run it in interpreter mode, {}",
+                module_path.display(),
+                e
+            );
+            Ok(module_path)
+        }
+    }
+}
+
+async fn aot_compile(cache: &ModuleCache, module_path: &Path) -> anyhow::Result<PathBuf> {
+    let data = tokio::fs::read(module_path).await?;
+    let key = ModuleCache::key(&data, &engine_tag());
+    if let Some(path) = cache.get(&key).await {
+        debug!("load aot compiled module {}", path.display());
+        return Ok(path);
+    }
+    let staging = cache.staging_path(&key);
+    let out_dir = staging.parent().map(Path::to_path_buf).unwrap_or_default();
+    let filename = staging
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let compiled = tokio::task::spawn_blocking(move || {
+        Compiler::new(None).and_then(|c| c.compile_from_bytes(&data, &filename, &out_dir))
+    })
+    .await??;
+    cache.insert(&key, &compiled).await
+}
+
+// engine_tag is the version of wasmedge in the key of the AOT compiled modules.
+fn engine_tag() -> String {
+    let version = built_info::DEPENDENCIES
+        .iter()
+        .find(|(name, _)| *name == "wasmedge-sys")
+        .map(|(_, version)| *version)
+        .unwrap_or_default();
+    format!("wasmedge-{}-aot", version)
+}
+
+// wasi_config generates the wasmedge config with the linear memory of instances limited to
+// the memory limit of the container, the same as the limit of the memories in wasmtime.
+fn wasi_config(spec: &Spec) -> containerd_shim::Result<Config> {
+    let host_options = HostRegistrationConfigOptions::default().wasi(true);
+    let mut builder = ConfigBuilder::new(CommonConfigOptions::default())
+        .with_host_registration_config(host_options);
+    if let Some(limit) = get_memory_limit(spec) {
+        let pages = (limit / WASM_PAGE_SIZE).clamp(1, u32::MAX as i64) as u32;
+        builder =
+            builder.with_runtime_config(RuntimeConfigOptions::default().max_memory_pages(pages));
+    }
+    builder
+        .build()
+        .map_err(other_error!(e, "generate default wasmedge config"))
+}
+
 pub fn maybe_open_stdio(path: &str) -> Result<Option<RawFd>, std::io::Error> {
     if path.is_empty() {
         return Ok(None);
@@ -379,7 +539,6 @@ pub fn maybe_open_stdio(path: &str) -> Result<Option<RawFd>, std::io::Error> {
 pub enum RunError {
     WasmEdge(Box<WasmEdgeError>),
     IO(std::io::Error),
-    NoRootInSpec,
     NoModule,
     Sys(Errno),
 }
@@ -389,7 +548,6 @@ impl RunError {
         match &self {
             RunError::WasmEdge(_e) => -100,
             RunError::IO(_e) => -101,
-            RunError::NoRootInSpec => -102,
             RunError::NoModule => -103,
             RunError::Sys(e) => -(*e as i32),
         }
@@ -401,9 +559,10 @@ fn run_wasi_func(
     args: Vec<String>,
     envs: Vec<String>,
     preopens: Vec<String>,
-    p: &InitProcess,
+    netns: &str,
+    module_path: &Path,
+    stdio: &Stdio,
 ) -> Result<(), RunError> {
-    let netns = &*p.lifecycle.netns;
     if !netns.is_empty() {
         let netns_fd =
             nix::fcntl::open(netns, OFlag::O_CLOEXEC, Mode::empty()).map_err(RunError::Sys)?;
@@ -415,23 +574,8 @@ fn run_wasi_func(
         Some(envs.iter().map(|s| s as &str).collect()),
         Some(preopens.iter().map(|s| s as &str).collect()),
     );
-    let mut cmd = args[0].clone();
-    let stripped = args[0].strip_prefix(std::path::MAIN_SEPARATOR);
-    if let Some(stripped_cmd) = stripped {
-        cmd = stripped_cmd.to_string()
-    }
-    let stdio = p.stdio.clone();
-
-    let rootfs = p
-        .lifecycle
-        .spec
-        .root()
-        .as_ref()
-        .ok_or(RunError::NoRootInSpec)?
-        .path();
-    let mod_path = rootfs.join(cmd);
     let vm = vm
-        .register_module_from_file("main", mod_path)
+        .register_module_from_file("main", module_path)
         .map_err(RunError::WasmEdge)?;
 
     if let Some(stdin) = maybe_open_stdio(&stdio.stdin).map_err(RunError::IO)? {
@@ -449,7 +593,10 @@ fn run_wasi_func(
 }
 
 // any wasm runtime implementation should implement this function
-pub async fn process_exits<F>(task: &TaskService<F, WasmEdgeContainer>) {
+pub async fn process_exits<F>(
+    task: &TaskService<F, WasmEdgeContainer>,
+    process_states: ProcessStates,
+) {
     let containers = task.containers.clone();
     let exit_signal = task.exit.clone();
     let mut s = monitor_subscribe(Topic::Pid)
@@ -468,19 +615,21 @@ pub async fn process_exits<F>(task: &TaskService<F, WasmEdgeContainer>) {
                         if let Subject::Pid(pid) = e.subject {
                             debug!("receive exit event: {}", &e);
                             let exit_code = e.exit_code;
-                            for (_k, cont) in containers.lock().await.iter_mut() {
+                            for (id, cont) in containers.lock().await.iter_mut() {
                                 // pid belongs to container init process
                                 if cont.init.pid == pid {
                                     // set exit for init process
                                     cont.init.set_exited(exit_code).await;
+                                    process_states.exited(id, "", exit_code).await;
                                     break;
                                 }
 
                                 // pid belongs to container common process
-                                for (_exec_id, p) in cont.processes.iter_mut() {
+                                for (exec_id, p) in cont.processes.iter_mut() {
                                     // set exit for exec process
                                     if p.pid == pid {
                                         p.set_exited(exit_code).await;
+                                        process_states.exited(id, exec_id, exit_code).await;
                                         break;
                                     }
                                 }
@@ -14,10 +14,22 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{path::Path, sync::Arc};
+use std::{
+    collections::hash_map::DefaultHasher,
+    future::Future,
+    hash::{Hash, Hasher},
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
 
 use containerd_shim::{
-    api::{CreateTaskRequest, ExecProcessRequest},
+    api::{CreateTaskRequest, ExecProcessRequest, Status},
     container::{ContainerFactory, ContainerTemplate, ProcessFactory},
     error::Error,
     io::Stdio,
@@ -25,31 +37,68 @@ use containerd_shim::{
     monitor::{monitor_notify_by_exec, monitor_subscribe, monitor_unsubscribe, Subject, Topic},
     other, other_error,
     processes::{Process, ProcessLifecycle, ProcessTemplate},
-    protos::{cgroups::metrics::Metrics, types::task::ProcessInfo},
+    protos::{
+        cgroups::metrics::{CPUStat, CPUUsage, MemoryEntry, MemoryStat, Metrics, PidsStat},
+        protobuf::MessageField,
+        types::task::ProcessInfo,
+    },
     task::TaskService,
     util::{mkdir, mount_rootfs, read_spec},
     ExitSignal,
 };
 use log::{debug, trace, warn};
+use nix::sys::signal::Signal;
 use oci_spec::runtime::{LinuxResources, Spec};
 use tokio::fs::read;
-use wasmtime::{Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
-use wasmtime_wasi::{tokio::WasiCtxBuilder, WasiCtx};
+use wasmtime::{
+    component::{Component, Linker as ComponentLinker},
+    Config, Engine, Extern, Func, Linker, Module, ResourceLimiter, Store, StoreLimits,
+    StoreLimitsBuilder, Trap,
+};
+use wasmtime_wasi::{
+    bindings::Command,
+    pipe::{AsyncReadStream, AsyncWriteStream},
+    preview1::{self, WasiP1Ctx},
+    AsyncStdinStream, AsyncStdoutStream, DirPerms, FilePerms, I32Exit, ResourceTable, WasiCtx,
+    WasiCtxBuilder, WasiView,
+};
 
-use crate::utils::{get_args, get_kv_envs, get_memory_limit, get_rootfs};
+use crate::{
+    cache::ModuleCache,
+    sandbox::ProcessStates,
+    utils::{
+        get_args, get_exec_args, get_exec_envs, get_kv_envs, get_memory_limit, get_rootfs,
+        get_spec_from_request, get_wasi_mounts, to_kv_envs,
+    },
+};
+
+// the exit code of the wasm program which is killed, as it is killed by SIGKILL
+const EXIT_CODE_KILLED: i32 = 128 + 9;
+// the exit code of the wasm program which traps or returns an error
+const EXIT_CODE_FAILED: i32 = 1;
+const STDIO_WRITE_BUDGET: usize = 1024 * 1024;
 
 pub type ExecProcess = ProcessTemplate<WasmtimeExecLifecycle>;
 pub type InitProcess = ProcessTemplate<WasmtimeInitLifecycle>;
 
 pub type WasmtimeContainer = ContainerTemplate<InitProcess, ExecProcess, ExecFactory>;
 
-pub struct ExecFactory {}
+pub struct ExecFactory {
+    context: WasmtimeContext,
+}
 
-pub struct WasmtimeExecLifecycle {}
+// WasmtimeExecLifecycle runs a new instance of the module in the rootfs of the container,
+// it has an engine of its own so that killing it does not interrupt the init process.
+pub struct WasmtimeExecLifecycle {
+    context: WasmtimeContext,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    engine: Engine,
+}
 
 pub struct WasmtimeInitLifecycle {
     _bundle: String,
-    spec: Spec,
+    context: WasmtimeContext,
     _netns: String,
     _exit_signal: Arc<ExitSignal>,
     engine: Engine,
@@ -58,11 +107,213 @@ pub struct WasmtimeInitLifecycle {
 #[derive(Default)]
 pub struct WasmtimeContainerFactory {
     pub(crate) netns: String,
+    pub(crate) module_cache: Option<Arc<ModuleCache>>,
+}
+
+// WasmtimeContext is what the processes of a container share.
+#[derive(Clone)]
+struct WasmtimeContext {
+    container_id: String,
+    spec: Spec,
+    stats: Arc<WasmtimeStats>,
+    module_cache: Option<Arc<ModuleCache>>,
 }
 
 struct WasmtimeContainerData {
-    ctx: WasiCtx,
-    limiter: StoreLimits,
+    ctx: WasiP1Ctx,
+    limiter: ContainerLimiter,
+}
+
+// WasmtimeStats is the resource usage of all the wasm instances of a container,
+// it is shared by the init process and the exec processes as they run in the same shim process.
+#[derive(Debug, Default)]
+pub struct WasmtimeStats {
+    memory_limit: Option<usize>,
+    // the total size of the linear memories of the instances
+    memory_usage: AtomicUsize,
+    max_memory_usage: AtomicUsize,
+    memory_failcnt: AtomicU64,
+    // the time spent in running the wasm code, in nanoseconds
+    cpu_usage: AtomicU64,
+    instances: AtomicU64,
+}
+
+impl WasmtimeStats {
+    fn new(spec: &Spec) -> Self {
+        Self {
+            memory_limit: get_memory_limit(spec).map(|x| x as usize),
+            ..Default::default()
+        }
+    }
+
+    fn try_grow_memory(&self, delta: usize) -> bool {
+        let res = self
+            .memory_usage
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |usage| {
+                let desired = usage.checked_add(delta)?;
+                match self.memory_limit {
+                    Some(limit) if desired > limit => None,
+                    _ => Some(desired),
+                }
+            });
+        match res {
+            Ok(usage) => {
+                self.max_memory_usage
+                    .fetch_max(usage + delta, Ordering::SeqCst);
+                true
+            }
+            Err(_) => {
+                self.memory_failcnt.fetch_add(1, Ordering::SeqCst);
+                false
+            }
+        }
+    }
+
+    fn release_memory(&self, size: usize) {
+        self.memory_usage.fetch_sub(size, Ordering::SeqCst);
+    }
+
+    fn metrics(&self) -> Metrics {
+        let mut cpu_usage = CPUUsage::new();
+        cpu_usage.total = self.cpu_usage.load(Ordering::SeqCst);
+        cpu_usage.user = cpu_usage.total;
+        let mut cpu = CPUStat::new();
+        cpu.usage = MessageField::some(cpu_usage);
+
+        let mut memory_usage = MemoryEntry::new();
+        memory_usage.usage = self.memory_usage.load(Ordering::SeqCst) as u64;
+        memory_usage.max = self.max_memory_usage.load(Ordering::SeqCst) as u64;
+        memory_usage.limit = self.memory_limit.map(|x| x as u64).unwrap_or(u64::MAX);
+        memory_usage.failcnt = self.memory_failcnt.load(Ordering::SeqCst);
+        let mut memory = MemoryStat::new();
+        memory.usage = MessageField::some(memory_usage);
+
+        let mut pids = PidsStat::new();
+        pids.current = self.instances.load(Ordering::SeqCst);
+
+        let mut metrics = Metrics::new();
+        metrics.cpu = MessageField::some(cpu);
+        metrics.memory = MessageField::some(memory);
+        metrics.pids = MessageField::some(pids);
+        metrics
+    }
+}
+
+// ContainerLimiter limits the linear memories of a store by the memory limit of the container,
+// which is shared with the other stores of the container, and accounts them in the stats.
+struct ContainerLimiter {
+    limits: StoreLimits,
+    stats: Arc<WasmtimeStats>,
+    // the total size of the linear memories of this store
+    memory_size: usize,
+    // the size of the last growth which may fail after it is accounted
+    growing: usize,
+}
+
+impl ContainerLimiter {
+    fn new(stats: Arc<WasmtimeStats>) -> Self {
+        Self {
+            limits: StoreLimitsBuilder::new().build(),
+            stats,
+            memory_size: 0,
+            growing: 0,
+        }
+    }
+}
+
+impl ResourceLimiter for ContainerLimiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        if !self.limits.memory_growing(current, desired, maximum)? {
+            return Ok(false);
+        }
+        let delta = desired.saturating_sub(current);
+        if !self.stats.try_grow_memory(delta) {
+            return Ok(false);
+        }
+        self.memory_size += delta;
+        self.growing = delta;
+        Ok(true)
+    }
+
+    fn memory_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.stats.release_memory(self.growing);
+        self.memory_size -= self.growing;
+        self.growing = 0;
+        self.limits.memory_grow_failed(error)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn table_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.limits.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
+
+impl Drop for ContainerLimiter {
+    fn drop(&mut self) {
+        self.stats.release_memory(self.memory_size);
+    }
+}
+
+// CpuAccounted accounts the time spent in polling the future of running a wasm program as
+// its cpu usage, the wasm code only runs in the poll as it is executed in the shim process.
+struct CpuAccounted<F> {
+    inner: Pin<Box<F>>,
+    stats: Arc<WasmtimeStats>,
+}
+
+impl<F: Future> Future for CpuAccounted<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let start = Instant::now();
+        let res = self.inner.as_mut().poll(cx);
+        self.stats
+            .cpu_usage
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::SeqCst);
+        res
+    }
+}
+
+impl WasiView for WasmtimeContainerData {
+    fn table(&mut self) -> &mut ResourceTable {
+        self.ctx.table()
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        self.ctx.ctx()
+    }
+}
+
+// WasmtimeEntry is the entry of the wasm program,
+// the "_start" function of a core module or the "wasi:cli/run" of a component.
+enum WasmtimeEntry {
+    Module(Func),
+    Component(Command),
 }
 
 #[async_trait::async_trait]
@@ -85,14 +336,20 @@ impl ContainerFactory<WasmtimeContainer> for WasmtimeContainerFactory {
         let stdio = Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal);
         let exit_signal = Arc::new(Default::default());
         let netns = self.netns.clone();
+        let context = WasmtimeContext {
+            container_id: req.id.to_string(),
+            stats: Arc::new(WasmtimeStats::new(&spec)),
+            spec,
+            module_cache: self.module_cache.clone(),
+        };
         let lifecycle =
-            WasmtimeInitLifecycle::new(&spec, exit_signal, &netns, req.bundle()).await?;
+            WasmtimeInitLifecycle::new(context.clone(), exit_signal, &netns, req.bundle()).await?;
         let init_process = InitProcess::new(req.id(), stdio, lifecycle);
         Ok(WasmtimeContainer {
             id: req.id.to_string(),
             bundle: req.id.to_string(),
             init: init_process,
-            process_factory: ExecFactory {},
+            process_factory: ExecFactory { context },
             processes: Default::default(),
         })
     }
@@ -103,156 +360,299 @@ impl ContainerFactory<WasmtimeContainer> for WasmtimeContainerFactory {
 }
 
 impl WasmtimeInitLifecycle {
-    pub async fn new(
-        spec: &Spec,
+    async fn new(
+        context: WasmtimeContext,
         exit_signal: Arc<ExitSignal>,
         netns: &str,
         bundle: &str,
     ) -> containerd_shim::Result<Self> {
-        let mut config = Config::new();
-        config.async_support(true).epoch_interruption(true);
-        let engine = Engine::new(&config).map_err(other_error!(e, "failed to new engine"))?;
-
         let res = Self {
             _bundle: bundle.to_string(),
-            spec: spec.clone(),
+            context,
             _netns: netns.to_string(),
             _exit_signal: exit_signal,
-            engine,
+            engine: new_engine()?,
         };
         Ok(res)
     }
 }
 
-#[async_trait::async_trait]
-impl ProcessLifecycle<InitProcess> for WasmtimeInitLifecycle {
-    async fn start(&self, p: &mut InitProcess) -> containerd_shim::Result<()> {
-        let args = get_args(&self.spec);
-        let envs = get_kv_envs(&self.spec);
-        let root = self
-            .spec
-            .root()
-            .as_ref()
-            .ok_or(Error::InvalidArgument(
-                "rootfs is not set in runtime spec".to_string(),
-            ))?
-            .path();
-
-        let mut builder = WasiCtxBuilder::new()
-            .args(&args)
-            .map_err(other_error!(e, "failed to set wasi args"))?
-            .envs(envs.as_slice())
-            .map_err(other_error!(e, "failed to set wasi envs"))?;
-        if !p.stdio.stdout.is_empty() {
-            builder = builder.stdout(Box::new(
-                open_wasi_cap_std_file(&p.stdio.stdout, false, true).await?,
-            ));
-        }
-        if !p.stdio.stderr.is_empty() {
-            builder = builder.stderr(Box::new(
-                open_wasi_cap_std_file(&p.stdio.stderr, false, true).await?,
-            ));
-        }
-        if !p.stdio.stdin.is_empty() {
-            builder = builder.stdin(Box::new(
-                open_wasi_cap_std_file(&p.stdio.stdin, true, false).await?,
-            ));
-        }
-        trace!("set rootfs {} to guest", root.display());
-        if root.exists() && root.is_dir() {
-            // TODO support file mount, and filesystem mount
-            let preopen_dir = cap_std::fs::Dir::from_std_file(
-                tokio::fs::File::open(root)
-                    .await
-                    .map_err(io_error!(e, "failed to open rootfs {}", root.display()))?
-                    .into_std()
-                    .await,
-            );
-            builder = builder
-                .preopened_dir(preopen_dir, "/")
-                .map_err(other_error!(e, "failed to set preopened_dir"))?;
-        } else {
-            warn!("rootfs should be a directory");
-        }
-        let ctx = builder.build();
+fn new_engine() -> containerd_shim::Result<Engine> {
+    let mut config = Config::new();
+    config
+        .async_support(true)
+        .epoch_interruption(true)
+        .wasm_component_model(true);
+    Engine::new(&config).map_err(other_error!(e, "failed to new engine"))
+}
 
-        let mut limits_builder = StoreLimitsBuilder::new();
-        if let Some(memory_size) = get_memory_limit(&self.spec).map(|x| x as usize) {
-            limits_builder = limits_builder.memory_size(memory_size);
+// engine_tag is the version and config of the engine in the key of the precompiled modules.
+fn engine_tag(engine: &Engine) -> String {
+    let mut hasher = DefaultHasher::new();
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    format!("wasmtime-{:016x}", hasher.finish())
+}
+
+// load_precompiled loads the module or component of the data from the cache if it is cached,
+// otherwise it is compiled and then saved to the cache.
+async fn load_precompiled<T, C, D, S>(
+    engine: &Engine,
+    cache: Option<&ModuleCache>,
+    data: &[u8],
+    compile: C,
+    deserialize: D,
+    serialize: S,
+) -> containerd_shim::Result<T>
+where
+    C: FnOnce(&Engine, &[u8]) -> anyhow::Result<T>,
+    D: FnOnce(&Engine, &Path) -> anyhow::Result<T>,
+    S: FnOnce(&T) -> anyhow::Result<Vec<u8>>,
+{
+    let Some(cache) = cache else {
+        return compile(engine, data).map_err(other_error!(e, "failed to compile"));
+    };
+    let key = ModuleCache::key(data, &engine_tag(engine));
+    if let Some(path) = cache.get(&key).await {
+        match deserialize(engine, &path) {
+            Ok(t) => {
+                debug!("load precompiled module {}", path.display());
+                return Ok(t);
+            }
+            Err(e) => warn!(
+                "failed to load precompiled module {}, {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+    let t = compile(engine, data).map_err(other_error!(e, "failed to compile"))?;
+    match serialize(&t) {
+        Ok(serialized) => {
+            if let Err(e) = cache.put(&key, &serialized).await {
+                warn!("failed to cache precompiled module, {}", e);
+            }
         }
-        let limiter = limits_builder.build();
+        Err(e) => warn!("failed to serialize precompiled module, {}", e),
+    }
+    Ok(t)
+}
 
-        let mut store = Store::new(&self.engine, WasmtimeContainerData { ctx, limiter });
-        store.limiter(|x| &mut x.limiter);
-        store.set_epoch_deadline(1);
-        debug!("start wasmtime container {}", p.id);
+async fn instantiate_module(
+    engine: &Engine,
+    cache: Option<&ModuleCache>,
+    store: &mut Store<WasmtimeContainerData>,
+    module_data: &[u8],
+) -> containerd_shim::Result<WasmtimeEntry> {
+    let module = load_precompiled(
+        engine,
+        cache,
+        module_data,
+        |engine, data| Module::new(engine, data),
+        // Safety: the file is serialized by the same engine and only written by the sandboxer
+        |engine, path| unsafe { Module::deserialize_file(engine, path) },
+        |module| module.serialize(),
+    )
+    .await?;
+    let mut linker = Linker::new(engine);
+    preview1::add_to_linker_async(&mut linker, |cx: &mut WasmtimeContainerData| &mut cx.ctx)
+        .map_err(other_error!(e, ""))?;
+    let instance = linker
+        .instantiate_async(&mut *store, &module)
+        .await
+        .map_err(other_error!(e, ""))?;
+    let export = instance
+        .get_export(&mut *store, "_start")
+        .ok_or_else(|| other!("_start import doesn't exist in wasm module"))?;
+    match export {
+        Extern::Func(f) => Ok(WasmtimeEntry::Module(f)),
+        _ => Err(other!("_start is not a function")),
+    }
+}
 
-        let mut cmd = args[0].clone();
-        if let Some(stripped_cmd) = args[0].strip_prefix(std::path::MAIN_SEPARATOR) {
-            cmd = stripped_cmd.to_string();
-        }
-        let module_path = root.join(&cmd);
-        trace!("start running module in {}", module_path.display());
-        let module_data = read(&module_path).await.map_err(io_error!(
+async fn instantiate_component(
+    engine: &Engine,
+    cache: Option<&ModuleCache>,
+    store: &mut Store<WasmtimeContainerData>,
+    component_data: &[u8],
+) -> containerd_shim::Result<WasmtimeEntry> {
+    let component = load_precompiled(
+        engine,
+        cache,
+        component_data,
+        |engine, data| Component::new(engine, data),
+        // Safety: the file is serialized by the same engine and only written by the sandboxer
+        |engine, path| unsafe { Component::deserialize_file(engine, path) },
+        |component| component.serialize(),
+    )
+    .await?;
+    let mut linker = ComponentLinker::new(engine);
+    wasmtime_wasi::add_to_linker_async(&mut linker).map_err(other_error!(e, ""))?;
+    let command = Command::instantiate_async(&mut *store, &component, &linker)
+        .await
+        .map_err(other_error!(
             e,
-            "failed to read module file {}",
-            module_path.display()
+            "failed to instantiate wasi:cli/command component"
         ))?;
+    Ok(WasmtimeEntry::Component(command))
+}
 
-        // Compilation of modules happens here, it will use rayon to parallelize the compilation tasks,
-        // rayon init as many threads as the cpu count, and each thread has a stack size of 4MB.
-        // if we run this on a machine with 100 cpus, 400MB memories will be consumed for these stacks
-        // if it is necessary to limit this memory, set env of RAYON_NUM_THREADS to a smaller number.
-        let module = Module::new(&self.engine, module_data).map_err(other_error!(e, ""))?;
-        let mut linker = Linker::new(&self.engine);
-        wasmtime_wasi::tokio::add_to_linker(&mut linker, |cx: &mut WasmtimeContainerData| {
-            &mut cx.ctx
-        })
-        .map_err(other_error!(e, ""))?;
-        let instance = linker
-            .instantiate_async(&mut store, &module)
-            .await
-            .map_err(other_error!(e, ""))?;
-        let export = instance
-            .get_export(&mut store, "_start")
-            .ok_or_else(|| other!("_start import doesn't exist in wasm module"))?;
-        let func = match export {
-            Extern::Func(f) => f,
-            _ => {
-                return Err(other!("_start is not a function"));
-            }
+// run_wasm instantiates the module of args[0] in the rootfs with the preopens of the container,
+// and notifies the exit code of the process by exec_id, which is empty for the init process.
+async fn run_wasm(
+    engine: &Engine,
+    context: &WasmtimeContext,
+    args: &[String],
+    envs: &[(String, String)],
+    stdio: &Stdio,
+    exec_id: &str,
+) -> containerd_shim::Result<()> {
+    let spec = &context.spec;
+    let stats = &context.stats;
+    let container_id = &context.container_id;
+    let root = spec
+        .root()
+        .as_ref()
+        .ok_or(Error::InvalidArgument(
+            "rootfs is not set in runtime spec".to_string(),
+        ))?
+        .path();
+
+    let mut builder = WasiCtxBuilder::new();
+    builder.args(args).envs(envs);
+    if !stdio.stdout.is_empty() {
+        let file = open_file(&stdio.stdout, false, true).await?;
+        builder.stdout(AsyncStdoutStream::new(AsyncWriteStream::new(
+            STDIO_WRITE_BUDGET,
+            file,
+        )));
+    }
+    if !stdio.stderr.is_empty() {
+        let file = open_file(&stdio.stderr, false, true).await?;
+        builder.stderr(AsyncStdoutStream::new(AsyncWriteStream::new(
+            STDIO_WRITE_BUDGET,
+            file,
+        )));
+    }
+    if !stdio.stdin.is_empty() {
+        let file = open_file(&stdio.stdin, true, false).await?;
+        builder.stdin(AsyncStdinStream::new(AsyncReadStream::new(file)));
+    }
+    trace!("set rootfs {} to guest", root.display());
+    if root.exists() && root.is_dir() {
+        builder
+            .preopened_dir(root, "/", DirPerms::all(), FilePerms::all())
+            .map_err(other_error!(e, "failed to set preopened_dir"))?;
+    } else {
+        warn!("rootfs should be a directory");
+    }
+    // only directories can be preopened in WASI, the file mounts are not visible to the guest
+    for m in get_wasi_mounts(spec) {
+        if !Path::new(&m.source).is_dir() {
+            debug!("skip mount {:?} as it is not a directory", m);
+            continue;
+        }
+        trace!("set mount {:?} to guest", m);
+        let (dir_perms, file_perms) = if m.read_only {
+            (DirPerms::READ, FilePerms::READ)
+        } else {
+            (DirPerms::all(), FilePerms::all())
         };
+        builder
+            .preopened_dir(&m.source, &m.destination, dir_perms, file_perms)
+            .map_err(other_error!(e, format!("failed to preopen mount {:?}", m)))?;
+    }
+    let ctx = builder.build_p1();
 
-        let id_clone = p.id.clone();
-        tokio::spawn(async move {
-            match func.call_async(&mut store, &[], &mut []).await {
-                Ok(_) => {
-                    debug!("function of container {} finished successfully", id_clone);
-                    monitor_notify_by_exec(&id_clone, "", 0)
-                        .await
-                        .unwrap_or_default();
-                }
-                Err(e) => {
-                    debug!(
-                        "function of container {} finished with error {}",
-                        id_clone, e
-                    );
-                    // TODO add exit code for wasmtime
-                    monitor_notify_by_exec(&id_clone, "", -1)
-                        .await
-                        .unwrap_or_default();
-                }
+    let limiter = ContainerLimiter::new(stats.clone());
+    let mut store = Store::new(engine, WasmtimeContainerData { ctx, limiter });
+    store.limiter(|x| &mut x.limiter);
+    store.set_epoch_deadline(1);
+    debug!(
+        "start wasmtime process {} of container {}",
+        exec_id, container_id
+    );
+
+    let mut cmd = args[0].clone();
+    if let Some(stripped_cmd) = args[0].strip_prefix(std::path::MAIN_SEPARATOR) {
+        cmd = stripped_cmd.to_string();
+    }
+    let module_path = root.join(&cmd);
+    trace!("start running module in {}", module_path.display());
+    let module_data = read(&module_path).await.map_err(io_error!(
+        e,
+        "failed to read module file {}",
+        module_path.display()
+    ))?;
+
+    // Compilation of modules happens here if it is not cached, it will use rayon to parallelize
+    // the compilation tasks, rayon init as many threads as the cpu count, and each thread has a
+    // stack size of 4MB. if we run this on a machine with 100 cpus, 400MB memories will be
+    // consumed for these stacks, set compile threads of the sandboxer to a smaller number.
+    let cache = context.module_cache.as_deref();
+    let entry = if is_component(&module_data) {
+        instantiate_component(engine, cache, &mut store, &module_data).await?
+    } else {
+        instantiate_module(engine, cache, &mut store, &module_data).await?
+    };
+    stats.instances.fetch_add(1, Ordering::SeqCst);
+
+    let id_clone = container_id.to_string();
+    let exec_id_clone = exec_id.to_string();
+    let stats = stats.clone();
+    tokio::spawn(async move {
+        let run = async move {
+            match entry {
+                WasmtimeEntry::Module(func) => func.call_async(&mut store, &[], &mut []).await,
+                WasmtimeEntry::Component(command) => command
+                    .wasi_cli_run()
+                    .call_run(&mut store)
+                    .await
+                    .and_then(|r| r.map_err(|_| anyhow::anyhow!("wasi:cli/run returned error"))),
             }
-        });
-        Ok(())
+        };
+        let res = CpuAccounted {
+            inner: Box::pin(run),
+            stats: stats.clone(),
+        }
+        .await;
+        stats.instances.fetch_sub(1, Ordering::SeqCst);
+        let exit_code = exit_code_of(&res);
+        match res {
+            Ok(_) => {
+                debug!(
+                    "function of process {} of container {} finished successfully",
+                    exec_id_clone, id_clone
+                );
+            }
+            Err(e) => {
+                debug!(
+                    "function of process {} of container {} finished with error {}, exit code {}",
+                    exec_id_clone, id_clone, e, exit_code
+                );
+            }
+        }
+        monitor_notify_by_exec(&id_clone, &exec_id_clone, exit_code)
+            .await
+            .unwrap_or_default();
+    });
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl ProcessLifecycle<InitProcess> for WasmtimeInitLifecycle {
+    async fn start(&self, p: &mut InitProcess) -> containerd_shim::Result<()> {
+        let args = get_args(&self.context.spec);
+        let envs = get_kv_envs(&self.context.spec);
+        run_wasm(&self.engine, &self.context, &args, &envs, &p.stdio, "").await
     }
 
     async fn kill(
         &self,
         _p: &mut InitProcess,
-        _signal: u32,
+        signal: u32,
         _all: bool,
     ) -> containerd_shim::Result<()> {
+        check_signal(signal)?;
         self.engine.increment_epoch();
         Ok(())
     }
@@ -273,39 +673,50 @@ impl ProcessLifecycle<InitProcess> for WasmtimeInitLifecycle {
     }
 
     async fn stats(&self, _p: &InitProcess) -> containerd_shim::Result<Metrics> {
-        Err(Error::Unimplemented(
-            "not supported for wasi containers".to_string(),
-        ))
+        Ok(self.context.stats.metrics())
     }
 
     async fn ps(&self, _p: &InitProcess) -> containerd_shim::Result<Vec<ProcessInfo>> {
-        Ok(vec![])
+        Ok(vec![shim_process_info()])
     }
 }
 
 #[async_trait::async_trait]
 impl ProcessLifecycle<ExecProcess> for WasmtimeExecLifecycle {
-    async fn start(&self, _p: &mut ExecProcess) -> containerd_shim::Result<()> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasi containers".to_string(),
-        ))
+    async fn start(&self, p: &mut ExecProcess) -> containerd_shim::Result<()> {
+        run_wasm(
+            &self.engine,
+            &self.context,
+            &self.args,
+            &self.envs,
+            &p.stdio,
+            &p.id,
+        )
+        .await?;
+        p.state = Status::RUNNING;
+        Ok(())
     }
 
     async fn kill(
         &self,
-        _p: &mut ExecProcess,
-        _signal: u32,
+        p: &mut ExecProcess,
+        signal: u32,
         _all: bool,
     ) -> containerd_shim::Result<()> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasi containers".to_string(),
-        ))
+        check_signal(signal)?;
+        if p.state != Status::RUNNING {
+            return Err(Error::NotFoundError(format!(
+                "exec process {} is not running",
+                p.id
+            )));
+        }
+        self.engine.increment_epoch();
+        Ok(())
     }
 
     async fn delete(&self, _p: &mut ExecProcess) -> containerd_shim::Result<()> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasi containers".to_string(),
-        ))
+        self.engine.increment_epoch();
+        Ok(())
     }
 
     async fn update(
@@ -314,42 +725,68 @@ impl ProcessLifecycle<ExecProcess> for WasmtimeExecLifecycle {
         _resources: &LinuxResources,
     ) -> containerd_shim::Result<()> {
         Err(Error::Unimplemented(
-            "exec not supported for wasi containers".to_string(),
+            "not supported for wasi containers".to_string(),
         ))
     }
 
     async fn stats(&self, _p: &ExecProcess) -> containerd_shim::Result<Metrics> {
         Err(Error::Unimplemented(
-            "exec not supported for wasi containers".to_string(),
+            "not supported for wasi containers".to_string(),
         ))
     }
 
-    async fn ps(&self, _p: &ExecProcess) -> containerd_shim::Result<Vec<ProcessInfo>> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasi containers".to_string(),
-        ))
+    async fn ps(&self, p: &ExecProcess) -> containerd_shim::Result<Vec<ProcessInfo>> {
+        if p.state != Status::RUNNING {
+            return Ok(vec![]);
+        }
+        Ok(vec![shim_process_info()])
+    }
+}
+
+// check_signal makes sure the signal terminates a process, as the only thing that can be done to
+// a wasm instance in the shim is interrupting it, the other signals can not be delivered.
+fn check_signal(signal: u32) -> containerd_shim::Result<()> {
+    match Signal::try_from(signal as i32) {
+        Ok(Signal::SIGKILL | Signal::SIGTERM | Signal::SIGINT) => Ok(()),
+        _ => Err(Error::InvalidArgument(format!(
+            "signal {} can not be delivered to wasm processes, only SIGKILL, SIGTERM and SIGINT",
+            signal
+        ))),
     }
 }
 
+// shim_process_info is the process of the wasm instances, which all run in the shim process.
+fn shim_process_info() -> ProcessInfo {
+    let mut info = ProcessInfo::new();
+    info.pid = std::process::id();
+    info
+}
+
 #[async_trait::async_trait]
 impl ProcessFactory<ExecProcess> for ExecFactory {
-    async fn create(&self, _req: &ExecProcessRequest) -> containerd_shim::Result<ExecProcess> {
-        Err(Error::Unimplemented(
-            "exec not supported for wasi containers".to_string(),
-        ))
+    async fn create(&self, req: &ExecProcessRequest) -> containerd_shim::Result<ExecProcess> {
+        let process = get_spec_from_request(req)?;
+        let stdio = Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal);
+        let lifecycle = WasmtimeExecLifecycle {
+            context: self.context.clone(),
+            args: get_exec_args(&self.context.spec, &process),
+            envs: to_kv_envs(&get_exec_envs(&self.context.spec, &process)),
+            engine: new_engine()?,
+        };
+        Ok(ExecProcess::new(req.exec_id(), stdio, lifecycle))
     }
 }
 
-async fn open_wasi_cap_std_file<T: AsRef<Path>>(
+async fn open_file<T: AsRef<Path>>(
     path: T,
     read: bool,
     write: bool,
-) -> containerd_shim::Result<wasi_cap_std_sync::file::File> {
+) -> containerd_shim::Result<tokio::fs::File> {
     debug!(
-        "start opening file {} as a wasi cap std file",
+        "start opening file {} for wasi stdio",
         path.as_ref().display()
     );
-    let file = tokio::fs::OpenOptions::new()
+    tokio::fs::OpenOptions::new()
         .write(write)
         .read(read)
         .open(path.as_ref())
@@ -358,17 +795,36 @@ async fn open_wasi_cap_std_file<T: AsRef<Path>>(
             e,
             "failed to open file {}",
             path.as_ref().display()
-        ))?;
-    debug!(
-        "convert std file {} to wasi cap std file",
-        path.as_ref().display()
-    );
-    Ok(wasi_cap_std_sync::file::File::from_cap_std(
-        cap_std::fs::File::from_std(file.into_std().await),
-    ))
+        ))
+}
+
+// is_component tells if the binary is a WebAssembly component rather than a core module,
+// they have the same magic but the layer field in the header of a component is 1.
+fn is_component(data: &[u8]) -> bool {
+    data.len() >= 8 && data[0..4] == *b"\0asm" && data[6..8] == [1, 0]
+}
+
+// exit_code_of gets the exit code of a wasm program, which is the code passed to proc_exit,
+// or the code of being killed if it is interrupted by the kill of the container.
+fn exit_code_of(res: &anyhow::Result<()>) -> i32 {
+    match res {
+        Ok(_) => 0,
+        Err(e) => {
+            if let Some(exit) = e.downcast_ref::<I32Exit>() {
+                exit.0
+            } else if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) {
+                EXIT_CODE_KILLED
+            } else {
+                EXIT_CODE_FAILED
+            }
+        }
+    }
 }
 
-pub(crate) async fn exec_exits<F>(task: &TaskService<F, WasmtimeContainer>) {
+pub(crate) async fn exec_exits<F>(
+    task: &TaskService<F, WasmtimeContainer>,
+    process_states: ProcessStates,
+) {
     let containers = task.containers.clone();
     let exit_signal = task.exit.clone();
     let mut s = monitor_subscribe(Topic::Exec)
@@ -384,11 +840,17 @@ pub(crate) async fn exec_exits<F>(task: &TaskService<F, WasmtimeContainer>) {
                 },
                 res = s.rx.recv() => {
                     if let Some(e) = res {
-                        if let Subject::Exec(cid, _) = &e.subject {
+                        if let Subject::Exec(cid, exec_id) = &e.subject {
                             debug!("receive exit event: {}", &e);
                             let exit_code = e.exit_code;
                             if let Some(cont) = containers.lock().await.get_mut(cid) {
-                                cont.init.set_exited(exit_code).await;
+                                // the exec id of the init process is empty
+                                if exec_id.is_empty() {
+                                    cont.init.set_exited(exit_code).await;
+                                } else if let Some(p) = cont.processes.get_mut(exec_id) {
+                                    p.set_exited(exit_code).await;
+                                }
+                                process_states.exited(cid, exec_id, exit_code).await;
                             }
                         }
                     } else {
@@ -400,3 +862,78 @@ pub(crate) async fn exec_exits<F>(task: &TaskService<F, WasmtimeContainer>) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use wasmtime::Trap;
+    use wasmtime_wasi::I32Exit;
+
+    use std::sync::{atomic::Ordering, Arc};
+
+    use wasmtime::ResourceLimiter;
+
+    use crate::wasmtime::{
+        check_signal, exit_code_of, is_component, ContainerLimiter, WasmtimeStats,
+        EXIT_CODE_FAILED, EXIT_CODE_KILLED,
+    };
+
+    #[test]
+    fn test_exit_code_of() {
+        assert_eq!(exit_code_of(&Ok(())), 0);
+        assert_eq!(exit_code_of(&Err(anyhow::Error::new(I32Exit(3)))), 3);
+        assert_eq!(exit_code_of(&Err(anyhow::Error::new(I32Exit(0)))), 0);
+        assert_eq!(
+            exit_code_of(&Err(anyhow::Error::new(Trap::Interrupt))),
+            EXIT_CODE_KILLED
+        );
+        assert_eq!(exit_code_of(&Err(anyhow!("unreachable"))), EXIT_CODE_FAILED);
+    }
+
+    #[test]
+    fn test_check_signal() {
+        assert!(check_signal(9).is_ok());
+        assert!(check_signal(15).is_ok());
+        assert!(check_signal(2).is_ok());
+        // SIGHUP, SIGUSR1 and the invalid signal 0 can not be delivered
+        assert!(check_signal(1).is_err());
+        assert!(check_signal(10).is_err());
+        assert!(check_signal(0).is_err());
+    }
+
+    #[test]
+    fn test_is_component() {
+        assert!(!is_component(b"\0asm\x01\x00\x00\x00"));
+        assert!(is_component(b"\0asm\x0d\x00\x01\x00"));
+        assert!(!is_component(b"\0asm"));
+    }
+
+    #[test]
+    fn test_container_limiter() {
+        let stats = Arc::new(WasmtimeStats {
+            memory_limit: Some(3 * 65536),
+            ..Default::default()
+        });
+        let mut limiter1 = ContainerLimiter::new(stats.clone());
+        let mut limiter2 = ContainerLimiter::new(stats.clone());
+        assert!(limiter1.memory_growing(0, 2 * 65536, None).unwrap());
+        // the limit is shared by all the stores of the container
+        assert!(!limiter2.memory_growing(0, 2 * 65536, None).unwrap());
+        assert!(limiter2.memory_growing(0, 65536, None).unwrap());
+        assert!(!limiter1.memory_growing(2 * 65536, 3 * 65536, None).unwrap());
+        assert_eq!(stats.memory_usage.load(Ordering::SeqCst), 3 * 65536);
+
+        limiter2
+            .memory_grow_failed(anyhow::anyhow!("failed to grow"))
+            .unwrap();
+        assert_eq!(stats.memory_usage.load(Ordering::SeqCst), 2 * 65536);
+        drop(limiter1);
+        assert_eq!(stats.memory_usage.load(Ordering::SeqCst), 0);
+
+        let metrics = stats.metrics();
+        assert_eq!(metrics.memory.usage.usage, 0);
+        assert_eq!(metrics.memory.usage.max, 3 * 65536);
+        assert_eq!(metrics.memory.usage.limit, 3 * 65536);
+        assert_eq!(metrics.memory.usage.failcnt, 2);
+    }
+}